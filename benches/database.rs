@@ -0,0 +1,114 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::hint::black_box;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use jsondb::{CompactOptions, Database, OpenOptions};
+
+const SIZES: [u32; 2] = [1_000, 10_000];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Item {
+    name: String,
+    value: u64,
+    tags: Vec<String>,
+}
+
+fn item(i: u32) -> Item {
+    Item {
+        name: format!("item {i}"),
+        value: u64::from(i) * 7,
+        tags: vec!["a".to_string(), "b".to_string()],
+    }
+}
+
+// writes `records` records, each updated `versions` times, straight to the file
+fn populate(path: &Path, records: u32, versions: u32) {
+    let mut out = BufWriter::new(File::create(path).unwrap());
+    for _ in 0..versions {
+        for id in 1..=records {
+            let mut line = serde_json::to_value(item(id)).unwrap();
+            line["id"] = id.into();
+            serde_json::to_writer(&mut out, &line).unwrap();
+            writeln!(out).unwrap();
+        }
+    }
+    out.flush().unwrap();
+}
+
+fn open(path: &Path) -> Database<Item, File> {
+    OpenOptions::new().lock(false).open(path).unwrap()
+}
+
+fn reload(c: &mut Criterion) {
+    let mut group = c.benchmark_group("reload");
+    for size in SIZES {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db.json");
+        populate(&path, size, 1);
+
+        group.throughput(Throughput::Elements(size.into()));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &path, |b, path| {
+            b.iter(|| open(path))
+        });
+    }
+    group.finish();
+}
+
+fn insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    for size in SIZES {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db.json");
+        populate(&path, size, 1);
+        let mut database = open(&path);
+
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| database.insert(item(0)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+fn get(c: &mut Criterion) {
+    let mut group = c.benchmark_group("get");
+    for size in SIZES {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db.json");
+        populate(&path, size, 1);
+        let database = open(&path);
+
+        let mut id = 0;
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter(|| {
+                id = id % size + 1;
+                black_box(database.get(id));
+            })
+        });
+    }
+    group.finish();
+}
+
+fn compact(c: &mut Criterion) {
+    let mut group = c.benchmark_group("compact");
+    group.sample_size(10);
+    for size in SIZES {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let path = tmp_dir.path().join("db.json");
+
+        group.throughput(Throughput::Elements(u64::from(size) * 4));
+        group.bench_function(BenchmarkId::from_parameter(size), |b| {
+            b.iter_batched(
+                || populate(&path, size, 4),
+                |()| jsondb::compact(&path, &CompactOptions::new()).unwrap(),
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, reload, insert, get, compact);
+criterion_main!(benches);
@@ -0,0 +1,30 @@
+// loads a database from bytes in memory rather than from a file, e.g. one a user uploaded to a
+// browser tool; this is the part of jsondb that also builds for wasm32-unknown-unknown:
+//
+//     cargo build --lib --no-default-features --target wasm32-unknown-unknown
+//
+// try it out with `cargo run --example in_memory < db.json`
+use serde_json::{Map, Value};
+use std::io::{self, Cursor, Read};
+
+use jsondb::Database;
+
+fn summarize(bytes: Vec<u8>) -> io::Result<Vec<String>> {
+    let mut database = Database::<Map<String, Value>, _>::new(Cursor::new(bytes))?;
+    database.reload()?;
+
+    Ok(database
+        .records()
+        .map(|record| format!("{}: {}", record.id, Value::Object(record.data.clone())))
+        .collect())
+}
+
+fn main() -> io::Result<()> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+
+    for line in summarize(bytes)? {
+        println!("{line}");
+    }
+    Ok(())
+}
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::Cursor;
+
+// loading may fail on malformed input, but must never panic
+fuzz_target!(|data: &[u8]| {
+    let _ = jsondb::Database::<serde_json::Value, _>::new(Cursor::new(data))
+        .and_then(|mut database| database.reload());
+});
@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let report = jsondb::verify(data).unwrap();
+    assert_eq!(report.bytes, data.len() as u64);
+});
@@ -0,0 +1,149 @@
+use actix_web::{
+    body::BoxBody,
+    dev::Payload,
+    error::ErrorInternalServerError,
+    http::{header, StatusCode},
+    web::{self, Bytes, ServiceConfig},
+    FromRequest, HttpRequest, HttpResponse, Responder,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::future::{ready, Ready};
+use std::io::{Read, Seek, Write};
+
+use crate::{
+    cache_tag::CacheTag,
+    record::{Record, RecordId},
+    shared::{handlers::Reply, SharedDatabase},
+};
+
+// lets handlers take a `SharedDatabase` argument, given one registered with `App::app_data`
+impl<T, S, C> FromRequest for SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned + 'static,
+    S: Read + Seek + 'static,
+    C: CacheTag<Record<T>> + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        ready(
+            req.app_data::<SharedDatabase<T, S, C>>()
+                .cloned()
+                .ok_or_else(|| ErrorInternalServerError("database is not registered as app data")),
+        )
+    }
+}
+
+impl<T, S, C> SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    // CRUD routes for records: `GET /`, `POST /`, and `GET`, `PUT` and `DELETE` on `/{id}`; writes
+    // honor `If-Match` against the record's entity tag, which single-record responses carry, while
+    // `GET /` carries the database's
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        cfg.app_data(self.clone())
+            .route("/", web::get().to(list::<T, S, C>))
+            .route("/", web::post().to(insert::<T, S, C>))
+            .route("/{id}", web::get().to(get_record::<T, S, C>))
+            .route("/{id}", web::put().to(put_record::<T, S, C>))
+            .route("/{id}", web::delete().to(delete_record::<T, S, C>));
+    }
+}
+
+// the handlers read and write the log, and may wait for its lock, so they run on actix's blocking
+// thread pool instead of the worker's event loop
+async fn blocking<F>(f: F) -> Reply
+where
+    F: FnOnce() -> Reply + Send + 'static,
+{
+    web::block(f)
+        .await
+        .unwrap_or_else(|err| Reply::error(500, &err.to_string()))
+}
+
+async fn list<T, S, C>(database: SharedDatabase<T, S, C>) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    blocking(move || database.handle_list()).await
+}
+
+async fn insert<T, S, C>(database: SharedDatabase<T, S, C>, body: Bytes) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    blocking(move || database.handle_insert(&body)).await
+}
+
+async fn get_record<T, S, C>(database: SharedDatabase<T, S, C>, id: web::Path<RecordId>) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    let id = id.into_inner();
+    blocking(move || database.handle_get(id)).await
+}
+
+async fn put_record<T, S, C>(
+    database: SharedDatabase<T, S, C>,
+    id: web::Path<RecordId>,
+    req: HttpRequest,
+    body: Bytes,
+) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    let (id, if_match) = (id.into_inner(), if_match(&req));
+    blocking(move || database.handle_put(id, &body, if_match.as_deref())).await
+}
+
+async fn delete_record<T, S, C>(
+    database: SharedDatabase<T, S, C>,
+    id: web::Path<RecordId>,
+    req: HttpRequest,
+) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    let (id, if_match) = (id.into_inner(), if_match(&req));
+    blocking(move || database.handle_delete(id, if_match.as_deref())).await
+}
+
+fn if_match(req: &HttpRequest) -> Option<String> {
+    Some(
+        req.headers()
+            .get(header::IF_MATCH)?
+            .to_str()
+            .ok()?
+            .to_string(),
+    )
+}
+
+impl Responder for Reply {
+    type Body = BoxBody;
+
+    fn respond_to(self, _req: &HttpRequest) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut response = HttpResponse::build(status);
+        if let Some(etag) = self.etag {
+            response.insert_header((header::ETAG, etag));
+        }
+        match self.body {
+            Some(body) => response.content_type("application/json").body(body),
+            None => response.finish(),
+        }
+    }
+}
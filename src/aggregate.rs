@@ -0,0 +1,118 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{Read, Seek};
+use std::ops::AddAssign;
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordData},
+};
+
+// a single pass over the live records, with one result per group; without `group_by` everything
+// ends up in the group `()`
+pub struct Aggregate<'a, T, K> {
+    records: Box<dyn Iterator<Item = &'a RecordData<T>> + 'a>,
+    key: Box<dyn Fn(&T) -> K + 'a>,
+}
+
+impl<'a, T: 'a, K: Eq + Hash> Aggregate<'a, T, K> {
+    pub fn filter<P>(self, mut predicate: P) -> Self
+    where
+        P: FnMut(&T) -> bool + 'a,
+    {
+        Aggregate {
+            records: Box::new(self.records.filter(move |record| predicate(&record.data))),
+            key: self.key,
+        }
+    }
+
+    pub fn group_by<K2, F>(self, key: F) -> Aggregate<'a, T, K2>
+    where
+        K2: Eq + Hash,
+        F: Fn(&T) -> K2 + 'a,
+    {
+        Aggregate {
+            records: self.records,
+            key: Box::new(key),
+        }
+    }
+
+    pub fn fold<A, F>(self, initial: A, mut f: F) -> HashMap<K, A>
+    where
+        A: Clone,
+        F: FnMut(&mut A, &T),
+    {
+        let mut groups = HashMap::new();
+        for record in self.records {
+            let acc = groups
+                .entry((self.key)(&record.data))
+                .or_insert_with(|| initial.clone());
+            f(acc, &record.data);
+        }
+        groups
+    }
+
+    pub fn count(self) -> HashMap<K, usize> {
+        self.fold(0, |count, _| *count += 1)
+    }
+
+    pub fn sum<S, F>(self, mut f: F) -> HashMap<K, S>
+    where
+        S: AddAssign + Default + Clone,
+        F: FnMut(&T) -> S,
+    {
+        self.fold(S::default(), |sum, data| *sum += f(data))
+    }
+
+    pub fn min<V, F>(self, f: F) -> HashMap<K, V>
+    where
+        V: Ord,
+        F: FnMut(&T) -> V,
+    {
+        self.extreme(f, |value, current| value < current)
+    }
+
+    pub fn max<V, F>(self, f: F) -> HashMap<K, V>
+    where
+        V: Ord,
+        F: FnMut(&T) -> V,
+    {
+        self.extreme(f, |value, current| value > current)
+    }
+
+    // replaces the value for a group only if `better` holds, so ties keep the first value seen
+    fn extreme<V, F>(self, mut f: F, better: fn(&V, &V) -> bool) -> HashMap<K, V>
+    where
+        F: FnMut(&T) -> V,
+    {
+        let mut groups: HashMap<K, V> = HashMap::new();
+        for record in self.records {
+            let value = f(&record.data);
+            let key = (self.key)(&record.data);
+            match groups.get_mut(&key) {
+                Some(current) if better(&value, current) => *current = value,
+                Some(_) => (),
+                None => {
+                    groups.insert(key, value);
+                }
+            }
+        }
+        groups
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn aggregate(&self) -> Aggregate<'_, T, ()> {
+        Aggregate {
+            records: Box::new(self.records()),
+            key: Box::new(|_| ()),
+        }
+    }
+}
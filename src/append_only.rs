@@ -0,0 +1,68 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// adapts a write-only sink to the stream interface of `Database`: it reads as empty, and seeking
+// only tracks the number of bytes written, so the database state is kept purely in memory
+#[derive(Debug)]
+pub struct AppendOnly<W> {
+    writer: W,
+    written: u64,
+    position: u64,
+}
+
+impl<W: Write> AppendOnly<W> {
+    pub fn new(writer: W) -> AppendOnly<W> {
+        AppendOnly {
+            writer,
+            written: 0,
+            position: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.writer
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W> Read for AppendOnly<W> {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl<W: Write> Write for AppendOnly<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.writer.write(buf)?;
+        self.written += n as u64;
+        self.position = self.written;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<W> Seek for AppendOnly<W> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.written.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
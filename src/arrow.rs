@@ -0,0 +1,100 @@
+use arrow::datatypes::{Schema, SchemaRef};
+use arrow::error::ArrowError;
+use arrow::json::reader::{infer_json_schema_from_iterator, ReaderBuilder};
+use arrow::record_batch::RecordBatch;
+#[cfg(feature = "parquet")]
+use parquet::{arrow::ArrowWriter, errors::ParquetError};
+use serde::{de::DeserializeOwned, Serialize};
+#[cfg(feature = "parquet")]
+use std::fs::File;
+#[cfg(feature = "parquet")]
+use std::io::Write;
+use std::io::{Read, Seek};
+#[cfg(feature = "parquet")]
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordData},
+};
+
+// rows per batch when exporting, so large databases aren't converted all at once
+const BATCH_ROWS: usize = 8192;
+
+fn record_batch<T: Serialize>(
+    schema: &SchemaRef,
+    records: &[&RecordData<T>],
+) -> Result<RecordBatch, ArrowError> {
+    let mut decoder = ReaderBuilder::new(schema.clone())
+        .with_batch_size(records.len().max(1))
+        .build_decoder()?;
+    decoder.serialize(records)?;
+
+    Ok(decoder
+        .flush()?
+        .unwrap_or_else(|| RecordBatch::new_empty(schema.clone())))
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn arrow_schema(&self) -> Result<Schema, ArrowError> {
+        infer_json_schema_from_iterator(self.records().map(|record| {
+            serde_json::to_value(record).map_err(|err| ArrowError::JsonError(err.to_string()))
+        }))
+    }
+
+    pub fn to_record_batch(&self) -> Result<RecordBatch, ArrowError> {
+        self.to_record_batch_with_schema(Arc::new(self.arrow_schema()?))
+    }
+
+    pub fn to_record_batch_with_schema(
+        &self,
+        schema: SchemaRef,
+    ) -> Result<RecordBatch, ArrowError> {
+        record_batch(&schema, &self.records().collect::<Vec<_>>())
+    }
+
+    // the live records in batches of at most `BATCH_ROWS`, all with the schema inferred from the
+    // whole database
+    pub fn export_arrow(&self) -> Result<Vec<RecordBatch>, ArrowError> {
+        let schema = Arc::new(self.arrow_schema()?);
+        self.record_batches(&schema).collect()
+    }
+
+    fn record_batches<'a>(
+        &'a self,
+        schema: &'a SchemaRef,
+    ) -> impl Iterator<Item = Result<RecordBatch, ArrowError>> + 'a {
+        let records = self.records().collect::<Vec<_>>();
+        (0..records.len()).step_by(BATCH_ROWS).map(move |start| {
+            let end = (start + BATCH_ROWS).min(records.len());
+            record_batch(schema, &records[start..end])
+        })
+    }
+
+    // writes the live records to `out` as Parquet, converting one batch at a time
+    #[cfg(feature = "parquet")]
+    pub fn write_parquet<W: Write + Send>(&self, out: W) -> Result<(), ParquetError> {
+        let schema = Arc::new(self.arrow_schema()?);
+        let mut writer = ArrowWriter::try_new(out, schema.clone(), None)?;
+        for batch in self.record_batches(&schema) {
+            writer.write(&batch?)?;
+        }
+        writer.close()?;
+        Ok(())
+    }
+
+    #[cfg(feature = "parquet")]
+    pub fn export_parquet(&self, path: impl AsRef<Path>) -> Result<(), ParquetError> {
+        let file = File::create(path)?;
+        self.write_parquet(&file)?;
+        file.sync_all()?;
+        Ok(())
+    }
+}
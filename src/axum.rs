@@ -0,0 +1,141 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::{FromRef, FromRequestParts, Path},
+    http::{header, request::Parts, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use std::convert::Infallible;
+use std::io::{Read, Seek, Write};
+
+use crate::{
+    cache_tag::CacheTag,
+    record::{Record, RecordId},
+    shared::{handlers::Reply, SharedDatabase},
+};
+
+// lets handlers take a `SharedDatabase` argument directly, given state that contains one
+impl<T, S, C, St> FromRequestParts<St> for SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+    SharedDatabase<T, S, C>: FromRef<St>,
+    St: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(_parts: &mut Parts, state: &St) -> Result<Self, Infallible> {
+        Ok(SharedDatabase::from_ref(state))
+    }
+}
+
+impl<T, S, C> SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    // CRUD routes for records: `GET /`, `POST /`, and `GET`, `PUT` and `DELETE` on `/{id}`; writes
+    // honor `If-Match` against the record's entity tag, which single-record responses carry, while
+    // `GET /` carries the database's
+    pub fn router<St>(self) -> Router<St> {
+        Router::new()
+            .route("/", get(list::<T, S, C>).post(insert::<T, S, C>))
+            .route(
+                "/{id}",
+                get(get_record::<T, S, C>)
+                    .put(put_record::<T, S, C>)
+                    .delete(delete_record::<T, S, C>),
+            )
+            .with_state(self)
+    }
+}
+
+// the handlers read and write the log, and may wait for its lock, so they run where blocking is fine
+async fn blocking<F>(f: F) -> Reply
+where
+    F: FnOnce() -> Reply + Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .unwrap_or_else(|err| Reply::error(500, &err.to_string()))
+}
+
+async fn list<T, S, C>(database: SharedDatabase<T, S, C>) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    blocking(move || database.handle_list()).await
+}
+
+async fn insert<T, S, C>(database: SharedDatabase<T, S, C>, body: Bytes) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    blocking(move || database.handle_insert(&body)).await
+}
+
+async fn get_record<T, S, C>(database: SharedDatabase<T, S, C>, Path(id): Path<RecordId>) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    blocking(move || database.handle_get(id)).await
+}
+
+async fn put_record<T, S, C>(
+    database: SharedDatabase<T, S, C>,
+    Path(id): Path<RecordId>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    let if_match = if_match(&headers);
+    blocking(move || database.handle_put(id, &body, if_match.as_deref())).await
+}
+
+async fn delete_record<T, S, C>(
+    database: SharedDatabase<T, S, C>,
+    Path(id): Path<RecordId>,
+    headers: HeaderMap,
+) -> Reply
+where
+    T: Serialize + DeserializeOwned + Send + Sync + 'static,
+    S: Read + Write + Seek + Send + Sync + 'static,
+    C: CacheTag<Record<T>> + Send + Sync + 'static,
+{
+    let if_match = if_match(&headers);
+    blocking(move || database.handle_delete(id, if_match.as_deref())).await
+}
+
+fn if_match(headers: &HeaderMap) -> Option<String> {
+    Some(headers.get(header::IF_MATCH)?.to_str().ok()?.to_string())
+}
+
+impl IntoResponse for Reply {
+    fn into_response(self) -> Response {
+        let mut response = Response::builder().status(self.status);
+        if let Some(etag) = self.etag {
+            response = response.header(header::ETAG, etag);
+        }
+        let response = match self.body {
+            Some(body) => response
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body)),
+            None => response.body(Body::empty()),
+        };
+        response.unwrap_or_else(|_| StatusCode::INTERNAL_SERVER_ERROR.into_response())
+    }
+}
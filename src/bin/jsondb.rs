@@ -1,11 +1,29 @@
-use clap::Parser;
-use indexmap::IndexMap;
-use serde::{de::DeserializeOwned, Serialize};
+use clap::{ArgGroup, CommandFactory, Parser, ValueEnum};
+use clap_complete::{
+    engine::{ArgValueCandidates, CompletionCandidate},
+    env::{Bash, Elvish, EnvCompleter, Fish, Powershell, Zsh},
+    CompleteEnv,
+};
+use indexmap::{IndexMap, IndexSet};
+use rand::Rng;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::value::{to_raw_value, RawValue};
 use serde_json::Value;
-use std::io::{self, Write};
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::env;
+use std::ffi::OsString;
+use std::fmt;
+use std::fs::{self, File};
+use std::hint;
+use std::io::{self, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process::{self, ExitCode};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
 
-use jsondb::RecordData;
+use jsondb::{Clock, ConflictStrategy, Filter, Record, RecordData, SystemClock};
 
 type StdError = Box<dyn std::error::Error + Send + Sync>;
 
@@ -13,189 +31,2217 @@ type Object = IndexMap<String, Value>;
 
 #[derive(Debug, Parser)]
 struct Options {
+    // where databases named as `@name` are looked up, see `Config`
+    #[clap(long = "config", global = true)]
+    config: Option<PathBuf>,
+
+    #[clap(long = "lock-timeout", global = true, value_parser = parse_duration)]
+    lock_timeout: Option<Duration>,
+
+    #[clap(long = "no-lock", global = true, conflicts_with = "lock_timeout")]
+    no_lock: bool,
+
+    // fail straight away if another writer has the database open, e.g. an overlapping cron job
+    #[clap(long = "exclusive", global = true)]
+    exclusive: bool,
+
+    #[clap(long = "checksums", global = true)]
+    checksums: bool,
+
+    // how records are printed by the commands that print them; `json` is a single array
+    #[clap(short = 'o', long = "output", global = true, value_enum)]
+    output: Option<OutputFormat>,
+
+    #[clap(long = "pretty", global = true)]
+    pretty: bool,
+
+    #[clap(long = "jq-engine", global = true, value_enum)]
+    jq_engine: Option<JqEngine>,
+
+    #[clap(short = 'v', long = "verbose", global = true)]
+    verbose: bool,
+
+    #[clap(long = "log-format", global = true, value_enum, default_value = "text")]
+    log_format: LogFormat,
+
+    #[clap(
+        long = "error-format",
+        global = true,
+        value_enum,
+        default_value = "text"
+    )]
+    error_format: ErrorFormat,
+
     #[structopt(subcommand)]
     command: Command,
 }
 
 #[derive(Debug, Parser)]
 enum Command {
+    // creates a new database with a header, failing if the file exists already
+    Init {
+        file: PathBuf,
+
+        // a JSON schema for `validate` to check records against by default
+        #[clap(long = "schema")]
+        schema: Option<PathBuf>,
+
+        #[clap(long = "id-type", value_enum, default_value = "u32")]
+        id_type: IdType,
+    },
     #[structopt(alias = "ls")]
     List {
         #[structopt(short = 'd', long = "include-deleted")]
         include_deleted: bool,
 
+        // like `--output`, which takes precedence
+        #[clap(short = 'f', long = "format", value_enum)]
+        format: Option<ListFormat>,
+
+        #[clap(long = "columns", value_delimiter = ',')]
+        columns: Vec<String>,
+
+        #[clap(long = "filter")]
+        filter: Option<Filter>,
+
         file: PathBuf,
+
+        #[clap(add = ArgValueCandidates::new(complete_ids))]
         ids: Vec<u32>,
     },
     Add {
         file: PathBuf,
+
+        // records as JSON objects; stdin is read if there are none here or in `--from`
+        records: Vec<String>,
+
+        // files of JSON objects, laid out like stdin
+        #[clap(long = "from")]
+        from: Vec<PathBuf>,
+
+        // prints the id each record was added as, one per line
+        #[clap(long = "print-ids")]
+        print_ids: bool,
     },
     #[structopt(alias = "upd")]
+    #[clap(group(ArgGroup::new("selection").multiple(true).args(["ids", "filter"])))]
+    #[clap(group(ArgGroup::new("change").multiple(true).args(["jq", "set", "unset"])))]
     Update {
         file: PathBuf,
 
-        #[clap(short = 'n', long = "dry-run", requires = "jq")]
+        #[clap(short = 'n', long = "dry-run", requires = "change")]
         dry_run: bool,
 
-        #[clap(short = 'j', long = "jq", requires = "ids")]
+        #[clap(
+            short = 'j',
+            long = "jq",
+            requires = "selection",
+            conflicts_with_all = ["set", "unset"]
+        )]
         jq: Option<String>,
 
-        #[clap(requires = "jq")]
+        // top-level fields to set, as `field=value`; values are parsed as JSON if they can be,
+        // and taken as strings otherwise
+        #[clap(long = "set", value_parser = parse_assignment, requires = "selection")]
+        set: Vec<(String, Value)>,
+
+        #[clap(long = "unset", requires = "selection")]
+        unset: Vec<String>,
+
+        #[clap(long = "filter", requires = "change")]
+        filter: Option<Filter>,
+
+        #[clap(requires = "change", add = ArgValueCandidates::new(complete_ids))]
         ids: Vec<u32>,
     },
+    Edit {
+        file: PathBuf,
+
+        #[clap(add = ArgValueCandidates::new(complete_ids))]
+        id: u32,
+    },
+    // something has to be picked, so a bare `rm` can't remove, or list, every record
     #[structopt(alias = "rm")]
+    #[clap(group(
+        ArgGroup::new("selector")
+            .required(true)
+            .multiple(true)
+            .args(["ids", "jq", "filter"])
+    ))]
     Remove {
         file: PathBuf,
+
+        #[clap(short = 'n', long = "dry-run")]
+        dry_run: bool,
+
+        #[clap(short = 'j', long = "jq", conflicts_with = "ids")]
+        jq: Option<String>,
+
+        #[clap(long = "filter", conflicts_with = "ids")]
+        filter: Option<Filter>,
+
+        #[clap(add = ArgValueCandidates::new(complete_ids))]
         ids: Vec<u32>,
     },
+    Import {
+        file: PathBuf,
+
+        #[clap(short = 'f', long = "format", value_enum, default_value = "ndjson")]
+        format: ImportFormat,
+
+        #[clap(long = "from")]
+        from: Option<PathBuf>,
+
+        #[clap(long = "id-field")]
+        id_field: Option<String>,
+
+        // lets documents replace the records that already have their `--id-field` ids; without
+        // it, the import fails before anything is written if any of those ids is taken
+        #[clap(long = "replace", requires = "id_field")]
+        replace: bool,
+
+        // the table to read rows from, for sqlite
+        #[clap(long = "table")]
+        table: Option<String>,
+
+        // a column identifying each source row; prints which record id each one ended up as
+        #[clap(long = "id-column")]
+        id_column: Option<String>,
+
+        #[clap(long = "columns", value_delimiter = ',')]
+        columns: Vec<String>,
+    },
+    Export {
+        file: PathBuf,
+
+        #[clap(short = 'f', long = "format", value_enum, default_value = "ndjson")]
+        format: ExportFormat,
+
+        #[clap(long = "to")]
+        to: Option<PathBuf>,
+
+        #[clap(long = "columns", value_delimiter = ',')]
+        columns: Vec<String>,
+
+        // for sqlite, a column per top-level field instead of a single JSON column
+        #[clap(long = "explode")]
+        explode: bool,
+    },
+    Convert {
+        file: PathBuf,
+
+        #[clap(long = "from")]
+        from: Option<PathBuf>,
+    },
+    Merge {
+        file: PathBuf,
+        other: PathBuf,
+
+        #[clap(short = 's', long = "strategy", value_enum, default_value = "newer")]
+        strategy: MergeStrategy,
+    },
+    Stats {
+        file: PathBuf,
+    },
+    Search {
+        file: PathBuf,
+        query: String,
+
+        #[clap(long = "fields", value_delimiter = ',')]
+        fields: Vec<String>,
+    },
+    Watch {
+        file: PathBuf,
+
+        #[clap(short = 'j', long = "jq")]
+        jq: Option<String>,
+
+        #[clap(long = "interval", value_parser = parse_duration, default_value = "500ms")]
+        interval: Duration,
+    },
+    Compact {
+        file: PathBuf,
+
+        #[clap(short = 't', long = "threads")]
+        threads: Option<usize>,
+    },
+    Health {
+        file: PathBuf,
+    },
+    Validate {
+        file: PathBuf,
+
+        // defaults to the schema given to `init`
+        #[clap(long = "schema")]
+        schema: Option<PathBuf>,
+    },
+    Verify {
+        file: PathBuf,
+
+        #[clap(long = "repair")]
+        repair: bool,
+    },
+    Bench {
+        file: PathBuf,
+
+        #[clap(long = "records", default_value = "10000", value_parser = clap::value_parser!(u32).range(1..))]
+        records: u32,
+
+        #[clap(long = "ops", default_value = "1000")]
+        ops: u32,
+    },
+    // prints a script for the shell to source, which completes commands, options and record ids
+    // by asking jsondb
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+}
+
+// record ids are u32 throughout the library, so `uuid` is accepted only to be turned down
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum IdType {
+    U32,
+    Uuid,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Shell {
+    Bash,
+    Elvish,
+    Fish,
+    Powershell,
+    Zsh,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum JqEngine {
+    // libjq, through the `jq` feature
+    Jq,
+    // the pure-Rust jaq implementation, through the `jaq` feature
+    Jaq,
+}
+
+impl Default for JqEngine {
+    fn default() -> JqEngine {
+        if cfg!(feature = "jq") {
+            JqEngine::Jq
+        } else {
+            JqEngine::Jaq
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum MergeStrategy {
+    Ours,
+    Theirs,
+    // the record updated last, or theirs if that can't be told apart
+    Newer,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ErrorFormat {
+    Text,
+    Json,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ListFormat {
+    Jsonl,
+    Table,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Json,
+    Jsonl,
+    // through the `yaml` feature
+    Yaml,
+    Table,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ImportFormat {
+    Ndjson,
+    Csv,
+    Sqlite,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum ExportFormat {
+    Ndjson,
+    Csv,
+    Json,
+    Parquet,
+    Sqlite,
 }
 
 impl Command {
     fn is_read_only(&self) -> bool {
         match self {
-            Command::List { .. } => true,
-            Command::Add { .. } | Command::Update { .. } | Command::Remove { .. } => false,
+            Command::List { .. }
+            | Command::Export { .. }
+            | Command::Stats { .. }
+            | Command::Search { .. }
+            | Command::Watch { .. }
+            | Command::Health { .. }
+            | Command::Validate { .. }
+            | Command::Completions { .. } => true,
+            Command::Add { .. }
+            | Command::Update { .. }
+            | Command::Edit { .. }
+            | Command::Remove { .. }
+            | Command::Import { .. }
+            | Command::Convert { .. }
+            | Command::Merge { .. }
+            | Command::Compact { .. }
+            | Command::Bench { .. }
+            | Command::Init { .. } => false,
+            Command::Verify { repair, .. } => !repair,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Command::Init { .. } => "init",
+            Command::List { .. } => "list",
+            Command::Add { .. } => "add",
+            Command::Update { .. } => "update",
+            Command::Edit { .. } => "edit",
+            Command::Remove { .. } => "remove",
+            Command::Import { .. } => "import",
+            Command::Export { .. } => "export",
+            Command::Convert { .. } => "convert",
+            Command::Merge { .. } => "merge",
+            Command::Stats { .. } => "stats",
+            Command::Search { .. } => "search",
+            Command::Watch { .. } => "watch",
+            Command::Compact { .. } => "compact",
+            Command::Health { .. } => "health",
+            Command::Validate { .. } => "validate",
+            Command::Verify { .. } => "verify",
+            Command::Bench { .. } => "bench",
+            Command::Completions { .. } => "completions",
+        }
+    }
+
+    fn file_mut(&mut self) -> &mut PathBuf {
+        match self {
+            Command::Init { file, .. }
+            | Command::List { file, .. }
+            | Command::Add { file, .. }
+            | Command::Update { file, .. }
+            | Command::Edit { file, .. }
+            | Command::Remove { file, .. }
+            | Command::Import { file, .. }
+            | Command::Export { file, .. }
+            | Command::Convert { file, .. }
+            | Command::Merge { file, .. }
+            | Command::Stats { file }
+            | Command::Search { file, .. }
+            | Command::Watch { file, .. }
+            | Command::Compact { file, .. }
+            | Command::Health { file }
+            | Command::Validate { file, .. }
+            | Command::Verify { file, .. }
+            | Command::Bench { file, .. } => file,
+            Command::Completions { .. } => unreachable!(),
         }
     }
 
     fn file(&self) -> &Path {
         match self {
-            Command::List { file, .. }
+            Command::Init { file, .. }
+            | Command::List { file, .. }
             | Command::Add { file, .. }
             | Command::Update { file, .. }
-            | Command::Remove { file, .. } => file,
+            | Command::Edit { file, .. }
+            | Command::Remove { file, .. }
+            | Command::Import { file, .. }
+            | Command::Export { file, .. }
+            | Command::Convert { file, .. }
+            | Command::Merge { file, .. }
+            | Command::Stats { file }
+            | Command::Search { file, .. }
+            | Command::Watch { file, .. }
+            | Command::Compact { file, .. }
+            | Command::Health { file }
+            | Command::Validate { file, .. }
+            | Command::Verify { file, .. }
+            | Command::Bench { file, .. } => file,
+            Command::Completions { .. } => unreachable!(),
         }
     }
 }
 
-fn main() -> Result<(), StdError> {
-    let opts = Options::parse();
-
-    let mut database = jsondb::OpenOptions::new()
-        .read_only(opts.command.is_read_only())
-        .open::<Object, _>(opts.command.file())?;
-
-    match opts.command {
-        Command::List {
-            include_deleted,
-            ids,
-            ..
-        } => {
-            let records = if include_deleted {
-                list_records(database.records_include_deleted(), &ids)
-            } else {
-                list_records(database.records(), &ids)
-            };
+// progress reports on stderr, which are only written with `--verbose`
+struct Logger {
+    verbose: bool,
+    format: LogFormat,
+    start: Instant,
+}
 
-            print_records(records)?;
+impl Logger {
+    fn new(verbose: bool, format: LogFormat) -> Logger {
+        Logger {
+            verbose,
+            format,
+            start: Instant::now(),
         }
+    }
 
-        Command::Add { .. } => {
-            let input = serde_json::Deserializer::from_reader(io::stdin()).into_iter::<Object>();
-            for record in input {
-                let mut record = record?;
+    // every event also reports the time since the command started
+    fn log(&self, event: &str, fields: &[(&str, Value)]) {
+        if !self.verbose {
+            return;
+        }
 
-                if record.contains_key("id") {
-                    record.shift_remove("id");
+        let elapsed_ms = self.start.elapsed().as_secs_f64() * 1000.0;
+        match self.format {
+            LogFormat::Text => {
+                let mut line = format!("jsondb: {event}");
+                for (key, value) in fields {
+                    line.push_str(&format!(" {key}={value}"));
                 }
-                if record.contains_key("deleted") {
-                    record.shift_remove("deleted");
+                eprintln!("{line} elapsed={elapsed_ms:.1}ms");
+            }
+            LogFormat::Json => {
+                let mut object = Object::new();
+                object.insert("event".to_string(), event.into());
+                for (key, value) in fields {
+                    object.insert(key.to_string(), value.clone());
+                }
+                let elapsed_ms = (elapsed_ms * 1000.0).round() / 1000.0;
+                object.insert("elapsed_ms".to_string(), elapsed_ms.into());
+                match serde_json::to_string(&object) {
+                    Ok(line) => eprintln!("{line}"),
+                    Err(err) => eprintln!("jsondb: {err}"),
                 }
-
-                database.insert(record)?;
             }
         }
+    }
+}
 
-        Command::Update {
-            dry_run, jq, ids, ..
-        } => {
-            if let Some(jq) = jq {
-                let records = list_records(database.records(), &ids);
-                let updated_records: Vec<RecordData<Object>> = run_jq_all(&jq, records)?;
+// what went wrong, as far as scripts are concerned; clap exits with 2 on usage errors
+#[derive(Clone, Copy, Debug)]
+enum Failure {
+    Other,
+    NotFound,
+    Invalid,
+    Locked,
+    Corrupt,
+}
 
-                if dry_run {
-                    print_records(&updated_records)?;
-                } else {
-                    for mut record in updated_records {
-                        if record.contains_key("id") {
-                            record.shift_remove("id");
-                        }
-                        if record.contains_key("deleted") {
-                            record.shift_remove("deleted");
-                        }
+impl Failure {
+    fn of(err: &(dyn std::error::Error + 'static)) -> Failure {
+        if let Some(err) = err.downcast_ref::<CliError>() {
+            return err.failure;
+        }
 
-                        database.upsert(record.id, |_| Some(record.data))?;
-                    }
-                }
-            } else {
-                let input = serde_json::Deserializer::from_reader(io::stdin())
-                    .into_iter::<RecordData<Object>>();
-                for record in input {
-                    let mut record = record?;
+        let io_err = err.downcast_ref::<io::Error>();
+        let err = io_err
+            .and_then(jsondb::Error::from_io)
+            .or_else(|| err.downcast_ref());
+        match err {
+            Some(jsondb::Error::NotFound { .. }) => Failure::NotFound,
+            Some(jsondb::Error::InvalidRecord { .. }) => Failure::Invalid,
+            Some(jsondb::Error::Locked { .. }) => Failure::Locked,
+            Some(
+                jsondb::Error::ChecksumMismatch { .. }
+                | jsondb::Error::MissingHeader
+                | jsondb::Error::UnsupportedHeader(_)
+                | jsondb::Error::Corrupt { .. },
+            ) => Failure::Corrupt,
+            Some(_) => Failure::Other,
+            // e.g. a database file that doesn't exist
+            None => match io_err.map(io::Error::kind) {
+                Some(io::ErrorKind::NotFound) => Failure::NotFound,
+                _ => Failure::Other,
+            },
+        }
+    }
 
-                    if record.contains_key("id") {
-                        record.shift_remove("id");
-                    }
-                    if record.contains_key("deleted") {
-                        record.shift_remove("deleted");
-                    }
+    fn name(self) -> &'static str {
+        match self {
+            Failure::Other => "error",
+            Failure::NotFound => "not_found",
+            Failure::Invalid => "invalid",
+            Failure::Locked => "locked",
+            Failure::Corrupt => "corrupt",
+        }
+    }
 
-                    database.upsert(record.id, |_| Some(record.data))?;
-                }
-            }
+    fn exit_code(self) -> u8 {
+        match self {
+            Failure::Other => 1,
+            Failure::NotFound => 3,
+            Failure::Invalid => 4,
+            Failure::Locked => 5,
+            Failure::Corrupt => 6,
         }
+    }
+}
 
-        Command::Remove { ids, .. } => {
-            for id in ids {
-                database.delete(id)?;
+// an error detected by the CLI itself rather than the library
+#[derive(Debug)]
+struct CliError {
+    failure: Failure,
+    message: String,
+}
+
+impl CliError {
+    fn new(failure: Failure, message: String) -> CliError {
+        CliError { failure, message }
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CliError {}
+
+// `~/.config/jsondb/config.toml`, naming databases so they can be given as `@name`:
+//
+//     [databases.inventory]
+//     path = "/srv/data/inventory.json"
+//     output = "table"
+//     filter = "stock > 0"
+//
+// relative paths are taken from the directory the config is in
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default)]
+    databases: BTreeMap<String, Profile>,
+}
+
+// defaults for a named database, which the command line overrides
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Profile {
+    path: PathBuf,
+    output: Option<OutputFormat>,
+    #[serde(default)]
+    pretty: bool,
+    // for `list`
+    filter: Option<String>,
+    // for `watch`
+    jq: Option<String>,
+}
+
+impl Config {
+    fn default_path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os("JSONDB_CONFIG") {
+            return Some(path.into());
+        }
+        let dir = match env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        };
+        Some(dir.join("jsondb").join("config.toml"))
+    }
+
+    fn load(path: &Path) -> Result<Config, StdError> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let message = format!("no config file at {}", path.display());
+                return Err(CliError::new(Failure::NotFound, message).into());
             }
+            Err(err) => return Err(err.into()),
+        };
+        let mut config: Config = toml::from_str(&contents)
+            .map_err(|err| format!("invalid config {}: {err}", path.display()))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        for profile in config.databases.values_mut() {
+            profile.path = expand_home(&profile.path).unwrap_or_else(|| dir.join(&profile.path));
         }
+        Ok(config)
+    }
+}
+
+fn expand_home(path: &Path) -> Option<PathBuf> {
+    let rest = path.strip_prefix("~").ok()?;
+    Some(PathBuf::from(env::var_os("HOME")?).join(rest))
+}
+
+// replaces databases given as `@name` with their paths, and fills in the defaults they come with
+fn apply_profiles(opts: &mut Options) -> Result<(), StdError> {
+    let profile_name = |path: &Path| path.to_str()?.strip_prefix('@').map(str::to_string);
+    let other = match &opts.command {
+        Command::Merge { other, .. } => profile_name(other),
+        _ => None,
+    };
+    let name = profile_name(opts.command.file());
+    if name.is_none() && other.is_none() {
+        return Ok(());
     }
 
+    let path = match opts.config.clone().or_else(Config::default_path) {
+        Some(path) => path,
+        None => return Err("can't tell where the config file is, use --config".into()),
+    };
+    let config = Config::load(&path)?;
+    let profile = |name: &str| {
+        config.databases.get(name).ok_or_else(|| {
+            let message = format!("no database named `{name}` in {}", path.display());
+            CliError::new(Failure::NotFound, message)
+        })
+    };
+
+    if let (Command::Merge { other, .. }, Some(name)) = (&mut opts.command, &other) {
+        *other = profile(name)?.path.clone();
+    }
+    let profile = match &name {
+        Some(name) => profile(name)?,
+        None => return Ok(()),
+    };
+    *opts.command.file_mut() = profile.path.clone();
+    // `list --format` still beats the profile
+    if !matches!(
+        opts.command,
+        Command::List {
+            format: Some(_),
+            ..
+        }
+    ) {
+        opts.output = opts.output.or(profile.output);
+    }
+    opts.pretty |= profile.pretty;
+    match &mut opts.command {
+        Command::List { filter, .. } if filter.is_none() => {
+            if let Some(source) = &profile.filter {
+                *filter = Some(source.parse()?);
+            }
+        }
+        Command::Watch { jq, .. } if jq.is_none() => jq.clone_from(&profile.jq),
+        _ => (),
+    }
     Ok(())
 }
 
-fn list_records<'a>(
-    records: impl IntoIterator<Item = &'a RecordData<Object>>,
-    ids: &[u32],
-) -> Vec<&'a RecordData<Object>> {
-    records
-        .into_iter()
-        .filter(move |record| ids.is_empty() || ids.contains(&record.id))
+// ids of the records in the database on the command line being completed, which comes after
+// `--` in the arguments jsondb is run with by the completion script
+fn complete_ids() -> Vec<CompletionCandidate> {
+    let args = env::args_os()
+        .skip_while(|arg| arg != "--")
+        .skip(1)
+        .collect::<Vec<OsString>>();
+    let matches = match Options::command()
+        .ignore_errors(true)
+        .try_get_matches_from(args)
+    {
+        Ok(matches) => matches,
+        Err(_) => return Vec::new(),
+    };
+    let file = match matches.subcommand() {
+        Some((_, matches)) => matches.get_one::<PathBuf>("file").cloned(),
+        None => None,
+    };
+    let file = match file {
+        Some(file) => file,
+        None => return Vec::new(),
+    };
+    let file = match file.to_str().and_then(|file| file.strip_prefix('@')) {
+        Some(name) => {
+            let config = matches.get_one::<PathBuf>("config").cloned();
+            let config = config
+                .or_else(Config::default_path)
+                .map(|path| Config::load(&path));
+            match config.and_then(Result::ok) {
+                Some(mut config) => match config.databases.remove(name) {
+                    Some(profile) => profile.path,
+                    None => return Vec::new(),
+                },
+                None => return Vec::new(),
+            }
+        }
+        None => file,
+    };
+
+    // completing must never hang, or modify anything
+    let database = jsondb::OpenOptions::new()
+        .read_only(true)
+        .lock(false)
+        .open::<Object, _>(&file);
+    let database = match database {
+        Ok(database) => database,
+        Err(_) => return Vec::new(),
+    };
+    database
+        .records()
+        .map(|record| {
+            let mut preview = serde_json::to_string(&record.data).unwrap_or_default();
+            if preview.chars().count() > 60 {
+                preview = preview.chars().take(59).chain(Some('…')).collect();
+            }
+            CompletionCandidate::new(record.id.to_string()).help(Some(preview.into()))
+        })
         .collect()
 }
 
-fn print_records<'a>(records: impl IntoIterator<Item = &'a RecordData<Object>>) -> io::Result<()> {
-    let mut out = io::stdout();
-    for record in records {
-        serde_json::to_writer(&mut out, &record)?;
-        writeln!(out)?;
-        out.flush()?
+fn print_completions(shell: Shell) -> ExitCode {
+    let completer: &dyn EnvCompleter = match shell {
+        Shell::Bash => &Bash,
+        Shell::Elvish => &Elvish,
+        Shell::Fish => &Fish,
+        Shell::Powershell => &Powershell,
+        Shell::Zsh => &Zsh,
+    };
+    // the script runs jsondb to complete, so it has to find this same binary
+    let bin = env::current_exe()
+        .ok()
+        .and_then(|path| path.to_str().map(str::to_string))
+        .unwrap_or_else(|| "jsondb".to_string());
+
+    let mut out = io::stdout().lock();
+    match completer.write_registration("COMPLETE", "jsondb", "jsondb", &bin, &mut out) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("jsondb: {err}");
+            ExitCode::FAILURE
+        }
     }
-    Ok(())
 }
 
-fn run_jq_all<'a, T: 'a + Serialize, U: DeserializeOwned>(
-    jq: &str,
-    inputs: impl IntoIterator<Item = &'a T>,
-) -> Result<Vec<U>, StdError> {
-    let mut program = jq_rs::compile(jq).map_err(|err| format!("jq error: {err}"))?;
+fn main() -> ExitCode {
+    CompleteEnv::with_factory(Options::command).complete();
 
-    let inputs = inputs
-        .into_iter()
-        .map(serde_json::to_string)
-        .collect::<Result<Vec<_>, _>>()?;
+    let opts = Options::parse();
+    if let Command::Completions { shell } = opts.command {
+        return print_completions(shell);
+    }
+    let logger = Logger::new(opts.verbose, opts.log_format);
+    let error_format = opts.error_format;
+    let command = opts.command.name();
 
-    let outputs = inputs
-        .into_iter()
-        .map(|input| program.run(&input))
-        .collect::<Result<Vec<_>, _>>()
-        .map_err(|err| format!("jq error: {err}"))?;
+    match run(opts, &logger) {
+        Ok(()) => {
+            logger.log("finished", &[("command", command.into())]);
+            ExitCode::SUCCESS
+        }
+        Err(err) => {
+            logger.log("failed", &[("command", command.into())]);
+            let failure = Failure::of(err.as_ref());
+            match error_format {
+                ErrorFormat::Text => eprintln!("jsondb: {err}"),
+                ErrorFormat::Json => eprintln!(
+                    "{}",
+                    serde_json::json!({
+                        "error": err.to_string(),
+                        "kind": failure.name(),
+                        "exit_code": failure.exit_code(),
+                    })
+                ),
+            }
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
 
-    let outputs = outputs
-        .into_iter()
-        .map(|output| serde_json::from_str(&output))
-        .collect::<Result<Vec<_>, _>>()?;
+fn run(mut opts: Options, logger: &Logger) -> Result<(), StdError> {
+    apply_profiles(&mut opts)?;
 
-    Ok(outputs)
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!(
+        "command",
+        name = opts.command.name(),
+        file = %opts.command.file().display()
+    )
+    .entered();
+
+    if let Command::Init {
+        file,
+        schema,
+        id_type,
+    } = &opts.command
+    {
+        init_database(file, schema.as_deref(), *id_type)?;
+        logger.log(
+            "initialized",
+            &[("file", file.display().to_string().into())],
+        );
+        return Ok(());
+    }
+
+    // compaction rewrites the file, so it must not be opened as a database first
+    if let Command::Compact { file, threads } = &opts.command {
+        let mut compact_opts = jsondb::CompactOptions::new();
+        if let Some(threads) = *threads {
+            compact_opts = compact_opts.threads(threads);
+        }
+        let stats = jsondb::compact(file, &compact_opts)?;
+        logger.log(
+            "compacted",
+            &[
+                ("file", file.display().to_string().into()),
+                ("records_read", stats.records_read.into()),
+                ("live_records", stats.live_records.into()),
+                ("bytes_before", stats.bytes_before.into()),
+                ("bytes_after", stats.bytes_after.into()),
+            ],
+        );
+        return Ok(());
+    }
+
+    // a probe should neither create the database nor hang on a stuck lock
+    if let Command::Health { file } = &opts.command {
+        fs::metadata(file)?;
+        let mut database = open_options(&opts, true)
+            .lock_timeout(opts.lock_timeout.or(Some(Duration::from_secs(1))))
+            .open::<Object, _>(file)?;
+
+        let health = database.health_check();
+        for (name, check) in health.checks() {
+            println!("{name}: {check}");
+        }
+        if !health.is_healthy() {
+            return Err("database is unhealthy".into());
+        }
+        return Ok(());
+    }
+
+    // checks the records as the library loads them, against a schema that may come from the header
+    if let Command::Validate { file, schema } = &opts.command {
+        let schema = match schema {
+            Some(schema) => schema.clone(),
+            None => header_schema(file, open_options(&opts, true))?,
+        };
+        let invalid = validate_log(file, &schema, open_options(&opts, true))?;
+        logger.log(
+            "validated",
+            &[
+                ("file", file.display().to_string().into()),
+                ("invalid", invalid.into()),
+            ],
+        );
+        if invalid > 0 {
+            let message = format!("{invalid} records do not match the schema");
+            return Err(CliError::new(Failure::Invalid, message).into());
+        }
+        return Ok(());
+    }
+
+    // corrupt logs can't be opened, so this reads the file directly
+    if let Command::Verify { file, repair } = &opts.command {
+        let verify_opts = jsondb::VerifyOptions::new().require_checksums(opts.checksums);
+        let report = if *repair {
+            jsondb::repair(file, &verify_opts)?
+        } else {
+            jsondb::verify_with_opts(File::open(file)?, &verify_opts)?
+        };
+        for issue in &report.issues {
+            let severity = match issue.severity {
+                jsondb::Severity::Error => "error",
+                jsondb::Severity::Warning => "warning",
+            };
+            println!(
+                "line {} (offset {}): {severity}: {}",
+                issue.line, issue.offset, issue.message
+            );
+        }
+
+        let errors = report.errors().count();
+        let warnings = report.warnings().count();
+        println!(
+            "{} lines, {} entries, {errors} errors, {warnings} warnings",
+            report.lines, report.entries
+        );
+        if *repair && errors > 0 {
+            println!("removed {errors} unreadable lines");
+        }
+        logger.log(
+            "verified",
+            &[
+                ("file", file.display().to_string().into()),
+                ("entries", report.entries.into()),
+                ("errors", errors.into()),
+                ("warnings", warnings.into()),
+                ("repaired", (*repair && errors > 0).into()),
+            ],
+        );
+        if !*repair && errors > 0 {
+            let message = format!("{errors} entries can't be read");
+            return Err(CliError::new(Failure::Corrupt, message).into());
+        }
+        return Ok(());
+    }
+
+    if let Command::Bench { file, records, ops } = &opts.command {
+        return run_bench(file, *records, *ops);
+    }
+
+    let read_only = opts.command.is_read_only();
+    let mut database = open_options(&opts, read_only).open::<Object, _>(opts.command.file())?;
+    let opened = database.log_position();
+    logger.log(
+        "opened",
+        &[
+            ("file", opts.command.file().display().to_string().into()),
+            ("records", database.record_count().into()),
+            ("entries", opened.records.into()),
+            ("bytes", opened.offset.into()),
+        ],
+    );
+
+    let jq_engine = opts.jq_engine.unwrap_or_default();
+    let (output, pretty) = (opts.output.unwrap_or(OutputFormat::Jsonl), opts.pretty);
+    match opts.command {
+        Command::List {
+            include_deleted,
+            format,
+            columns,
+            filter,
+            ids,
+            ..
+        } => {
+            let records = if include_deleted {
+                list_records(database.records_include_deleted(), &ids, filter.as_ref())
+            } else {
+                list_records(database.records(), &ids, filter.as_ref())
+            };
+
+            let output = opts.output.unwrap_or(match format {
+                Some(ListFormat::Table) => OutputFormat::Table,
+                Some(ListFormat::Jsonl) | None => OutputFormat::Jsonl,
+            });
+            let objects = records.into_iter().map(record_object);
+            // tables always lead with the id
+            let objects = match output {
+                OutputFormat::Table => objects.collect(),
+                _ => objects.map(|object| project(&object, &columns)).collect(),
+            };
+            print_objects(objects, &columns, output, pretty)?
+        }
+
+        Command::Add {
+            records,
+            from,
+            print_ids,
+            ..
+        } => {
+            // arguments and files are read in full first, so a typo doesn't leave only some of
+            // the records added
+            let mut parsed = Vec::new();
+            for (i, record) in records.iter().enumerate() {
+                let record = serde_json::from_str::<Object>(record)
+                    .map_err(|err| format!("record {}: {err}", i + 1))?;
+                parsed.push(record);
+            }
+            for path in &from {
+                let reader = BufReader::new(File::open(path)?);
+                for record in serde_json::Deserializer::from_reader(reader).into_iter::<Object>() {
+                    parsed.push(record.map_err(|err| format!("{}: {err}", path.display()))?);
+                }
+            }
+
+            let stdin = if records.is_empty() && from.is_empty() {
+                Some(serde_json::Deserializer::from_reader(io::stdin()).into_iter::<Object>())
+            } else {
+                None
+            };
+            let input = parsed
+                .into_iter()
+                .map(Ok)
+                .chain(stdin.into_iter().flatten());
+            for record in input {
+                let mut record = record?;
+
+                strip_reserved(&mut record);
+
+                let id = database.insert(record)?;
+                if print_ids {
+                    println!("{id}");
+                }
+            }
+        }
+
+        Command::Update {
+            dry_run,
+            jq,
+            set,
+            unset,
+            filter,
+            ids,
+            ..
+        } => {
+            if !set.is_empty() || !unset.is_empty() {
+                if let Some(&id) = ids.iter().find(|&&id| !database.contains(id)) {
+                    return Err(jsondb::Error::NotFound { id }.into());
+                }
+
+                let records = list_records(database.records(), &ids, filter.as_ref());
+                let updated_records: Vec<RecordData<Object>> = records
+                    .into_iter()
+                    .map(|record| {
+                        let mut data = record.data.clone();
+                        for (field, value) in &set {
+                            data.insert(field.clone(), value.clone());
+                        }
+                        for field in &unset {
+                            data.shift_remove(field);
+                        }
+                        RecordData {
+                            id: record.id,
+                            data,
+                        }
+                    })
+                    .collect();
+
+                if dry_run {
+                    print_records(&updated_records, output, pretty)?;
+                } else {
+                    for record in updated_records {
+                        database.upsert(record.id, |_| Some(record.data))?;
+                    }
+                }
+            } else if let Some(jq) = jq {
+                let records = list_records(database.records(), &ids, filter.as_ref());
+                let updated_records: Vec<RecordData<Object>> = run_jq_all(jq_engine, &jq, records)?;
+
+                if dry_run {
+                    print_records(&updated_records, output, pretty)?;
+                } else {
+                    for mut record in updated_records {
+                        strip_reserved(&mut record);
+
+                        database.upsert(record.id, |_| Some(record.data))?;
+                    }
+                }
+            } else {
+                let input = serde_json::Deserializer::from_reader(io::stdin())
+                    .into_iter::<RecordData<Object>>();
+                for record in input {
+                    let mut record = record?;
+
+                    strip_reserved(&mut record);
+
+                    database.upsert(record.id, |_| Some(record.data))?;
+                }
+            }
+        }
+
+        Command::Edit { id, .. } => {
+            let original = match database.get(id) {
+                Some(record) => record.data.clone(),
+                None => return Err(jsondb::Error::NotFound { id }.into()),
+            };
+
+            let edited = match edit_record(id, &original)? {
+                Some(edited) => edited,
+                None => return Ok(()),
+            };
+
+            // refuse to overwrite changes made by someone else while the editor was open
+            database.reload()?;
+            if database.get(id).map(|record| &record.data) != Some(&original) {
+                return Err(format!("record {id} was modified while editing").into());
+            }
+
+            database.upsert(id, |_| Some(edited))?;
+        }
+
+        Command::Remove {
+            dry_run,
+            jq,
+            filter,
+            ids,
+            ..
+        } => {
+            // ids that were asked for by name have to exist, so typos aren't silently ignored
+            if let Some(&id) = ids.iter().find(|&&id| !database.contains(id)) {
+                return Err(jsondb::Error::NotFound { id }.into());
+            }
+
+            // a dry run lists exactly the records a real one removes
+            let mut records = list_records(database.records(), &ids, filter.as_ref());
+            if let Some(jq) = jq {
+                // records are removed when the expression is truthy, as with `select(EXPR)`, or
+                // when it is `select(EXPR)` and lets them through; no output at all is false
+                let selected: Vec<Vec<Value>> = run_jq_each(jq_engine, &jq, &records)?;
+                let mut selected = selected.into_iter();
+                records.retain(|_| {
+                    let outputs = selected.next().unwrap_or_default();
+                    !matches!(
+                        outputs.first(),
+                        Some(Value::Null | Value::Bool(false)) | None
+                    )
+                });
+            }
+            if dry_run {
+                return print_records(records, output, pretty);
+            }
+
+            let ids = records
+                .into_iter()
+                .map(|record| record.id)
+                .collect::<Vec<_>>();
+            for id in ids {
+                database.delete(id)?;
+            }
+        }
+
+        Command::Import {
+            format,
+            from,
+            id_field,
+            replace,
+            table,
+            id_column,
+            columns,
+            ..
+        } => {
+            let input = || -> io::Result<Box<dyn Read>> {
+                Ok(match &from {
+                    Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                    None => Box::new(io::stdin()),
+                })
+            };
+
+            let documents = match (format, &table) {
+                (ImportFormat::Sqlite, Some(table)) => match &from {
+                    Some(path) => read_sqlite(path, table)?,
+                    None => return Err("sqlite import needs a file to read from (--from)".into()),
+                },
+                (ImportFormat::Sqlite, None) => {
+                    return Err("sqlite import needs a table to read from (--table)".into());
+                }
+                (_, Some(_)) => return Err("--table is only supported for sqlite import".into()),
+                (ImportFormat::Ndjson, None) => {
+                    read_ndjson(input()?).collect::<Result<Vec<_>, _>>()?
+                }
+                (ImportFormat::Csv, None) => read_csv(input()?)?,
+            };
+
+            // the source keys and ids are checked up front, so a bad row doesn't leave a partial
+            // import
+            let keys = match &id_column {
+                Some(column) => documents
+                    .iter()
+                    .enumerate()
+                    .map(|(i, document)| match document.get(column) {
+                        Some(key) if !key.is_null() => Ok(Some(format_cell(Some(key)))),
+                        _ => Err(format!(
+                            "row {} has no value in id column `{column}`",
+                            i + 1
+                        )),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![None; documents.len()],
+            };
+            let ids = match &id_field {
+                Some(field) => documents
+                    .iter()
+                    .map(|document| natural_key(document, field).map(Some))
+                    .collect::<Result<Vec<_>, _>>()?,
+                None => vec![None; documents.len()],
+            };
+            if !replace {
+                let mut seen = HashSet::new();
+                let taken = ids
+                    .iter()
+                    .flatten()
+                    .find(|&&id| !seen.insert(id) || database.contains(id));
+                if let Some(id) = taken {
+                    return Err(format!(
+                        "record id {id} is already in use, use --replace to overwrite it"
+                    )
+                    .into());
+                }
+            }
+
+            for ((document, key), id) in documents.into_iter().zip(keys).zip(ids) {
+                let mut document = project(&document, &columns);
+                strip_reserved(&mut document);
+
+                let id = match id {
+                    Some(id) => {
+                        database.upsert(id, |_| Some(document))?;
+                        id
+                    }
+                    None => database.insert(document)?,
+                };
+                if let Some(key) = key {
+                    println!("{key}\t{id}");
+                }
+            }
+        }
+
+        Command::Export {
+            format,
+            to,
+            columns,
+            explode,
+            ..
+        } => {
+            let out = || -> io::Result<Box<dyn Write + Send>> {
+                Ok(match &to {
+                    Some(path) => Box::new(File::create(path)?),
+                    None => Box::new(io::stdout()),
+                })
+            };
+
+            if explode && !matches!(format, ExportFormat::Sqlite) {
+                return Err("--explode is only supported for sqlite export".into());
+            }
+
+            match format {
+                ExportFormat::Ndjson => {
+                    let objects = database.records().map(record_object);
+                    let objects = objects.map(|object| project(&object, &columns));
+                    write_ndjson(objects, out()?, pretty)?
+                }
+                ExportFormat::Csv => {
+                    let objects: Vec<_> = database.records().map(record_object).collect();
+                    write_csv(&objects, &columns, out()?)?
+                }
+                ExportFormat::Json | ExportFormat::Parquet | ExportFormat::Sqlite
+                    if !columns.is_empty() =>
+                {
+                    return Err("--columns is only supported for ndjson and csv export".into());
+                }
+                ExportFormat::Json if pretty => {
+                    let objects: Vec<_> = database.records().map(record_object).collect();
+                    let mut out = out()?;
+                    serde_json::to_writer_pretty(&mut out, &objects)?;
+                    writeln!(out)?;
+                    out.flush()?
+                }
+                ExportFormat::Json => database.write_json_array(out()?)?,
+                ExportFormat::Parquet => export_parquet(&database, out()?)?,
+                ExportFormat::Sqlite => match &to {
+                    Some(path) => export_sqlite(&database, path, explode)?,
+                    None => return Err("sqlite export needs a file to write to (--to)".into()),
+                },
+            }
+        }
+
+        Command::Convert { from, .. } => {
+            let input: Box<dyn Read> = match from {
+                Some(path) => Box::new(BufReader::new(File::open(path)?)),
+                None => Box::new(io::stdin()),
+            };
+
+            for record in jsondb::read_json_array::<Object, _>(input)? {
+                match record {
+                    Record::Upsert(record) => {
+                        let mut record = Arc::unwrap_or_clone(record.data);
+                        strip_reserved(&mut record);
+
+                        database.upsert(record.id, |_| Some(record.data))?;
+                    }
+                    Record::Delete(record) => database.delete(record.id)?,
+                    Record::Merge(record) => database.merge(record.id, record.operand)?,
+                }
+            }
+        }
+
+        Command::Merge {
+            other, strategy, ..
+        } => {
+            let other_path = other.display().to_string();
+            let other = jsondb::OpenOptions::new()
+                .lock(!opts.no_lock)
+                .lock_timeout(opts.lock_timeout)
+                .open_read_only::<Object, _>(other)?;
+
+            let strategy = match strategy {
+                MergeStrategy::Ours => ConflictStrategy::PreferSelf,
+                MergeStrategy::Theirs => ConflictStrategy::PreferOther,
+                MergeStrategy::Newer => ConflictStrategy::PreferNewer,
+            };
+            let written = database.merge_from(&other, strategy)?;
+            logger.log(
+                "merged",
+                &[("other", other_path.into()), ("records", written.into())],
+            );
+        }
+
+        Command::Stats { .. } => {
+            let stats = database.stats()?;
+            let format_id = |id: Option<u32>| id.map_or("-".to_string(), |id| id.to_string());
+
+            println!("live records:    {}", stats.live_records);
+            println!("deleted records: {}", stats.deleted_records);
+            println!("log entries:     {}", stats.log_entries);
+            println!("file size:       {} bytes", stats.log_bytes);
+            println!("dead bytes:      {:.1}%", stats.dead_bytes_ratio() * 100.0);
+            println!("min id:          {}", format_id(stats.min_id));
+            println!("max id:          {}", format_id(stats.max_id));
+
+            if !stats.field_counts.is_empty() {
+                println!();
+                let width = stats
+                    .field_counts
+                    .keys()
+                    .map(|field| field.chars().count())
+                    .max();
+                for (field, count) in &stats.field_counts {
+                    let percent = *count as f64 * 100.0 / stats.live_records as f64;
+                    println!(
+                        "{field:width$}  {count} ({percent:.1}%)",
+                        width = width.unwrap_or(0)
+                    );
+                }
+            }
+        }
+
+        Command::Search { query, fields, .. } => {
+            let query = query.to_lowercase();
+            let matches = database.records().filter(|record| {
+                let object = project(&record.data, &fields);
+                object.values().any(|value| contains_text(value, &query))
+            });
+            print_records(matches, output, pretty)?;
+        }
+
+        Command::Watch { jq, interval, .. } => {
+            // changes are printed as they come, so there's no array or table to put them in
+            if matches!(output, OutputFormat::Json | OutputFormat::Table) {
+                return Err("watch can only print jsonl or yaml".into());
+            }
+
+            let mut out = io::stdout();
+            let mut position = database.log_position();
+            loop {
+                thread::sleep(interval);
+                if !database.is_stale()? {
+                    continue;
+                }
+                let before = database.records_owned();
+                let changes = if database.reload()?.replaced {
+                    // compaction or a restore leaves nothing to tell what's new in the log, so
+                    // the records are compared with what they were instead
+                    replaced_changes(before, &database)?
+                } else {
+                    // unresolved merges and deletes are printed as they appear in the log
+                    database
+                        .changes_since(position)?
+                        .iter()
+                        .map(|record| match record.data() {
+                            Some(data) => to_raw_value(data),
+                            None => to_raw_value(record),
+                        })
+                        .collect::<Result<Vec<_>, _>>()?
+                };
+                position = database.log_position();
+
+                // a filter such as `select(...)` leaves changes out, and others may print several
+                let changes: Vec<Box<RawValue>> = match &jq {
+                    Some(jq) => run_jq_each(jq_engine, jq, &changes)?
+                        .into_iter()
+                        .flatten()
+                        .collect(),
+                    None => changes,
+                };
+                for change in changes {
+                    if output == OutputFormat::Yaml {
+                        writeln!(out, "---")?;
+                        write_yaml(&serde_json::from_str::<Value>(change.get())?, &mut out)?;
+                        continue;
+                    }
+                    if pretty {
+                        serde_json::to_writer_pretty(&mut out, &change)?;
+                    } else {
+                        serde_json::to_writer(&mut out, &change)?;
+                    }
+                    writeln!(out)?;
+                }
+                out.flush()?;
+            }
+        }
+
+        Command::Compact { .. }
+        | Command::Health { .. }
+        | Command::Validate { .. }
+        | Command::Verify { .. }
+        | Command::Bench { .. }
+        | Command::Init { .. }
+        | Command::Completions { .. } => unreachable!(),
+    }
+
+    let position = database.log_position();
+    if !read_only && position != opened {
+        logger.log(
+            "wrote",
+            &[
+                ("records", (position.records - opened.records).into()),
+                ("bytes", (position.offset - opened.offset).into()),
+            ],
+        );
+    }
+    database.close()?;
+    Ok(())
+}
+
+// fills a scratch database and times the basic operations on it
+fn run_bench(file: &Path, records: u32, ops: u32) -> Result<(), StdError> {
+    if file.exists() {
+        return Err(format!("{} already exists; bench needs a new file", file.display()).into());
+    }
+    // the scratch database is removed again however the benchmark ends
+    let scratch = tempfile::TempPath::try_from_path(file)?;
+    let file: &Path = &scratch;
+
+    let item = |i: u32| -> Object {
+        let mut object = Object::new();
+        object.insert("name".to_string(), format!("item {i}").into());
+        object.insert("value".to_string(), (u64::from(i) * 7).into());
+        object.insert("tags".to_string(), serde_json::json!(["a", "b"]));
+        object
+    };
+    let mut results = Vec::new();
+
+    let mut database = jsondb::OpenOptions::new().open::<Object, _>(file)?;
+    let start = Instant::now();
+    for i in 0..records {
+        database.insert(item(i))?;
+    }
+    results.push(("insert", records, start.elapsed()));
+
+    let start = Instant::now();
+    let mut database = jsondb::OpenOptions::new().open::<Object, _>(file)?;
+    results.push(("reload", records, start.elapsed()));
+
+    let mut rng = rand::rng();
+    let ids: Vec<u32> = (0..ops).map(|_| rng.random_range(1..=records)).collect();
+
+    let start = Instant::now();
+    for &id in &ids {
+        hint::black_box(database.get(id));
+    }
+    results.push(("get", ops, start.elapsed()));
+
+    let start = Instant::now();
+    for &id in &ids {
+        database.upsert(id, |data| data.cloned())?;
+    }
+    results.push(("update", ops, start.elapsed()));
+
+    let timings = database.stats()?.timings;
+    drop(database);
+
+    let start = Instant::now();
+    jsondb::compact(file, &jsondb::CompactOptions::new())?;
+    results.push(("compact", records + ops, start.elapsed()));
+
+    println!(
+        "{:10}  {:>10}  {:>10}  {:>12}",
+        "operation", "ops", "total", "ops/s"
+    );
+    for (name, ops, elapsed) in results {
+        let rate = f64::from(ops) / elapsed.as_secs_f64();
+        println!(
+            "{name:10}  {ops:>10}  {:>10}  {rate:>12.0}",
+            format_elapsed(elapsed)
+        );
+    }
+
+    // only the handle used from the reload onwards
+    println!();
+    println!(
+        "reloads:   {} in {}",
+        timings.reloads,
+        format_elapsed(timings.reload_time)
+    );
+    println!(
+        "writes:    {} in {}",
+        timings.writes,
+        format_elapsed(timings.write_time)
+    );
+    Ok(())
+}
+
+fn format_elapsed(elapsed: Duration) -> String {
+    format!("{:.3}s", elapsed.as_secs_f64())
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let (value, unit) = match s.find(|c: char| !c.is_ascii_digit() && c != '.') {
+        Some(i) => s.split_at(i),
+        None => (s, "s"),
+    };
+    let value = value
+        .parse::<f64>()
+        .map_err(|_| format!("invalid duration: {s}"))?;
+
+    let seconds = match unit {
+        "ms" => value / 1000.0,
+        "s" => value,
+        "m" => value * 60.0,
+        _ => return Err(format!("invalid duration unit: {unit}")),
+    };
+    Ok(Duration::from_secs_f64(seconds))
+}
+
+// parses `field=value` for `update --set`
+fn parse_assignment(s: &str) -> Result<(String, Value), String> {
+    let (field, value) = s
+        .split_once('=')
+        .ok_or_else(|| format!("expected `field=value`, got `{s}`"))?;
+    if field.is_empty() || RESERVED_FIELDS.contains(&field) {
+        return Err(format!("can't set field `{field}`"));
+    }
+    let value = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.to_string()));
+    Ok((field.to_string(), value))
+}
+
+const RESERVED_FIELDS: [&str; 5] = ["id", "deleted", "_meta", "_merge", "_crc"];
+
+fn strip_reserved(record: &mut Object) {
+    for key in RESERVED_FIELDS {
+        record.shift_remove(key);
+    }
+}
+
+fn natural_key(document: &Object, field: &str) -> Result<u32, StdError> {
+    let value = document
+        .get(field)
+        .ok_or_else(|| format!("document is missing id field `{field}`"))?;
+
+    let id = match value {
+        Value::Number(n) => n.as_u64().and_then(|n| u32::try_from(n).ok()),
+        Value::String(s) => s.parse().ok(),
+        _ => None,
+    };
+    id.ok_or_else(|| format!("id field `{field}` is not a valid record id: {value}").into())
+}
+
+fn read_ndjson(input: impl Read) -> impl Iterator<Item = serde_json::Result<Object>> {
+    serde_json::Deserializer::from_reader(input).into_iter::<Object>()
+}
+
+fn write_ndjson(
+    objects: impl IntoIterator<Item = Object>,
+    mut out: impl Write,
+    pretty: bool,
+) -> io::Result<()> {
+    for object in objects {
+        if pretty {
+            serde_json::to_writer_pretty(&mut out, &object)?;
+        } else {
+            serde_json::to_writer(&mut out, &object)?;
+        }
+        writeln!(out)?;
+    }
+    out.flush()
+}
+
+fn read_csv(input: impl Read) -> Result<Vec<Object>, StdError> {
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader.headers()?.clone();
+
+    let mut objects = Vec::new();
+    for row in reader.records() {
+        let row = row?;
+        let object = headers
+            .iter()
+            .zip(row.iter())
+            .filter(|(_, cell)| !cell.is_empty())
+            .map(|(header, cell)| (header.to_string(), parse_cell(cell)))
+            .collect();
+        objects.push(object);
+    }
+    Ok(objects)
+}
+
+fn print_table(objects: &[Object], columns: &[String]) -> io::Result<()> {
+    // the id always comes first, followed by the selected (or all) other keys
+    let mut headers = IndexSet::new();
+    headers.insert("id".to_string());
+    if columns.is_empty() {
+        for object in objects {
+            headers.extend(object.keys().cloned());
+        }
+    } else {
+        headers.extend(columns.iter().cloned());
+    }
+
+    let rows: Vec<Vec<String>> = objects
+        .iter()
+        .map(|object| {
+            headers
+                .iter()
+                .map(|header| format_cell(object.get(header)))
+                .collect()
+        })
+        .collect();
+
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, header)| {
+            rows.iter()
+                .map(|row| row[i].chars().count())
+                .chain(Some(header.chars().count()))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let headers: Vec<String> = headers.into_iter().collect();
+    let separator: Vec<String> = widths.iter().map(|&width| "-".repeat(width)).collect();
+
+    let mut out = io::stdout().lock();
+    write_table_row(&mut out, &headers, &widths)?;
+    write_table_row(&mut out, &separator, &widths)?;
+    for row in &rows {
+        write_table_row(&mut out, row, &widths)?;
+    }
+    out.flush()
+}
+
+fn write_table_row(out: &mut impl Write, cells: &[String], widths: &[usize]) -> io::Result<()> {
+    let line = cells
+        .iter()
+        .zip(widths)
+        .map(|(cell, &width)| format!("{cell:width$}"))
+        .collect::<Vec<_>>()
+        .join("  ");
+    writeln!(out, "{}", line.trim_end())
+}
+
+fn write_csv(objects: &[Object], columns: &[String], out: impl Write) -> Result<(), StdError> {
+    // without explicit columns, use every key in order of first appearance
+    let columns = if columns.is_empty() {
+        let mut headers = IndexSet::new();
+        for object in objects {
+            headers.extend(object.keys().cloned());
+        }
+        headers.into_iter().collect()
+    } else {
+        columns.to_vec()
+    };
+
+    let mut writer = csv::Writer::from_writer(out);
+    writer.write_record(&columns)?;
+    for object in objects {
+        writer.write_record(columns.iter().map(|column| csv_cell(object.get(column))))?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+// cells are read back as JSON where they can be, and as strings where they can't
+fn parse_cell(cell: &str) -> Value {
+    serde_json::from_str(cell).unwrap_or_else(|_| Value::String(cell.to_string()))
+}
+
+// like `format_cell`, but so that `parse_cell` reads it back as the same value: strings that
+// would read as something else are written as JSON strings, quotes and all, and null is written
+// as `null`, since an empty cell is a missing field
+fn csv_cell(value: Option<&Value>) -> String {
+    match value {
+        Some(Value::String(s)) if s.is_empty() || serde_json::from_str::<Value>(s).is_ok() => {
+            Value::String(s.clone()).to_string()
+        }
+        Some(Value::Null) => "null".to_string(),
+        value => format_cell(value),
+    }
+}
+
+fn format_cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(value) => value.to_string(),
+    }
+}
+
+fn record_object(record: &RecordData<Object>) -> Object {
+    let mut object = Object::new();
+    object.insert("id".to_string(), record.id.into());
+    object.extend(record.data.iter().map(|(k, v)| (k.clone(), v.clone())));
+    object
+}
+
+fn project(object: &Object, columns: &[String]) -> Object {
+    if columns.is_empty() {
+        return object.clone();
+    }
+
+    columns
+        .iter()
+        .filter_map(|column| Some((column.clone(), object.get(column)?.clone())))
+        .collect()
+}
+
+// whether any string in `value`, however deeply nested, contains the lowercased `query`
+fn contains_text(value: &Value, query: &str) -> bool {
+    match value {
+        Value::String(s) => s.to_lowercase().contains(query),
+        Value::Array(values) => values.iter().any(|value| contains_text(value, query)),
+        Value::Object(fields) => fields.values().any(|value| contains_text(value, query)),
+        Value::Null | Value::Bool(_) | Value::Number(_) => false,
+    }
+}
+
+// returns None if the record was left unchanged
+fn edit_record(id: u32, data: &Object) -> Result<Option<Object>, StdError> {
+    // created exclusively under a random name, and removed again however editing ends
+    let mut file = tempfile::Builder::new()
+        .prefix(&format!("jsondb-edit-{id}-"))
+        .suffix(".json")
+        .tempfile()?;
+    let contents = serde_json::to_string_pretty(&RecordData {
+        id,
+        data: data.clone(),
+    })?;
+    writeln!(file, "{contents}")?;
+    file.flush()?;
+
+    loop {
+        run_editor(file.path())?;
+
+        let edited = fs::read_to_string(file.path())?;
+        match serde_json::from_str::<Object>(&edited) {
+            Ok(mut edited) => {
+                strip_reserved(&mut edited);
+                return Ok((&edited != data).then_some(edited));
+            }
+            Err(err) => {
+                eprint!("jsondb: invalid JSON: {err}\nedit again? [Y/n] ");
+                let mut answer = String::new();
+                io::stdin().read_line(&mut answer)?;
+                if answer.trim().eq_ignore_ascii_case("n") {
+                    return Err("edit aborted".into());
+                }
+            }
+        }
+    }
+}
+
+fn run_editor(path: &Path) -> Result<(), StdError> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // the editor may carry its own arguments, e.g. `code --wait`
+    let mut args = editor.split_whitespace();
+    let program = args.next().ok_or("$EDITOR is empty")?;
+    let status = process::Command::new(program)
+        .args(args)
+        .arg(path)
+        .status()
+        .map_err(|err| format!("failed to run {program}: {err}"))?;
+
+    if !status.success() {
+        return Err(format!("{program} exited with {status}").into());
+    }
+    Ok(())
+}
+
+fn list_records<'a>(
+    records: impl IntoIterator<Item = &'a RecordData<Object>>,
+    ids: &[u32],
+    filter: Option<&Filter>,
+) -> Vec<&'a RecordData<Object>> {
+    records
+        .into_iter()
+        .filter(move |record| ids.is_empty() || ids.contains(&record.id))
+        .filter(|record| match filter {
+            Some(filter) => {
+                filter.matches(&Value::Object(record_object(record).into_iter().collect()))
+            }
+            None => true,
+        })
+        .collect()
+}
+
+// the records that differ from `before`, followed by deletes for the ones that are gone
+fn replaced_changes(
+    before: Vec<Arc<RecordData<Object>>>,
+    database: &jsondb::Database<Object, File>,
+) -> Result<Vec<Box<RawValue>>, StdError> {
+    let mut before = before
+        .into_iter()
+        .map(|record| (record.id, record))
+        .collect::<BTreeMap<_, _>>();
+    let mut changes = Vec::new();
+    for record in database.records() {
+        match before.remove(&record.id) {
+            Some(old) if old.data == record.data => {}
+            _ => changes.push(to_raw_value(record)?),
+        }
+    }
+    for id in before.into_keys() {
+        changes.push(to_raw_value(&Record::<Object>::delete(id))?);
+    }
+    Ok(changes)
+}
+
+fn print_records<'a>(
+    records: impl IntoIterator<Item = &'a RecordData<Object>>,
+    output: OutputFormat,
+    pretty: bool,
+) -> Result<(), StdError> {
+    let objects = records.into_iter().map(record_object).collect();
+    print_objects(objects, &[], output, pretty)
+}
+
+fn print_objects(
+    objects: Vec<Object>,
+    columns: &[String],
+    output: OutputFormat,
+    pretty: bool,
+) -> Result<(), StdError> {
+    let mut out = io::stdout().lock();
+    match output {
+        OutputFormat::Jsonl => write_ndjson(objects, out, pretty)?,
+        OutputFormat::Json => {
+            if pretty {
+                serde_json::to_writer_pretty(&mut out, &objects)?;
+            } else {
+                serde_json::to_writer(&mut out, &objects)?;
+            }
+            writeln!(out)?;
+            out.flush()?
+        }
+        OutputFormat::Yaml => write_yaml(&objects, out)?,
+        OutputFormat::Table => print_table(&objects, columns)?,
+    }
+    Ok(())
+}
+
+#[cfg(feature = "yaml")]
+fn write_yaml(value: &impl Serialize, mut out: impl Write) -> Result<(), StdError> {
+    serde_yaml_ng::to_writer(&mut out, value)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(not(feature = "yaml"))]
+fn write_yaml(_value: &impl Serialize, _out: impl Write) -> Result<(), StdError> {
+    Err("yaml output requires jsondb to be built with the `yaml` feature".into())
+}
+
+fn run_jq_all<'a, T: 'a + Serialize, U: DeserializeOwned>(
+    engine: JqEngine,
+    jq: &str,
+    inputs: impl IntoIterator<Item = &'a T>,
+) -> Result<Vec<U>, StdError> {
+    run_jq_each(engine, jq, inputs)?
+        .into_iter()
+        .map(|outputs| {
+            let mut outputs = outputs.into_iter();
+            match (outputs.next(), outputs.next()) {
+                (Some(output), None) => Ok(output),
+                _ => Err("jq error: expected exactly one output per input".into()),
+            }
+        })
+        .collect()
+}
+
+// every output for each of the inputs, which may be none at all, e.g. for `select`
+fn run_jq_each<'a, T: 'a + Serialize, U: DeserializeOwned>(
+    engine: JqEngine,
+    jq: &str,
+    inputs: impl IntoIterator<Item = &'a T>,
+) -> Result<Vec<Vec<U>>, StdError> {
+    let inputs = inputs
+        .into_iter()
+        .map(serde_json::to_string)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let outputs = match engine {
+        JqEngine::Jq => run_libjq(jq, &inputs)?,
+        JqEngine::Jaq => run_jaq(jq, &inputs)?,
+    };
+
+    let outputs = outputs
+        .into_iter()
+        .map(|outputs| {
+            outputs
+                .iter()
+                .map(|output| serde_json::from_str(output))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(outputs)
+}
+
+#[cfg(feature = "jq")]
+fn run_libjq(jq: &str, inputs: &[String]) -> Result<Vec<Vec<String>>, StdError> {
+    let mut program = jq_rs::compile(jq).map_err(|err| format!("jq error: {err}"))?;
+
+    inputs
+        .iter()
+        .map(|input| {
+            // libjq puts the outputs one after the other, which is nothing at all if there are none
+            let output = program
+                .run(input)
+                .map_err(|err| format!("jq error: {err}"))?;
+            let outputs = serde_json::Deserializer::from_str(&output)
+                .into_iter::<Box<RawValue>>()
+                .map(|output| output.map(|output| output.get().to_owned()))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok(outputs)
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "jq"))]
+fn run_libjq(_jq: &str, _inputs: &[String]) -> Result<Vec<Vec<String>>, StdError> {
+    Err("jsondb was built without libjq support (the `jq` feature); try --jq-engine jaq".into())
+}
+
+#[cfg(feature = "jaq")]
+fn run_jaq(jq: &str, inputs: &[String]) -> Result<Vec<Vec<String>>, StdError> {
+    use jaq_core::load::{Arena, File, Loader};
+    use jaq_core::{data, unwrap_valr, Compiler, Ctx, Vars};
+    use jaq_json::{read, Val};
+
+    let defs = jaq_core::defs()
+        .chain(jaq_std::defs())
+        .chain(jaq_json::defs());
+    let funs = jaq_core::funs()
+        .chain(jaq_std::funs())
+        .chain(jaq_json::funs());
+
+    let arena = Arena::default();
+    let modules = Loader::new(defs)
+        .load(&arena, File { code: jq, path: () })
+        .map_err(|_| format!("jq error: could not parse {jq:?}"))?;
+    let filter = Compiler::default()
+        .with_funs(funs)
+        .compile(modules)
+        .map_err(|errs| {
+            let names = errs
+                .into_iter()
+                .flat_map(|(_, errs)| errs)
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>();
+            format!("jq error: undefined {}", names.join(", "))
+        })?;
+
+    inputs
+        .iter()
+        .map(|input| {
+            let input =
+                read::parse_single(input.as_bytes()).map_err(|err| format!("jq error: {err}"))?;
+            let ctx = Ctx::<data::JustLut<Val>>::new(&filter.lut, Vars::new([]));
+            filter
+                .id
+                .run((ctx, input))
+                .map(|output| match unwrap_valr(output) {
+                    Ok(output) => Ok(output.to_string()),
+                    Err(err) => Err(format!("jq error: {err}").into()),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(not(feature = "jaq"))]
+fn run_jaq(_jq: &str, _inputs: &[String]) -> Result<Vec<Vec<String>>, StdError> {
+    Err("jsondb was built without jaq support (the `jaq` feature)".into())
+}
+
+#[cfg(feature = "parquet")]
+fn export_parquet(
+    database: &jsondb::Database<Object, File>,
+    out: Box<dyn Write + Send>,
+) -> Result<(), StdError> {
+    database.write_parquet(out)?;
+    Ok(())
+}
+
+#[cfg(not(feature = "parquet"))]
+fn export_parquet(
+    _database: &jsondb::Database<Object, File>,
+    _out: Box<dyn Write + Send>,
+) -> Result<(), StdError> {
+    Err("parquet export requires jsondb to be built with the `parquet` feature".into())
+}
+
+// every row of `table` as an object, leaving out NULL columns; text holding a JSON object or array
+// is taken as one, as that's how nested values are exported
+#[cfg(feature = "sqlite")]
+fn read_sqlite(path: &Path, table: &str) -> Result<Vec<Object>, StdError> {
+    use rusqlite::types::ValueRef;
+
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let query = format!("SELECT * FROM \"{}\"", table.replace('"', "\"\""));
+    let mut statement = conn.prepare(&query)?;
+    let names: Vec<String> = statement
+        .column_names()
+        .into_iter()
+        .map(String::from)
+        .collect();
+
+    let mut objects = Vec::new();
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let mut object = Object::new();
+        for (i, name) in names.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => continue,
+                ValueRef::Integer(n) => Value::from(n),
+                ValueRef::Real(n) => Value::from(n),
+                ValueRef::Text(text) => {
+                    let text = String::from_utf8_lossy(text);
+                    match serde_json::from_str::<Value>(&text) {
+                        Ok(value) if value.is_object() || value.is_array() => value,
+                        _ => Value::String(text.into_owned()),
+                    }
+                }
+                ValueRef::Blob(_) => {
+                    return Err(
+                        format!("column `{name}` holds a blob, which can't be imported").into(),
+                    );
+                }
+            };
+            object.insert(name.clone(), value);
+        }
+        objects.push(object);
+    }
+    Ok(objects)
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn read_sqlite(_path: &Path, _table: &str) -> Result<Vec<Object>, StdError> {
+    Err("sqlite import requires jsondb to be built with the `sqlite` feature".into())
+}
+
+// replaces whatever is at `path` with a database holding a `records` table
+#[cfg(feature = "sqlite")]
+fn export_sqlite(
+    database: &jsondb::Database<Object, File>,
+    path: &Path,
+    explode: bool,
+) -> Result<(), StdError> {
+    File::create(path)?;
+    let conn = rusqlite::Connection::open(path)?;
+    match explode {
+        true => database.export_sqlite_exploded(&conn, "records")?,
+        false => database.export_sqlite(&conn, "records")?,
+    };
+    conn.close().map_err(|(_, err)| err)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn export_sqlite(
+    _database: &jsondb::Database<Object, File>,
+    _path: &Path,
+    _explode: bool,
+) -> Result<(), StdError> {
+    Err("sqlite export requires jsondb to be built with the `sqlite` feature".into())
+}
+
+// writes the header of a new database, with a reference to `schema` that's relative to the
+// database if the schema is next to it or further down
+fn init_database(file: &Path, schema: Option<&Path>, id_type: IdType) -> Result<(), StdError> {
+    let mut header = jsondb::Header::new(Some(SystemClock.now_millis()));
+    header.id_type = Some(match id_type {
+        IdType::U32 => "u32".to_string(),
+        IdType::Uuid => {
+            return Err("--id-type uuid isn't supported, records only have u32 ids".into())
+        }
+    });
+    if let Some(schema) = schema {
+        // fail now rather than on the first `validate`
+        serde_json::from_slice::<Value>(&fs::read(schema)?)
+            .map_err(|err| format!("invalid schema {}: {err}", schema.display()))?;
+        let schema = fs::canonicalize(schema)?;
+        let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let dir = fs::canonicalize(dir.unwrap_or_else(|| Path::new(".")))?;
+        let reference = schema.strip_prefix(&dir).unwrap_or(&schema);
+        let reference = reference.to_str().ok_or("schema path isn't valid UTF-8")?;
+        header.extra.insert("schema".to_string(), reference.into());
+    }
+
+    let mut out = match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(file)
+    {
+        Ok(out) => out,
+        Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+            return Err(format!("{} already exists", file.display()).into());
+        }
+        Err(err) => return Err(err.into()),
+    };
+    serde_json::to_writer(&mut out, &header)?;
+    writeln!(out)?;
+    out.sync_all()?;
+    Ok(())
+}
+
+// how the database is opened, as the global options ask for
+fn open_options(opts: &Options, read_only: bool) -> jsondb::OpenOptions {
+    jsondb::OpenOptions::new()
+        .read_only(read_only)
+        .lock(!opts.no_lock)
+        .lock_timeout(opts.lock_timeout)
+        .exclusive(opts.exclusive)
+        .checksums(opts.checksums)
+}
+
+// the schema given to `init`, relative to the database
+fn header_schema(file: &Path, open_opts: jsondb::OpenOptions) -> Result<PathBuf, StdError> {
+    let database = open_opts.open::<Object, _>(file)?;
+    let schema = database
+        .metadata()
+        .and_then(|header| header.extra.get("schema"))
+        .and_then(Value::as_str);
+    match schema {
+        Some(schema) => Ok(file.parent().unwrap_or(Path::new("")).join(schema)),
+        None => Err("the database has no schema, use --schema".into()),
+    }
+}
+
+// checks the latest version of each live record, and returns the number of invalid ones
+#[cfg(feature = "jsonschema")]
+fn validate_log(
+    file: &Path,
+    schema: &Path,
+    open_opts: jsondb::OpenOptions,
+) -> Result<usize, StdError> {
+    let schema = jsondb::Schema::from_path(schema)?;
+    let database = open_opts.open::<Object, _>(file)?;
+
+    let mut invalid = 0;
+    for record in database.records() {
+        // merges can only be applied by the application's operator, so the payload here is stale
+        if database.history(record.id).any(Record::is_unresolved_merge) {
+            println!(
+                "record {}: skipped, it has merges that weren't applied",
+                record.id
+            );
+            continue;
+        }
+        if let Err(err) = schema.check_data(&record.data) {
+            match database.entry_offset(record.id) {
+                Some(offset) => println!("record {} (offset {offset}): {err}", record.id),
+                None => println!("record {}: {err}", record.id),
+            }
+            invalid += 1;
+        }
+    }
+    Ok(invalid)
+}
+
+#[cfg(not(feature = "jsonschema"))]
+fn validate_log(
+    _file: &Path,
+    _schema: &Path,
+    _open_opts: jsondb::OpenOptions,
+) -> Result<usize, StdError> {
+    Err("schema validation requires jsondb to be built with the `jsonschema` feature".into())
 }
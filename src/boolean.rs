@@ -13,7 +13,7 @@ impl<'de> Deserialize<'de> for True {
         D: Deserializer<'de>,
     {
         let value = bool::deserialize(deserializer)?;
-        if value == true {
+        if value {
             Ok(True)
         } else {
             Err(de::Error::invalid_value(
@@ -42,7 +42,7 @@ impl<'de> Deserialize<'de> for False {
         D: Deserializer<'de>,
     {
         let value = bool::deserialize(deserializer)?;
-        if value == false {
+        if !value {
             Ok(False)
         } else {
             Err(de::Error::invalid_value(
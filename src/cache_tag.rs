@@ -1,11 +1,26 @@
-use std::hash::{Hash, Hasher};
+use serde::Serialize;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, BuildHasherDefault, Hash, Hasher};
+use xxhash_rust::xxh3::Xxh3DefaultBuilder;
+
+use crate::record::{Record, RecordId};
 
 pub trait CacheTag<T> {
     fn process_value(&mut self, value: &T);
     fn tag(&self) -> u64;
+
+    // forgets every value processed so far, before they're all processed again; tags that can't
+    // start over keep going, which never hands out a tag that was already used for other contents
+    fn reset(&mut self) {}
+
+    // the tag of a single record, for cache tags that keep track of them
+    fn record_tag(&self, _id: RecordId) -> Option<u64> {
+        None
+    }
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct DefaultCacheTag {
     counter: u64,
 }
@@ -20,7 +35,7 @@ impl<T> CacheTag<T> for DefaultCacheTag {
     }
 }
 
-#[derive(Default, Debug)]
+#[derive(Clone, Default, Debug)]
 pub struct HashCacheTag<H> {
     hasher: H,
 }
@@ -44,3 +59,130 @@ where
         self.hasher.finish()
     }
 }
+
+#[derive(Clone, Default, Debug)]
+pub struct StateCacheTag<B = BuildHasherDefault<DefaultHasher>> {
+    build_hasher: B,
+    hashes: HashMap<RecordId, u64>,
+    state: u64,
+}
+
+impl StateCacheTag {
+    pub fn new() -> StateCacheTag {
+        StateCacheTag::default()
+    }
+}
+
+impl<B> StateCacheTag<B> {
+    pub fn with_hasher(build_hasher: B) -> StateCacheTag<B> {
+        Self {
+            build_hasher,
+            hashes: HashMap::new(),
+            state: 0,
+        }
+    }
+}
+
+impl<B, T> CacheTag<Record<T>> for StateCacheTag<B>
+where
+    B: BuildHasher,
+    T: Hash,
+{
+    fn process_value(&mut self, value: &Record<T>) {
+        let hash = value.data().map(|data| {
+            let mut hasher = self.build_hasher.build_hasher();
+            data.id.hash(&mut hasher);
+            data.data.hash(&mut hasher);
+            hasher.finish()
+        });
+        replace_hash(&mut self.hashes, &mut self.state, value.id(), hash);
+    }
+
+    fn tag(&self) -> u64 {
+        self.state
+    }
+
+    fn reset(&mut self) {
+        self.hashes.clear();
+        self.state = 0;
+    }
+
+    fn record_tag(&self, id: RecordId) -> Option<u64> {
+        self.hashes.get(&id).copied()
+    }
+}
+
+// like `StateCacheTag`, but hashes the JSON of each record instead of requiring `T: Hash`, so it
+// also works for payloads with floats or types from other crates; with the default xxh3 hasher,
+// tags stay the same across processes
+#[derive(Clone, Default, Debug)]
+pub struct ContentCacheTag<B = Xxh3DefaultBuilder> {
+    build_hasher: B,
+    hashes: HashMap<RecordId, u64>,
+    state: u64,
+}
+
+impl ContentCacheTag {
+    pub fn new() -> ContentCacheTag {
+        ContentCacheTag::default()
+    }
+}
+
+impl<B> ContentCacheTag<B> {
+    pub fn with_hasher(build_hasher: B) -> ContentCacheTag<B> {
+        Self {
+            build_hasher,
+            hashes: HashMap::new(),
+            state: 0,
+        }
+    }
+}
+
+impl<B, T> CacheTag<Record<T>> for ContentCacheTag<B>
+where
+    B: BuildHasher,
+    T: Serialize,
+{
+    fn process_value(&mut self, value: &Record<T>) {
+        // a payload that can't be serialized couldn't have been written either, so it only
+        // contributes its id
+        let hash = value.data().map(|data| {
+            let mut hasher = self.build_hasher.build_hasher();
+            data.id.hash(&mut hasher);
+            if let Ok(json) = serde_json::to_vec(&data.data) {
+                hasher.write(&json);
+            }
+            hasher.finish()
+        });
+        replace_hash(&mut self.hashes, &mut self.state, value.id(), hash);
+    }
+
+    fn tag(&self) -> u64 {
+        self.state
+    }
+
+    fn reset(&mut self) {
+        self.hashes.clear();
+        self.state = 0;
+    }
+
+    fn record_tag(&self, id: RecordId) -> Option<u64> {
+        self.hashes.get(&id).copied()
+    }
+}
+
+// the state is the XOR of the hashes of all live records, so records can be swapped out in any order
+fn replace_hash(
+    hashes: &mut HashMap<RecordId, u64>,
+    state: &mut u64,
+    id: RecordId,
+    hash: Option<u64>,
+) {
+    if let Some(old_hash) = hashes.remove(&id) {
+        *state ^= old_hash;
+    }
+    if let Some(hash) = hash {
+        *state ^= hash;
+        hashes.insert(id, hash);
+    }
+}
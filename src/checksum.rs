@@ -0,0 +1,89 @@
+use serde::Serialize;
+use std::borrow::Cow;
+use std::io;
+
+use crate::{
+    error::Error,
+    record::{self, Format, Record},
+    validation::ValidationError,
+};
+
+// the checksum is the CRC32 of the record serialized without it, stored as a trailing property
+const CHECKSUM_KEY: &str = r#","_crc":"#;
+
+pub(crate) fn encode_record<T: Serialize>(
+    record: &Record<T>,
+    checksum: bool,
+    format: Format,
+    out: &mut Vec<u8>,
+) -> io::Result<()> {
+    let start = out.len();
+    if !format.is_default() {
+        serde_json::to_writer(&mut *out, &format.encode(record)?)?;
+    } else if let Err(err) = serde_json::to_writer(&mut *out, record) {
+        // payloads that aren't objects can't be flattened, so they are written nested instead
+        out.truncate(start);
+        serde_json::to_writer(&mut *out, &format.encode(record).map_err(|_| err)?)?;
+    }
+
+    let clash = match record {
+        Record::Upsert(_) if format.is_default() => record::reserved_clash(record, &out[start..]),
+        _ => None,
+    };
+    if let Some(key) = clash {
+        out.truncate(start);
+        return Err(Error::InvalidRecord {
+            id: record.id(),
+            error: ValidationError::new(format!("payload has a reserved field `{key}`")),
+        }
+        .into());
+    }
+
+    // a trailing numeric `_crc` property of the payload would be read back as a checksum
+    let ambiguous = std::str::from_utf8(&out[start..])
+        .ok()
+        .and_then(split_checksum)
+        .is_some();
+    if !checksum && ambiguous {
+        out.truncate(start);
+        return Err(Error::InvalidRecord {
+            id: record.id(),
+            error: ValidationError::new(
+                "a trailing `_crc` property can only be written with checksums enabled",
+            ),
+        }
+        .into());
+    }
+
+    if checksum {
+        let crc = crc32fast::hash(&out[start..]);
+        out.pop();
+        out.extend_from_slice(CHECKSUM_KEY.as_bytes());
+        out.extend_from_slice(crc.to_string().as_bytes());
+        out.push(b'}');
+    }
+
+    out.push(b'\n');
+    Ok(())
+}
+
+// splits a line into the record without its checksum, less the closing brace, and the checksum
+fn split_checksum(raw: &str) -> Option<(&str, u32)> {
+    let raw = raw.strip_suffix('}')?;
+    let i = raw.rfind(CHECKSUM_KEY)?;
+    Some((&raw[..i], raw[i + CHECKSUM_KEY.len()..].parse().ok()?))
+}
+
+// returns the record without its checksum, or unchanged if it was written without one
+pub(crate) fn verify_checksum(raw: &str, offset: u64) -> io::Result<Cow<'_, str>> {
+    let (body, expected) = match split_checksum(raw) {
+        Some(checksum) => checksum,
+        None => return Ok(Cow::Borrowed(raw)),
+    };
+
+    let body = format!("{body}}}");
+    if crc32fast::hash(body.as_bytes()) != expected {
+        return Err(Error::ChecksumMismatch { offset }.into());
+    }
+    Ok(Cow::Owned(body))
+}
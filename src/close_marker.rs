@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    compact::sibling_path,
+};
+
+// left next to the log as `<log>.closed` by `close`, with the length the log had then; if the log
+// has the same length when it's opened again, nothing was written after a clean shutdown
+#[derive(Serialize, Deserialize)]
+struct CloseMarker {
+    len: u64,
+    closed: u64,
+}
+
+fn marker_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".closed");
+    path.with_file_name(name)
+}
+
+// the log length recorded by the last clean shutdown, if any
+pub(crate) fn read_marker(path: &Path) -> io::Result<Option<u64>> {
+    let bytes = match fs::read(marker_path(path)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err),
+    };
+    // a damaged marker doesn't vouch for anything
+    Ok(serde_json::from_slice::<CloseMarker>(&bytes)
+        .ok()
+        .map(|marker| marker.len))
+}
+
+pub(crate) fn write_marker(path: &Path, len: u64) -> io::Result<()> {
+    let marker = CloseMarker {
+        len,
+        closed: SystemClock.now_millis(),
+    };
+    let path = marker_path(path);
+    let tmp_path = sibling_path(&path, "tmp");
+    let result = File::create(&tmp_path).and_then(|mut file| {
+        serde_json::to_writer(&mut file, &marker)?;
+        file.write_all(b"\n")?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
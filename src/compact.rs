@@ -0,0 +1,656 @@
+use serde::{Deserialize, Serialize};
+use serde_json::value::RawValue;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    record::{Envelope, FieldNames, RecordId, RecordMeta},
+};
+
+#[derive(Clone, Debug)]
+pub struct CompactOptions {
+    pub threads: usize,
+    pub chunk_size: usize,
+    pub memory_limit: usize,
+    pub tombstone_retention: TombstoneRetention,
+    pub field_names: FieldNames,
+}
+
+impl CompactOptions {
+    pub fn new() -> CompactOptions {
+        CompactOptions {
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            chunk_size: 4 << 20,
+            memory_limit: 256 << 20,
+            tombstone_retention: TombstoneRetention::Drop,
+            field_names: FieldNames::DEFAULT,
+        }
+    }
+
+    pub const fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    pub const fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    pub const fn memory_limit(mut self, memory_limit: usize) -> Self {
+        self.memory_limit = memory_limit;
+        self
+    }
+
+    pub const fn tombstone_retention(mut self, tombstone_retention: TombstoneRetention) -> Self {
+        self.tombstone_retention = tombstone_retention;
+        self
+    }
+
+    // must match the names the log was written with, see `OpenOptions::field_names`
+    pub const fn field_names(mut self, id: &'static str, deleted: &'static str) -> Self {
+        self.field_names = FieldNames::new(id, deleted);
+        self
+    }
+}
+
+impl Default for CompactOptions {
+    fn default() -> CompactOptions {
+        CompactOptions::new()
+    }
+}
+
+// whether compaction keeps delete markers, e.g. so followers replicating the log still see them;
+// the one for the highest id is always kept, so the id is never handed out again
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TombstoneRetention {
+    #[default]
+    Drop,
+    Keep,
+    // markers written without a timestamp (see `OpenOptions::timestamps`) are kept, as their age
+    // is unknown; `Database::purge_tombstones` removes them
+    KeepFor(Duration),
+}
+
+impl TombstoneRetention {
+    fn keeps(&self, raw: &RawValue, now_millis: u64) -> io::Result<bool> {
+        #[derive(Deserialize)]
+        struct Tombstone {
+            #[serde(rename = "_meta", default)]
+            meta: Option<RecordMeta>,
+        }
+
+        match self {
+            TombstoneRetention::Drop => Ok(false),
+            TombstoneRetention::Keep => Ok(true),
+            TombstoneRetention::KeepFor(period) => {
+                let tombstone: Tombstone = serde_json::from_str(raw.get())?;
+                Ok(match tombstone.meta.and_then(|meta| meta.updated_at) {
+                    Some(deleted_at) => {
+                        u128::from(now_millis.saturating_sub(deleted_at)) < period.as_millis()
+                    }
+                    None => true,
+                })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CompactionPolicy {
+    pub max_dead_ratio: Option<f64>,
+    pub max_file_size: Option<u64>,
+    pub min_file_size: u64,
+    pub tombstone_retention: TombstoneRetention,
+}
+
+impl CompactionPolicy {
+    pub const fn new() -> CompactionPolicy {
+        CompactionPolicy {
+            max_dead_ratio: Some(0.5),
+            max_file_size: None,
+            min_file_size: 1 << 20,
+            tombstone_retention: TombstoneRetention::Drop,
+        }
+    }
+
+    pub const fn max_dead_ratio(mut self, max_dead_ratio: Option<f64>) -> Self {
+        self.max_dead_ratio = max_dead_ratio;
+        self
+    }
+
+    pub const fn max_file_size(mut self, max_file_size: Option<u64>) -> Self {
+        self.max_file_size = max_file_size;
+        self
+    }
+
+    pub const fn min_file_size(mut self, min_file_size: u64) -> Self {
+        self.min_file_size = min_file_size;
+        self
+    }
+
+    pub const fn tombstone_retention(mut self, tombstone_retention: TombstoneRetention) -> Self {
+        self.tombstone_retention = tombstone_retention;
+        self
+    }
+
+    // `compacted_size` is the size right after the last compaction; the size limit is raised to
+    // twice that, so a file whose live data alone exceeds the limit isn't rewritten on every write
+    pub fn should_compact(
+        &self,
+        entries: usize,
+        live_records: usize,
+        file_size: u64,
+        compacted_size: u64,
+    ) -> bool {
+        if file_size < self.min_file_size || entries <= live_records {
+            return false;
+        }
+
+        let dead_ratio = 1.0 - live_records as f64 / entries as f64;
+        let too_dead = self
+            .max_dead_ratio
+            .is_some_and(|max_dead_ratio| dead_ratio > max_dead_ratio);
+        let too_large = self
+            .max_file_size
+            .is_some_and(|max_file_size| file_size > max_file_size.max(compacted_size * 2));
+        too_dead || too_large
+    }
+}
+
+impl Default for CompactionPolicy {
+    fn default() -> CompactionPolicy {
+        CompactionPolicy::new()
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CompactStats {
+    pub records_read: usize,
+    pub live_records: usize,
+    pub tombstones: usize,
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+}
+
+pub fn compact(path: impl AsRef<Path>, opts: &CompactOptions) -> io::Result<CompactStats> {
+    let path = path.as_ref();
+    let tmp_path = sibling_path(path, "compact");
+    #[cfg(feature = "tracing")]
+    let _span = tracing::info_span!("compact", path = %path.display()).entered();
+
+    // hold a lock on the source, so cooperating writers can't append while we rewrite it
+    let source = File::open(path)?;
+    source.lock()?;
+
+    let result = compact_file(path, &tmp_path, opts).and_then(|stats| {
+        fs::rename(&tmp_path, path)?;
+        Ok(stats)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    source.unlock()?;
+    #[cfg(feature = "tracing")]
+    match &result {
+        Ok(stats) => tracing::info!(
+            records_read = stats.records_read,
+            live_records = stats.live_records,
+            bytes_before = stats.bytes_before,
+            bytes_after = stats.bytes_after,
+            "compacted"
+        ),
+        Err(err) => tracing::warn!(error = %err, "compaction failed"),
+    }
+    result
+}
+
+pub fn compact_file(
+    src: impl AsRef<Path>,
+    dst: impl AsRef<Path>,
+    opts: &CompactOptions,
+) -> io::Result<CompactStats> {
+    let src = src.as_ref();
+    let dst = dst.as_ref();
+
+    let mut collector = Collector::new(sibling_path(dst, "run"), opts.memory_limit);
+    let result = collect_parallel(src, opts, &mut collector).or_else(|err| {
+        match err.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => (),
+            _ => return Err(err),
+        }
+
+        // a record spans chunk boundaries, so fall back to a sequential parse
+        collector.clear()?;
+        collect_sequential(src, opts.field_names, &mut collector)
+    });
+
+    let stats = result.and_then(|()| {
+        let mut out = BufWriter::new(File::create(dst)?);
+        if let Some(header) = read_header(src, opts.field_names)? {
+            out.write_all(header.get().as_bytes())?;
+            out.write_all(b"\n")?;
+        }
+        let mut stats = collector.finish(&mut out, opts.tombstone_retention)?;
+        let out = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+        out.sync_all()?;
+
+        stats.bytes_before = fs::metadata(src)?.len();
+        stats.bytes_after = out.metadata()?.len();
+        Ok(stats)
+    });
+
+    collector.clear()?;
+    stats
+}
+
+pub(crate) fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".{}.{}", suffix, std::process::id()));
+    path.with_file_name(name)
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+enum EntryKind {
+    Upsert,
+    Delete,
+    Merge,
+}
+
+// a single log entry: its position in the log, what kind of change it is, and its raw JSON
+type Piece = (u64, EntryKind, Box<RawValue>);
+type Entry = (RecordId, u64, EntryKind, Box<RawValue>);
+
+// headers are not entries, see `read_header`
+fn parse_entry(raw: Box<RawValue>, seq: u64, names: FieldNames) -> io::Result<Option<Entry>> {
+    let envelope = Envelope::parse(raw.get(), names)?;
+    envelope.validate()?;
+    if envelope.header {
+        return Ok(None);
+    }
+
+    let kind = if envelope.merge {
+        EntryKind::Merge
+    } else if envelope.deleted {
+        EntryKind::Delete
+    } else {
+        EntryKind::Upsert
+    };
+    Ok(Some((envelope.id, seq, kind, raw)))
+}
+
+fn read_header(src: &Path, names: FieldNames) -> io::Result<Option<Box<RawValue>>> {
+    let reader = BufReader::new(File::open(src)?);
+    let raw = serde_json::Deserializer::from_reader(reader)
+        .into_iter::<Box<RawValue>>()
+        .next()
+        .transpose()?;
+
+    match raw {
+        Some(raw) if Envelope::parse(raw.get(), names)?.header => Ok(Some(raw)),
+        _ => Ok(None),
+    }
+}
+
+// only the latest upsert or delete and the merges applied on top of it affect the final state
+fn prune(pieces: &mut Vec<Piece>) {
+    let base = pieces
+        .iter()
+        .filter(|(_, kind, _)| *kind != EntryKind::Merge)
+        .map(|(seq, _, _)| *seq)
+        .max();
+    if let Some(base) = base {
+        pieces.retain(|(seq, _, _)| *seq >= base);
+    }
+}
+
+fn pieces_size(pieces: &[Piece]) -> usize {
+    pieces.iter().map(|(_, _, raw)| 64 + raw.get().len()).sum()
+}
+
+fn parse_chunk(index: u64, chunk: &[u8], names: FieldNames) -> io::Result<(usize, Vec<Entry>)> {
+    let mut count = 0;
+    let mut entries = BTreeMap::<RecordId, Vec<Piece>>::new();
+    let records = serde_json::Deserializer::from_slice(chunk).into_iter::<Box<RawValue>>();
+    for (i, raw) in records.enumerate() {
+        let (id, seq, kind, raw) = match parse_entry(raw?, (index << 32) | i as u64, names)? {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let pieces = entries.entry(id).or_default();
+        if kind != EntryKind::Merge {
+            pieces.clear();
+        }
+        pieces.push((seq, kind, raw));
+        count += 1;
+    }
+
+    let entries = entries
+        .into_iter()
+        .flat_map(|(id, pieces)| {
+            pieces
+                .into_iter()
+                .map(move |(seq, kind, raw)| (id, seq, kind, raw))
+        })
+        .collect();
+    Ok((count, entries))
+}
+
+fn collect_parallel(
+    src: &Path,
+    opts: &CompactOptions,
+    collector: &mut Collector,
+) -> io::Result<()> {
+    let threads = opts.threads.max(1);
+    let (chunk_tx, chunk_rx) = mpsc::sync_channel::<(u64, Vec<u8>)>(threads);
+    let (entry_tx, entry_rx) = mpsc::sync_channel::<io::Result<(usize, Vec<Entry>)>>(threads);
+    let chunk_rx = Mutex::new(chunk_rx);
+
+    thread::scope(|s| {
+        let reader = s.spawn(move || -> io::Result<()> {
+            let mut reader = BufReader::new(File::open(src)?);
+            for index in 0.. {
+                let mut chunk = Vec::with_capacity(opts.chunk_size);
+                while chunk.len() < opts.chunk_size {
+                    if reader.read_until(b'\n', &mut chunk)? == 0 {
+                        break;
+                    }
+                }
+                if chunk.is_empty() {
+                    break;
+                }
+                if chunk_tx.send((index, chunk)).is_err() {
+                    break;
+                }
+            }
+            Ok(())
+        });
+
+        for _ in 0..threads {
+            let chunk_rx = &chunk_rx;
+            let entry_tx = entry_tx.clone();
+            s.spawn(move || loop {
+                let next = chunk_rx.lock().unwrap().recv();
+                let (index, chunk) = match next {
+                    Ok(next) => next,
+                    Err(_) => break,
+                };
+                if entry_tx
+                    .send(parse_chunk(index, &chunk, opts.field_names))
+                    .is_err()
+                {
+                    break;
+                }
+            });
+        }
+        drop(entry_tx);
+
+        let mut result = Ok(());
+        for entries in entry_rx {
+            match entries.and_then(|(count, entries)| collector.extend(count, entries)) {
+                Ok(()) => (),
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+
+        // unblock the reader if we stopped early
+        while chunk_rx.lock().unwrap().recv().is_ok() {}
+        result.and(reader.join().unwrap())
+    })
+}
+
+fn collect_sequential(src: &Path, names: FieldNames, collector: &mut Collector) -> io::Result<()> {
+    let reader = BufReader::new(File::open(src)?);
+    let records = serde_json::Deserializer::from_reader(reader).into_iter::<Box<RawValue>>();
+    for (seq, raw) in records.enumerate() {
+        if let Some(entry) = parse_entry(raw?, seq as u64, names)? {
+            collector.insert(entry)?;
+        }
+    }
+    Ok(())
+}
+
+struct Collector {
+    run_path: PathBuf,
+    memory_limit: usize,
+    memory: usize,
+    entries: BTreeMap<RecordId, Vec<Piece>>,
+    runs: Vec<PathBuf>,
+    records_read: usize,
+}
+
+impl Collector {
+    fn new(run_path: PathBuf, memory_limit: usize) -> Collector {
+        Collector {
+            run_path,
+            memory_limit,
+            memory: 0,
+            entries: BTreeMap::new(),
+            runs: Vec::new(),
+            records_read: 0,
+        }
+    }
+
+    fn extend(&mut self, count: usize, entries: Vec<Entry>) -> io::Result<()> {
+        self.records_read += count;
+        for entry in entries {
+            self.merge(entry)?;
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, entry: Entry) -> io::Result<()> {
+        self.records_read += 1;
+        self.merge(entry)
+    }
+
+    fn merge(&mut self, (id, seq, kind, raw): Entry) -> io::Result<()> {
+        let pieces = self.entries.entry(id).or_default();
+        self.memory -= pieces_size(pieces);
+        pieces.push((seq, kind, raw));
+        prune(pieces);
+        self.memory += pieces_size(pieces);
+
+        if self.memory > self.memory_limit {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> io::Result<()> {
+        let mut path = self.run_path.clone().into_os_string();
+        path.push(format!(".{}", self.runs.len()));
+        let path = PathBuf::from(path);
+
+        let mut out = BufWriter::new(File::create(&path)?);
+        self.runs.push(path);
+        for (id, pieces) in std::mem::take(&mut self.entries) {
+            for (seq, kind, raw) in pieces {
+                serde_json::to_writer(&mut out, &(id, seq, kind, raw))?;
+                writeln!(out)?;
+            }
+        }
+        out.flush()?;
+
+        self.memory = 0;
+        Ok(())
+    }
+
+    fn finish(
+        &mut self,
+        out: &mut impl Write,
+        retention: TombstoneRetention,
+    ) -> io::Result<CompactStats> {
+        let now_millis = SystemClock.now_millis();
+        let mut sources: Vec<Box<dyn Iterator<Item = io::Result<Entry>>>> = Vec::new();
+        for path in &self.runs {
+            let reader = BufReader::new(File::open(path)?);
+            sources.push(Box::new(
+                serde_json::Deserializer::from_reader(reader)
+                    .into_iter::<Entry>()
+                    .map(|entry| entry.map_err(io::Error::from)),
+            ));
+        }
+        sources.push(Box::new(
+            std::mem::take(&mut self.entries)
+                .into_iter()
+                .flat_map(|(id, pieces)| {
+                    pieces
+                        .into_iter()
+                        .map(move |(seq, kind, raw)| Ok((id, seq, kind, raw)))
+                }),
+        ));
+
+        // k-way merge of sorted runs, gathering all entries for the same id
+        let mut heap = BinaryHeap::new();
+        let mut heads = Vec::new();
+        for (i, source) in sources.iter_mut().enumerate() {
+            let head = source.next().transpose()?;
+            if let Some((id, _, _, _)) = &head {
+                heap.push(Reverse((*id, i)));
+            }
+            heads.push(head);
+        }
+
+        let mut stats = CompactStats {
+            records_read: self.records_read,
+            ..CompactStats::default()
+        };
+        let mut current: Option<(RecordId, Vec<Piece>)> = None;
+        while let Some(Reverse((_, i))) = heap.pop() {
+            let (id, seq, kind, raw) = heads[i].take().unwrap();
+            heads[i] = sources[i].next().transpose()?;
+            if let Some((id, _, _, _)) = &heads[i] {
+                heap.push(Reverse((*id, i)));
+            }
+
+            match &mut current {
+                Some((current_id, pieces)) if *current_id == id => {
+                    pieces.push((seq, kind, raw));
+                }
+                _ => {
+                    if let Some((_, pieces)) = current.take() {
+                        write_pieces(out, pieces, retention, false, now_millis, &mut stats)?;
+                    }
+                    current = Some((id, vec![(seq, kind, raw)]));
+                }
+            }
+        }
+        // the highest id comes last
+        if let Some((_, pieces)) = current {
+            write_pieces(out, pieces, retention, true, now_millis, &mut stats)?;
+        }
+
+        out.flush()?;
+        Ok(stats)
+    }
+
+    fn clear(&mut self) -> io::Result<()> {
+        for path in self.runs.drain(..) {
+            fs::remove_file(path)?;
+        }
+        self.entries.clear();
+        self.memory = 0;
+        self.records_read = 0;
+        Ok(())
+    }
+}
+
+fn write_pieces(
+    out: &mut impl Write,
+    mut pieces: Vec<Piece>,
+    retention: TombstoneRetention,
+    keep_tombstone: bool,
+    now_millis: u64,
+    stats: &mut CompactStats,
+) -> io::Result<()> {
+    prune(&mut pieces);
+    pieces.sort_by_key(|(seq, _, _)| *seq);
+
+    if let [(_, EntryKind::Delete, raw)] = pieces.as_slice() {
+        if keep_tombstone || retention.keeps(raw, now_millis)? {
+            out.write_all(raw.get().as_bytes())?;
+            writeln!(out)?;
+            stats.tombstones += 1;
+        }
+        return Ok(());
+    }
+
+    for (_, _, raw) in pieces {
+        out.write_all(raw.get().as_bytes())?;
+        writeln!(out)?;
+    }
+    stats.live_records += 1;
+    Ok(())
+}
+
+// removes every entry for the given records from the log, as long as the last entry for each of
+// them is still a delete marker; returns how many records were removed
+pub(crate) fn purge_records(
+    path: &Path,
+    ids: &HashSet<RecordId>,
+    names: FieldNames,
+) -> io::Result<usize> {
+    let tmp_path = sibling_path(path, "purge");
+
+    let source = File::open(path)?;
+    source.lock()?;
+
+    let result = purge_file(path, &tmp_path, ids, names).and_then(|purged| {
+        fs::rename(&tmp_path, path)?;
+        Ok(purged)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    source.unlock()?;
+    result
+}
+
+fn purge_file(
+    src: &Path,
+    dst: &Path,
+    ids: &HashSet<RecordId>,
+    names: FieldNames,
+) -> io::Result<usize> {
+    let entries = |path| -> io::Result<_> {
+        let reader = BufReader::new(File::open(path)?);
+        Ok(serde_json::Deserializer::from_reader(reader).into_iter::<Box<RawValue>>())
+    };
+
+    // another writer may have brought some of the records back in the meantime
+    let mut deleted = HashMap::new();
+    for raw in entries(src)? {
+        let envelope = Envelope::parse(raw?.get(), names)?;
+        if !envelope.header && ids.contains(&envelope.id) {
+            deleted.insert(envelope.id, envelope.deleted);
+        }
+    }
+    deleted.retain(|_, deleted| *deleted);
+
+    let mut out = BufWriter::new(File::create(dst)?);
+    for raw in entries(src)? {
+        let raw = raw?;
+        let envelope = Envelope::parse(raw.get(), names)?;
+        if envelope.header || !deleted.contains_key(&envelope.id) {
+            out.write_all(raw.get().as_bytes())?;
+            writeln!(out)?;
+        }
+    }
+    let out = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+    out.sync_all()?;
+
+    Ok(deleted.len())
+}
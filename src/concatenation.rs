@@ -0,0 +1,75 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::record::{Record, RecordId};
+
+// kept under `OpenOptions::tolerate_duplicates` for a log that may be several logs concatenated,
+// such as with `cat a.json b.json > merged.json`; each header after the start of the log begins
+// another part, whose ids may have been taken by an earlier part already
+#[derive(Default)]
+pub(crate) struct Concatenation {
+    // the ids the current part has referred to so far, and the ids they were loaded as
+    ids: HashMap<RecordId, RecordId>,
+    // every payload each record has had, so a part that repeats an earlier one keeps its ids
+    versions: HashSet<(RecordId, u64)>,
+    remapped: bool,
+}
+
+// the same payload always hashes the same, whatever the process or toolchain, so loading a log
+// always gives the same ids
+pub(crate) fn payload_hash<T: Serialize>(data: &T) -> Option<u64> {
+    Some(xxh3_64(&serde_json::to_vec(data).ok()?))
+}
+
+impl Concatenation {
+    pub fn start_part(&mut self) {
+        self.ids.clear();
+    }
+
+    // whether any record was loaded under another id than the one in the log
+    pub fn is_remapped(&self) -> bool {
+        self.remapped
+    }
+
+    // the id an entry of the current part is loaded as: its own, unless a record of an earlier
+    // part has it and the entry isn't a version that record has had, in which case it's
+    // `next_id`, which it keeps for the rest of the part
+    pub fn resolve<T: Serialize>(
+        &mut self,
+        record: &Record<T>,
+        known: impl Fn(RecordId) -> bool,
+        next_id: RecordId,
+    ) -> RecordId {
+        let id = record.id();
+        if let Some(&resolved) = self.ids.get(&id) {
+            return resolved;
+        }
+
+        let repeated = match record {
+            Record::Upsert(record) => payload_hash(&record.data.data)
+                .is_some_and(|hash| self.versions.contains(&(id, hash))),
+            Record::Merge(_) | Record::Delete(_) => false,
+        };
+        let resolved = if !known(id) || repeated {
+            id
+        } else {
+            self.remapped = true;
+            next_id
+        };
+        self.ids.insert(id, resolved);
+        resolved
+    }
+
+    // keeps track of a record as it was loaded or written; writes are only allowed as long as
+    // every id is its own, so those written later in the same part are too
+    pub fn observe<T: Serialize>(&mut self, record: &Record<T>) {
+        let id = record.id();
+        if !self.remapped {
+            self.ids.entry(id).or_insert(id);
+        }
+        if let Some(hash) = record.data().and_then(|data| payload_hash(&data.data)) {
+            self.versions.insert((id, hash));
+        }
+    }
+}
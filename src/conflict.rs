@@ -0,0 +1,80 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{self, Read, Seek, Write};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordId, RecordMeta},
+};
+
+pub type ConflictResolver<'a, T> = dyn FnMut(RecordId, &T, &T) -> T + 'a;
+
+pub enum ConflictStrategy<'a, T> {
+    PreferSelf,
+    PreferOther,
+    PreferNewer,
+    Resolve(Box<ConflictResolver<'a, T>>),
+}
+
+impl<'a, T> ConflictStrategy<'a, T> {
+    pub fn resolve<F>(f: F) -> ConflictStrategy<'a, T>
+    where
+        F: FnMut(RecordId, &T, &T) -> T + 'a,
+    {
+        ConflictStrategy::Resolve(Box::new(f))
+    }
+}
+
+impl<T, S, C> Database<T, S, C>
+where
+    T: Serialize + DeserializeOwned + Clone + PartialEq,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // copies every live record of `other` into this database, returning how many were written
+    pub fn merge_from<S2, C2, M2>(
+        &mut self,
+        other: &Database<T, S2, C2, M2>,
+        mut strategy: ConflictStrategy<'_, T>,
+    ) -> io::Result<usize>
+    where
+        S2: Read + Seek,
+        C2: CacheTag<Record<T>>,
+    {
+        let mut written = 0;
+        for theirs in other.records() {
+            let ours = match self.get(theirs.id) {
+                Some(ours) if ours.data == theirs.data => continue,
+                Some(ours) => &ours.data,
+                None => {
+                    self.upsert(theirs.id, |_| Some(theirs.data.clone()))?;
+                    written += 1;
+                    continue;
+                }
+            };
+
+            let merged = match &mut strategy {
+                ConflictStrategy::PreferSelf => continue,
+                ConflictStrategy::PreferOther => theirs.data.clone(),
+                ConflictStrategy::PreferNewer => {
+                    // records without timestamps are treated as older than any with one; when
+                    // neither is newer, including when neither has a timestamp, theirs is taken
+                    let updated_at =
+                        |meta: Option<&RecordMeta>| meta.and_then(|meta| meta.updated_at);
+                    if updated_at(other.meta(theirs.id)) < updated_at(self.meta(theirs.id)) {
+                        continue;
+                    }
+                    theirs.data.clone()
+                }
+                ConflictStrategy::Resolve(resolve) => resolve(theirs.id, ours, &theirs.data),
+            };
+
+            if &merged != ours {
+                self.upsert(theirs.id, |_| Some(merged))?;
+                written += 1;
+            }
+        }
+
+        Ok(written)
+    }
+}
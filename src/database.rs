@@ -1,15 +1,218 @@
-use itertools::Itertools;
+use rand::Rng;
 use serde::{de::DeserializeOwned, Serialize};
+use serde_json::{value::RawValue, Map, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs::{self, File};
-use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::hash::{Hash, Hasher};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use xxhash_rust::xxh3::Xxh3Default;
 
 use crate::{
+    append_only::AppendOnly,
     cache_tag::{CacheTag, DefaultCacheTag},
-    record::{Record, RecordData, RecordId},
+    checksum::{encode_record, verify_checksum},
+    clock::{Clock, Instant, SystemClock},
+    close_marker::{read_marker, write_marker},
+    compact::{compact, purge_records, sibling_path, CompactOptions, CompactionPolicy},
+    concatenation::{payload_hash, Concatenation},
+    detached::Detached,
+    error::Error,
+    header::{parse_header, Header},
+    health::{Check, Health},
+    id_allocator::{IdAllocator, IdSpace, SequentialIds},
+    lazy::LazyDatabase,
+    lock::{FileLock, LockFile},
+    mode::{ReadOnly, ReadWrite},
+    observer::{replay, Observers, RecordObserver},
+    position::LogPosition,
+    record::{FieldNames, Format, Layout, Record, RecordData, RecordId, RecordMeta, UpsertRecord},
+    segment::SegmentedFile,
+    snapshot::Snapshot,
+    stats::{ReloadSummary, SkippedEntry, Timings},
+    storage::{BackendStream, StorageBackend},
+    validation::{InvalidRecordPolicy, ReloadValidation, ValidationError},
+    view::{View, ViewChange},
 };
 
-pub struct Database<T, S, C = DefaultCacheTag>
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+#[cfg(feature = "notify")]
+use crate::watch::ChangeWatcher;
+
+pub type MergeOperator<T> = dyn Fn(Option<&T>, &Value) -> T + Send + Sync;
+
+pub type Validator<T> = dyn Fn(&T) -> Result<(), ValidationError> + Send + Sync;
+
+// compacts the underlying file and reopens it, since the old handle still points at the original
+type Compactor<S> = dyn Fn() -> io::Result<(S, Option<FileLock>)> + Send + Sync;
+
+// writes to a stream and flushes it, for code that doesn't know whether the stream is writable
+type Writer<S> = fn(&mut S, &[u8]) -> io::Result<()>;
+
+// opens the file now at the path if compaction or a restore replaced the one the stream reads,
+// locked the same way as the old one if that was locked
+type Reopener<S> =
+    fn(&S, &Path, Option<&FileLock>, Access) -> io::Result<Option<(S, Option<FileLock>)>>;
+
+// how a replaced log is opened again: writable or not, and locked for writing or for reading
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Access {
+    pub writable: bool,
+    pub exclusive: bool,
+}
+
+// tells whether a stream has changed since it was loaded up to the offset, given its path
+type StaleCheck<S> = fn(&S, u64, Option<&Path>) -> io::Result<bool>;
+
+// the payload a record was loaded with, and where in the log its entry starts and ends
+type Span<T> = (Weak<RecordData<T>>, (u64, u64));
+
+struct AutoCompact<S> {
+    policy: CompactionPolicy,
+    compacted_size: u64,
+    compactor: Box<Compactor<S>>,
+}
+
+// set by writes to a stream that can be synced, until `close` syncs them; dropping the database
+// before that is fine for readers in this process, but the writes may not have reached the disk
+#[derive(Default)]
+struct Unsynced(bool);
+
+impl Drop for Unsynced {
+    fn drop(&mut self) {
+        #[cfg(feature = "tracing")]
+        if self.0 && !std::thread::panicking() {
+            tracing::warn!("database dropped with unsynced writes; use `close` to sync them");
+        }
+    }
+}
+
+// records held back by the flush policy, which are already applied in memory but not in the log
+struct Pending<S> {
+    buffer: Vec<u8>,
+    count: usize,
+    since: Option<Instant>,
+    // set if other writers' records were loaded meanwhile, which then come first in the log
+    reordered: bool,
+    // set by the writes that are held back, which only writable streams can make
+    write: Option<Writer<S>>,
+}
+
+impl<S> Pending<S> {
+    fn new() -> Pending<S> {
+        Pending {
+            buffer: Vec::new(),
+            count: 0,
+            since: None,
+            reordered: false,
+            write: None,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.count = 0;
+        self.since = None;
+        self.reordered = false;
+    }
+}
+
+fn write_flushed<S: Write>(stream: &mut S, bytes: &[u8]) -> io::Result<()> {
+    stream.write_all(bytes)?;
+    stream.flush()
+}
+
+// whether `path` is no longer the file that's open, which can only be told on unix
+fn is_replaced(file: &File, path: &Path) -> io::Result<bool> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let (open, current) = (file.metadata()?, fs::metadata(path)?);
+        Ok((open.dev(), open.ino()) != (current.dev(), current.ino()))
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = (file, path);
+        Ok(false)
+    }
+}
+
+fn is_file_stale(file: &File, offset: u64, path: Option<&Path>) -> io::Result<bool> {
+    let metadata = file.metadata()?;
+    if metadata.len() != offset {
+        return Ok(true);
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(false),
+    };
+    let path_metadata = fs::metadata(path)?;
+    if path_metadata.len() != metadata.len() || path_metadata.modified()? != metadata.modified()? {
+        return Ok(true);
+    }
+    is_replaced(file, path)
+}
+
+pub(crate) fn reopen_replaced(
+    file: &File,
+    path: &Path,
+    lock: Option<&FileLock>,
+    access: Access,
+) -> io::Result<Option<(File, Option<FileLock>)>> {
+    if !is_replaced(file, path)? {
+        return Ok(None);
+    }
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .append(access.writable)
+        .open(path)?;
+    let lock = match lock {
+        Some(lock) => {
+            let lock = FileLock::new(&file, lock.timeout())?;
+            if access.exclusive {
+                lock.lock_exclusive()?;
+            } else {
+                lock.lock_shared()?;
+            }
+            Some(lock)
+        }
+        None => None,
+    };
+    Ok(Some((file, lock)))
+}
+
+impl<S> Drop for Pending<S> {
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        if !self.is_empty() {
+            tracing::warn!(
+                records = self.count,
+                "database dropped with unflushed writes; use `flush` or `close` to write them"
+            );
+        }
+        debug_assert!(
+            self.is_empty(),
+            "database dropped with {} unflushed writes; use `flush` or `close` to write them",
+            self.count
+        );
+    }
+}
+
+pub struct Database<T, S, C = DefaultCacheTag, M = ReadWrite>
 where
     T: Serialize + DeserializeOwned,
     S: Read + Seek,
@@ -18,7 +221,62 @@ where
     stream: BufReader<S>,
     offset: u64,
     records: Vec<Record<T>>,
+    latest: BTreeMap<RecordId, usize>,
+    live_count: usize,
     next_record_id: RecordId,
+    id_allocator: Box<dyn IdAllocator>,
+    // ids handed out by `reserve_id` that haven't been inserted yet
+    reserved_ids: BTreeSet<RecordId>,
+    written: Vec<usize>,
+    // our entries that `written` no longer has: writes that were undone and the entries undoing them
+    undone: BTreeSet<usize>,
+    // where our last write ended in the log
+    last_write: Option<LogPosition>,
+    keep_history: bool,
+    // entries overwritten in place without history, which `records` no longer counts
+    overwritten: usize,
+    // ids with merges that couldn't be resolved yet, whose entries have to stay in log order
+    unresolved_merges: HashSet<RecordId>,
+    // where in the log the latest entry of each record was loaded from, if it was, for `project`;
+    // only good for as long as that payload is still the record's latest one, since entries that
+    // are left out afterwards, e.g. under `ReloadValidation::Lenient`, don't come back here
+    spans: HashMap<RecordId, Span<T>>,
+    clock: Option<Box<dyn Clock>>,
+    write_context: Map<String, Value>,
+    merge_operator: Option<Box<MergeOperator<T>>>,
+    validator: Option<Box<Validator<T>>>,
+    reload_validation: ReloadValidation,
+    on_invalid_record: InvalidRecordPolicy,
+    // every entry skipped under `on_invalid_record` so far
+    skipped: Vec<SkippedEntry>,
+    concatenation: Option<Concatenation>,
+    lock: Option<FileLock>,
+    // removes the lock file when dropped
+    lock_file: Option<LockFile>,
+    path: Option<PathBuf>,
+    read_only: bool,
+    // makes writes durable on close, for streams that can do that
+    sync: Option<fn(&S) -> io::Result<()>>,
+    reopen: Option<Reopener<S>>,
+    stale: Option<StaleCheck<S>>,
+    unsynced: Unsynced,
+    flush_policy: FlushPolicy,
+    pending: Pending<S>,
+    close_marker: bool,
+    closed_cleanly: Option<bool>,
+    checksums: bool,
+    format: Format,
+    header: Option<Header>,
+    auto_compact: Option<AutoCompact<S>>,
+    auto_reload: AutoReload,
+    last_reload: Option<Instant>,
+    timings: Timings,
+    #[cfg(feature = "notify")]
+    watcher: Option<ChangeWatcher>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
+    observers: Observers<T>,
+    mode: PhantomData<M>,
 
     cache_tag: C,
 }
@@ -31,228 +289,2547 @@ where
         Database::open_with_opts(path, OpenOptions::new())
     }
 
+    pub fn open_read_only(
+        path: impl AsRef<Path>,
+    ) -> io::Result<Database<T, File, DefaultCacheTag, ReadOnly>> {
+        OpenOptions::new().open_read_only(path)
+    }
+
     pub fn open_with_opts(
         path: impl AsRef<Path>,
         opts: OpenOptions,
     ) -> io::Result<Database<T, File>> {
-        let file = fs::OpenOptions::new()
-            .create(!opts.read_only)
-            .read(true)
-            .append(!opts.read_only)
-            .open(path)?;
+        Database::open_with_reload(path.as_ref(), opts, Database::reload)
+    }
+
+    // `reload` loads the existing log once the file is open
+    pub(crate) fn open_with_reload(
+        path: &Path,
+        opts: OpenOptions,
+        reload: fn(&mut Self) -> io::Result<ReloadSummary>,
+    ) -> io::Result<Database<T, File>> {
+        let lock_file = opts.lock_file(path)?;
+        let file = opts.open_file(path)?;
+        let lock = if opts.lock {
+            Some(FileLock::new(&file, opts.lock_timeout)?)
+        } else {
+            None
+        };
         let stream = BufReader::new(file);
 
         let mut database = Database {
             stream,
             offset: 0,
             records: Vec::new(),
+            latest: BTreeMap::new(),
+            live_count: 0,
             next_record_id: 1,
+            id_allocator: Box::new(SequentialIds),
+            reserved_ids: BTreeSet::new(),
+            written: Vec::new(),
+            undone: BTreeSet::new(),
+            last_write: None,
+            keep_history: true,
+            overwritten: 0,
+            unresolved_merges: HashSet::new(),
+            spans: HashMap::new(),
+            clock: None,
+            write_context: Map::new(),
+            merge_operator: None,
+            validator: None,
+            reload_validation: ReloadValidation::Skip,
+            on_invalid_record: InvalidRecordPolicy::Fail,
+            skipped: Vec::new(),
+            concatenation: None,
+            lock,
+            lock_file,
+            path: Some(path.to_path_buf()),
+            read_only: opts.read_only,
+            sync: Some(File::sync_all),
+            reopen: Some(reopen_replaced),
+            stale: Some(is_file_stale),
+            unsynced: Unsynced::default(),
+            flush_policy: FlushPolicy::Immediate,
+            pending: Pending::new(),
+            close_marker: false,
+            closed_cleanly: None,
+            checksums: opts.checksums,
+            format: opts.format(),
+            header: None,
+            auto_compact: None,
+            auto_reload: AutoReload::Manual,
+            last_reload: None,
+            timings: Timings::default(),
+            #[cfg(feature = "notify")]
+            watcher: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            observers: Observers::new(),
+            mode: PhantomData,
             cache_tag: DefaultCacheTag::default(),
         };
+        if opts.timestamps {
+            database.clock = Some(Box::new(SystemClock));
+        }
+        if let (Some(policy), false) = (opts.auto_compact, opts.read_only) {
+            let path = path.to_path_buf();
+            let (lock, lock_timeout) = (opts.lock, opts.lock_timeout);
+            let field_names = opts.field_names;
+            let compactor = move || {
+                let compact_opts = CompactOptions::new()
+                    .tombstone_retention(policy.tombstone_retention)
+                    .field_names(field_names.id, field_names.deleted);
+                compact(&path, &compact_opts)?;
+                let file = fs::OpenOptions::new().read(true).append(true).open(&path)?;
+                let lock = if lock {
+                    Some(FileLock::new(&file, lock_timeout)?)
+                } else {
+                    None
+                };
+                Ok((file, lock))
+            };
+            database.auto_compact = Some(AutoCompact {
+                policy,
+                compacted_size: 0,
+                compactor: Box::new(compactor),
+            });
+        }
 
-        database.reload()?;
+        database.init(&opts, reload)?;
         Ok(database)
     }
+
+    // replaces the database at `path` with a copy of `backup`, which must be a readable log
+    pub fn restore_from(
+        backup: impl AsRef<Path>,
+        path: impl AsRef<Path>,
+        opts: OpenOptions,
+    ) -> io::Result<Database<T, File>> {
+        let (backup, path) = (backup.as_ref(), path.as_ref());
+        Database::<T, File>::open_with_opts(
+            backup,
+            OpenOptions::new()
+                .read_only(true)
+                .lock(false)
+                .checksums(opts.checksums)
+                .field_names(opts.field_names.id, opts.field_names.deleted)
+                .layout(opts.layout),
+        )?;
+
+        let tmp_path = sibling_path(path, "restore");
+        let mut lock = None;
+        let result = fs::copy(backup, &tmp_path).and_then(|_| {
+            File::open(&tmp_path)?.sync_all()?;
+
+            // keep cooperating writers out while the file is swapped
+            let existing = match File::open(path) {
+                Ok(file) => Some(file),
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err),
+            };
+            if let Some(file) = &existing {
+                let existing_lock = FileLock::new(file, opts.lock_timeout)?;
+                existing_lock.lock_exclusive()?;
+                lock = Some(existing_lock);
+            }
+            fs::rename(&tmp_path, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+
+        // and until the restored log is open, so writers still on the old file only get to it
+        // once this handle has loaded the restored one
+        let database = Database::open_with_opts(path, opts);
+        if let Some(lock) = lock {
+            lock.unlock()?;
+        }
+        database
+    }
 }
 
-impl<T, S> Database<T, S>
+impl<T, W> Database<T, AppendOnly<W>>
 where
     T: Serialize + DeserializeOwned,
-    S: Read + Seek,
+    W: Write,
 {
-    pub fn new(mut stream: S) -> io::Result<Database<T, S>> {
-        let offset = stream.stream_position()?;
-        let stream = BufReader::new(stream);
-        Ok(Database {
-            stream,
-            offset,
-            records: Vec::new(),
-            next_record_id: 1,
-            cache_tag: DefaultCacheTag::default(),
-        })
+    // for sinks that can't be read back, such as pipes or sockets; nothing is re-read before writes
+    pub fn append_only(writer: W) -> io::Result<Database<T, AppendOnly<W>>> {
+        Database::new(AppendOnly::new(writer))
+    }
+
+    pub fn into_writer(self) -> W {
+        self.into_inner().into_inner()
     }
 }
 
-impl<T, S, C> Database<T, S, C>
+impl<T> Database<T, Detached>
 where
     T: Serialize + DeserializeOwned,
-    S: Read + Seek,
-    C: CacheTag<Record<T>>,
 {
-    pub fn close(self) -> io::Result<()> {
-        drop(self);
-        Ok(())
-    }
+    // loads a log in a single forward pass, e.g. from a network response; the result can't be written to
+    pub fn from_reader(reader: impl Read) -> io::Result<Database<T, Detached>> {
+        let mut database = Database::new(Detached::new())?;
 
-    pub fn with_cache_tag<C2: CacheTag<Record<T>>>(self, mut cache_tag: C2) -> Database<T, S, C2> {
-        for record in &self.records {
-            cache_tag.process_value(record);
+        let reader = BufReader::new(reader);
+        let mut d = serde_json::Deserializer::from_reader(reader).into_iter::<Box<RawValue>>();
+        while let Some(raw) = d.next().transpose()? {
+            let end = d.byte_offset() as u64;
+            if let Some(record) = database.decode_entry(raw.get(), end)? {
+                database.handle_record(record, None);
+            }
         }
 
-        Database {
-            stream: self.stream,
-            offset: self.offset,
-            records: self.records,
-            next_record_id: self.next_record_id,
-            cache_tag,
+        database.offset = d.byte_offset() as u64;
+        Ok(database)
+    }
+}
+
+impl<T> Database<T, SegmentedFile>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn open_segmented(
+        path: impl AsRef<Path>,
+        opts: OpenOptions,
+    ) -> io::Result<Database<T, SegmentedFile>> {
+        let path = path.as_ref();
+        let lock_file = opts.lock_file(path)?;
+        let file = SegmentedFile::open(path, opts.max_segment_size, opts.read_only)?;
+        // the first segment is never removed, so it doubles as the lock for the whole log
+        let lock = if opts.lock {
+            Some(FileLock::new(file.first_segment(), opts.lock_timeout)?)
+        } else {
+            None
+        };
+
+        let mut database = Database::new(file)?;
+        database.lock = lock;
+        database.lock_file = lock_file;
+        database.path = Some(path.to_path_buf());
+        database.read_only = opts.read_only;
+        database.sync = Some(SegmentedFile::sync_all);
+        database.checksums = opts.checksums;
+        database.format = opts.format();
+        if opts.timestamps {
+            database.clock = Some(Box::new(SystemClock));
         }
+
+        database.init(&opts, Database::reload)?;
+        Ok(database)
     }
+}
 
-    pub fn cache_tag(&self) -> u64 {
-        self.cache_tag.tag()
+impl<T, B> Database<T, BackendStream<B>>
+where
+    T: Serialize + DeserializeOwned,
+    B: StorageBackend,
+{
+    // a database kept in `backend`, which isn't locked, so only one writer should use it at a time
+    // unless the backend itself rejects conflicting appends
+    pub fn from_backend(backend: B) -> io::Result<Database<T, BackendStream<B>>> {
+        let mut database = Database::new(BackendStream::new(backend))?;
+        database.sync = Some(BackendStream::sync_all);
+        Ok(database)
     }
+}
 
-    fn handle_record(&mut self, record: Record<T>) {
-        if record.id() >= self.next_record_id {
-            self.next_record_id = record.id() + 1;
-        }
-        self.cache_tag.process_value(&record);
-        self.records.push(record);
+impl<T, C, M> Database<T, File, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    C: CacheTag<Record<T>>,
+{
+    // keeps track of changes through filesystem notifications, so `is_stale` doesn't need to look
+    // at the file at all
+    #[cfg(feature = "notify")]
+    pub fn watch(&mut self) -> io::Result<()> {
+        let path = self.path.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::Unsupported, "database has no path to watch")
+        })?;
+        self.watcher = Some(ChangeWatcher::new(&path)?);
+        Ok(())
     }
 
-    fn read_next(&mut self) -> io::Result<Option<Record<T>>> {
-        self.stream.seek(SeekFrom::Start(self.offset))?;
-        let mut d = serde_json::Deserializer::from_reader(&mut self.stream).into_iter();
+    pub fn health_check(&mut self) -> Health {
+        let reachable = match &self.path {
+            Some(path) => fs::metadata(path).map(|_| ()),
+            None => self.stream.get_ref().metadata().map(|_| ()),
+        };
 
-        // read next record
-        let record = d.next().transpose()?;
-        self.offset = self.stream.stream_position()?;
+        let lockable = match &self.lock {
+            Some(lock) => Check::from(lock.probe()),
+            None => Check::Skipped,
+        };
 
-        Ok(record)
-    }
+        // a record that fails to parse, or a partially written one, stops the reader before EOF
+        let tail = self.reload().and_then(|_| {
+            if self.is_at_end()? {
+                Ok(())
+            } else {
+                Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("unparseable data at offset {}", self.offset),
+                ))
+            }
+        });
 
-    fn is_at_end(&mut self) -> io::Result<bool> {
-        let offset = self.stream.seek(SeekFrom::End(0))?;
-        Ok(offset == self.offset)
+        // probed on the file itself, so a read-only handle can tell whether writers would get in
+        let writable = match &self.path {
+            Some(path) => Check::from(fs::OpenOptions::new().append(true).open(path).map(|_| ())),
+            None => Check::Skipped,
+        };
+
+        Health {
+            reachable: Check::from(reachable),
+            lockable,
+            tail: Check::from(tail),
+            writable,
+        }
     }
+}
 
-    pub fn reload(&mut self) -> io::Result<()> {
-        while let Some(record) = self.read_next()? {
-            self.handle_record(record);
+impl<T, C> Database<T, File, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: CacheTag<Record<T>>,
+{
+    // removes records deleted before `before` from the log altogether, including their delete
+    // markers; the log is rewritten, so this handle starts over from the new file afterwards. the
+    // record with the highest id is kept, so its id isn't handed out again
+    pub fn purge_tombstones(&mut self, before: LogPosition) -> io::Result<usize> {
+        let path = self.rewritable_path()?;
+        self.reload()?;
+        let max_id = self.latest.keys().next_back().copied();
+        let ids = self
+            .latest
+            .iter()
+            .filter(|&(&id, &index)| {
+                index < before.records
+                    && Some(id) != max_id
+                    && matches!(self.records[index], Record::Delete(_))
+            })
+            .map(|(&id, _)| id)
+            .collect::<HashSet<_>>();
+        if ids.is_empty() {
+            return Ok(0);
         }
 
-        Ok(())
+        let purged = purge_records(&path, &ids, self.format.names)?;
+        self.reopen(&path)?;
+        Ok(purged)
     }
 
-    pub fn records(&self) -> impl Iterator<Item = &RecordData<T>> {
-        let mut items = self
-            .records
-            .iter()
-            .rev()
-            .unique_by(|record| record.id())
-            .filter_map(Record::data)
-            .collect::<Vec<_>>();
-        items.sort_by_key(|data| data.id);
-        items.into_iter()
+    // gives the live records the ids 1, 2, ... in the order of their current ids, and returns
+    // the new id of each; the log is rewritten with just the latest version of each record, so
+    // this handle starts over from the new file afterwards, and ids given by
+    // `OpenOptions::tolerate_duplicates` become the ones in the log
+    pub fn renumber(&mut self) -> io::Result<BTreeMap<RecordId, RecordId>>
+    where
+        T: Clone,
+    {
+        let path = self.rewritable_path()?;
+        self.lock_exclusive()?;
+        let result = self.renumber_unlocked(&path);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        let ids = result?;
+        // ids past the renumbered ones are free again, as for anyone opening the log afresh
+        self.next_record_id = 1;
+        self.reopen(&path)?;
+        Ok(ids)
     }
 
-    pub fn records_include_deleted(&self) -> impl Iterator<Item = &RecordData<T>> {
-        let mut items = self
-            .records
-            .iter()
-            .rev()
-            .filter_map(Record::data)
-            .unique_by(|record| record.id)
-            .collect::<Vec<_>>();
-        items.sort_by_key(|data| data.id);
-        items.into_iter()
+    fn renumber_unlocked(&mut self, path: &Path) -> io::Result<BTreeMap<RecordId, RecordId>>
+    where
+        T: Clone,
+    {
+        self.flush_pending_unlocked()?;
+        self.reload_unlocked()?;
+
+        let mut buffer = Vec::new();
+        if let Some(header) = &self.header {
+            serde_json::to_writer(&mut buffer, header)?;
+            buffer.push(b'\n');
+        }
+        let mut ids = BTreeMap::new();
+        for (record, id) in self.records().zip(1..) {
+            ids.insert(record.id, id);
+            let mut renumbered = Record::upsert(id, record.data.clone());
+            if let Some(meta) = self.meta(record.id) {
+                renumbered = renumbered.with_meta(meta.clone());
+            }
+            encode_record(&renumbered, self.checksums, self.format, &mut buffer)?;
+        }
+
+        let tmp_path = sibling_path(path, "renumber");
+        let result = File::create(&tmp_path).and_then(|mut file| {
+            file.write_all(&buffer)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+        Ok(ids)
     }
 
-    pub fn record_count(&self) -> usize {
-        self.records().count()
+    fn rewritable_path(&self) -> io::Result<PathBuf> {
+        match &self.path {
+            Some(_) if self.read_only => Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "database was opened read-only",
+            )),
+            Some(path) => Ok(path.clone()),
+            None => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "database was not opened from a path",
+            )),
+        }
     }
 
-    pub fn get(&self, id: RecordId) -> Option<&RecordData<T>> {
-        self.records().find(|record| record.id == id)
+    // picks up the log at `path` after it was rewritten
+    fn reopen(&mut self, path: &Path) -> io::Result<()> {
+        let file = fs::OpenOptions::new().read(true).append(true).open(path)?;
+        let lock = match &self.lock {
+            Some(lock) => Some(FileLock::new(&file, lock.timeout())?),
+            None => None,
+        };
+        self.replace_stream(file, lock)
     }
 }
 
-impl<T, S, C> Database<T, S, C>
+impl<T, S> Database<T, S>
 where
     T: Serialize + DeserializeOwned,
-    S: Read + Write + Seek,
+    S: Read + Seek,
+{
+    pub fn new(mut stream: S) -> io::Result<Database<T, S>> {
+        let offset = stream.stream_position()?;
+        let stream = BufReader::new(stream);
+        Ok(Database {
+            stream,
+            offset,
+            records: Vec::new(),
+            latest: BTreeMap::new(),
+            live_count: 0,
+            next_record_id: 1,
+            id_allocator: Box::new(SequentialIds),
+            reserved_ids: BTreeSet::new(),
+            written: Vec::new(),
+            undone: BTreeSet::new(),
+            last_write: None,
+            keep_history: true,
+            overwritten: 0,
+            unresolved_merges: HashSet::new(),
+            spans: HashMap::new(),
+            clock: None,
+            write_context: Map::new(),
+            merge_operator: None,
+            validator: None,
+            reload_validation: ReloadValidation::Skip,
+            on_invalid_record: InvalidRecordPolicy::Fail,
+            skipped: Vec::new(),
+            concatenation: None,
+            lock: None,
+            lock_file: None,
+            path: None,
+            read_only: false,
+            sync: None,
+            reopen: None,
+            stale: None,
+            unsynced: Unsynced::default(),
+            flush_policy: FlushPolicy::Immediate,
+            pending: Pending::new(),
+            close_marker: false,
+            closed_cleanly: None,
+            checksums: false,
+            format: Format::default(),
+            header: None,
+            auto_compact: None,
+            auto_reload: AutoReload::Manual,
+            last_reload: None,
+            timings: Timings::default(),
+            #[cfg(feature = "notify")]
+            watcher: None,
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            observers: Observers::new(),
+            mode: PhantomData,
+            cache_tag: DefaultCacheTag::default(),
+        })
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
     C: CacheTag<Record<T>>,
 {
-    fn writer(&mut self) -> io::Result<BufWriter<&mut S>> {
-        // reset buffer
-        #[allow(clippy::seek_from_current)]
-        self.stream.seek(SeekFrom::Current(0))?;
+    // makes sure everything written is on disk, and leaves the close marker if asked to, so that
+    // errors show up here rather than being lost when the database is dropped
+    pub fn close(mut self) -> io::Result<()> {
+        if self.read_only {
+            return self.lock.take().map_or(Ok(()), |lock| lock.unlock());
+        }
 
-        // return inner
-        Ok(BufWriter::new(self.stream.get_mut()))
+        // nothing may be appended between measuring the log and writing the marker
+        self.lock_exclusive()?;
+        let result = self.close_unlocked();
+        if let Some(lock) = self.lock.take() {
+            lock.unlock()?;
+        }
+        result?;
+        self.lock_file.take().map_or(Ok(()), LockFile::release)
     }
 
-    fn write_record(&mut self, record: Record<T>) -> io::Result<()> {
-        // move to end of file
-        self.reload()?;
-        if !self.is_at_end()? {
-            return Err(io::Error::new(io::ErrorKind::Other, "Expected EOF"));
+    // takes the write lock, first moving on to a new file if the log was replaced, so nothing is
+    // appended to a file that's no longer there
+    fn lock_exclusive(&mut self) -> io::Result<()> {
+        if let Some(lock) = &self.lock {
+            lock.lock_exclusive()?;
         }
+        let result = self.follow_replaced_unlocked(true);
+        if let (Err(_), Some(lock)) = (&result, &self.lock) {
+            lock.unlock()?;
+        }
+        result.map(drop)
+    }
 
-        // append and flush
+    // moves on to the file now at the path if the log was replaced, taking the same kind of lock
+    // on it as is held on the old one, which is then released; returns whether it did
+    fn follow_replaced_unlocked(&mut self, exclusive: bool) -> io::Result<bool> {
+        let (reopen, path) = match (self.reopen, &self.path) {
+            (Some(reopen), Some(path)) => (reopen, path.clone()),
+            _ => return Ok(false),
+        };
+        let access = Access {
+            writable: !self.read_only,
+            exclusive,
+        };
+        let mut replaced = false;
+        // the new file may be replaced as well before it's locked
+        while let Some((stream, lock)) =
+            reopen(self.stream.get_ref(), &path, self.lock.as_ref(), access)?
         {
-            let mut writer = self.writer()?;
-            serde_json::to_writer(&mut writer, &record)?;
-            writeln!(writer)?;
-            writer.flush()?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(path = %path.display(), "log was replaced, reopening it");
+            if let Some(old) = std::mem::replace(&mut self.lock, lock) {
+                old.unlock()?;
+            }
+            self.stream = BufReader::new(stream);
+            self.clear_loaded();
+            // held back writes go after whatever the new file has
+            self.pending.reordered |= !self.pending.is_empty();
+            replaced = true;
         }
+        Ok(replaced)
+    }
 
-        // update internal state
-        self.handle_record(record);
-
+    fn close_unlocked(&mut self) -> io::Result<()> {
+        self.flush_pending_unlocked()?;
+        if let Some(sync) = self.sync {
+            sync(self.stream.get_ref())?;
+            self.unsynced.0 = false;
+        }
+        if let (true, Some(path)) = (self.close_marker, &self.path) {
+            let len = self.stream.seek(SeekFrom::End(0))?;
+            write_marker(path, len)?;
+        }
         Ok(())
     }
 
-    pub fn insert(&mut self, data: T) -> io::Result<RecordId> {
-        let id = self.next_record_id;
-        self.next_record_id += 1;
+    // writes out the records held back by the flush policy in one go, syncing them as well unless
+    // every write is flushed anyway; returns whether the log had to be loaded again, which picks
+    // up the records that were written as well
+    fn flush_pending_unlocked(&mut self) -> io::Result<bool> {
+        let write = match (self.pending.is_empty(), self.pending.write) {
+            (false, Some(write)) => write,
+            _ => return Ok(false),
+        };
+        let buffer = std::mem::take(&mut self.pending.buffer);
+        let reordered = self.pending.reordered;
+        self.pending.clear();
 
-        self.write_record(Record::upsert(id, data))?;
+        let end = self.stream.seek(SeekFrom::End(0))?;
+        self.unsynced.0 |= self.sync.is_some();
+        write(self.stream.get_mut(), &buffer)?;
+        if let (Some(sync), false) = (self.sync, self.flush_policy == FlushPolicy::Immediate) {
+            sync(self.stream.get_ref())?;
+            self.unsynced.0 = false;
+        }
 
-        Ok(id)
+        let reload = reordered || end != self.offset;
+        if reload {
+            // the log has other writers' records before ours, so load it again in its order
+            #[cfg(feature = "tracing")]
+            tracing::debug!("log was appended to while writes were held back, reloading it");
+            self.clear_loaded();
+            self.reload_unlocked()?;
+        } else {
+            self.offset = self.stream.stream_position()?;
+        }
+        self.last_write = Some(self.log_position());
+        Ok(reload)
     }
 
-    pub fn upsert<F>(&mut self, id: RecordId, f: F) -> io::Result<()>
-    where
-        F: FnOnce(Option<&T>) -> Option<T>,
-    {
-        let data = self.get(id).map(|record_data| &record_data.data);
-
-        match f(data) {
-            Some(new_data) => self.write_record(Record::upsert(id, new_data))?,
-            None if data.is_some() => self.write_record(Record::delete(id))?,
-            None => (),
+    // forgets everything loaded from the log, so it can be read again from the start
+    fn clear_loaded(&mut self) {
+        self.offset = 0;
+        self.records.clear();
+        self.overwritten = 0;
+        self.latest.clear();
+        self.live_count = 0;
+        self.written.clear();
+        self.undone.clear();
+        self.unresolved_merges.clear();
+        self.spans.clear();
+        self.header = None;
+        if let Some(concatenation) = &mut self.concatenation {
+            *concatenation = Concatenation::default();
         }
+        self.cache_tag.reset();
+        self.observers.reset();
+    }
 
-        Ok(())
+    // hands back the stream without syncing it, which is then up to the caller
+    pub fn into_inner(mut self) -> S {
+        self.unsynced.0 = false;
+        self.stream.into_inner()
     }
 
-    pub fn delete(&mut self, id: RecordId) -> io::Result<()> {
-        self.write_record(Record::delete(id))
+    // whether the log was left as it was by the last `close`, going by its close marker; `None`
+    // unless opened with `OpenOptions::close_marker`
+    pub fn closed_cleanly(&self) -> Option<bool> {
+        self.closed_cleanly
     }
-}
 
-#[derive(Clone, Debug)]
-pub struct OpenOptions {
-    pub read_only: bool,
-}
+    pub fn with_cache_tag<C2: CacheTag<Record<T>>>(
+        self,
+        mut cache_tag: C2,
+    ) -> Database<T, S, C2, M> {
+        for record in &self.records {
+            cache_tag.process_value(record);
+        }
+        self.convert(|_| cache_tag)
+    }
 
-impl OpenOptions {
-    pub const fn new() -> OpenOptions {
-        OpenOptions { read_only: false }
+    // there's no way back, since the database may have been opened without write access
+    pub fn into_read_only(mut self) -> Database<T, S, C, ReadOnly> {
+        self.read_only = true;
+        self.auto_compact = None;
+        self.convert(|cache_tag| cache_tag)
     }
 
-    pub const fn read_only(mut self, read_only: bool) -> Self {
-        self.read_only = read_only;
+    fn convert<C2, M2>(self, cache_tag: impl FnOnce(C) -> C2) -> Database<T, S, C2, M2>
+    where
+        C2: CacheTag<Record<T>>,
+    {
+        Database {
+            stream: self.stream,
+            offset: self.offset,
+            records: self.records,
+            latest: self.latest,
+            live_count: self.live_count,
+            next_record_id: self.next_record_id,
+            id_allocator: self.id_allocator,
+            reserved_ids: self.reserved_ids,
+            written: self.written,
+            undone: self.undone,
+            last_write: self.last_write,
+            keep_history: self.keep_history,
+            overwritten: self.overwritten,
+            unresolved_merges: self.unresolved_merges,
+            spans: self.spans,
+            clock: self.clock,
+            write_context: self.write_context,
+            merge_operator: self.merge_operator,
+            validator: self.validator,
+            reload_validation: self.reload_validation,
+            on_invalid_record: self.on_invalid_record,
+            skipped: self.skipped,
+            concatenation: self.concatenation,
+            lock: self.lock,
+            lock_file: self.lock_file,
+            path: self.path,
+            read_only: self.read_only,
+            sync: self.sync,
+            reopen: self.reopen,
+            stale: self.stale,
+            unsynced: self.unsynced,
+            flush_policy: self.flush_policy,
+            pending: self.pending,
+            close_marker: self.close_marker,
+            closed_cleanly: self.closed_cleanly,
+            checksums: self.checksums,
+            format: self.format,
+            header: self.header,
+            auto_compact: self.auto_compact,
+            auto_reload: self.auto_reload,
+            last_reload: self.last_reload,
+            timings: self.timings,
+            #[cfg(feature = "notify")]
+            watcher: self.watcher,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+            observers: self.observers,
+            mode: PhantomData,
+            cache_tag: cache_tag(self.cache_tag),
+        }
+    }
+
+    // entries left out under `OpenOptions::on_invalid_record` so far, including while opening
+    pub fn skipped_entries(&self) -> &[SkippedEntry] {
+        &self.skipped
+    }
+
+    pub fn with_checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
         self
     }
 
-    pub fn open<T: Serialize + DeserializeOwned, P: AsRef<Path>>(
-        self,
+    pub fn field_names(&self) -> FieldNames {
+        self.format.names
+    }
+
+    pub(crate) fn format(&self) -> Format {
+        self.format
+    }
+
+    // these only affect records read or written from now on, so set them before loading anything
+    pub fn with_field_names(mut self, id: &'static str, deleted: &'static str) -> Self {
+        self.format.names = FieldNames::new(id, deleted);
+        self
+    }
+
+    pub fn with_layout(mut self, layout: Layout) -> Self {
+        self.format.layout = layout;
+        self
+    }
+
+    pub fn with_id_allocator(mut self, id_allocator: impl IdAllocator + 'static) -> Self {
+        self.id_allocator = Box::new(id_allocator);
+        self
+    }
+
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
+    pub fn with_write_context(mut self, context: Map<String, Value>) -> Self {
+        self.write_context = context;
+        self
+    }
+
+    pub fn set_write_context(&mut self, context: Map<String, Value>) {
+        self.write_context = context;
+    }
+
+    pub fn write_context(&self) -> &Map<String, Value> {
+        &self.write_context
+    }
+
+    pub fn with_merge_operator<F>(mut self, merge_operator: F) -> Self
+    where
+        F: Fn(Option<&T>, &Value) -> T + Send + Sync + 'static,
+    {
+        // resolve merge records that were loaded before the operator was known
+        self.merge_operator = Some(Box::new(merge_operator));
+        self.resolve_merges();
+        self.rebuild_index();
+        self.replay_records();
+        self
+    }
+
+    fn resolve_merges(&mut self) {
+        let merge_operator = match &self.merge_operator {
+            Some(merge_operator) => merge_operator,
+            None => return,
+        };
+
+        for i in 0..self.records.len() {
+            let (before, rest) = self.records.split_at_mut(i);
+            if let Record::Merge(record) = &mut rest[0] {
+                let existing = Snapshot::new(before).get(record.id);
+                record.resolved = Some(Arc::new(RecordData {
+                    id: record.id,
+                    data: merge_operator(existing.map(|data| &data.data), &record.operand),
+                }));
+            }
+        }
+    }
+
+    // merge operands aren't values of `T`, so only upserts are validated
+    pub fn set_validator<F>(&mut self, validator: F)
+    where
+        F: Fn(&T) -> Result<(), ValidationError> + Send + Sync + 'static,
+    {
+        self.validator = Some(Box::new(validator));
+    }
+
+    // records that were already loaded are checked as well
+    pub fn set_reload_validation(&mut self, reload_validation: ReloadValidation) -> io::Result<()> {
+        self.reload_validation = reload_validation;
+        match reload_validation {
+            ReloadValidation::Skip => Ok(()),
+            ReloadValidation::Strict => self
+                .records
+                .iter()
+                .try_for_each(|record| self.validate(record)),
+            ReloadValidation::Lenient => {
+                let valid = self
+                    .records
+                    .iter()
+                    .map(|record| self.validate(record).is_ok())
+                    .collect::<Vec<_>>();
+                if valid.iter().all(|&valid| valid) {
+                    return Ok(());
+                }
+
+                // positions of our own writes shift, so they can no longer be undone
+                let mut valid = valid.into_iter();
+                self.records.retain(|_| valid.next().unwrap_or(true));
+                self.written.clear();
+                self.undone.clear();
+                self.resolve_merges();
+                self.rebuild_index();
+                self.replay_records();
+                Ok(())
+            }
+        }
+    }
+
+    fn validate(&self, record: &Record<T>) -> io::Result<()> {
+        match (&self.validator, record) {
+            (Some(validator), Record::Upsert(UpsertRecord { data, .. })) => validator(&data.data)
+                .map_err(|error| Error::InvalidRecord { id: data.id, error }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn cache_tag(&self) -> u64 {
+        self.cache_tag.tag()
+    }
+
+    pub(crate) fn check_cache_tag(&self, expected: u64) -> io::Result<()> {
+        match self.cache_tag() {
+            actual if actual == expected => Ok(()),
+            actual => Err(Error::Conflict { expected, actual }.into()),
+        }
+    }
+
+    // feeds the loaded records to the cache tag again, after they were changed in place
+    pub fn recompute_cache_tag(&mut self) {
+        self.cache_tag.reset();
+        for record in &self.records {
+            self.cache_tag.process_value(record);
+        }
+    }
+
+    // the observer is fed the records that are already loaded straight away; to read its state
+    // afterwards, add it as an `Arc<Mutex<_>>` and keep a clone
+    pub fn add_observer(&mut self, observer: impl RecordObserver<T> + 'static) {
+        let mut observer: Box<dyn RecordObserver<T>> = Box::new(observer);
+        replay(observer.as_mut(), &self.records);
+        self.observers.custom.push(observer);
+    }
+
+    // `update` is applied to `initial` for every live record, and then whenever records change, so
+    // the view never has to be recomputed; an existing view with the same name is replaced
+    pub fn create_view<V, F>(&mut self, name: impl Into<String>, initial: V, update: F)
+    where
+        V: Clone + Send + Sync + 'static,
+        F: Fn(&mut V, ViewChange<'_, T>) + Send + Sync + 'static,
+    {
+        let mut view = View::new(initial, update);
+        replay(&mut view, &self.records);
+        self.observers.views.insert(name.into(), view);
+    }
+
+    // `None` if there is no view with that name, or if it holds a different type
+    pub fn view<V: 'static>(&self, name: &str) -> Option<&V> {
+        self.observers.views.get(name)?.get()
+    }
+
+    pub fn drop_view(&mut self, name: &str) -> bool {
+        self.observers.views.remove(name)
+    }
+
+    // feeds the loaded records to the cache tag, observers and views again, after they changed in
+    // place
+    fn replay_records(&mut self) {
+        self.recompute_cache_tag();
+        self.observers.reset();
+        replay(&mut self.observers, &self.records);
+    }
+
+    pub fn cache_tag_snapshot(&self) -> C
+    where
+        C: Clone,
+    {
+        self.cache_tag.clone()
+    }
+
+    // for putting back a tag taken when the records were last in the same state, e.g. after
+    // writing back an earlier version of the log
+    pub fn restore_cache_tag(&mut self, snapshot: C) {
+        self.cache_tag = snapshot;
+    }
+
+    // falls back to hashing the serialized record if the cache tag doesn't keep per-record tags,
+    // the same way as `ContentCacheTag`, so tags don't change with the process or toolchain
+    pub fn record_tag(&self, id: RecordId) -> Option<u64> {
+        let record = self.get(id)?;
+        self.cache_tag.record_tag(id).or_else(|| {
+            let mut hasher = Xxh3Default::new();
+            record.id.hash(&mut hasher);
+            hasher.write(&serde_json::to_vec(&record.data).ok()?);
+            Some(hasher.finish())
+        })
+    }
+
+    pub(crate) fn stream_mut(&mut self) -> &mut BufReader<S> {
+        &mut self.stream
+    }
+
+    #[cfg(feature = "rayon")]
+    pub(crate) fn set_offset(&mut self, offset: u64) {
+        self.offset = offset;
+    }
+
+    // every loaded entry, including superseded ones
+    pub(crate) fn loaded_records(&self) -> &[Record<T>] {
+        &self.records
+    }
+
+    pub(crate) fn observers(&self) -> &Observers<T> {
+        &self.observers
+    }
+
+    pub(crate) fn observers_mut(&mut self) -> &mut Observers<T> {
+        &mut self.observers
+    }
+
+    pub(crate) fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    fn record_reload_time(&mut self, elapsed: Duration) {
+        self.timings.reloads += 1;
+        self.timings.reload_time += elapsed;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record_reload(elapsed, self.offset);
+        }
+    }
+
+    fn record_write_time(&mut self, elapsed: Duration) {
+        self.timings.writes += 1;
+        self.timings.write_time += elapsed;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record_write(elapsed, self.offset);
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn metrics(&self) -> Option<&Metrics> {
+        self.metrics.as_ref()
+    }
+
+    #[cfg(feature = "metrics")]
+    pub(crate) fn set_metrics(&mut self, metrics: Metrics) {
+        self.metrics = Some(metrics);
+    }
+
+    // `reserved` ids are treated as taken, for records that haven't been written yet
+    pub(crate) fn allocate_id(&self, reserved: &BTreeSet<RecordId>) -> io::Result<RecordId> {
+        let reserved = if self.reserved_ids.is_empty() {
+            Cow::Borrowed(reserved)
+        } else {
+            Cow::Owned(reserved | &self.reserved_ids)
+        };
+        let ids = IdSpace::new(self.next_record_id, &self.latest, &reserved);
+        self.id_allocator.allocate(&ids).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::StorageFull, "no record ids left to allocate")
+        })
+    }
+
+    // `span` is where the entry was loaded from, if it was read from the log
+    fn handle_record(&mut self, mut record: Record<T>, span: Option<(u64, u64)>) {
+        if let (Record::Merge(record), Some(merge_operator)) = (&mut record, &self.merge_operator) {
+            let existing = self.get(record.id);
+            record.resolved = Some(Arc::new(RecordData {
+                id: record.id,
+                data: merge_operator(existing.map(|data| &data.data), &record.operand),
+            }));
+        }
+
+        if record.id() >= self.next_record_id {
+            self.next_record_id = record.id() + 1;
+        }
+        if let Some(concatenation) = &mut self.concatenation {
+            concatenation.observe(&record);
+        }
+        self.cache_tag.process_value(&record);
+
+        let id = record.id();
+        match (&record, span) {
+            (Record::Upsert(upsert), Some(span)) => {
+                self.spans.insert(id, (Arc::downgrade(&upsert.data), span))
+            }
+            _ => self.spans.remove(&id),
+        };
+        let records = &self.records;
+        let previous = self
+            .latest
+            .get(&id)
+            .and_then(|&index| records[index].data());
+        self.observers.observe(&record, previous);
+
+        // without history, the entry for the record is overwritten instead of appending another
+        match self.latest.get(&id) {
+            Some(&index)
+                if !self.keep_history
+                    && !record.is_unresolved_merge()
+                    && !self.unresolved_merges.contains(&id) =>
+            {
+                let was_live = self.records[index].data().is_some();
+                let is_live = record.data().is_some();
+                self.records[index] = record;
+                self.overwritten += 1;
+                match (was_live, is_live) {
+                    (false, true) => self.live_count += 1,
+                    (true, false) => self.live_count -= 1,
+                    _ => (),
+                }
+            }
+            _ => {
+                if record.is_unresolved_merge() {
+                    self.unresolved_merges.insert(id);
+                }
+                self.records.push(record);
+                self.index_record(self.records.len() - 1);
+            }
+        }
+    }
+
+    // remembers the last `count` records as our own writes, for `undo_last`
+    fn mark_written(&mut self, count: usize) {
+        // overwritten entries can't be undone, so there is nothing to remember
+        if self.keep_history {
+            let len = self.records.len();
+            self.written.extend(len - count..len);
+        }
+    }
+
+    // keeps track of the latest entry for each id, so lookups don't have to scan the log
+    fn index_record(&mut self, index: usize) {
+        let record = &self.records[index];
+        if record.is_unresolved_merge() {
+            return;
+        }
+
+        let is_live = record.data().is_some();
+        let was_live = match self.latest.insert(record.id(), index) {
+            Some(previous) => self.records[previous].data().is_some(),
+            None => false,
+        };
+        match (was_live, is_live) {
+            (false, true) => self.live_count += 1,
+            (true, false) => self.live_count -= 1,
+            _ => (),
+        }
+    }
+
+    fn rebuild_index(&mut self) {
+        self.latest.clear();
+        self.live_count = 0;
+        for index in 0..self.records.len() {
+            self.index_record(index);
+        }
+    }
+
+    // reads the next entry along with where it starts, past any whitespace before it
+    fn read_next(&mut self, summary: &mut ReloadSummary) -> io::Result<Option<(u64, Record<T>)>> {
+        loop {
+            self.stream.seek(SeekFrom::Start(self.offset))?;
+            let mut d = serde_json::Deserializer::from_reader(&mut self.stream).into_iter();
+
+            // read next record
+            let start = self.offset;
+            let raw: Box<RawValue> = match d.next().transpose() {
+                Ok(Some(raw)) => raw,
+                Ok(None) => {
+                    self.offset = self.stream.stream_position()?;
+                    return Ok(None);
+                }
+                Err(err) => match self.skip_invalid(Error::parse(start, err), summary)? {
+                    true => continue,
+                    false => return Ok(None),
+                },
+            };
+            let end = self.stream.stream_position()?;
+            let entry_start = end - raw.get().len() as u64;
+
+            let record = match self.decode_entry(raw.get(), end) {
+                Ok(record) => record,
+                Err(err) => match self.skip_invalid(err, summary)? {
+                    true => continue,
+                    false => return Ok(None),
+                },
+            };
+            self.offset = end;
+            if let Some(record) = record {
+                return Ok(Some((entry_start, record)));
+            }
+        }
+    }
+
+    // moves past the line after the offset if `err` says it's unreadable and the policy allows
+    // skipping it, or returns `err` otherwise; returns whether it did, since a line that isn't
+    // finished yet may still be being written
+    fn skip_invalid(&mut self, err: io::Error, summary: &mut ReloadSummary) -> io::Result<bool> {
+        let corrupt = matches!(
+            Error::from_io(&err),
+            Some(Error::Corrupt { .. } | Error::ChecksumMismatch { .. })
+        );
+        if !corrupt || self.on_invalid_record == InvalidRecordPolicy::Fail {
+            return Err(err);
+        }
+
+        // the offset is usually right before the line break ending the previous entry
+        self.stream.seek(SeekFrom::Start(self.offset))?;
+        let mut start = self.offset;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            self.stream.read_until(b'\n', &mut line)?;
+            if line.last() != Some(&b'\n') {
+                return Ok(false);
+            }
+            if !line.trim_ascii().is_empty() {
+                break;
+            }
+            start += line.len() as u64;
+        }
+        self.offset = start + line.len() as u64;
+
+        let indent = line.len() - line.trim_ascii_start().len();
+        let entry = SkippedEntry {
+            offset: start + indent as u64,
+            message: err.to_string(),
+            line: match self.on_invalid_record {
+                InvalidRecordPolicy::Collect => {
+                    Some(String::from_utf8_lossy(line.trim_ascii()).into_owned())
+                }
+                _ => None,
+            },
+        };
+        #[cfg(feature = "tracing")]
+        tracing::warn!(offset = entry.offset, error = %err, "skipped unreadable entry");
+        summary.skipped.push(entry.clone());
+        self.skipped.push(entry);
+        Ok(true)
+    }
+
+    // decodes a raw log entry ending at `end`, or returns `None` if it was the header
+    pub(crate) fn decode_entry(&mut self, raw: &str, end: u64) -> io::Result<Option<Record<T>>> {
+        let offset = end - raw.len() as u64;
+        let raw = verify_checksum(raw, offset)?;
+
+        // only the very first entry of the log may be a header
+        if self.records.is_empty() && self.header.is_none() {
+            if let Some(header) = parse_header(&raw, self.format.names) {
+                header.validate()?;
+                self.header = Some(header);
+                return Ok(None);
+            }
+        }
+        // a header further in is where another log was appended to this one
+        if let Some(concatenation) = &mut self.concatenation {
+            if let Some(header) = parse_header(&raw, self.format.names) {
+                header.validate()?;
+                concatenation.start_part();
+                return Ok(None);
+            }
+        }
+
+        let record = (self.format)
+            .decode(&raw)
+            .map_err(|err| Error::parse(offset, err))?;
+        Ok(Some(record))
+    }
+
+    fn is_at_end(&mut self) -> io::Result<bool> {
+        let offset = self.stream.seek(SeekFrom::End(0))?;
+        Ok(offset == self.offset)
+    }
+
+    pub fn reload(&mut self) -> io::Result<ReloadSummary> {
+        self.reload_with(Database::read_new_records)
+    }
+
+    // reloads with `read` reading the new entries, which it passes on to `apply_loaded`
+    pub(crate) fn reload_with(
+        &mut self,
+        read: impl FnOnce(&mut Self, &mut ReloadSummary) -> io::Result<()>,
+    ) -> io::Result<ReloadSummary> {
+        if let Some(lock) = &self.lock {
+            lock.lock_shared()?;
+        }
+        // a reader has to move on to a compacted or restored log just like a writer does
+        let result = self.follow_replaced_unlocked(false).and_then(|replaced| {
+            let summary = self.reload_unlocked_with(read)?;
+            Ok(ReloadSummary {
+                replaced,
+                ..summary
+            })
+        });
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn reload_unlocked(&mut self) -> io::Result<ReloadSummary> {
+        self.reload_unlocked_with(Database::read_new_records)
+    }
+
+    fn reload_unlocked_with(
+        &mut self,
+        read: impl FnOnce(&mut Self, &mut ReloadSummary) -> io::Result<()>,
+    ) -> io::Result<ReloadSummary> {
+        #[cfg(feature = "notify")]
+        if let Some(watcher) = &self.watcher {
+            watcher.clear();
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("reload", from = self.offset).entered();
+
+        let start = Instant::now();
+        let mut summary = ReloadSummary::default();
+        let from = self.offset;
+        read(self, &mut summary)?;
+        if !self.pending.is_empty() && self.offset != from {
+            self.pending.reordered = true;
+        }
+
+        self.last_reload = Some(Instant::now());
+        summary.bytes_read = self.offset - from;
+        summary.duration = start.elapsed();
+        self.record_reload_time(summary.duration);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            to = self.offset,
+            records = self.records.len(),
+            elapsed = ?start.elapsed(),
+            "reloaded"
+        );
+        Ok(summary)
+    }
+
+    // reads and applies every complete entry after the current offset
+    pub(crate) fn read_new_records(&mut self, summary: &mut ReloadSummary) -> io::Result<()> {
+        while let Some((start, record)) = self.read_next(summary)? {
+            let end = self.offset;
+            self.apply_loaded(record, (start, end), summary)?;
+        }
+        Ok(())
+    }
+
+    // applies a record read from the log, after the offset has been moved past it
+    pub(crate) fn apply_loaded(
+        &mut self,
+        record: Record<T>,
+        span: (u64, u64),
+        summary: &mut ReloadSummary,
+    ) -> io::Result<()> {
+        match self.reload_validation {
+            ReloadValidation::Skip => (),
+            ReloadValidation::Strict => self.validate(&record)?,
+            ReloadValidation::Lenient if self.validate(&record).is_err() => return Ok(()),
+            ReloadValidation::Lenient => (),
+        }
+        let record = match self.resolve_concatenated(record, summary) {
+            Some(record) => record,
+            None => {
+                summary.duplicates += 1;
+                return Ok(());
+            }
+        };
+        match record {
+            Record::Delete(_) => summary.new_deletes += 1,
+            Record::Upsert(_) | Record::Merge(_) => summary.new_records += 1,
+        }
+        self.handle_record(record, Some(span));
+        Ok(())
+    }
+
+    // under `tolerate_duplicates`, gives a loaded entry the id it resolves to, or leaves it out
+    // if it's the same as the latest entry of that record
+    fn resolve_concatenated(
+        &mut self,
+        mut record: Record<T>,
+        summary: &mut ReloadSummary,
+    ) -> Option<Record<T>> {
+        let concatenation = match &mut self.concatenation {
+            Some(concatenation) => concatenation,
+            None => return Some(record),
+        };
+        let latest = &self.latest;
+        let id = concatenation.resolve(&record, |id| latest.contains_key(&id), self.next_record_id);
+        if id != record.id() {
+            record.set_id(id);
+            summary.remapped += 1;
+        }
+
+        let current = self.latest.get(&id).map(|&index| &self.records[index]);
+        let duplicate = match (current, &record) {
+            (Some(Record::Delete(_)), Record::Delete(_)) => true,
+            (Some(Record::Upsert(current)), Record::Upsert(upsert)) => {
+                let hash = payload_hash(&current.data.data);
+                current.meta == upsert.meta
+                    && hash.is_some()
+                    && hash == payload_hash(&upsert.data.data)
+            }
+            _ => false,
+        };
+        (!duplicate).then_some(record)
+    }
+
+    // cheap check for whether `reload` would pick up anything, including the file being replaced;
+    // streams that can't tell always count as stale
+    pub fn is_stale(&self) -> io::Result<bool> {
+        #[cfg(feature = "notify")]
+        if let Some(watcher) = &self.watcher {
+            return Ok(watcher.is_dirty());
+        }
+
+        match self.stale {
+            Some(stale) => stale(self.stream.get_ref(), self.offset, self.path.as_deref()),
+            None => Ok(true),
+        }
+    }
+
+    // whether the auto-reload policy asks for a reload before the next read
+    pub fn reload_due(&self) -> bool {
+        self.auto_reload.is_due(self.last_reload)
+    }
+
+    pub fn reload_if_due(&mut self) -> io::Result<()> {
+        if self.reload_due() {
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    // where the last write made through this database ended in the log, once it's there, for
+    // other readers to `wait_for`; positions only compare within the same log, so they're
+    // meaningless after compacting it
+    pub fn last_write_position(&self) -> Option<LogPosition> {
+        self.last_write
+    }
+
+    // reloads unless everything up to `position` has been loaded already, and returns whether it
+    // has been now; only the offset is compared, since readers without history count records
+    // differently
+    pub fn reload_until(&mut self, position: LogPosition) -> io::Result<bool> {
+        if self.offset < position.offset {
+            self.reload()?;
+        }
+        Ok(self.offset >= position.offset)
+    }
+
+    // keeps reloading until everything up to `position` has been loaded, so that a write made
+    // through another database is visible here, failing with `TimedOut` after `timeout`
+    pub fn wait_for(&mut self, position: LogPosition, timeout: Duration) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        let mut delay = Duration::from_millis(1);
+        while !self.reload_until(position)? {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!(
+                        "log wasn't loaded up to {} within {:?}",
+                        position.offset, timeout
+                    ),
+                ));
+            }
+            std::thread::sleep(delay.min(deadline - now));
+            delay = (delay * 2).min(Duration::from_millis(100));
+        }
+        Ok(())
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &RecordData<T>> {
+        Snapshot::indexed(&self.records, &self.latest).records()
+    }
+
+    // handles to the live records, which don't borrow the database, so they can be held on to
+    // across writes; the payloads themselves aren't copied
+    pub fn records_owned(&self) -> Vec<Arc<RecordData<T>>> {
+        let records = self.latest.values().map(|&index| &self.records[index]);
+        records.filter_map(Record::shared_data).cloned().collect()
+    }
+
+    pub fn records_sorted_by<F>(&self, compare: F) -> impl Iterator<Item = &RecordData<T>>
+    where
+        F: FnMut(&&RecordData<T>, &&RecordData<T>) -> Ordering,
+    {
+        Snapshot::indexed(&self.records, &self.latest).records_sorted_by(compare)
+    }
+
+    pub fn records_map<'a, U, F>(&'a self, f: F) -> impl Iterator<Item = U> + 'a
+    where
+        F: FnMut(&'a RecordData<T>) -> U + 'a,
+        U: 'a,
+    {
+        Snapshot::indexed(&self.records, &self.latest).records_map(f)
+    }
+
+    pub fn records_include_deleted(&self) -> impl Iterator<Item = &RecordData<T>> {
+        Snapshot::new(&self.records).records_include_deleted()
+    }
+
+    pub fn sample<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<&RecordData<T>> {
+        Snapshot::indexed(&self.records, &self.latest).sample(n, rng)
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.live_count
+    }
+
+    pub fn contains(&self, id: RecordId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn get(&self, id: RecordId) -> Option<&RecordData<T>> {
+        self.latest_record(id).and_then(Record::data)
+    }
+
+    pub fn get_owned(&self, id: RecordId) -> Option<Arc<RecordData<T>>> {
+        self.latest_record(id)
+            .and_then(Record::shared_data)
+            .cloned()
+    }
+
+    fn latest_record(&self, id: RecordId) -> Option<&Record<T>> {
+        self.latest.get(&id).map(|&index| &self.records[index])
+    }
+
+    pub fn get_deleted(&self, id: RecordId) -> Option<&RecordData<T>> {
+        Snapshot::indexed(&self.records, &self.latest).get_deleted(id)
+    }
+
+    pub fn history(&self, id: RecordId) -> impl Iterator<Item = &Record<T>> {
+        Snapshot::new(&self.records).history(id)
+    }
+
+    // where in the log the latest entry of the record starts, if it was loaded from there
+    pub fn entry_offset(&self, id: RecordId) -> Option<u64> {
+        let (loaded, (start, _)) = self.spans.get(&id)?;
+        let data = self.latest_record(id)?.shared_data()?;
+        (Weak::as_ptr(loaded) == Arc::as_ptr(data)).then_some(*start)
+    }
+
+    pub fn meta(&self, id: RecordId) -> Option<&RecordMeta> {
+        self.latest_record(id)
+            .filter(|record| record.data().is_some())
+            .and_then(Record::meta)
+    }
+
+    pub fn metadata(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    pub fn log_position(&self) -> LogPosition {
+        LogPosition::new(self.records.len(), self.offset)
+    }
+
+    // positions count the entries kept in memory, which only line up with the log with history
+    pub fn as_of(&self, position: LogPosition) -> io::Result<Snapshot<'_, T>> {
+        self.check_history()?;
+        if position.records >= self.records.len() {
+            return Ok(Snapshot::indexed(&self.records, &self.latest));
+        }
+        Ok(Snapshot::new(&self.records[..position.records]))
+    }
+
+    pub fn changes_since(&self, position: LogPosition) -> io::Result<&[Record<T>]> {
+        self.check_history()?;
+        let start = position.records.min(self.records.len());
+        Ok(&self.records[start..])
+    }
+
+    fn check_history(&self) -> io::Result<()> {
+        if self.keep_history {
+            Ok(())
+        } else {
+            Err(Error::HistoryDisabled.into())
+        }
+    }
+
+    // the entries kept in memory, which is only the latest one of each record without history
+    pub(crate) fn entries(&self) -> &[Record<T>] {
+        &self.records
+    }
+
+    // how many entries were loaded from or written to the log, including ones since overwritten
+    pub(crate) fn entry_count(&self) -> usize {
+        self.records.len() + self.overwritten
+    }
+
+    // reads the live records back from the log as `P`, which usually has only a few of the fields
+    // of `T`, so the rest are skipped over rather than cloned; records that weren't loaded from the
+    // log as a whole entry, such as resolved merges or writes still held back, are converted from
+    // their value instead
+    pub fn project<P: DeserializeOwned>(&mut self) -> io::Result<Vec<RecordData<P>>> {
+        let live = self
+            .records_owned()
+            .into_iter()
+            .map(|record| {
+                let span = match self.spans.get(&record.id) {
+                    Some((loaded, span)) if Weak::as_ptr(loaded) == Arc::as_ptr(&record) => {
+                        Some(*span)
+                    }
+                    _ => None,
+                };
+                (record, span)
+            })
+            .collect::<Vec<_>>();
+
+        live.into_iter()
+            .map(|(record, span)| {
+                let projected = match span {
+                    Some((start, end)) => self.project_entry(start, end)?,
+                    None => None,
+                };
+                let data = match projected {
+                    Some(data) => data,
+                    None => serde_json::from_value(serde_json::to_value(&record.data)?)?,
+                };
+                Ok(RecordData {
+                    id: record.id,
+                    data,
+                })
+            })
+            .collect()
+    }
+
+    // decodes the payload of the entry between `start` and `end` as `P`
+    fn project_entry<P: DeserializeOwned>(
+        &mut self,
+        start: u64,
+        end: u64,
+    ) -> io::Result<Option<P>> {
+        self.stream.seek(SeekFrom::Start(start))?;
+        let mut raw = String::new();
+        (&mut self.stream)
+            .take(end - start)
+            .read_to_string(&mut raw)?;
+        let trimmed = raw.trim_start();
+        let offset = start + (raw.len() - trimmed.len()) as u64;
+        let raw = verify_checksum(trimmed.trim_end(), offset)?;
+
+        // payloads that aren't objects are nested even in a flat layout
+        if self.format.is_default() {
+            if let Ok(data) = serde_json::from_str::<P>(&raw) {
+                return Ok(Some(data));
+            }
+        }
+        Ok(match self.format.decode::<P>(&raw)? {
+            // just decoded, so nothing else holds on to it
+            Record::Upsert(record) => Arc::try_unwrap(record.data).ok().map(|data| data.data),
+            _ => None,
+        })
+    }
+
+    // copies the log as of the returned position; writers are held off until the copy is complete
+    pub fn backup_to(&mut self, path: impl AsRef<Path>) -> io::Result<LogPosition> {
+        self.lock_exclusive()?;
+        let result = self.backup_to_unlocked(path.as_ref());
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn backup_to_unlocked(&mut self, path: &Path) -> io::Result<LogPosition> {
+        // writes held back by the flush policy count towards the position, so they're copied too
+        self.reload_unlocked()?;
+        self.flush_pending_unlocked()?;
+
+        let tmp_path = sibling_path(path, "backup");
+        let result = File::create(&tmp_path).and_then(|mut out| {
+            self.stream.seek(SeekFrom::Start(0))?;
+            io::copy(&mut (&mut self.stream).take(self.offset), &mut out)?;
+            out.sync_all()?;
+            fs::rename(&tmp_path, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result?;
+
+        Ok(self.log_position())
+    }
+
+    pub fn export_since<W: Write>(
+        &self,
+        position: LogPosition,
+        mut writer: W,
+    ) -> io::Result<LogPosition> {
+        let mut buffer = Vec::new();
+        for record in self.changes_since(position)? {
+            buffer.clear();
+            encode_record(record, false, self.format, &mut buffer)?;
+            writer.write_all(&buffer)?;
+        }
+        writer.flush()?;
+
+        Ok(self.log_position())
+    }
+}
+
+impl<T, S, C> Database<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    fn init(
+        &mut self,
+        opts: &OpenOptions,
+        reload: fn(&mut Self) -> io::Result<ReloadSummary>,
+    ) -> io::Result<()> {
+        self.auto_reload = opts.auto_reload;
+        self.keep_history = opts.keep_history;
+        self.flush_policy = opts.flush_policy;
+        self.on_invalid_record = opts.on_invalid_record;
+        self.concatenation = opts.tolerate_duplicates.then(Concatenation::default);
+        if let (true, Some(path)) = (opts.close_marker, &self.path) {
+            let len = self.stream.seek(SeekFrom::End(0))?;
+            // a new log has nothing to lose
+            self.closed_cleanly = Some(read_marker(path)?.unwrap_or(0) == len);
+            self.close_marker = true;
+        }
+        reload(self)?;
+        if opts.header && self.header.is_none() {
+            if !self.records.is_empty() {
+                return Err(Error::MissingHeader.into());
+            }
+            if !opts.read_only {
+                self.write_header()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.lock_exclusive()?;
+        let result = self.write_header_unlocked();
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn write_header_unlocked(&mut self) -> io::Result<()> {
+        // another process may have initialized the file in the meantime
+        self.reload_unlocked()?;
+        if self.header.is_some() || !self.records.is_empty() || !self.is_at_end()? {
+            return Ok(());
+        }
+
+        let header = Header::new(Some(SystemClock.now_millis()));
+        let mut buffer = serde_json::to_vec(&header)?;
+        buffer.push(b'\n');
+        {
+            let mut writer = self.writer()?;
+            writer.write_all(&buffer)?;
+            writer.flush()?;
+        }
+
+        self.offset = self.stream.stream_position()?;
+        self.header = Some(header);
+        Ok(())
+    }
+
+    fn writer(&mut self) -> io::Result<BufWriter<&mut S>> {
+        self.unsynced.0 |= self.sync.is_some();
+
+        // reset buffer
+        #[allow(clippy::seek_from_current)]
+        self.stream.seek(SeekFrom::Current(0))?;
+
+        // return inner
+        Ok(BufWriter::new(self.stream.get_mut()))
+    }
+
+    // writes out the records held back by the flush policy; they're written when the database is
+    // closed as well, but since the policy is only checked on writes, a database that goes quiet
+    // should be flushed
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.lock_exclusive()?;
+        let result = self.flush_pending_unlocked();
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result?;
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: Record<T>) -> io::Result<()> {
+        self.append_record(record)?;
+        self.mark_written(1);
+        self.maybe_compact()
+    }
+
+    fn maybe_compact(&mut self) -> io::Result<()> {
+        let auto_compact = match &self.auto_compact {
+            Some(auto_compact) => auto_compact,
+            None => return Ok(()),
+        };
+        if !auto_compact.policy.should_compact(
+            self.records.len(),
+            self.live_count,
+            self.offset,
+            auto_compact.compacted_size,
+        ) {
+            return Ok(());
+        }
+
+        #[cfg(feature = "tracing")]
+        let _span = tracing::info_span!(
+            "auto_compact",
+            records = self.records.len(),
+            live = self.live_count,
+            bytes = self.offset
+        )
+        .entered();
+
+        // the compacted log has to include everything written so far
+        self.flush()?;
+        let start = Instant::now();
+        let (stream, lock) = match &self.auto_compact {
+            Some(auto_compact) => (auto_compact.compactor)()?,
+            None => return Ok(()),
+        };
+        self.replace_stream(stream, lock)?;
+
+        if let Some(auto_compact) = &mut self.auto_compact {
+            auto_compact.compacted_size = self.offset;
+        }
+        self.timings.compactions += 1;
+        self.timings.compaction_time += start.elapsed();
+        Ok(())
+    }
+
+    // the rewritten log no longer contains all of the history, so start over from the new file
+    fn replace_stream(&mut self, stream: S, lock: Option<FileLock>) -> io::Result<()> {
+        self.stream = BufReader::new(stream);
+        self.lock = lock;
+        self.clear_loaded();
+        self.reload()?;
+        Ok(())
+    }
+
+    fn append_record(&mut self, record: Record<T>) -> io::Result<()> {
+        self.lock_exclusive()?;
+        let result = self.append_record_unlocked(record);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn append_record_unlocked(&mut self, record: Record<T>) -> io::Result<()> {
+        self.append_records_unlocked(vec![record])
+    }
+
+    fn append_records_unlocked(&mut self, records: Vec<Record<T>>) -> io::Result<()> {
+        self.append_unlocked(records, true)
+    }
+
+    // records applied from another log keep their metadata as it was, rather than being stamped
+    fn append_unlocked(&mut self, records: Vec<Record<T>>, stamp: bool) -> io::Result<()> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!("write", records = records.len()).entered();
+
+        let start = Instant::now();
+        for record in &records {
+            self.validate(record)?;
+        }
+
+        // move to end of file
+        self.reload_unlocked()?;
+        if !self.is_at_end()? {
+            return Err(io::Error::other("Expected EOF"));
+        }
+        // the ids written would mean other records to anyone reading the log as it is
+        if self
+            .concatenation
+            .as_ref()
+            .is_some_and(Concatenation::is_remapped)
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "records were loaded under other ids than in the log; renumber the database first",
+            ));
+        }
+
+        let records = match stamp {
+            true => records
+                .into_iter()
+                .map(|record| self.stamp_record(record))
+                .collect::<Vec<_>>(),
+            false => records,
+        };
+
+        // append everything in a single write, now or once the flush policy says so
+        let mut buffer = Vec::new();
+        for record in &records {
+            encode_record(record, self.checksums, self.format, &mut buffer)?;
+        }
+        self.pending.buffer.extend_from_slice(&buffer);
+        self.pending.count += records.len();
+        self.pending.since.get_or_insert(start);
+        self.pending.write = Some(write_flushed::<S>);
+        let reloaded = self
+            .flush_policy
+            .is_due(self.pending.count, self.pending.since)
+            && self.flush_pending_unlocked()?;
+
+        // update internal state
+        if !reloaded {
+            for record in records {
+                self.handle_record(record, None);
+            }
+        }
+        if self.pending.is_empty() {
+            self.last_write = Some(self.log_position());
+        }
+
+        self.record_write_time(start.elapsed());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            bytes = buffer.len(),
+            to = self.offset,
+            elapsed = ?start.elapsed(),
+            "wrote records"
+        );
+        Ok(())
+    }
+
+    fn stamp_record(&self, record: Record<T>) -> Record<T> {
+        let mut meta = RecordMeta {
+            context: self.write_context.clone(),
+            ..RecordMeta::default()
+        };
+        if let Some(now) = self.clock.as_ref().map(|clock| clock.now_millis()) {
+            let created_at = self
+                .meta(record.id())
+                .and_then(|meta| meta.created_at)
+                .unwrap_or(now);
+            meta.created_at = Some(created_at);
+            meta.updated_at = Some(now);
+        }
+        if meta.is_empty() {
+            record
+        } else {
+            record.with_meta(meta)
+        }
+    }
+
+    // appends records exported from another database verbatim, failing without writing anything
+    // if any of them touch a record changed locally after `last_applied`
+    pub fn apply_log<R: Read>(
+        &mut self,
+        reader: R,
+        last_applied: LogPosition,
+    ) -> io::Result<LogPosition> {
+        // entries are in the format `export_since` writes them in, which is this database's own
+        let mut incoming = Vec::new();
+        let mut d = serde_json::Deserializer::from_reader(reader).into_iter::<Box<RawValue>>();
+        let mut start = 0;
+        while let Some(raw) = d
+            .next()
+            .transpose()
+            .map_err(|err| Error::parse(start, err))?
+        {
+            let raw = verify_checksum(raw.get(), start)?;
+            let record = (self.format)
+                .decode(&raw)
+                .map_err(|err| Error::parse(start, err))?;
+            incoming.push(record);
+            start = d.byte_offset() as u64;
+        }
+
+        self.lock_exclusive()?;
+        let result = self.apply_log_unlocked(incoming, last_applied);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result?;
+        // compaction renumbers the log, so report the position after it
+        self.maybe_compact()?;
+        Ok(self.log_position())
+    }
+
+    fn apply_log_unlocked(
+        &mut self,
+        incoming: Vec<Record<T>>,
+        last_applied: LogPosition,
+    ) -> io::Result<LogPosition> {
+        self.reload_unlocked()?;
+        let local_ids: HashSet<RecordId> = self
+            .changes_since(last_applied)?
+            .iter()
+            .map(Record::id)
+            .collect();
+        let conflicts: BTreeSet<RecordId> = incoming
+            .iter()
+            .map(Record::id)
+            .filter(|id| local_ids.contains(id))
+            .collect();
+        if !conflicts.is_empty() {
+            let ids = conflicts
+                .iter()
+                .map(RecordId::to_string)
+                .collect::<Vec<_>>();
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "records changed locally since last sync: {}",
+                    ids.join(", ")
+                ),
+            ));
+        }
+
+        self.append_unlocked(incoming, false)?;
+        Ok(self.log_position())
+    }
+
+    pub fn insert(&mut self, data: T) -> io::Result<RecordId> {
+        self.lock_exclusive()?;
+        let result = self.insert_unlocked(data);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        let id = result?;
+        self.mark_written(1);
+        self.maybe_compact()?;
+
+        Ok(id)
+    }
+
+    // the id is picked after catching up with the log, so it can't collide with other writers' ids
+    fn insert_unlocked(&mut self, data: T) -> io::Result<RecordId> {
+        self.reload_unlocked()?;
+        let id = self.allocate_id(&BTreeSet::new())?;
+        self.append_record_unlocked(Record::upsert(id, data))?;
+        Ok(id)
+    }
+
+    // looks up the first live record matching `predicate`, and inserts `f()` if there is none; other
+    // writers are held off in between, so two processes can't both insert the same record
+    pub fn get_or_insert_with<P, F>(
+        &mut self,
+        predicate: P,
+        f: F,
+    ) -> io::Result<(RecordId, &RecordData<T>)>
+    where
+        P: FnMut(&T) -> bool,
+        F: FnOnce() -> T,
+    {
+        self.lock_exclusive()?;
+        let result = self.get_or_insert_with_unlocked(predicate, f);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        let (id, inserted) = result?;
+        if inserted {
+            self.mark_written(1);
+            self.maybe_compact()?;
+        }
+
+        let record = self.get(id).expect("record was just found or inserted");
+        Ok((id, record))
+    }
+
+    fn get_or_insert_with_unlocked<P, F>(
+        &mut self,
+        mut predicate: P,
+        f: F,
+    ) -> io::Result<(RecordId, bool)>
+    where
+        P: FnMut(&T) -> bool,
+        F: FnOnce() -> T,
+    {
+        self.reload_unlocked()?;
+        if let Some(record) = self.records().find(|record| predicate(&record.data)) {
+            return Ok((record.id, false));
+        }
+
+        let id = self.allocate_id(&BTreeSet::new())?;
+        self.append_record_unlocked(Record::upsert(id, f()))?;
+        Ok((id, true))
+    }
+
+    // unlike `upsert`, fails with `Error::IdInUse` if the id was ever used, even by a deleted record
+    pub fn insert_with_id(&mut self, id: RecordId, data: T) -> io::Result<()> {
+        self.lock_exclusive()?;
+        let result = self.insert_with_id_unlocked(id, data);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result?;
+        self.mark_written(1);
+        self.maybe_compact()
+    }
+
+    fn insert_with_id_unlocked(&mut self, id: RecordId, data: T) -> io::Result<()> {
+        self.reload_unlocked()?;
+        if self.latest.contains_key(&id) {
+            return Err(Error::IdInUse { id }.into());
+        }
+        self.append_record_unlocked(Record::upsert(id, data))
+    }
+
+    // the id won't be handed out by this handle again until it's inserted with `insert_reserved` or
+    // released; other processes don't know about it, so inserting it can still fail with `IdInUse`
+    pub fn reserve_id(&mut self) -> io::Result<RecordId> {
+        self.reload()?;
+        let id = self.allocate_id(&BTreeSet::new())?;
+        self.reserved_ids.insert(id);
+        Ok(id)
+    }
+
+    // the id the next insert would get as of the last reload, which is only a guess for allocators
+    // that pick ids at random
+    pub fn peek_next_id(&self) -> Option<RecordId> {
+        self.allocate_id(&BTreeSet::new()).ok()
+    }
+
+    pub fn insert_reserved(&mut self, id: RecordId, data: T) -> io::Result<()> {
+        if !self.reserved_ids.contains(&id) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("record id {id} was not reserved"),
+            ));
+        }
+        self.insert_with_id(id, data)?;
+        self.reserved_ids.remove(&id);
+        Ok(())
+    }
+
+    // returns whether the id was reserved
+    pub fn release_id(&mut self, id: RecordId) -> bool {
+        self.reserved_ids.remove(&id)
+    }
+
+    pub fn upsert<F>(&mut self, id: RecordId, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Option<&T>) -> Option<T>,
+    {
+        let data = self.get(id).map(|record_data| &record_data.data);
+
+        match f(data) {
+            Some(new_data) => self.write_record(Record::upsert(id, new_data))?,
+            None if data.is_some() => self.write_record(Record::delete(id))?,
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    // rewrites every live record matching `predicate` with a single write, returning how many changed
+    pub fn update_where<P, F>(&mut self, predicate: P, f: F) -> io::Result<usize>
+    where
+        P: FnMut(&T) -> bool,
+        F: FnMut(&T) -> T,
+    {
+        self.lock_exclusive()?;
+        let result = self.update_where_unlocked(predicate, f);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        let updated = result?;
+        self.maybe_compact()?;
+        Ok(updated)
+    }
+
+    fn update_where_unlocked<P, F>(&mut self, mut predicate: P, mut f: F) -> io::Result<usize>
+    where
+        P: FnMut(&T) -> bool,
+        F: FnMut(&T) -> T,
+    {
+        self.reload_unlocked()?;
+        let records = self
+            .records()
+            .filter(|record| predicate(&record.data))
+            .map(|record| Record::upsert(record.id, f(&record.data)))
+            .collect::<Vec<_>>();
+
+        let count = records.len();
+        if count > 0 {
+            self.append_records_unlocked(records)?;
+            self.mark_written(count);
+        }
+        Ok(count)
+    }
+
+    // the staging area handed out `inserted` ids itself, so they must still be free when committing;
+    // with `expected_tag`, nothing else may have been written since it was taken either
+    pub(crate) fn commit_staged(
+        &mut self,
+        records: Vec<Record<T>>,
+        inserted: &BTreeSet<RecordId>,
+        expected_tag: Option<u64>,
+    ) -> io::Result<()> {
+        self.lock_exclusive()?;
+        let result = self.commit_staged_unlocked(records, inserted, expected_tag);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result?;
+        self.maybe_compact()
+    }
+
+    fn commit_staged_unlocked(
+        &mut self,
+        records: Vec<Record<T>>,
+        inserted: &BTreeSet<RecordId>,
+        expected_tag: Option<u64>,
+    ) -> io::Result<()> {
+        self.reload_unlocked()?;
+        if let Some(expected_tag) = expected_tag {
+            self.check_cache_tag(expected_tag)?;
+        }
+        if inserted.iter().any(|id| self.latest.contains_key(id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "staged record ids were taken by another writer",
+            ));
+        }
+
+        let count = records.len();
+        self.append_records_unlocked(records)?;
+        self.mark_written(count);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: RecordId) -> io::Result<()> {
+        self.write_record(Record::delete(id))
+    }
+
+    pub fn restore(&mut self, id: RecordId) -> io::Result<bool>
+    where
+        T: Clone,
+    {
+        self.reload()?;
+        match self.get_deleted(id) {
+            Some(record_data) => {
+                let data = record_data.data.clone();
+                self.write_record(Record::upsert(id, data))?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn merge(&mut self, id: RecordId, operand: Value) -> io::Result<()> {
+        self.write_record(Record::merge(id, operand))
+    }
+
+    pub fn undo_last(&mut self, n: usize) -> io::Result<usize>
+    where
+        T: Clone,
+    {
+        // the previous values are only current if nothing can be written between reading and undoing
+        self.lock_exclusive()?;
+        let result = self.undo_last_unlocked(n);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        let undone = result?;
+        self.maybe_compact()?;
+        Ok(undone)
+    }
+
+    fn undo_last_unlocked(&mut self, n: usize) -> io::Result<usize>
+    where
+        T: Clone,
+    {
+        self.reload_unlocked()?;
+        let undo = self.written.split_off(self.written.len().saturating_sub(n));
+
+        // a record another writer wrote since is left alone, rather than overwritten with what it
+        // was before our write
+        let changed = undo.iter().find_map(|&index| {
+            let id = self.records[index].id();
+            self.records[index + 1..]
+                .iter()
+                .zip(index + 1..)
+                .any(|(record, later)| {
+                    record.id() == id
+                        && undo.binary_search(&later).is_err()
+                        && !self.undone.contains(&later)
+                })
+                .then_some(id)
+        });
+        if let Some(id) = changed {
+            self.written.extend(undo);
+            return Err(io::Error::other(format!(
+                "record {id} was changed by another writer since it was written"
+            )));
+        }
+
+        for &index in undo.iter().rev() {
+            self.undone.insert(index);
+            let id = self.records[index].id();
+            let previous = Snapshot::new(&self.records[..index])
+                .get(id)
+                .map(|record_data| record_data.data.clone());
+
+            let record = match previous {
+                Some(data) => Record::upsert(id, data),
+                None if self.get(id).is_some() => Record::delete(id),
+                None => continue,
+            };
+            self.append_record_unlocked(record)?;
+            self.undone.insert(self.records.len() - 1);
+        }
+
+        Ok(undo.len())
+    }
+}
+
+// when reads pick up changes made by other processes; `SharedDatabase` and `LazyDatabase` reload
+// on their own, while plain `Database` reads borrow the records, so callers use `reload_if_due`
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum AutoReload {
+    #[default]
+    Manual,
+    OnRead,
+    OnInterval(Duration),
+}
+
+impl AutoReload {
+    pub(crate) fn is_due(&self, last_reload: Option<Instant>) -> bool {
+        match self {
+            AutoReload::Manual => false,
+            AutoReload::OnRead => true,
+            AutoReload::OnInterval(interval) => {
+                last_reload.is_none_or(|last_reload| last_reload.elapsed() >= *interval)
+            }
+        }
+    }
+}
+
+// when appended records are written to the log; anything but `Immediate` holds them back until
+// `n` of them are waiting or the first has waited `d`, then writes and syncs them in one go,
+// which is much faster on slow or network filesystems
+//
+// held back records are visible to the database that wrote them straight away, but not to other
+// processes, and until they're written their ids may be handed out to another writer as well, so
+// this is meant for a single writer (see `OpenOptions::exclusive`)
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FlushPolicy {
+    #[default]
+    Immediate,
+    EveryN(usize),
+    Interval(Duration),
+}
+
+impl FlushPolicy {
+    fn is_due(&self, count: usize, since: Option<Instant>) -> bool {
+        match self {
+            FlushPolicy::Immediate => true,
+            FlushPolicy::EveryN(n) => count >= *n,
+            FlushPolicy::Interval(interval) => {
+                since.is_some_and(|since| since.elapsed() >= *interval)
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenOptions {
+    pub read_only: bool,
+    // defaults to creating the file unless it's opened read-only
+    pub create: Option<bool>,
+    pub create_new: bool,
+    pub truncate: bool,
+    pub mode: Option<u32>,
+    pub timestamps: bool,
+    pub cache_capacity: usize,
+    pub index_file: bool,
+    pub close_marker: bool,
+    pub lock: bool,
+    pub lock_timeout: Option<Duration>,
+    pub exclusive: bool,
+    pub checksums: bool,
+    pub field_names: FieldNames,
+    pub layout: Layout,
+    pub header: bool,
+    pub max_segment_size: u64,
+    pub auto_compact: Option<CompactionPolicy>,
+    pub auto_reload: AutoReload,
+    pub on_invalid_record: InvalidRecordPolicy,
+    pub tolerate_duplicates: bool,
+    pub flush_policy: FlushPolicy,
+    pub keep_history: bool,
+}
+
+impl OpenOptions {
+    pub const fn new() -> OpenOptions {
+        OpenOptions {
+            read_only: false,
+            create: None,
+            create_new: false,
+            truncate: false,
+            mode: None,
+            timestamps: false,
+            cache_capacity: 1024,
+            index_file: false,
+            close_marker: false,
+            lock: true,
+            lock_timeout: None,
+            exclusive: false,
+            checksums: false,
+            field_names: FieldNames::DEFAULT,
+            layout: Layout::Flat,
+            header: false,
+            max_segment_size: 64 << 20,
+            auto_compact: None,
+            auto_reload: AutoReload::Manual,
+            on_invalid_record: InvalidRecordPolicy::Fail,
+            tolerate_duplicates: false,
+            flush_policy: FlushPolicy::Immediate,
+            keep_history: true,
+        }
+    }
+
+    pub const fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    // creating a read-only database writes an empty file, so that it can be opened
+    pub const fn create(mut self, create: bool) -> Self {
+        self.create = Some(create);
+        self
+    }
+
+    // fails with `AlreadyExists` if there's a file at the path already
+    pub const fn create_new(mut self, create_new: bool) -> Self {
+        self.create_new = create_new;
+        self
+    }
+
+    // starts from an empty log, discarding everything in an existing file
+    pub const fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    // the permissions of a newly created file, as in `std::os::unix::fs::OpenOptionsExt::mode`;
+    // ignored on other platforms
+    pub const fn mode(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub(crate) fn open_file(&self, path: &Path) -> io::Result<File> {
+        let create = self.create.unwrap_or(!self.read_only);
+        let mut file_opts = fs::OpenOptions::new();
+        file_opts.read(true);
+        #[cfg(unix)]
+        if let Some(mode) = self.mode {
+            use std::os::unix::fs::OpenOptionsExt;
+            file_opts.mode(mode);
+        }
+
+        if self.read_only {
+            if self.create_new || self.truncate {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "can't create a new or truncated database read-only",
+                ));
+            }
+            if create {
+                file_opts.clone().append(true).create(true).open(path)?;
+            }
+            return file_opts.open(path);
+        }
+
+        // truncating isn't allowed together with appending, so the file is emptied after opening
+        let file = file_opts
+            .append(true)
+            .create(create)
+            .create_new(self.create_new)
+            .open(path)?;
+        if self.truncate {
+            file.set_len(0)?;
+        }
+        Ok(file)
+    }
+
+    pub const fn timestamps(mut self, timestamps: bool) -> Self {
+        self.timestamps = timestamps;
+        self
+    }
+
+    pub const fn lock(mut self, lock: bool) -> Self {
+        self.lock = lock;
+        self
+    }
+
+    pub const fn lock_timeout(mut self, lock_timeout: Option<Duration>) -> Self {
+        self.lock_timeout = lock_timeout;
+        self
+    }
+
+    // claims the log for this writer alone with a `<log>.lock` file for as long as the database is
+    // open, so a second writer fails with `Error::Locked` right away, rather than taking turns
+    // appending; a lock file left behind by a process that is gone is taken over
+    pub const fn exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    pub(crate) fn lock_file(&self, path: &Path) -> io::Result<Option<LockFile>> {
+        match self.exclusive && !self.read_only {
+            true => LockFile::acquire(path).map(Some),
+            false => Ok(None),
+        }
+    }
+
+    pub const fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    // the keys used for the id and tombstone properties instead of `id` and `deleted`, e.g. when
+    // the payload has its own `id` field
+    pub const fn field_names(mut self, id: &'static str, deleted: &'static str) -> Self {
+        self.field_names = FieldNames::new(id, deleted);
+        self
+    }
+
+    pub const fn layout(mut self, layout: Layout) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    pub(crate) const fn format(&self) -> Format {
+        Format {
+            names: self.field_names,
+            layout: self.layout,
+        }
+    }
+
+    pub const fn header(mut self, header: bool) -> Self {
+        self.header = header;
+        self
+    }
+
+    pub const fn max_segment_size(mut self, max_segment_size: u64) -> Self {
+        self.max_segment_size = max_segment_size;
+        self
+    }
+
+    pub const fn auto_compact(mut self, auto_compact: Option<CompactionPolicy>) -> Self {
+        self.auto_compact = auto_compact;
+        self
+    }
+
+    pub const fn auto_reload(mut self, auto_reload: AutoReload) -> Self {
+        self.auto_reload = auto_reload;
+        self
+    }
+
+    pub const fn on_invalid_record(mut self, on_invalid_record: InvalidRecordPolicy) -> Self {
+        self.on_invalid_record = on_invalid_record;
+        self
+    }
+
+    // loads a log that may be several logs concatenated, each starting with a header, giving
+    // records of later ones new ids where theirs are taken; entries that are the same as the
+    // record they would replace are left out either way
+    pub const fn tolerate_duplicates(mut self, tolerate_duplicates: bool) -> Self {
+        self.tolerate_duplicates = tolerate_duplicates;
+        self
+    }
+
+    pub const fn flush_policy(mut self, flush_policy: FlushPolicy) -> Self {
+        self.flush_policy = flush_policy;
+        self
+    }
+
+    // without history, only the latest entry for each record is kept in memory, so `history`,
+    // `get_deleted` and `undo_last` can't see earlier ones, and `as_of`, `changes_since`,
+    // `export_since` and `apply_log` fail with `Error::HistoryDisabled`
+    pub const fn keep_history(mut self, keep_history: bool) -> Self {
+        self.keep_history = keep_history;
+        self
+    }
+
+    pub const fn cache_capacity(mut self, cache_capacity: usize) -> Self {
+        self.cache_capacity = cache_capacity;
+        self
+    }
+
+    // keeps the offsets of lazily loaded records in `<log>.idx` next to the log, so reopening
+    // only has to read what was appended since
+    pub const fn index_file(mut self, index_file: bool) -> Self {
+        self.index_file = index_file;
+        self
+    }
+
+    // has `close` leave `<log>.closed` next to the log, see `Database::closed_cleanly`
+    pub const fn close_marker(mut self, close_marker: bool) -> Self {
+        self.close_marker = close_marker;
+        self
+    }
+
+    pub fn open<T: Serialize + DeserializeOwned, P: AsRef<Path>>(
+        self,
         path: P,
     ) -> io::Result<Database<T, File>> {
         Database::open_with_opts(path, self)
     }
+
+    // like `open` with `read_only(true)`, but writing is ruled out by the type as well
+    pub fn open_read_only<T: Serialize + DeserializeOwned, P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> io::Result<Database<T, File, DefaultCacheTag, ReadOnly>> {
+        let database = Database::open_with_opts(path, self.read_only(true))?;
+        Ok(database.into_read_only())
+    }
+
+    pub fn open_segmented<T: Serialize + DeserializeOwned, P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> io::Result<Database<T, SegmentedFile>> {
+        Database::open_segmented(path, self)
+    }
+
+    pub fn open_lazy<T: Serialize + DeserializeOwned, P: AsRef<Path>>(
+        self,
+        path: P,
+    ) -> io::Result<LazyDatabase<T>> {
+        LazyDatabase::open_with_opts(path, self)
+    }
 }
 
 impl Default for OpenOptions {
@@ -0,0 +1,37 @@
+use std::io::{self, Read, Seek, SeekFrom};
+
+// stands in for the stream of a database that was loaded up front and has nothing left to read;
+// it deliberately doesn't implement `Write`, so such databases can't be modified
+#[derive(Debug, Default)]
+pub struct Detached {
+    position: u64,
+}
+
+impl Detached {
+    pub fn new() -> Detached {
+        Detached::default()
+    }
+}
+
+impl Read for Detached {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Ok(0)
+    }
+}
+
+impl Seek for Detached {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        self.position = match pos {
+            SeekFrom::Start(offset) => offset,
+            SeekFrom::End(offset) | SeekFrom::Current(offset) => {
+                self.position.checked_add_signed(offset).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "invalid seek to a negative or overflowing position",
+                    )
+                })?
+            }
+        };
+        Ok(self.position)
+    }
+}
@@ -0,0 +1,146 @@
+use std::fmt;
+use std::io;
+
+use crate::{record::RecordId, validation::ValidationError};
+
+// typed errors are carried inside `io::Error`, so callers can downcast with `Error::from_io`
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    ChecksumMismatch {
+        offset: u64,
+    },
+    MissingHeader,
+    UnsupportedHeader(String),
+    InvalidFilter {
+        position: usize,
+        message: String,
+    },
+    InvalidRecord {
+        id: RecordId,
+        error: ValidationError,
+    },
+    IdInUse {
+        id: RecordId,
+    },
+    NotFound {
+        id: RecordId,
+    },
+    Locked {
+        pid: Option<u32>,
+        // when the lock was taken, in milliseconds since the Unix epoch, if it's known
+        since: Option<u64>,
+    },
+    Corrupt {
+        offset: u64,
+        message: String,
+    },
+    StillReferenced {
+        id: RecordId,
+        referenced_by: Vec<RecordId>,
+    },
+    // the cache tag wasn't the expected one anymore when writing
+    Conflict {
+        expected: u64,
+        actual: u64,
+    },
+    // log positions can't be resolved when earlier entries were overwritten in memory
+    HistoryDisabled,
+}
+
+impl Error {
+    pub fn from_io(err: &io::Error) -> Option<&Error> {
+        err.get_ref()?.downcast_ref()
+    }
+
+    // entries that can't be parsed are corrupt, while running out of input is left as it is,
+    // since it may be a write that is still in progress
+    pub(crate) fn parse(offset: u64, err: serde_json::Error) -> io::Error {
+        if err.is_syntax() || err.is_data() {
+            Error::Corrupt {
+                offset,
+                message: Error::parse_message(&err),
+            }
+            .into()
+        } else {
+            err.into()
+        }
+    }
+
+    // the position is relative to where parsing started, which the offset of the entry already says
+    pub(crate) fn parse_message(err: &serde_json::Error) -> String {
+        let message = err.to_string();
+        let position = format!(" at line {} column {}", err.line(), err.column());
+        match message.strip_suffix(&position) {
+            Some(message) => message.to_string(),
+            None => message,
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            Error::ChecksumMismatch { .. }
+            | Error::MissingHeader
+            | Error::UnsupportedHeader(_)
+            | Error::Corrupt { .. } => io::ErrorKind::InvalidData,
+            Error::InvalidFilter { .. }
+            | Error::InvalidRecord { .. }
+            | Error::StillReferenced { .. } => io::ErrorKind::InvalidInput,
+            Error::IdInUse { .. } => io::ErrorKind::AlreadyExists,
+            Error::NotFound { .. } => io::ErrorKind::NotFound,
+            Error::Locked { .. } => io::ErrorKind::WouldBlock,
+            Error::Conflict { .. } => io::ErrorKind::Other,
+            Error::HistoryDisabled => io::ErrorKind::Unsupported,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::ChecksumMismatch { offset } => {
+                write!(f, "checksum mismatch in record at offset {offset}")
+            }
+            Error::MissingHeader => write!(f, "file does not start with a jsondb header"),
+            Error::UnsupportedHeader(reason) => write!(f, "unsupported jsondb header: {reason}"),
+            Error::InvalidFilter { position, message } => {
+                write!(f, "invalid filter at position {position}: {message}")
+            }
+            Error::InvalidRecord { id, error } => write!(f, "record {id} is invalid: {error}"),
+            Error::IdInUse { id } => write!(f, "record id {id} is already in use"),
+            Error::NotFound { id } => write!(f, "no record with id {id}"),
+            Error::Locked { pid, since } => {
+                write!(f, "database is locked by another process")?;
+                match (pid, since) {
+                    (Some(pid), Some(since)) => write!(f, " (pid {pid}, locked at {since})"),
+                    (Some(pid), None) => write!(f, " (pid {pid})"),
+                    (None, Some(since)) => write!(f, " (locked at {since})"),
+                    (None, None) => Ok(()),
+                }
+            }
+            Error::Corrupt { offset, message } => {
+                write!(f, "corrupt entry at offset {offset}: {message}")
+            }
+            Error::StillReferenced { id, referenced_by } => {
+                let referenced_by = referenced_by.iter().map(ToString::to_string);
+                let referenced_by = referenced_by.collect::<Vec<_>>().join(", ");
+                write!(f, "record {id} is still referenced by {referenced_by}")
+            }
+            Error::Conflict { expected, actual } => write!(
+                f,
+                "database was changed by another writer (cache tag {actual:#x}, expected {expected:#x})"
+            ),
+            Error::HistoryDisabled => {
+                write!(f, "log positions are only available when history is kept")
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Error> for io::Error {
+    fn from(err: Error) -> io::Error {
+        io::Error::new(err.kind(), err)
+    }
+}
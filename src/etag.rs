@@ -0,0 +1,84 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Seek};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordId},
+};
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PreconditionFailed {
+    pub etag: String,
+}
+
+impl fmt::Display for PreconditionFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.etag.is_empty() {
+            write!(f, "precondition failed, record does not exist")
+        } else {
+            write!(
+                f,
+                "precondition failed, current entity tag is {}",
+                self.etag
+            )
+        }
+    }
+}
+
+impl Error for PreconditionFailed {}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // only as precise as the cache tag, so use a content-aware one like `StateCacheTag` to avoid
+    // spurious mismatches after no-op writes or compaction
+    pub fn etag(&self) -> String {
+        format!("\"{:016x}\"", self.cache_tag())
+    }
+
+    // the entity tag of a single record, or `None` if it doesn't exist
+    pub fn record_etag(&self, id: RecordId) -> Option<String> {
+        self.record_tag(id).map(|tag| format!("\"{:016x}\"", tag))
+    }
+
+    // checks the value of an `If-Match` header, which is either `*` or a list of entity tags
+    pub fn check_precondition(&self, if_match: &str) -> Result<(), PreconditionFailed> {
+        check_if_match(if_match, self.etag())
+    }
+
+    // like `check_precondition`, but against a single record, so `*` fails if it doesn't exist;
+    // the error's entity tag is empty in that case
+    pub fn check_record_precondition(
+        &self,
+        id: RecordId,
+        if_match: &str,
+    ) -> Result<(), PreconditionFailed> {
+        match self.record_etag(id) {
+            Some(etag) => check_if_match(if_match, etag),
+            None => Err(PreconditionFailed {
+                etag: String::new(),
+            }),
+        }
+    }
+}
+
+fn check_if_match(if_match: &str, etag: String) -> Result<(), PreconditionFailed> {
+    let matches = if_match.trim() == "*"
+        || if_match
+            .split(',')
+            .map(str::trim)
+            // weak tags never match, since `If-Match` uses strong comparison
+            .any(|tag| tag == etag);
+
+    if matches {
+        Ok(())
+    } else {
+        Err(PreconditionFailed { etag })
+    }
+}
@@ -0,0 +1,269 @@
+use serde_json::Value;
+use std::cmp::Ordering;
+use std::str::FromStr;
+
+use crate::error::Error;
+
+// a small expression language for selecting records, e.g. `b > 5 && a == "foo"`:
+//
+//   expr    := and ("||" and)*
+//   and     := unary ("&&" unary)*
+//   unary   := "!" unary | compare
+//   compare := operand (("==" | "!=" | "<" | "<=" | ">" | ">=") operand)?
+//   operand := literal | path | "(" expr ")"
+//
+// paths like `a.b.0` look up object keys and array indices, and are `null` when missing
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    expr: Expr,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Literal(Value),
+    Path(Vec<String>),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Compare(CompareOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum CompareOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Filter {
+    pub fn parse(source: &str) -> Result<Filter, Error> {
+        let mut parser = Parser {
+            source,
+            position: 0,
+        };
+        let expr = parser.expr()?;
+        parser.skip_whitespace();
+        if parser.position < source.len() {
+            return Err(parser.error("unexpected input"));
+        }
+        Ok(Filter { expr })
+    }
+
+    pub fn matches(&self, value: &Value) -> bool {
+        is_truthy(&self.expr.eval(value))
+    }
+}
+
+impl FromStr for Filter {
+    type Err = Error;
+
+    fn from_str(source: &str) -> Result<Filter, Error> {
+        Filter::parse(source)
+    }
+}
+
+impl Expr {
+    fn eval(&self, value: &Value) -> Value {
+        match self {
+            Expr::Literal(literal) => literal.clone(),
+            Expr::Path(path) => path
+                .iter()
+                .try_fold(value, |value, key| match value {
+                    Value::Object(map) => map.get(key),
+                    Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+                    _ => None,
+                })
+                .cloned()
+                .unwrap_or(Value::Null),
+            Expr::Not(expr) => Value::Bool(!is_truthy(&expr.eval(value))),
+            Expr::And(lhs, rhs) => {
+                Value::Bool(is_truthy(&lhs.eval(value)) && is_truthy(&rhs.eval(value)))
+            }
+            Expr::Or(lhs, rhs) => {
+                Value::Bool(is_truthy(&lhs.eval(value)) || is_truthy(&rhs.eval(value)))
+            }
+            Expr::Compare(op, lhs, rhs) => {
+                let ordering = compare(&lhs.eval(value), &rhs.eval(value));
+                Value::Bool(match op {
+                    CompareOp::Eq => ordering == Some(Ordering::Equal),
+                    CompareOp::Ne => ordering != Some(Ordering::Equal),
+                    CompareOp::Lt => ordering == Some(Ordering::Less),
+                    CompareOp::Le => matches!(ordering, Some(Ordering::Less | Ordering::Equal)),
+                    CompareOp::Gt => ordering == Some(Ordering::Greater),
+                    CompareOp::Ge => {
+                        matches!(ordering, Some(Ordering::Greater | Ordering::Equal))
+                    }
+                })
+            }
+        }
+    }
+}
+
+fn is_truthy(value: &Value) -> bool {
+    !matches!(value, Value::Null | Value::Bool(false))
+}
+
+// values of different types are unequal and unordered; numbers compare by value, so `1 == 1.0`
+fn compare(lhs: &Value, rhs: &Value) -> Option<Ordering> {
+    match (lhs, rhs) {
+        (Value::Number(lhs), Value::Number(rhs)) => lhs.as_f64()?.partial_cmp(&rhs.as_f64()?),
+        (Value::String(lhs), Value::String(rhs)) => Some(lhs.cmp(rhs)),
+        (Value::Bool(lhs), Value::Bool(rhs)) => Some(lhs.cmp(rhs)),
+        (lhs, rhs) if lhs == rhs => Some(Ordering::Equal),
+        _ => None,
+    }
+}
+
+struct Parser<'a> {
+    source: &'a str,
+    position: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, Error> {
+        let mut lhs = self.unary()?;
+        while self.eat("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.unary()?));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Error> {
+        if !self.peek("!=") && self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.compare()
+    }
+
+    fn compare(&mut self) -> Result<Expr, Error> {
+        let lhs = self.operand()?;
+
+        // longer operators first, so `<=` isn't read as `<`
+        let ops = [
+            ("==", CompareOp::Eq),
+            ("!=", CompareOp::Ne),
+            ("<=", CompareOp::Le),
+            (">=", CompareOp::Ge),
+            ("<", CompareOp::Lt),
+            (">", CompareOp::Gt),
+        ];
+        for (token, op) in ops {
+            if self.eat(token) {
+                let rhs = self.operand()?;
+                return Ok(Expr::Compare(op, Box::new(lhs), Box::new(rhs)));
+            }
+        }
+        Ok(lhs)
+    }
+
+    fn operand(&mut self) -> Result<Expr, Error> {
+        self.skip_whitespace();
+        let rest = &self.source[self.position..];
+        match rest.chars().next() {
+            Some('(') => {
+                self.position += 1;
+                let expr = self.expr()?;
+                if !self.eat(")") {
+                    return Err(self.error("expected `)`"));
+                }
+                Ok(expr)
+            }
+            Some('"') => self.string(),
+            Some(c) if c == '-' || c.is_ascii_digit() => self.number(),
+            Some(c) if c == '_' || c.is_alphabetic() => {
+                let path = self.path()?;
+                Ok(match path.as_slice() {
+                    [word] if word == "true" => Expr::Literal(Value::Bool(true)),
+                    [word] if word == "false" => Expr::Literal(Value::Bool(false)),
+                    [word] if word == "null" => Expr::Literal(Value::Null),
+                    _ => Expr::Path(path),
+                })
+            }
+            Some(_) => Err(self.error("expected a value or field name")),
+            None => Err(self.error("unexpected end of filter")),
+        }
+    }
+
+    fn string(&mut self) -> Result<Expr, Error> {
+        // JSON string syntax, so escapes work the same way as in records
+        let rest = &self.source[self.position..];
+        let mut stream = serde_json::Deserializer::from_str(rest).into_iter::<String>();
+        match stream.next() {
+            Some(Ok(string)) => {
+                self.position += stream.byte_offset();
+                Ok(Expr::Literal(Value::String(string)))
+            }
+            _ => Err(self.error("invalid string literal")),
+        }
+    }
+
+    fn number(&mut self) -> Result<Expr, Error> {
+        let rest = &self.source[self.position..];
+        let len = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')))
+            .unwrap_or(rest.len());
+        match serde_json::from_str::<serde_json::Number>(&rest[..len]) {
+            Ok(number) => {
+                self.position += len;
+                Ok(Expr::Literal(Value::Number(number)))
+            }
+            Err(_) => Err(self.error("invalid number")),
+        }
+    }
+
+    fn path(&mut self) -> Result<Vec<String>, Error> {
+        let mut path = Vec::new();
+        loop {
+            let rest = &self.source[self.position..];
+            let len = rest
+                .find(|c: char| !(c == '_' || c.is_alphanumeric()))
+                .unwrap_or(rest.len());
+            if len == 0 {
+                return Err(self.error("expected a field name"));
+            }
+            path.push(rest[..len].to_string());
+            self.position += len;
+
+            if !self.source[self.position..].starts_with('.') {
+                return Ok(path);
+            }
+            self.position += 1;
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = &self.source[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn peek(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        self.source[self.position..].starts_with(token)
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        let found = self.peek(token);
+        if found {
+            self.position += token.len();
+        }
+        found
+    }
+
+    fn error(&self, message: &str) -> Error {
+        Error::InvalidFilter {
+            position: self.position,
+            message: message.to_string(),
+        }
+    }
+}
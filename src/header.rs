@@ -0,0 +1,52 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io;
+
+use crate::{error::Error, record::FieldNames};
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    #[serde(rename = "jsondb")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id_type: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Header {
+    pub fn new(created: Option<u64>) -> Header {
+        Header {
+            version: FORMAT_VERSION,
+            created,
+            id_type: Some("u32".to_string()),
+            extra: Map::new(),
+        }
+    }
+
+    pub(crate) fn validate(&self) -> io::Result<()> {
+        if self.version != FORMAT_VERSION {
+            let reason = format!("unsupported format version {}", self.version);
+            return Err(Error::UnsupportedHeader(reason).into());
+        }
+        match self.id_type.as_deref() {
+            None | Some("u32") => Ok(()),
+            Some(id_type) => {
+                let reason = format!("unsupported id type {id_type:?}");
+                Err(Error::UnsupportedHeader(reason).into())
+            }
+        }
+    }
+}
+
+// a header is recognized by its `jsondb` property, and can never carry a record id, under
+// whatever name the log keeps its ids
+pub(crate) fn parse_header(raw: &str, names: FieldNames) -> Option<Header> {
+    serde_json::from_str::<Header>(raw)
+        .ok()
+        .filter(|header| !header.extra.contains_key(names.id))
+}
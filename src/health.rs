@@ -0,0 +1,57 @@
+use std::fmt;
+use std::io;
+
+#[derive(Debug)]
+pub struct Health {
+    pub reachable: Check,
+    pub lockable: Check,
+    pub tail: Check,
+    pub writable: Check,
+}
+
+impl Health {
+    pub fn is_healthy(&self) -> bool {
+        self.checks().iter().all(|(_, check)| !check.is_failed())
+    }
+
+    pub fn checks(&self) -> [(&'static str, &Check); 4] {
+        [
+            ("reachable", &self.reachable),
+            ("lockable", &self.lockable),
+            ("tail", &self.tail),
+            ("writable", &self.writable),
+        ]
+    }
+}
+
+#[derive(Debug)]
+pub enum Check {
+    Ok,
+    Skipped,
+    Failed(io::Error),
+}
+
+impl Check {
+    pub fn is_failed(&self) -> bool {
+        matches!(self, Check::Failed(_))
+    }
+}
+
+impl From<io::Result<()>> for Check {
+    fn from(result: io::Result<()>) -> Check {
+        match result {
+            Ok(()) => Check::Ok,
+            Err(err) => Check::Failed(err),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Ok => write!(f, "ok"),
+            Check::Skipped => write!(f, "skipped"),
+            Check::Failed(err) => write!(f, "failed: {err}"),
+        }
+    }
+}
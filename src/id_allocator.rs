@@ -0,0 +1,215 @@
+use rand::Rng;
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    record::RecordId,
+};
+
+// picks the id for each inserted record; returning `None` means there are no ids left to hand out
+pub trait IdAllocator: Send + Sync {
+    fn allocate(&self, ids: &IdSpace<'_>) -> Option<RecordId>;
+}
+
+// the ids that are in use, either in the log or by records that are about to be written
+pub struct IdSpace<'a> {
+    next_id: RecordId,
+    ids: &'a BTreeMap<RecordId, usize>,
+    reserved: &'a BTreeSet<RecordId>,
+}
+
+impl<'a> IdSpace<'a> {
+    pub(crate) fn new(
+        next_id: RecordId,
+        ids: &'a BTreeMap<RecordId, usize>,
+        reserved: &'a BTreeSet<RecordId>,
+    ) -> IdSpace<'a> {
+        IdSpace {
+            next_id,
+            ids,
+            reserved,
+        }
+    }
+
+    // one past the highest id in use, or `None` once `RecordId::MAX` has been used
+    pub fn next_id(&self) -> Option<RecordId> {
+        match self.reserved.last() {
+            Some(&last) if last >= self.next_id => last.checked_add(1),
+            _ => Some(self.next_id),
+        }
+    }
+
+    pub fn is_taken(&self, id: RecordId) -> bool {
+        self.ids.contains_key(&id) || self.reserved.contains(&id)
+    }
+
+    pub fn last_in(&self, range: RangeInclusive<RecordId>) -> Option<RecordId> {
+        let last = self.ids.range(range.clone()).next_back().map(|(&id, _)| id);
+        let reserved = self.reserved.range(range).next_back().copied();
+        last.max(reserved)
+    }
+}
+
+// one past the highest id ever used, which is what `Database` has always done
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SequentialIds;
+
+impl IdAllocator for SequentialIds {
+    fn allocate(&self, ids: &IdSpace<'_>) -> Option<RecordId> {
+        ids.next_id()
+    }
+}
+
+// random ids, so writers appending to diverged copies of a log are unlikely to collide
+#[derive(Clone, Debug)]
+pub struct RandomIds {
+    range: RangeInclusive<RecordId>,
+}
+
+impl RandomIds {
+    pub fn new(range: RangeInclusive<RecordId>) -> RandomIds {
+        RandomIds { range }
+    }
+}
+
+impl Default for RandomIds {
+    fn default() -> RandomIds {
+        RandomIds::new(1..=RecordId::MAX)
+    }
+}
+
+impl IdAllocator for RandomIds {
+    fn allocate(&self, ids: &IdSpace<'_>) -> Option<RecordId> {
+        if self.range.is_empty() {
+            return None;
+        }
+
+        let mut rng = rand::rng();
+        for _ in 0..64 {
+            let id = rng.random_range(self.range.clone());
+            if !ids.is_taken(id) {
+                return Some(id);
+            }
+        }
+
+        // the range is nearly full, so look for a gap instead of guessing
+        self.range.clone().find(|&id| !ids.is_taken(id))
+    }
+}
+
+// sequential ids within a block reserved for each node, where the high `node_bits` bits of every
+// id are the node id
+#[derive(Clone, Copy, Debug)]
+pub struct ShardedIds {
+    node: u32,
+    node_bits: u32,
+}
+
+impl ShardedIds {
+    pub fn new(node: u32, node_bits: u32) -> ShardedIds {
+        assert!(
+            (1..32).contains(&node_bits),
+            "node_bits must be between 1 and 31, not {}",
+            node_bits
+        );
+        assert!(
+            node < 1 << node_bits,
+            "node {} does not fit in {} bits",
+            node,
+            node_bits
+        );
+        ShardedIds { node, node_bits }
+    }
+
+    pub fn node_of(&self, id: RecordId) -> u32 {
+        id >> (32 - self.node_bits)
+    }
+
+    fn range(&self) -> RangeInclusive<RecordId> {
+        let shift = 32 - self.node_bits;
+        let start = self.node << shift;
+        start..=start | ((1 << shift) - 1)
+    }
+}
+
+impl IdAllocator for ShardedIds {
+    fn allocate(&self, ids: &IdSpace<'_>) -> Option<RecordId> {
+        let range = self.range();
+        match ids.last_in(range.clone()) {
+            Some(last) if last == *range.end() => None,
+            Some(last) => Some(last + 1),
+            // id 0 is left unused, like in an unsharded database
+            None => Some((*range.start()).max(1)),
+        }
+    }
+}
+
+// ids that sort by creation time, like ULIDs: the number of minutes since `epoch` in the high bits
+// followed by `random_bits` random bits; with the defaults, ids run out after about 31 years
+#[derive(Clone)]
+pub struct TimeOrderedIds {
+    epoch: SystemTime,
+    random_bits: u32,
+    clock: Arc<dyn Clock>,
+}
+
+impl TimeOrderedIds {
+    pub fn new() -> TimeOrderedIds {
+        TimeOrderedIds {
+            // 2024-01-01T00:00:00Z
+            epoch: UNIX_EPOCH + Duration::from_secs(1_704_067_200),
+            random_bits: 8,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    pub fn epoch(mut self, epoch: SystemTime) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn random_bits(mut self, random_bits: u32) -> Self {
+        assert!(
+            random_bits < 32,
+            "random_bits must be less than 32, not {}",
+            random_bits
+        );
+        self.random_bits = random_bits;
+        self
+    }
+
+    pub fn clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl Default for TimeOrderedIds {
+    fn default() -> TimeOrderedIds {
+        TimeOrderedIds::new()
+    }
+}
+
+impl IdAllocator for TimeOrderedIds {
+    fn allocate(&self, ids: &IdSpace<'_>) -> Option<RecordId> {
+        let minutes = self
+            .clock
+            .now()
+            .duration_since(self.epoch)
+            .map_or(0, |elapsed| elapsed.as_secs() / 60);
+        let time = RecordId::try_from(minutes)
+            .ok()
+            .filter(|&time| time.leading_zeros() >= self.random_bits)?;
+        let prefix = time << self.random_bits;
+        let range = prefix.max(1)..=prefix | ((1u64 << self.random_bits) - 1) as RecordId;
+
+        // if this minute's ids are used up, the next free id still sorts after everything before it
+        RandomIds::new(range.clone())
+            .allocate(ids)
+            .or_else(|| (*range.end()..=RecordId::MAX).find(|&id| !ids.is_taken(id)))
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{compact::sibling_path, header::Header, record::RecordId};
+
+const MAGIC: &[u8; 8] = b"jsondbi1";
+
+// the end of the covered log is checksummed, to tell whether the log was rewritten since
+const TAIL_WINDOW: u64 = 4096;
+
+// a snapshot of a lazily loaded database's index, kept next to the log as `<log>.idx`, so
+// reopening only has to read what was appended since
+pub(crate) struct IndexFile {
+    // the length of the log it covers
+    pub len: u64,
+    pub next_record_id: RecordId,
+    pub header: Option<Header>,
+    // the byte range of the latest entry of each live record
+    pub entries: BTreeMap<RecordId, (u64, u64)>,
+}
+
+pub(crate) fn index_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".idx");
+    path.with_file_name(name)
+}
+
+fn tail_checksum(log: &mut (impl Read + Seek), len: u64) -> io::Result<u32> {
+    let start = len.saturating_sub(TAIL_WINDOW);
+    log.seek(SeekFrom::Start(start))?;
+    let mut tail = Vec::new();
+    log.take(len - start).read_to_end(&mut tail)?;
+    Ok(crc32fast::hash(&tail))
+}
+
+impl IndexFile {
+    // `None` if there is no index, or if it's damaged or doesn't match `log` anymore; either way
+    // the log has to be read from the start
+    pub fn load(path: &Path, log: &mut (impl Read + Seek)) -> io::Result<Option<IndexFile>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let (index, checksum) = match IndexFile::decode(&bytes) {
+            Some(decoded) => decoded,
+            None => return Ok(None),
+        };
+
+        let log_len = log.seek(SeekFrom::End(0))?;
+        if index.len > log_len || tail_checksum(log, index.len)? != checksum {
+            return Ok(None);
+        }
+        Ok(Some(index))
+    }
+
+    // replaces the index at `path`, which covers `log` up to `self.len`
+    pub fn save(&self, path: &Path, log: &mut (impl Read + Seek)) -> io::Result<()> {
+        let checksum = tail_checksum(log, self.len)?;
+        let header = serde_json::to_vec(&self.header)?;
+
+        let mut bytes = Vec::with_capacity(40 + header.len() + 20 * self.entries.len());
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes.extend_from_slice(&checksum.to_le_bytes());
+        bytes.extend_from_slice(&self.next_record_id.to_le_bytes());
+        bytes.extend_from_slice(&(header.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&header);
+        bytes.extend_from_slice(&(self.entries.len() as u64).to_le_bytes());
+        for (id, (start, len)) in &self.entries {
+            bytes.extend_from_slice(&id.to_le_bytes());
+            bytes.extend_from_slice(&start.to_le_bytes());
+            bytes.extend_from_slice(&len.to_le_bytes());
+        }
+        let crc = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&crc.to_le_bytes());
+
+        let tmp_path = sibling_path(path, "tmp");
+        let result = File::create(&tmp_path).and_then(|mut file| {
+            file.write_all(&bytes)?;
+            file.sync_all()?;
+            fs::rename(&tmp_path, path)
+        });
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        result
+    }
+
+    fn decode(bytes: &[u8]) -> Option<(IndexFile, u32)> {
+        let (bytes, crc) = bytes.split_at(bytes.len().checked_sub(4)?);
+        if crc32fast::hash(bytes).to_le_bytes() != crc {
+            return None;
+        }
+
+        let mut reader = Reader(bytes);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let len = reader.u64()?;
+        let checksum = reader.u32()?;
+        let next_record_id = reader.u32()?;
+        let header_len = reader.u32()? as usize;
+        let header = serde_json::from_slice(reader.take(header_len)?).ok()?;
+        let mut entries = BTreeMap::new();
+        for _ in 0..reader.u64()? {
+            entries.insert(reader.u32()?, (reader.u64()?, reader.u64()?));
+        }
+
+        let index = IndexFile {
+            len,
+            next_record_id,
+            header,
+            entries,
+        };
+        Some((index, checksum))
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len > self.0.len() {
+            return None;
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Some(bytes)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.take(8)?.try_into().ok()?))
+    }
+}
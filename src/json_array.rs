@@ -0,0 +1,96 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::io::{self, Cursor, Read, Seek, Write};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordData},
+};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ArrayItem<T> {
+    Record(Record<T>),
+    Data(T),
+}
+
+pub fn read_json_array<T, R>(reader: R) -> io::Result<Vec<Record<T>>>
+where
+    T: DeserializeOwned,
+    R: Read,
+{
+    let items: Vec<ArrayItem<T>> = serde_json::from_reader(reader)?;
+
+    // elements without an id get fresh ids after the highest existing one
+    let mut next_record_id = items
+        .iter()
+        .filter_map(|item| match item {
+            ArrayItem::Record(record) => Some(record.id()),
+            ArrayItem::Data(_) => None,
+        })
+        .max()
+        .unwrap_or(0)
+        + 1;
+
+    let records = items
+        .into_iter()
+        .map(|item| match item {
+            ArrayItem::Record(record) => record,
+            ArrayItem::Data(data) => {
+                let id = next_record_id;
+                next_record_id += 1;
+                Record::upsert(id, data)
+            }
+        })
+        .collect();
+
+    Ok(records)
+}
+
+pub fn write_json_array<'a, T, W>(
+    mut writer: W,
+    records: impl IntoIterator<Item = &'a RecordData<T>>,
+) -> io::Result<()>
+where
+    T: Serialize + 'a,
+    W: Write,
+{
+    write!(writer, "[")?;
+    for (i, record) in records.into_iter().enumerate() {
+        if i > 0 {
+            write!(writer, ",")?;
+        }
+        writeln!(writer)?;
+        serde_json::to_writer(&mut writer, record)?;
+    }
+    writeln!(writer, "\n]")?;
+    writer.flush()
+}
+
+impl<T> Database<T, Cursor<Vec<u8>>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn from_json_array<R: Read>(reader: R) -> io::Result<Database<T, Cursor<Vec<u8>>>> {
+        let mut log = Vec::new();
+        for record in read_json_array::<T, _>(reader)? {
+            serde_json::to_writer(&mut log, &record)?;
+            writeln!(log)?;
+        }
+
+        let mut database = Database::new(Cursor::new(log))?;
+        database.reload()?;
+        Ok(database)
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn write_json_array<W: Write>(&self, writer: W) -> io::Result<()> {
+        write_json_array(writer, self.records())
+    }
+}
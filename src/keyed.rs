@@ -0,0 +1,151 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::io::{self, Read, Seek, Write};
+use std::ops::Deref;
+
+use crate::{
+    cache_tag::{CacheTag, DefaultCacheTag},
+    database::Database,
+    position::LogPosition,
+    record::{Record, RecordData, RecordId},
+};
+
+pub type KeyFn<K, T> = dyn Fn(&T) -> K + Send + Sync;
+
+// writes must go through the wrapper so the key index stays in sync, hence no `DerefMut`
+pub struct KeyedDatabase<K, T, S, C = DefaultCacheTag>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    database: Database<T, S, C>,
+    key_fn: Box<KeyFn<K, T>>,
+    keys: HashMap<K, RecordId>,
+    ids: HashMap<RecordId, K>,
+    position: LogPosition,
+}
+
+impl<K, T, S, C> KeyedDatabase<K, T, S, C>
+where
+    K: Eq + Hash + Clone,
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn new<F>(database: Database<T, S, C>, key_fn: F) -> KeyedDatabase<K, T, S, C>
+    where
+        F: Fn(&T) -> K + Send + Sync + 'static,
+    {
+        let mut keyed = KeyedDatabase {
+            database,
+            key_fn: Box::new(key_fn),
+            keys: HashMap::new(),
+            ids: HashMap::new(),
+            position: LogPosition::default(),
+        };
+        keyed.sync_index();
+        keyed
+    }
+
+    pub fn into_inner(self) -> Database<T, S, C> {
+        self.database
+    }
+
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.database.reload()?;
+        self.sync_index();
+        Ok(())
+    }
+
+    fn sync_index(&mut self) {
+        // without history, entries are overwritten where they are, so the index is built anew
+        let changes = match self.database.changes_since(self.position) {
+            Ok(changes) => changes,
+            Err(_) => {
+                self.keys.clear();
+                self.ids.clear();
+                self.database.entries()
+            }
+        };
+        for record in changes {
+            if record.is_unresolved_merge() {
+                continue;
+            }
+
+            let id = record.id();
+            if let Some(key) = self.ids.remove(&id) {
+                if self.keys.get(&key) == Some(&id) {
+                    self.keys.remove(&key);
+                }
+            }
+            if let Some(data) = record.data() {
+                let key = (self.key_fn)(&data.data);
+                self.keys.insert(key.clone(), id);
+                self.ids.insert(id, key);
+            }
+        }
+        self.position = self.database.log_position();
+    }
+
+    pub fn id_for_key(&self, key: &K) -> Option<RecordId> {
+        self.keys.get(key).copied()
+    }
+
+    pub fn get_by_key(&self, key: &K) -> Option<&RecordData<T>> {
+        self.database.get(self.id_for_key(key)?)
+    }
+}
+
+impl<K, T, S, C> KeyedDatabase<K, T, S, C>
+where
+    K: Eq + Hash + Clone,
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // replaces the record with the same key as `data`, or inserts a new one
+    pub fn upsert_by_key(&mut self, data: T) -> io::Result<RecordId> {
+        // pick up records written by other handles before deciding whether the key exists
+        self.reload()?;
+
+        let key = (self.key_fn)(&data);
+        let id = match self.id_for_key(&key) {
+            Some(id) => {
+                self.database.upsert(id, |_| Some(data))?;
+                id
+            }
+            None => self.database.insert(data)?,
+        };
+
+        self.sync_index();
+        Ok(id)
+    }
+
+    pub fn delete_by_key(&mut self, key: &K) -> io::Result<bool> {
+        self.reload()?;
+
+        let id = match self.id_for_key(key) {
+            Some(id) => id,
+            None => return Ok(false),
+        };
+        self.database.delete(id)?;
+
+        self.sync_index();
+        Ok(true)
+    }
+}
+
+impl<K, T, S, C> Deref for KeyedDatabase<K, T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    type Target = Database<T, S, C>;
+
+    fn deref(&self) -> &Database<T, S, C> {
+        &self.database
+    }
+}
@@ -0,0 +1,416 @@
+use lru::LruCache;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::{
+    checksum::{encode_record, verify_checksum},
+    clock::{Clock, Instant, SystemClock},
+    database::{reopen_replaced, Access, AutoReload, OpenOptions},
+    error::Error,
+    header::{parse_header, Header},
+    index_file::{index_path, IndexFile},
+    lock::{FileLock, LockFile},
+    record::{Envelope, Format, Record, RecordData, RecordId},
+};
+
+// only keeps where the latest entry of each record is, reading records when they're asked for;
+// logs with merge records (see `Database::merge`) can't be loaded this way, since resolving a
+// merge takes every entry of the record before it
+pub struct LazyDatabase<T> {
+    stream: BufReader<File>,
+    offset: u64,
+    index: BTreeMap<RecordId, (u64, u64)>,
+    next_record_id: RecordId,
+    lock: Option<FileLock>,
+    // removes the lock file when dropped
+    lock_file: Option<LockFile>,
+    checksums: bool,
+    format: Format,
+    header: Option<Header>,
+    auto_reload: AutoReload,
+    last_reload: Option<Instant>,
+    read_only: bool,
+    path: PathBuf,
+    // where the index is kept between runs, and how much of the log it covered when last written
+    index_file: Option<PathBuf>,
+    indexed_len: u64,
+
+    cache: LruCache<RecordId, Arc<RecordData<T>>>,
+}
+
+impl<T> LazyDatabase<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn open(path: impl AsRef<Path>) -> io::Result<LazyDatabase<T>> {
+        LazyDatabase::open_with_opts(path, OpenOptions::new())
+    }
+
+    pub fn open_with_opts(
+        path: impl AsRef<Path>,
+        opts: OpenOptions,
+    ) -> io::Result<LazyDatabase<T>> {
+        let path = path.as_ref();
+        let lock_file = opts.lock_file(path)?;
+        let file = opts.open_file(path)?;
+        let capacity = NonZeroUsize::new(opts.cache_capacity).unwrap_or(NonZeroUsize::MIN);
+
+        let lock = if opts.lock {
+            Some(FileLock::new(&file, opts.lock_timeout)?)
+        } else {
+            None
+        };
+
+        let mut database = LazyDatabase {
+            lock,
+            lock_file,
+            checksums: opts.checksums,
+            format: opts.format(),
+            header: None,
+            auto_reload: opts.auto_reload,
+            last_reload: None,
+            read_only: opts.read_only,
+            path: path.to_path_buf(),
+            index_file: None,
+            indexed_len: 0,
+            stream: BufReader::new(file),
+            offset: 0,
+            index: BTreeMap::new(),
+            next_record_id: 1,
+            cache: LruCache::new(capacity),
+        };
+
+        if opts.index_file {
+            let index_file = index_path(path);
+            if let Some(index) = IndexFile::load(&index_file, &mut database.stream)? {
+                database.offset = index.len;
+                database.index = index.entries;
+                database.next_record_id = index.next_record_id;
+                database.header = index.header;
+                database.indexed_len = index.len;
+            }
+            database.index_file = Some(index_file);
+        }
+
+        database.reload()?;
+        if opts.header && database.header.is_none() {
+            if database.next_record_id != 1 {
+                return Err(Error::MissingHeader.into());
+            }
+            if !opts.read_only {
+                database.write_header()?;
+            }
+        }
+        database.save_index()?;
+        Ok(database)
+    }
+
+    pub fn close(mut self) -> io::Result<()> {
+        self.save_index()?;
+        if !self.read_only {
+            self.stream.get_ref().sync_all()?;
+        }
+        if let Some(lock) = self.lock.take() {
+            lock.unlock()?;
+        }
+        self.lock_file.take().map_or(Ok(()), LockFile::release)
+    }
+
+    // writes the index file, if there is one and it's behind; this happens on opening and
+    // closing as well
+    pub fn save_index(&mut self) -> io::Result<()> {
+        let path = match &self.index_file {
+            Some(_) if self.read_only || self.indexed_len == self.offset => return Ok(()),
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        let index = IndexFile {
+            len: self.offset,
+            next_record_id: self.next_record_id,
+            header: self.header.clone(),
+            entries: self.index.clone(),
+        };
+        index.save(path, &mut self.stream)?;
+        self.indexed_len = self.offset;
+        Ok(())
+    }
+
+    pub fn reload(&mut self) -> io::Result<()> {
+        self.lock(false)?;
+        let result = self.reload_unlocked();
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    // takes the lock, first moving on to the file now at the path if compaction or a restore
+    // replaced the log, which is then read from the start
+    fn lock(&mut self, exclusive: bool) -> io::Result<()> {
+        if let Some(lock) = &self.lock {
+            if exclusive {
+                lock.lock_exclusive()?;
+            } else {
+                lock.lock_shared()?;
+            }
+        }
+        let result = self.follow_replaced_unlocked(exclusive);
+        if let (Err(_), Some(lock)) = (&result, &self.lock) {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn follow_replaced_unlocked(&mut self, exclusive: bool) -> io::Result<()> {
+        let access = Access {
+            writable: !self.read_only,
+            exclusive,
+        };
+        while let Some((file, lock)) = reopen_replaced(
+            self.stream.get_ref(),
+            &self.path,
+            self.lock.as_ref(),
+            access,
+        )? {
+            if let Some(old) = std::mem::replace(&mut self.lock, lock) {
+                old.unlock()?;
+            }
+            self.stream = BufReader::new(file);
+            self.offset = 0;
+            self.index.clear();
+            self.next_record_id = 1;
+            self.header = None;
+            self.indexed_len = 0;
+            self.cache.clear();
+        }
+        Ok(())
+    }
+
+    fn reload_unlocked(&mut self) -> io::Result<()> {
+        self.stream.seek(SeekFrom::Start(self.offset))?;
+        let mut d =
+            serde_json::Deserializer::from_reader(&mut self.stream).into_iter::<Box<RawValue>>();
+
+        let base = self.offset;
+        let mut start = base;
+        let mut envelopes = Vec::new();
+        while let Some(raw) = d.next().transpose()? {
+            let envelope = Envelope::parse(raw.get(), self.format.names)?;
+            let end = base + d.byte_offset() as u64;
+            envelopes.push((envelope, start, end));
+            start = end;
+        }
+        self.offset = base + d.byte_offset() as u64;
+
+        for (envelope, start, end) in envelopes {
+            self.handle_envelope(envelope, start, end)?;
+        }
+        self.last_reload = Some(Instant::now());
+        Ok(())
+    }
+
+    fn reload_if_due(&mut self) -> io::Result<()> {
+        if self.auto_reload.is_due(self.last_reload) {
+            self.reload()?;
+        }
+        Ok(())
+    }
+
+    fn handle_envelope(&mut self, envelope: Envelope, start: u64, end: u64) -> io::Result<()> {
+        envelope.validate()?;
+        if envelope.header {
+            // only the very first entry of the log may be a header
+            let header = match (self.next_record_id, &self.header) {
+                (1, None) => parse_header(&self.read_raw(start, end - start)?, self.format.names),
+                _ => None,
+            };
+            let header = header.ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "unexpected header record")
+            })?;
+            header.validate()?;
+            self.header = Some(header);
+            return Ok(());
+        }
+
+        if envelope.merge {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "merge records are not supported by lazily loaded databases",
+            ));
+        }
+
+        if envelope.id >= self.next_record_id {
+            self.next_record_id = envelope.id + 1;
+        }
+        self.cache.pop(&envelope.id);
+        if envelope.deleted {
+            self.index.remove(&envelope.id);
+        } else {
+            self.index.insert(envelope.id, (start, end - start));
+        }
+
+        Ok(())
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = RecordId> + '_ {
+        self.index.keys().copied()
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn contains(&self, id: RecordId) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    pub fn get(&mut self, id: RecordId) -> io::Result<Option<Arc<RecordData<T>>>> {
+        self.reload_if_due()?;
+        if let Some(record) = self.cache.get(&id) {
+            return Ok(Some(record.clone()));
+        }
+
+        let (start, len) = match self.index.get(&id) {
+            Some(&range) => range,
+            None => return Ok(None),
+        };
+
+        let raw = self.read_raw(start, len)?;
+        let record: Record<T> = self.format.decode(&raw)?;
+        let record = match record {
+            Record::Upsert(record) => record.data,
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "indexed record is not an upsert",
+                ))
+            }
+        };
+
+        self.cache.put(id, record.clone());
+        Ok(Some(record))
+    }
+
+    pub fn metadata(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    // reads a single entry with its checksum (if any) removed
+    fn read_raw(&mut self, start: u64, len: u64) -> io::Result<String> {
+        self.stream.seek(SeekFrom::Start(start))?;
+        let mut raw = String::new();
+        (&mut self.stream).take(len).read_to_string(&mut raw)?;
+
+        let trimmed = raw.trim_start();
+        let offset = start + (raw.len() - trimmed.len()) as u64;
+        Ok(verify_checksum(trimmed.trim_end(), offset)?.into_owned())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        self.lock(true)?;
+        let result = self.write_header_unlocked();
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn write_header_unlocked(&mut self) -> io::Result<()> {
+        // another process may have initialized the file in the meantime
+        self.reload_unlocked()?;
+        if self.header.is_some() || self.next_record_id != 1 {
+            return Ok(());
+        }
+        if self.stream.seek(SeekFrom::End(0))? != self.offset {
+            return Err(io::Error::other("Expected EOF"));
+        }
+
+        let header = Header::new(Some(SystemClock.now_millis()));
+        let mut buffer = serde_json::to_vec(&header)?;
+        buffer.push(b'\n');
+        self.stream.get_mut().write_all(&buffer)?;
+        self.stream.get_mut().flush()?;
+
+        self.offset += buffer.len() as u64;
+        self.header = Some(header);
+        Ok(())
+    }
+
+    fn write_record(&mut self, record: Record<T>) -> io::Result<()> {
+        self.lock(true)?;
+        let result = self.write_record_unlocked(record);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    fn write_record_unlocked(&mut self, record: Record<T>) -> io::Result<()> {
+        // move to end of file
+        self.reload_unlocked()?;
+        if self.stream.seek(SeekFrom::End(0))? != self.offset {
+            return Err(io::Error::other("Expected EOF"));
+        }
+
+        let mut buffer = Vec::new();
+        encode_record(&record, self.checksums, self.format, &mut buffer)?;
+        self.stream.get_mut().write_all(&buffer)?;
+        self.stream.get_mut().flush()?;
+
+        let start = self.offset;
+        self.offset += buffer.len() as u64;
+        self.handle_envelope(
+            Envelope {
+                id: record.id(),
+                deleted: record.data().is_none(),
+                merge: false,
+                header: false,
+            },
+            start,
+            self.offset,
+        )
+    }
+
+    pub fn insert(&mut self, data: T) -> io::Result<RecordId> {
+        self.lock(true)?;
+        let result = self.insert_unlocked(data);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result
+    }
+
+    // the id is only taken once the record is written, after catching up with other writers
+    fn insert_unlocked(&mut self, data: T) -> io::Result<RecordId> {
+        self.reload_unlocked()?;
+        let id = self.next_record_id;
+        self.write_record_unlocked(Record::upsert(id, data))?;
+        Ok(id)
+    }
+
+    pub fn upsert<F>(&mut self, id: RecordId, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Option<&T>) -> Option<T>,
+    {
+        let existing = self.get(id)?;
+
+        match f(existing.as_deref().map(|record_data| &record_data.data)) {
+            Some(new_data) => self.write_record(Record::upsert(id, new_data))?,
+            None if existing.is_some() => self.write_record(Record::delete(id))?,
+            None => (),
+        }
+
+        Ok(())
+    }
+
+    pub fn delete(&mut self, id: RecordId) -> io::Result<()> {
+        self.write_record(Record::delete(id))
+    }
+}
@@ -1,12 +1,102 @@
+#[cfg(feature = "actix")]
+mod actix;
+mod aggregate;
+mod append_only;
+#[cfg(feature = "arrow")]
+mod arrow;
+#[cfg(feature = "axum")]
+mod axum;
 mod boolean;
 mod cache_tag;
+mod checksum;
+mod clock;
+mod close_marker;
+mod compact;
+mod concatenation;
+mod conflict;
 mod database;
+mod detached;
+mod error;
+mod etag;
+mod filter;
+mod header;
+mod health;
+mod id_allocator;
+mod index_file;
+mod json_array;
+mod keyed;
+mod lazy;
+mod lock;
+mod log_writer;
+#[cfg(feature = "metrics")]
+mod metrics;
+mod mode;
+mod observer;
+#[cfg(feature = "rayon")]
+mod parallel;
+mod position;
+mod raw;
 mod record;
+mod reference;
+#[cfg(feature = "jsonschema")]
+mod schema;
+mod segment;
+mod shared;
+mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod staged;
+mod stats;
+mod storage;
+mod temporary;
+mod text_index;
+mod validation;
+mod verify;
+mod versioned;
+mod view;
+#[cfg(feature = "notify")]
+mod watch;
 
 #[cfg(test)]
 mod tests;
 
+pub use aggregate::*;
+pub use append_only::*;
 pub use boolean::*;
 pub use cache_tag::*;
+pub use clock::*;
+pub use compact::*;
+pub use conflict::*;
 pub use database::*;
+pub use detached::*;
+pub use error::*;
+pub use etag::*;
+pub use filter::*;
+pub use header::*;
+pub use health::*;
+pub use id_allocator::*;
+pub use json_array::*;
+pub use keyed::*;
+pub use lazy::*;
+pub use log_writer::*;
+#[cfg(feature = "metrics")]
+pub use metrics::*;
+pub use mode::*;
+pub use observer::*;
+pub use position::*;
+pub use raw::*;
 pub use record::*;
+pub use reference::*;
+#[cfg(feature = "jsonschema")]
+pub use schema::*;
+pub use segment::*;
+pub use shared::*;
+pub use snapshot::*;
+pub use staged::*;
+pub use stats::*;
+pub use storage::*;
+pub use temporary::*;
+pub use validation::*;
+pub use verify::*;
+pub use versioned::*;
+pub use view::*;
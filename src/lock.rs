@@ -0,0 +1,233 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::{
+    clock::{Clock, Instant, SystemClock},
+    error::Error,
+};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug)]
+pub(crate) struct FileLock {
+    file: File,
+    timeout: Option<Duration>,
+}
+
+impl FileLock {
+    pub fn new(file: &File, timeout: Option<Duration>) -> io::Result<FileLock> {
+        // a duplicated descriptor shares the lock with the original one
+        Ok(FileLock {
+            file: file.try_clone()?,
+            timeout,
+        })
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn lock_shared(&self) -> io::Result<()> {
+        self.acquire(File::lock_shared, File::try_lock_shared)
+    }
+
+    pub fn lock_exclusive(&self) -> io::Result<()> {
+        self.acquire(File::lock, File::try_lock)
+    }
+
+    pub fn unlock(&self) -> io::Result<()> {
+        self.file.unlock()
+    }
+
+    // takes and releases a shared lock, never blocking for longer than a second
+    pub fn probe(&self) -> io::Result<()> {
+        let timeout = self.timeout.unwrap_or(PROBE_TIMEOUT).min(PROBE_TIMEOUT);
+        self.acquire_with_timeout(File::try_lock_shared, timeout)?;
+        self.unlock()
+    }
+
+    fn acquire(
+        &self,
+        lock: fn(&File) -> io::Result<()>,
+        try_lock: fn(&File) -> Result<(), TryLockError>,
+    ) -> io::Result<()> {
+        match self.timeout {
+            Some(timeout) => self.acquire_with_timeout(try_lock, timeout),
+            None => lock(&self.file),
+        }
+    }
+
+    fn acquire_with_timeout(
+        &self,
+        try_lock: fn(&File) -> Result<(), TryLockError>,
+        timeout: Duration,
+    ) -> io::Result<()> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match try_lock(&self.file) {
+                Ok(()) => return Ok(()),
+                Err(TryLockError::Error(err)) => return Err(err),
+                Err(TryLockError::WouldBlock) if Instant::now() >= deadline => {
+                    return Err(locked_error(&self.file));
+                }
+                Err(TryLockError::WouldBlock) => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+    }
+}
+
+pub(crate) fn locked_error(file: &File) -> io::Error {
+    Error::Locked {
+        pid: lock_holder(file),
+        since: None,
+    }
+    .into()
+}
+
+// what's written to a lock file, to tell who is holding it
+#[derive(Serialize, Deserialize, PartialEq)]
+struct LockHolder {
+    pid: u32,
+    since: u64,
+}
+
+// a `<log>.lock` file claiming the log for a single writer, for as long as it exists; unlike
+// `FileLock`, it's held from opening the database until it's dropped, and keeps out writers that
+// don't lock the log themselves
+#[derive(Debug)]
+pub(crate) struct LockFile {
+    path: PathBuf,
+}
+
+impl LockFile {
+    pub fn acquire(log: &Path) -> io::Result<LockFile> {
+        let mut name = log.file_name().unwrap_or_default().to_os_string();
+        name.push(".lock");
+        let path = log.with_file_name(name);
+
+        let holder = LockHolder {
+            pid: std::process::id(),
+            since: SystemClock.now_millis(),
+        };
+        // a stale lock is removed at most once, so two writers can't keep removing each other's
+        let mut removed_stale = false;
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(mut file) => {
+                    let written = serde_json::to_writer(&mut file, &holder)
+                        .map_err(io::Error::from)
+                        .and_then(|()| file.sync_all());
+                    if let Err(err) = written {
+                        let _ = fs::remove_file(&path);
+                        return Err(err);
+                    }
+                    return Ok(LockFile { path });
+                }
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+                Err(err) => return Err(err),
+            }
+
+            // the holder may still be writing the file, in which case it's taken to be alive
+            let existing = match read_holder(&path) {
+                Ok(existing) => existing,
+                // released in the meantime
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            match &existing {
+                Some(existing) if !removed_stale && process_alive(existing.pid) == Some(false) => {
+                    // only if it's still the same stale lock, and not one that replaced it
+                    if read_holder(&path).ok().flatten().as_ref() == Some(existing) {
+                        match fs::remove_file(&path) {
+                            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                            _ => (),
+                        }
+                    }
+                    removed_stale = true;
+                }
+                _ => {
+                    return Err(Error::Locked {
+                        pid: existing.as_ref().map(|holder| holder.pid),
+                        since: existing.as_ref().map(|holder| holder.since),
+                    }
+                    .into());
+                }
+            }
+        }
+    }
+
+    // like dropping it, but reports whether the lock file could be removed
+    pub fn release(mut self) -> io::Result<()> {
+        fs::remove_file(std::mem::take(&mut self.path))
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        if !self.path.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn read_holder(path: &Path) -> io::Result<Option<LockHolder>> {
+    Ok(serde_json::from_slice(&fs::read(path)?).ok())
+}
+
+// `None` where that can't be told
+#[cfg(target_os = "linux")]
+fn process_alive(pid: u32) -> Option<bool> {
+    Some(Path::new("/proc").join(pid.to_string()).exists())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_alive(_pid: u32) -> Option<bool> {
+    None
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn lock_holder(file: &File) -> Option<u32> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = file.metadata().ok()?;
+    let locks = std::fs::read_to_string("/proc/locks").ok()?;
+    find_lock_holder(&locks, metadata.dev(), metadata.ino(), std::process::id())
+}
+
+// finds who else holds a lock on the file with inode `inode` on device `dev`, where inode numbers
+// are only unique within a device
+#[cfg(target_os = "linux")]
+pub(crate) fn find_lock_holder(locks: &str, dev: u64, inode: u64, own_pid: u32) -> Option<u32> {
+    // the same split of a `dev_t` into major and minor numbers as glibc's
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & !0xfff);
+    let minor = (dev & 0xff) | ((dev >> 12) & !0xff);
+
+    // lines look like `1: FLOCK  ADVISORY  WRITE 1234 08:01:5678 0 EOF`, with `->` marking waiters
+    // and the device numbers in hex
+    locks.lines().find_map(|line| {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        if fields.get(1) == Some(&"->") || fields.get(1) != Some(&"FLOCK") {
+            return None;
+        }
+        let pid = fields.get(4)?.parse::<u32>().ok()?;
+        let mut file = fields.get(5)?.split(':');
+        let lock_major = u64::from_str_radix(file.next()?, 16).ok()?;
+        let lock_minor = u64::from_str_radix(file.next()?, 16).ok()?;
+        let lock_inode = file.next()?.parse::<u64>().ok()?;
+        (lock_major == major && lock_minor == minor && lock_inode == inode && pid != own_pid)
+            .then_some(pid)
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn lock_holder(_file: &File) -> Option<u32> {
+    None
+}
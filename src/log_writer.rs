@@ -0,0 +1,134 @@
+use serde::Serialize;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{
+    checksum::encode_record,
+    lock::FileLock,
+    record::{FieldNames, Format, Layout, Record, RecordId},
+};
+
+pub struct LogWriter<T> {
+    file: File,
+    lock: Option<FileLock>,
+    sync: bool,
+    checksums: bool,
+    format: Format,
+    buffer: Vec<u8>,
+    _marker: PhantomData<fn(T)>,
+}
+
+impl<T> LogWriter<T>
+where
+    T: Serialize,
+{
+    pub fn open(path: impl AsRef<Path>) -> io::Result<LogWriter<T>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?;
+
+        LogWriter::new(file)
+    }
+
+    pub fn new(file: File) -> io::Result<LogWriter<T>> {
+        Ok(LogWriter {
+            lock: Some(FileLock::new(&file, None)?),
+            file,
+            sync: false,
+            checksums: false,
+            format: Format::default(),
+            buffer: Vec::new(),
+            _marker: PhantomData,
+        })
+    }
+
+    pub fn lock(mut self, lock: bool) -> io::Result<Self> {
+        self.lock = if lock {
+            Some(FileLock::new(&self.file, None)?)
+        } else {
+            None
+        };
+        Ok(self)
+    }
+
+    pub fn lock_timeout(mut self, lock_timeout: Option<Duration>) -> io::Result<Self> {
+        self.lock = Some(FileLock::new(&self.file, lock_timeout)?);
+        Ok(self)
+    }
+
+    pub fn sync(mut self, sync: bool) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    pub fn checksums(mut self, checksums: bool) -> Self {
+        self.checksums = checksums;
+        self
+    }
+
+    pub fn field_names(mut self, id: &'static str, deleted: &'static str) -> Self {
+        self.format.names = FieldNames::new(id, deleted);
+        self
+    }
+
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.format.layout = layout;
+        self
+    }
+
+    pub fn upsert(&mut self, id: RecordId, data: T) -> io::Result<()> {
+        self.append(&Record::upsert(id, data))
+    }
+
+    pub fn delete(&mut self, id: RecordId) -> io::Result<()> {
+        self.append(&Record::delete(id))
+    }
+
+    pub fn append(&mut self, record: &Record<T>) -> io::Result<()> {
+        self.append_all(Some(record))
+    }
+
+    pub fn append_all<'a>(
+        &mut self,
+        records: impl IntoIterator<Item = &'a Record<T>>,
+    ) -> io::Result<()>
+    where
+        T: 'a,
+    {
+        // serialize everything up front, so the file is only touched by a single write
+        self.buffer.clear();
+        for record in records {
+            encode_record(record, self.checksums, self.format, &mut self.buffer)?;
+        }
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(lock) = &self.lock {
+            lock.lock_exclusive()?;
+        }
+        let result = self.file.write_all(&self.buffer);
+        if let Some(lock) = &self.lock {
+            lock.unlock()?;
+        }
+        result?;
+
+        if self.sync {
+            self.file.sync_data()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn sync_all(&self) -> io::Result<()> {
+        self.file.sync_all()
+    }
+
+    pub fn into_inner(self) -> File {
+        self.file
+    }
+}
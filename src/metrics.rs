@@ -0,0 +1,94 @@
+use metrics::{counter, gauge, histogram, Counter, Gauge, Histogram};
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{self, Read, Seek};
+use std::time::Duration;
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    observer::RecordObserver,
+    record::{Record, RecordData},
+};
+
+// metrics for one database, labelled with `database = name`; the handles are registered with the
+// recorder when this is created, so install the recorder (e.g. a Prometheus exporter) first
+#[derive(Clone)]
+pub struct Metrics {
+    records_loaded: Counter,
+    live_records: Gauge,
+    writes: Counter,
+    write_duration: Histogram,
+    reload_duration: Histogram,
+    file_size: Gauge,
+    dead_bytes_ratio: Gauge,
+}
+
+impl Metrics {
+    pub fn new(name: impl Into<String>) -> Metrics {
+        let name = name.into();
+        Metrics {
+            records_loaded: counter!("jsondb_records_loaded_total", "database" => name.clone()),
+            live_records: gauge!("jsondb_live_records", "database" => name.clone()),
+            writes: counter!("jsondb_writes_total", "database" => name.clone()),
+            write_duration: histogram!("jsondb_write_duration_seconds", "database" => name.clone()),
+            reload_duration: histogram!("jsondb_reload_duration_seconds", "database" => name.clone()),
+            file_size: gauge!("jsondb_file_size_bytes", "database" => name.clone()),
+            dead_bytes_ratio: gauge!("jsondb_dead_bytes_ratio", "database" => name),
+        }
+    }
+
+    pub(crate) fn record_reload(&self, duration: Duration, file_size: u64) {
+        self.reload_duration.record(duration);
+        self.file_size.set(file_size as f64);
+    }
+
+    pub(crate) fn record_write(&self, duration: Duration, file_size: u64) {
+        self.writes.increment(1);
+        self.write_duration.record(duration);
+        self.file_size.set(file_size as f64);
+    }
+}
+
+impl<T> RecordObserver<T> for Metrics {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>) {
+        self.records_loaded.increment(1);
+        if record.is_unresolved_merge() {
+            return;
+        }
+        match (previous.is_some(), record.data().is_some()) {
+            (false, true) => self.live_records.increment(1.0),
+            (true, false) => self.live_records.decrement(1.0),
+            _ => (),
+        }
+    }
+
+    fn reset(&mut self) {
+        self.live_records.set(0.0);
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // records that are already loaded count as loaded
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.set_metrics(metrics.clone());
+        self.add_observer(metrics);
+        self
+    }
+
+    // the dead-byte ratio needs a pass over all records, so it's only updated when this is called,
+    // e.g. periodically or before the metrics are scraped
+    pub fn update_metrics(&self) -> io::Result<()> {
+        if let Some(metrics) = self.metrics() {
+            let stats = self.stats()?;
+            metrics.live_records.set(stats.live_records as f64);
+            metrics.file_size.set(stats.log_bytes as f64);
+            metrics.dead_bytes_ratio.set(stats.dead_bytes_ratio());
+        }
+        Ok(())
+    }
+}
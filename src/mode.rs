@@ -0,0 +1,8 @@
+// whether a `Database` can be written to, checked at compile time: the methods that write are only
+// implemented for `ReadWrite`, which is the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReadWrite;
+
+// see `Database::open_read_only` and `Database::into_read_only`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ReadOnly;
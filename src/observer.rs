@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{
+    record::{Record, RecordData},
+    text_index::TextIndex,
+    view::Views,
+};
+
+// fed every record as it's loaded or written, along with the version it replaces, for keeping
+// derived state like indexes or counters up to date; any number of them can be added to a database
+pub trait RecordObserver<T>: Send + Sync {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>);
+
+    // forgets everything observed so far, before all loaded records are observed again, e.g. after
+    // compaction or when loaded records are dropped
+    fn reset(&mut self);
+}
+
+// lets the observer's state be read while the database owns it
+impl<T, O: RecordObserver<T>> RecordObserver<T> for Arc<Mutex<O>> {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>) {
+        // a panic elsewhere while holding the lock doesn't leave the observer half-updated
+        let mut observer = self.lock().unwrap_or_else(PoisonError::into_inner);
+        observer.observe(record, previous);
+    }
+
+    fn reset(&mut self) {
+        self.lock().unwrap_or_else(PoisonError::into_inner).reset();
+    }
+}
+
+// everything a database feeds records to besides its cache tag
+pub(crate) struct Observers<T> {
+    pub custom: Vec<Box<dyn RecordObserver<T>>>,
+    pub views: Views<T>,
+    pub text_index: Option<TextIndex<T>>,
+}
+
+impl<T> Observers<T> {
+    pub fn new() -> Observers<T> {
+        Observers {
+            custom: Vec::new(),
+            views: Views::new(),
+            text_index: None,
+        }
+    }
+}
+
+impl<T> RecordObserver<T> for Observers<T> {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>) {
+        for observer in &mut self.custom {
+            observer.observe(record, previous);
+        }
+        self.views.observe(record, previous);
+        if let Some(text_index) = &mut self.text_index {
+            text_index.observe(record, previous);
+        }
+    }
+
+    fn reset(&mut self) {
+        for observer in &mut self.custom {
+            observer.reset();
+        }
+        self.views.reset();
+        if let Some(text_index) = &mut self.text_index {
+            text_index.reset();
+        }
+    }
+}
+
+// observes `records` from the start, as if they were loaded one by one
+pub(crate) fn replay<T>(observer: &mut dyn RecordObserver<T>, records: &[Record<T>]) {
+    let mut latest = HashMap::new();
+    for record in records {
+        let previous = latest.get(&record.id()).copied().flatten();
+        observer.observe(record, previous);
+        if !record.is_unresolved_merge() {
+            latest.insert(record.id(), record.data());
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use rayon::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use crate::{
+    cache_tag::CacheTag,
+    checksum::verify_checksum,
+    database::{Database, OpenOptions},
+    record::{Format, Record},
+    stats::ReloadSummary,
+};
+
+// how much of the log is read into memory at a time
+const BATCH_SIZE: u64 = 64 << 20;
+
+// each chunk runs on its own, to the end of the line this far in
+const CHUNK_SIZE: usize = 1 << 20;
+
+enum Decoded<T> {
+    Record(Record<T>),
+    // may be the header, so it's left to `decode_entry`
+    Raw(Box<RawValue>),
+}
+
+// decoded entries and where they start and end, up to the first one that doesn't decode; returns
+// whether the whole chunk could be decoded
+fn decode_chunk<T: DeserializeOwned>(
+    offset: u64,
+    chunk: &[u8],
+    format: Format,
+    first: bool,
+) -> (Vec<(u64, u64, Decoded<T>)>, bool) {
+    let mut entries = Vec::new();
+    let mut d = serde_json::Deserializer::from_slice(chunk).into_iter::<Box<RawValue>>();
+    while let Some(raw) = d.next() {
+        let raw = match raw {
+            Ok(raw) => raw,
+            Err(_) => return (entries, false),
+        };
+        let end = offset + d.byte_offset() as u64;
+        let start = end - raw.get().len() as u64;
+        if first && entries.is_empty() {
+            entries.push((start, end, Decoded::Raw(raw)));
+            continue;
+        }
+
+        let decoded = verify_checksum(raw.get(), start)
+            .ok()
+            .and_then(|raw| format.decode(&raw).ok());
+        match decoded {
+            Some(record) => entries.push((start, end, Decoded::Record(record))),
+            None => return (entries, false),
+        }
+    }
+    (entries, true)
+}
+
+// splits complete lines into chunks of at least `CHUNK_SIZE` bytes, along with their offsets
+fn split_lines(bytes: &[u8]) -> Vec<(usize, &[u8])> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    while start < bytes.len() {
+        let from = (start + CHUNK_SIZE).min(bytes.len());
+        let end = bytes[from..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(bytes.len(), |i| from + i + 1);
+        chunks.push((start, &bytes[start..end]));
+        start = end;
+    }
+    chunks
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned + Send + Sync,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // like `reload`, but decodes new entries on the rayon thread pool before applying them in
+    // order; mostly worth it with a lot to catch up on, such as when first opening a large log
+    pub fn reload_parallel(&mut self) -> io::Result<ReloadSummary> {
+        self.reload_with(Database::read_parallel)
+    }
+
+    fn read_parallel(&mut self, summary: &mut ReloadSummary) -> io::Result<()> {
+        let format = self.format();
+        let start = self.log_position().offset;
+        let mut pos = start;
+        'batches: loop {
+            let mut batch = Vec::new();
+            let stream = self.stream_mut();
+            stream.seek(SeekFrom::Start(pos))?;
+            stream.take(BATCH_SIZE).read_to_end(&mut batch)?;
+
+            // a partially written entry, or a line longer than a batch, is left for below
+            let len = match batch.iter().rposition(|&b| b == b'\n') {
+                Some(i) => i + 1,
+                None => break,
+            };
+            let decoded = split_lines(&batch[..len])
+                .into_par_iter()
+                .map(|(offset, chunk)| {
+                    let first = pos == start && offset == 0;
+                    decode_chunk::<T>(pos + offset as u64, chunk, format, first)
+                })
+                .collect::<Vec<_>>();
+
+            for (entries, complete) in decoded {
+                for (start, end, entry) in entries {
+                    let record = match entry {
+                        Decoded::Record(record) => Some(record),
+                        Decoded::Raw(raw) => self.decode_entry(raw.get(), end)?,
+                    };
+                    self.set_offset(end);
+                    if let Some(record) = record {
+                        self.apply_loaded(record, (start, end), summary)?;
+                    }
+                }
+                if !complete {
+                    break 'batches;
+                }
+            }
+            pos += len as u64;
+        }
+
+        // picks up where decoding stopped, so anything that didn't decode fails just like it
+        // would in `reload`
+        self.read_new_records(summary)
+    }
+}
+
+impl OpenOptions {
+    // like `open`, but loads the existing log with `reload_parallel`
+    pub fn open_parallel<T, P>(self, path: P) -> io::Result<Database<T, File>>
+    where
+        T: Serialize + DeserializeOwned + Send + Sync,
+        P: AsRef<Path>,
+    {
+        Database::open_with_reload(path.as_ref(), self, Database::reload_parallel)
+    }
+}
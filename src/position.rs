@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(
+    Copy, Clone, Debug, Default, Eq, PartialEq, Ord, PartialOrd, Hash, Serialize, Deserialize,
+)]
+pub struct LogPosition {
+    pub records: usize,
+    pub offset: u64,
+}
+
+impl LogPosition {
+    pub const fn new(records: usize, offset: u64) -> LogPosition {
+        LogPosition { records, offset }
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::value::RawValue;
+use std::borrow::Cow;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+
+use crate::{
+    cache_tag::CacheTag,
+    checksum::verify_checksum,
+    database::Database,
+    record::{Envelope, FieldNames, Record, RecordId},
+};
+
+// calls `f` with every entry of the log in `reader`, in order and including deletes and merges,
+// without keeping any of them around; checksums are verified and stripped, and the header skipped
+pub fn for_each_raw<R, F>(reader: R, f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(RecordId, &RawValue),
+{
+    for_each_raw_with(reader, FieldNames::DEFAULT, f)
+}
+
+pub(crate) fn for_each_raw_with<R, F>(reader: R, names: FieldNames, mut f: F) -> io::Result<()>
+where
+    R: Read,
+    F: FnMut(RecordId, &RawValue),
+{
+    let mut d =
+        serde_json::Deserializer::from_reader(BufReader::new(reader)).into_iter::<Box<RawValue>>();
+    let mut first = true;
+    while let Some(raw) = d.next().transpose()? {
+        let raw = match verify_checksum(raw.get(), d.byte_offset() as u64 - raw.get().len() as u64)?
+        {
+            Cow::Borrowed(_) => raw,
+            Cow::Owned(body) => RawValue::from_string(body)?,
+        };
+
+        let envelope = Envelope::parse(raw.get(), names)?;
+        if !(first && envelope.header) {
+            envelope.validate()?;
+            f(envelope.id, &raw);
+        }
+        first = false;
+    }
+    Ok(())
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // walks the log as far as it has been loaded, reading it from the stream again
+    pub fn for_each_raw<F>(&mut self, f: F) -> io::Result<()>
+    where
+        F: FnMut(RecordId, &RawValue),
+    {
+        let offset = self.log_position().offset;
+        let names = self.field_names();
+        let stream = self.stream_mut();
+        stream.seek(SeekFrom::Start(0))?;
+        for_each_raw_with(stream.by_ref().take(offset), names, f)
+    }
+}
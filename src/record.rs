@@ -1,5 +1,9 @@
-use serde::{Deserialize, Serialize};
+use serde::de::{self, DeserializeOwned, Deserializer, IgnoredAny};
+use serde::{ser, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io;
 use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
 
 use crate::boolean::{False, True};
 
@@ -8,35 +12,108 @@ pub type RecordId = u32;
 #[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Record<T> {
+    // merge records must come first, since an upsert of a map payload would also accept them
+    Merge(MergeRecord<T>),
     Upsert(UpsertRecord<T>),
     Delete(DeleteRecord),
 }
 
 impl<T> Record<T> {
-    pub const fn upsert(id: RecordId, data: T) -> Record<T> {
+    pub fn upsert(id: RecordId, data: T) -> Record<T> {
         Record::Upsert(UpsertRecord {
             deleted: False,
-            data: RecordData { id, data },
+            meta: None,
+            data: Arc::new(RecordData { id, data }),
         })
     }
 
     pub const fn delete(id: RecordId) -> Record<T> {
-        Record::Delete(DeleteRecord { id, deleted: True })
+        Record::Delete(DeleteRecord {
+            id,
+            deleted: True,
+            meta: None,
+        })
+    }
+
+    pub const fn merge(id: RecordId, operand: Value) -> Record<T> {
+        Record::Merge(MergeRecord {
+            id,
+            operand,
+            meta: None,
+            resolved: None,
+        })
+    }
+
+    pub fn with_meta(mut self, meta: RecordMeta) -> Record<T> {
+        match &mut self {
+            Record::Merge(record) => record.meta = Some(meta),
+            Record::Upsert(record) => record.meta = Some(meta),
+            Record::Delete(record) => record.meta = Some(meta),
+        }
+        self
     }
 
     pub fn id(&self) -> RecordId {
         match self {
+            Record::Merge(record) => record.id(),
             Record::Upsert(record) => record.id(),
             Record::Delete(record) => record.id(),
         }
     }
 
+    // only for records that were just decoded, whose payload isn't shared yet
+    pub(crate) fn set_id(&mut self, id: RecordId) {
+        match self {
+            Record::Merge(record) => record.id = id,
+            Record::Upsert(record) => {
+                Arc::get_mut(&mut record.data)
+                    .expect("payload of a decoded record")
+                    .id = id
+            }
+            Record::Delete(record) => record.id = id,
+        }
+    }
+
     pub fn data(&self) -> Option<&RecordData<T>> {
+        self.shared_data().map(|data| &**data)
+    }
+
+    // the payload as it's stored, which can be held on to without copying it
+    pub fn shared_data(&self) -> Option<&Arc<RecordData<T>>> {
         match self {
-            Record::Upsert(UpsertRecord { data, .. }) => Some(&data),
+            Record::Merge(MergeRecord { resolved, .. }) => resolved.as_ref(),
+            Record::Upsert(UpsertRecord { data, .. }) => Some(data),
             Record::Delete(_) => None,
         }
     }
+
+    pub fn meta(&self) -> Option<&RecordMeta> {
+        match self {
+            Record::Merge(record) => record.meta.as_ref(),
+            Record::Upsert(record) => record.meta.as_ref(),
+            Record::Delete(record) => record.meta.as_ref(),
+        }
+    }
+
+    pub fn is_unresolved_merge(&self) -> bool {
+        matches!(self, Record::Merge(MergeRecord { resolved: None, .. }))
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RecordMeta {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub updated_at: Option<u64>,
+    #[serde(flatten)]
+    pub context: Map<String, Value>,
+}
+
+impl RecordMeta {
+    pub fn is_empty(&self) -> bool {
+        self.created_at.is_none() && self.updated_at.is_none() && self.context.is_empty()
+    }
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
@@ -64,8 +141,10 @@ impl<T> DerefMut for RecordData<T> {
 pub struct UpsertRecord<T> {
     #[serde(rename = "deleted", default, skip_serializing)]
     pub deleted: False,
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<RecordMeta>,
     #[serde(flatten)]
-    pub data: RecordData<T>,
+    pub data: Arc<RecordData<T>>,
 }
 
 impl<T> UpsertRecord<T> {
@@ -78,6 +157,8 @@ impl<T> UpsertRecord<T> {
 pub struct DeleteRecord {
     pub id: RecordId,
     pub deleted: True,
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<RecordMeta>,
 }
 
 impl DeleteRecord {
@@ -85,3 +166,245 @@ impl DeleteRecord {
         self.id
     }
 }
+
+#[derive(Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(bound(serialize = "", deserialize = ""))]
+pub struct MergeRecord<T> {
+    pub id: RecordId,
+    #[serde(rename = "_merge")]
+    pub operand: Value,
+    #[serde(rename = "_meta", default, skip_serializing_if = "Option::is_none")]
+    pub meta: Option<RecordMeta>,
+    #[serde(skip)]
+    pub resolved: Option<Arc<RecordData<T>>>,
+}
+
+impl<T> MergeRecord<T> {
+    pub fn id(&self) -> RecordId {
+        self.id
+    }
+}
+
+// the keys of the id and tombstone properties, which can be changed for payloads that have fields
+// of these names themselves
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct FieldNames {
+    pub id: &'static str,
+    pub deleted: &'static str,
+}
+
+impl FieldNames {
+    pub const DEFAULT: FieldNames = FieldNames::new("id", "deleted");
+
+    pub const fn new(id: &'static str, deleted: &'static str) -> FieldNames {
+        FieldNames { id, deleted }
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == FieldNames::DEFAULT
+    }
+}
+
+impl Default for FieldNames {
+    fn default() -> FieldNames {
+        FieldNames::DEFAULT
+    }
+}
+
+// how the payload is stored in a log entry
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Layout {
+    // the payload's fields sit next to the reserved properties; payloads that aren't objects can't
+    // be flattened, so they are nested anyway
+    #[default]
+    Flat,
+    // the payload is stored under `data`, so it can be any value; flat entries can still be read
+    Nested,
+}
+
+// everything that decides how records are written to and read from the log
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub(crate) struct Format {
+    pub names: FieldNames,
+    pub layout: Layout,
+}
+
+impl Format {
+    pub fn is_default(&self) -> bool {
+        *self == Format::default()
+    }
+
+    // the serde derives on `Record` only know the default format and can only flatten objects, so
+    // anything else goes through a map
+    pub fn encode<T: Serialize>(&self, record: &Record<T>) -> serde_json::Result<Value> {
+        let names = self.names;
+        let mut entry = Map::new();
+        match (record, self.layout) {
+            (Record::Upsert(record), Layout::Flat) => {
+                match serde_json::to_value(&record.data.data)? {
+                    Value::Object(data) => entry = data,
+                    data => {
+                        entry.insert("data".to_string(), data);
+                    }
+                }
+                for key in &[names.id, names.deleted, "_merge", "_meta"] {
+                    if entry.contains_key(*key) {
+                        return Err(ser::Error::custom(format!(
+                            "payload has a reserved field `{}`",
+                            key
+                        )));
+                    }
+                }
+            }
+            (Record::Upsert(record), Layout::Nested) => {
+                entry.insert("data".to_string(), serde_json::to_value(&record.data.data)?);
+            }
+            (Record::Delete(_), _) => {
+                entry.insert(names.deleted.to_string(), Value::Bool(true));
+            }
+            (Record::Merge(record), _) => {
+                entry.insert("_merge".to_string(), record.operand.clone());
+            }
+        }
+        entry.insert(names.id.to_string(), record.id().into());
+        if let Some(meta) = record.meta() {
+            entry.insert("_meta".to_string(), serde_json::to_value(meta)?);
+        }
+        Ok(Value::Object(entry))
+    }
+
+    pub fn decode<T: DeserializeOwned>(&self, raw: &str) -> serde_json::Result<Record<T>> {
+        if self.is_default() {
+            return serde_json::from_str(raw).or_else(|err| self.decode_map(raw).map_err(|_| err));
+        }
+        self.decode_map(raw)
+    }
+
+    fn decode_map<T: DeserializeOwned>(&self, raw: &str) -> serde_json::Result<Record<T>> {
+        let names = self.names;
+        let mut entry: Map<String, Value> = serde_json::from_str(raw)?;
+        entry.remove("_crc");
+        let id = match entry.remove(names.id) {
+            Some(id) => RecordId::deserialize(id)?,
+            None => return Err(de::Error::missing_field(names.id)),
+        };
+        let deleted = entry
+            .remove(names.deleted)
+            .map(bool::deserialize)
+            .transpose()?;
+        let meta = entry
+            .remove("_meta")
+            .map(RecordMeta::deserialize)
+            .transpose()?;
+
+        let record = if let Some(operand) = entry.remove("_merge") {
+            Record::Merge(MergeRecord {
+                id,
+                operand,
+                meta,
+                resolved: None,
+            })
+        } else if deleted == Some(true) {
+            Record::Delete(DeleteRecord {
+                id,
+                deleted: True,
+                meta,
+            })
+        } else {
+            // an entry is nested if `data` is all that's left besides the reserved properties, but in
+            // a flat layout that may also be an object payload with a single `data` field
+            let nested = match entry.len() {
+                1 => entry.remove("data"),
+                _ => None,
+            };
+            let data = match (nested, self.layout) {
+                (Some(data), Layout::Nested) => T::deserialize(data)?,
+                (Some(data), Layout::Flat) => {
+                    let mut flat = Map::new();
+                    flat.insert("data".to_string(), data.clone());
+                    T::deserialize(Value::Object(flat)).or_else(|_| T::deserialize(data))?
+                }
+                (None, _) => T::deserialize(Value::Object(entry))?,
+            };
+            Record::Upsert(UpsertRecord {
+                deleted: False,
+                meta,
+                data: Arc::new(RecordData { id, data }),
+            })
+        };
+        Ok(record)
+    }
+}
+
+// only the reserved properties of a record, for code paths that don't need the payload
+#[derive(Deserialize)]
+pub(crate) struct Envelope {
+    #[serde(default)]
+    pub id: RecordId,
+    #[serde(default)]
+    pub deleted: bool,
+    #[serde(rename = "_merge", default, deserialize_with = "is_present")]
+    pub merge: bool,
+    #[serde(rename = "jsondb", default, deserialize_with = "is_present")]
+    pub header: bool,
+}
+
+impl Envelope {
+    // like `parse_header`, an entry with an id is a record, even one with a `jsondb` field
+    pub fn parse(raw: &str, names: FieldNames) -> serde_json::Result<Envelope> {
+        if names.is_default() {
+            let mut envelope: Envelope = serde_json::from_str(raw)?;
+            envelope.header &= envelope.id == 0;
+            return Ok(envelope);
+        }
+
+        let entry: Map<String, Value> = serde_json::from_str(raw)?;
+        let id = entry.get(names.id).map(RecordId::deserialize).transpose()?;
+        let deleted = entry
+            .get(names.deleted)
+            .map(bool::deserialize)
+            .transpose()?;
+        Ok(Envelope {
+            id: id.unwrap_or(0),
+            deleted: deleted.unwrap_or(false),
+            merge: entry.contains_key("_merge"),
+            header: id.is_none() && entry.contains_key("jsondb"),
+        })
+    }
+
+    pub fn validate(&self) -> io::Result<()> {
+        if !self.header && self.id == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "record is missing an id",
+            ));
+        }
+        Ok(())
+    }
+}
+
+// the serde derives write a flat payload's fields next to the reserved properties without
+// checking them, so a payload field that clashes with one only shows in the encoded entry
+#[derive(Deserialize)]
+struct Reserved {
+    #[serde(rename = "_merge", default, deserialize_with = "is_present")]
+    merge: bool,
+    #[serde(rename = "_meta", default, deserialize_with = "is_present")]
+    meta: bool,
+}
+
+// returns the reserved property an encoded upsert has that isn't the record's own
+pub(crate) fn reserved_clash<T>(record: &Record<T>, encoded: &[u8]) -> Option<&'static str> {
+    match serde_json::from_slice::<Reserved>(encoded) {
+        // written twice, by both the record and its payload
+        Err(_) => Some("_meta"),
+        // an upsert with it would be read back as a merge
+        Ok(reserved) if reserved.merge => Some("_merge"),
+        Ok(reserved) if reserved.meta && record.meta().is_none() => Some("_meta"),
+        Ok(_) => None,
+    }
+}
+
+fn is_present<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    IgnoredAny::deserialize(deserializer).map(|_| true)
+}
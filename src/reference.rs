@@ -0,0 +1,176 @@
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, Write};
+use std::marker::PhantomData;
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    error::Error,
+    record::{Record, RecordData, RecordId},
+};
+
+// the id of a record of `T`, usually in another database; serialized as the bare id
+pub struct Ref<T> {
+    id: RecordId,
+    target: PhantomData<fn() -> T>,
+}
+
+impl<T> Ref<T> {
+    pub const fn new(id: RecordId) -> Ref<T> {
+        Ref {
+            id,
+            target: PhantomData,
+        }
+    }
+
+    pub const fn id(&self) -> RecordId {
+        self.id
+    }
+}
+
+// implemented by hand, so `T` doesn't need to implement any of these itself
+impl<T> Clone for Ref<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Ref<T> {}
+
+impl<T> fmt::Debug for Ref<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Ref").field(&self.id).finish()
+    }
+}
+
+impl<T> PartialEq for Ref<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for Ref<T> {}
+
+impl<T> PartialOrd for Ref<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Ref<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.id.cmp(&other.id)
+    }
+}
+
+impl<T> Hash for Ref<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+impl<T> From<RecordId> for Ref<T> {
+    fn from(id: RecordId) -> Ref<T> {
+        Ref::new(id)
+    }
+}
+
+impl<T> Serialize for Ref<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.id.serialize(serializer)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Ref<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        RecordId::deserialize(deserializer).map(Ref::new)
+    }
+}
+
+// records that point at records of `T`, e.g. orders referring to their customer
+pub trait References<T> {
+    fn refs(&self) -> Vec<Ref<T>>;
+}
+
+// something holding references to records of `T`, checked before deleting them
+pub trait ReferenceCheck<T> {
+    // ids of the live records that refer to `target`
+    fn referrers(&self, target: RecordId) -> Vec<RecordId>;
+}
+
+impl<T, U, S, C, M> ReferenceCheck<T> for Database<U, S, C, M>
+where
+    U: Serialize + DeserializeOwned + References<T>,
+    S: Read + Seek,
+    C: CacheTag<Record<U>>,
+{
+    fn referrers(&self, target: RecordId) -> Vec<RecordId> {
+        self.records()
+            .filter(|record| record.data.refs().contains(&Ref::new(target)))
+            .map(|record| record.id)
+            .collect()
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn resolve(&self, target: &Ref<T>) -> Option<&RecordData<T>> {
+        self.get(target.id())
+    }
+
+    // references that point at records missing from `targets`, as (referring id, reference) pairs
+    pub fn dangling_refs<U, S2, C2, M2>(
+        &self,
+        targets: &Database<U, S2, C2, M2>,
+    ) -> Vec<(RecordId, Ref<U>)>
+    where
+        T: References<U>,
+        U: Serialize + DeserializeOwned,
+        S2: Read + Seek,
+        C2: CacheTag<Record<U>>,
+    {
+        self.records()
+            .flat_map(|record| {
+                let id = record.id;
+                record
+                    .data
+                    .refs()
+                    .into_iter()
+                    .filter(|target| targets.resolve(target).is_none())
+                    .map(move |target| (id, target))
+            })
+            .collect()
+    }
+}
+
+impl<T, S, C> Database<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // fails with `Error::StillReferenced` if any of `referrers` refers to the record, as of their
+    // last reload
+    pub fn delete_unreferenced(
+        &mut self,
+        id: RecordId,
+        referrers: &[&dyn ReferenceCheck<T>],
+    ) -> io::Result<()> {
+        let mut referenced_by = referrers
+            .iter()
+            .flat_map(|referrer| referrer.referrers(id))
+            .collect::<Vec<_>>();
+        if !referenced_by.is_empty() {
+            referenced_by.sort_unstable();
+            return Err(Error::StillReferenced { id, referenced_by }.into());
+        }
+        self.delete(id)
+    }
+}
@@ -0,0 +1,69 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::io::{self, Read, Seek};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{cache_tag::CacheTag, database::Database, record::Record, validation::ValidationError};
+
+// a compiled JSON Schema, which records are checked against without their reserved fields
+#[derive(Clone)]
+pub struct Schema {
+    validator: Arc<jsonschema::Validator>,
+}
+
+impl Schema {
+    pub fn new(schema: &Value) -> io::Result<Schema> {
+        let validator = jsonschema::validator_for(schema).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid schema: {err}"),
+            )
+        })?;
+        Ok(Schema {
+            validator: Arc::new(validator),
+        })
+    }
+
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Schema> {
+        let schema = serde_json::from_slice(&fs::read(path)?)?;
+        Schema::new(&schema)
+    }
+
+    // every violation is reported, joined into a single message
+    pub fn check(&self, value: &Value) -> Result<(), ValidationError> {
+        let errors = self
+            .validator
+            .iter_errors(value)
+            .map(|err| match err.instance_path().to_string() {
+                path if path.is_empty() => err.to_string(),
+                path => format!("{path}: {err}"),
+            })
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationError::new(errors.join("; ")))
+        }
+    }
+
+    pub fn check_data<T: Serialize>(&self, data: &T) -> Result<(), ValidationError> {
+        let value =
+            serde_json::to_value(data).map_err(|err| ValidationError::new(err.to_string()))?;
+        self.check(&value)
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // replaces any validator set with `set_validator`
+    pub fn set_schema(&mut self, schema: Schema) {
+        self.set_validator(move |data| schema.check_data(data));
+    }
+}
@@ -0,0 +1,196 @@
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// a log split over numbered segment files (`db.json.0001`, `db.json.0002`, ...), presented as a
+// single stream; only the newest segment is ever appended to
+#[derive(Debug)]
+pub struct SegmentedFile {
+    path: PathBuf,
+    max_segment_size: u64,
+    read_only: bool,
+    segments: Vec<Segment>,
+    position: u64,
+}
+
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl SegmentedFile {
+    pub fn open(
+        path: impl AsRef<Path>,
+        max_segment_size: u64,
+        read_only: bool,
+    ) -> io::Result<SegmentedFile> {
+        let mut file = SegmentedFile {
+            path: path.as_ref().to_path_buf(),
+            max_segment_size,
+            read_only,
+            segments: Vec::new(),
+            position: 0,
+        };
+
+        file.refresh()?;
+        if file.segments.is_empty() {
+            if read_only {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no segments found for {}", file.path.display()),
+                ));
+            }
+            file.create_segment()?;
+        }
+        Ok(file)
+    }
+
+    pub fn segment_path(path: impl AsRef<Path>, number: usize) -> PathBuf {
+        let mut name = path.as_ref().as_os_str().to_owned();
+        name.push(format!(".{:04}", number));
+        PathBuf::from(name)
+    }
+
+    pub fn segment_paths(&self) -> impl Iterator<Item = &Path> {
+        self.segments.iter().map(|segment| segment.path.as_path())
+    }
+
+    pub fn max_segment_size(&self) -> u64 {
+        self.max_segment_size
+    }
+
+    pub fn sync_all(&self) -> io::Result<()> {
+        for segment in &self.segments {
+            segment.file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn first_segment(&self) -> &File {
+        &self.segments[0].file
+    }
+
+    fn len(&self) -> u64 {
+        self.segments.iter().map(|segment| segment.len).sum()
+    }
+
+    // picks up data and segments written by other processes since the last call
+    fn refresh(&mut self) -> io::Result<()> {
+        if let Some(last) = self.segments.last_mut() {
+            last.len = last.file.metadata()?.len();
+        }
+
+        loop {
+            let path = SegmentedFile::segment_path(&self.path, self.segments.len() + 1);
+            let file = match fs::OpenOptions::new()
+                .read(true)
+                .append(!self.read_only)
+                .open(&path)
+            {
+                Ok(file) => file,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let len = file.metadata()?.len();
+            self.segments.push(Segment { path, file, len });
+        }
+    }
+
+    fn create_segment(&mut self) -> io::Result<()> {
+        let path = SegmentedFile::segment_path(&self.path, self.segments.len() + 1);
+        match fs::OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create_new(true)
+            .open(&path)
+        {
+            // another writer may have rotated first, in which case we append to its segment
+            Ok(_) => (),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => (),
+            Err(err) => return Err(err),
+        }
+        self.refresh()
+    }
+
+    fn read_segment(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let count = self.segments.len();
+        let mut start = 0;
+        for (index, segment) in self.segments.iter_mut().enumerate() {
+            let is_last = index + 1 == count;
+            if self.position < start + segment.len || is_last {
+                segment.file.seek(SeekFrom::Start(self.position - start))?;
+                let n = segment.file.read(buf)?;
+                self.position += n as u64;
+                return Ok(n);
+            }
+            start += segment.len;
+        }
+        Ok(0)
+    }
+}
+
+impl Read for SegmentedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.read_segment(buf)? {
+            // another process may have rotated to a new segment
+            0 if !buf.is_empty() => {
+                self.refresh()?;
+                self.read_segment(buf)
+            }
+            n => Ok(n),
+        }
+    }
+}
+
+impl Write for SegmentedFile {
+    // each call is written to a single segment, so records written in one call are never split
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.read_only {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                "segmented log was opened read-only",
+            ));
+        }
+
+        self.refresh()?;
+        let last = self.segments.last().map_or(0, |segment| segment.len);
+        if last > 0 && last + buf.len() as u64 > self.max_segment_size {
+            self.create_segment()?;
+        }
+
+        let segment = self.segments.last_mut().expect("at least one segment");
+        segment.file.write_all(buf)?;
+        segment.len += buf.len() as u64;
+        self.position = self.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.segments.last_mut() {
+            Some(segment) => segment.file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Seek for SegmentedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => {
+                self.refresh()?;
+                self.len().checked_add_signed(offset)
+            }
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
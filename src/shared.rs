@@ -0,0 +1,283 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::fs::File;
+use std::io::{self, Read, Seek, Write};
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::{
+    cache_tag::{CacheTag, DefaultCacheTag},
+    database::Database,
+    record::{Record, RecordData, RecordId},
+    stats::ReloadSummary,
+};
+
+// a database that can be shared between threads and request handlers
+pub struct SharedDatabase<T, S = File, C = DefaultCacheTag>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    inner: Arc<RwLock<Database<T, S, C>>>,
+}
+
+impl<T, S, C> SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn new(database: Database<T, S, C>) -> SharedDatabase<T, S, C> {
+        SharedDatabase {
+            inner: Arc::new(RwLock::new(database)),
+        }
+    }
+
+    // a panic while holding the lock can't leave the database half-updated, so poisoning is ignored
+    pub fn read(&self) -> RwLockReadGuard<'_, Database<T, S, C>> {
+        self.inner.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn write(&self) -> RwLockWriteGuard<'_, Database<T, S, C>> {
+        self.inner.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn reload(&self) -> io::Result<ReloadSummary> {
+        self.write().reload()
+    }
+
+    // reloads first if the database's auto-reload policy asks for it
+    fn refresh(&self) -> io::Result<RwLockReadGuard<'_, Database<T, S, C>>> {
+        if self.read().reload_due() {
+            self.write().reload_if_due()?;
+        }
+        Ok(self.read())
+    }
+
+    pub fn record_count(&self) -> io::Result<usize> {
+        Ok(self.refresh()?.record_count())
+    }
+
+    pub fn contains(&self, id: RecordId) -> io::Result<bool> {
+        Ok(self.refresh()?.contains(id))
+    }
+
+    // records are handed out as shared handles, so the lock isn't held while the caller uses
+    // them, and nothing is copied
+    pub fn get(&self, id: RecordId) -> io::Result<Option<Arc<RecordData<T>>>> {
+        Ok(self.refresh()?.get_owned(id))
+    }
+
+    pub fn records(&self) -> io::Result<Vec<Arc<RecordData<T>>>> {
+        Ok(self.refresh()?.records_owned())
+    }
+}
+
+impl<T, S, C> SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn insert(&self, data: T) -> io::Result<RecordId> {
+        self.write().insert(data)
+    }
+
+    pub fn upsert<F>(&self, id: RecordId, f: F) -> io::Result<()>
+    where
+        F: FnOnce(Option<&T>) -> Option<T>,
+    {
+        self.write().upsert(id, f)
+    }
+
+    pub fn delete(&self, id: RecordId) -> io::Result<()> {
+        self.write().delete(id)
+    }
+}
+
+impl<T, S, C> Database<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn into_shared(self) -> SharedDatabase<T, S, C> {
+        SharedDatabase::new(self)
+    }
+}
+
+impl<T, S, C> Clone for SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    fn clone(&self) -> Self {
+        SharedDatabase {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+impl<T, S, C> From<Database<T, S, C>> for SharedDatabase<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    fn from(database: Database<T, S, C>) -> Self {
+        SharedDatabase::new(database)
+    }
+}
+
+// framework-independent CRUD handlers, which the axum and actix integrations translate from and to
+// their own request and response types
+#[cfg(any(feature = "axum", feature = "actix"))]
+pub(crate) mod handlers {
+    use serde::{de::DeserializeOwned, Serialize};
+    use std::io::{self, Read, Seek, Write};
+    use std::sync::RwLockReadGuard;
+
+    use super::SharedDatabase;
+    use crate::{
+        cache_tag::CacheTag, database::Database, error::Error, record::Record, record::RecordId,
+    };
+
+    pub(crate) struct Reply {
+        pub status: u16,
+        pub etag: Option<String>,
+        pub body: Option<Vec<u8>>,
+    }
+
+    impl Reply {
+        fn json(status: u16, etag: Option<String>, body: &impl Serialize) -> Reply {
+            match serde_json::to_vec(body) {
+                Ok(body) => Reply {
+                    status,
+                    etag,
+                    body: Some(body),
+                },
+                Err(err) => Reply::error(500, &err.to_string()),
+            }
+        }
+
+        fn empty(status: u16) -> Reply {
+            Reply {
+                status,
+                etag: None,
+                body: None,
+            }
+        }
+
+        pub(crate) fn error(status: u16, message: &str) -> Reply {
+            Reply::json(status, None, &serde_json::json!({ "error": message }))
+        }
+
+        fn from_io(err: io::Error) -> Reply {
+            let status = match Error::from_io(&err) {
+                Some(Error::InvalidFilter { .. }) => 400,
+                Some(Error::NotFound { .. }) => 404,
+                Some(Error::IdInUse { .. } | Error::Conflict { .. }) => 409,
+                Some(Error::InvalidRecord { .. }) => 422,
+                Some(Error::Locked { .. }) => 503,
+                _ => 500,
+            };
+            Reply::error(status, &err.to_string())
+        }
+    }
+
+    impl<T, S, C> SharedDatabase<T, S, C>
+    where
+        T: Serialize + DeserializeOwned,
+        S: Read + Write + Seek,
+        C: CacheTag<Record<T>>,
+    {
+        // reads only take the write lock to reload, and only when the log has changed
+        fn fresh(&self) -> io::Result<RwLockReadGuard<'_, Database<T, S, C>>> {
+            if self.read().is_stale()? {
+                let mut database = self.write();
+                if database.is_stale()? {
+                    database.reload()?;
+                }
+            }
+            Ok(self.read())
+        }
+
+        pub(crate) fn handle_list(&self) -> Reply {
+            let database = match self.fresh() {
+                Ok(database) => database,
+                Err(err) => return Reply::from_io(err),
+            };
+            let records = database.records().collect::<Vec<_>>();
+            Reply::json(200, Some(database.etag()), &records)
+        }
+
+        pub(crate) fn handle_get(&self, id: RecordId) -> Reply {
+            let database = match self.fresh() {
+                Ok(database) => database,
+                Err(err) => return Reply::from_io(err),
+            };
+            match database.get(id) {
+                Some(record) => Reply::json(200, database.record_etag(id), record),
+                None => Reply::error(404, "record not found"),
+            }
+        }
+
+        pub(crate) fn handle_insert(&self, body: &[u8]) -> Reply {
+            let data = match serde_json::from_slice(body) {
+                Ok(data) => data,
+                Err(err) => return Reply::error(400, &err.to_string()),
+            };
+
+            let mut database = self.write();
+            match database.insert(data) {
+                Ok(id) => Reply::json(201, database.record_etag(id), &database.get(id)),
+                Err(err) => Reply::from_io(err),
+            }
+        }
+
+        pub(crate) fn handle_put(
+            &self,
+            id: RecordId,
+            body: &[u8],
+            if_match: Option<&str>,
+        ) -> Reply {
+            let data = match serde_json::from_slice(body) {
+                Ok(data) => data,
+                Err(err) => return Reply::error(400, &err.to_string()),
+            };
+
+            let mut database = self.write();
+            if let Err(err) = database.reload() {
+                return Reply::from_io(err);
+            }
+            if let Some(err) =
+                if_match.and_then(|if_match| database.check_record_precondition(id, if_match).err())
+            {
+                return Reply::error(412, &err.to_string());
+            }
+            match database.upsert(id, |_| Some(data)) {
+                Ok(()) => Reply::json(200, database.record_etag(id), &database.get(id)),
+                Err(err) => Reply::from_io(err),
+            }
+        }
+
+        pub(crate) fn handle_delete(&self, id: RecordId, if_match: Option<&str>) -> Reply {
+            let mut database = self.write();
+            if let Err(err) = database.reload() {
+                return Reply::from_io(err);
+            }
+            if let Some(err) =
+                if_match.and_then(|if_match| database.check_record_precondition(id, if_match).err())
+            {
+                return Reply::error(412, &err.to_string());
+            }
+            if !database.contains(id) {
+                return Reply::error(404, "record not found");
+            }
+            match database.delete(id) {
+                Ok(()) => Reply::empty(204),
+                Err(err) => Reply::from_io(err),
+            }
+        }
+    }
+}
@@ -0,0 +1,165 @@
+use itertools::{Either, Itertools};
+use rand::Rng;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use crate::record::{Record, RecordData, RecordId, RecordMeta};
+
+#[derive(Debug)]
+pub struct Snapshot<'a, T> {
+    records: &'a [Record<T>],
+    // where the latest entry of each record is, if the snapshot is of everything that was loaded
+    latest: Option<&'a BTreeMap<RecordId, usize>>,
+}
+
+impl<'a, T> Clone for Snapshot<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for Snapshot<'a, T> {}
+
+impl<'a, T> Snapshot<'a, T> {
+    pub(crate) fn new(records: &'a [Record<T>]) -> Snapshot<'a, T> {
+        Snapshot {
+            records,
+            latest: None,
+        }
+    }
+
+    pub(crate) fn indexed(
+        records: &'a [Record<T>],
+        latest: &'a BTreeMap<RecordId, usize>,
+    ) -> Snapshot<'a, T> {
+        Snapshot {
+            records,
+            latest: Some(latest),
+        }
+    }
+
+    // live records, in id order if the snapshot is indexed and in no particular order otherwise
+    fn live(&self) -> impl Iterator<Item = &'a RecordData<T>> {
+        let records = self.records;
+        match self.latest {
+            Some(latest) => Either::Left(latest.values().map(move |&index| &records[index])),
+            None => Either::Right(
+                records
+                    .iter()
+                    .rev()
+                    .filter(|record| !record.is_unresolved_merge())
+                    .unique_by(|record| record.id()),
+            ),
+        }
+        .filter_map(Record::data)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &'a RecordData<T>> {
+        if self.latest.is_some() {
+            return Either::Left(self.live());
+        }
+        let mut items = self.live().collect::<Vec<_>>();
+        items.sort_unstable_by_key(|data| data.id);
+        Either::Right(items.into_iter())
+    }
+
+    pub fn records_sorted_by<F>(&self, compare: F) -> impl Iterator<Item = &'a RecordData<T>>
+    where
+        F: FnMut(&&'a RecordData<T>, &&'a RecordData<T>) -> Ordering,
+    {
+        let mut items = self.live().collect::<Vec<_>>();
+        items.sort_by(compare);
+        items.into_iter()
+    }
+
+    // like `records().map(f)`, but only the projected values are collected
+    pub fn records_map<U, F>(&self, mut f: F) -> impl Iterator<Item = U> + 'a
+    where
+        F: FnMut(&'a RecordData<T>) -> U + 'a,
+        U: 'a,
+    {
+        if self.latest.is_some() {
+            return Either::Left(self.live().map(f));
+        }
+        let mut items = self
+            .live()
+            .map(|record| (record.id, f(record)))
+            .collect::<Vec<_>>();
+        items.sort_unstable_by_key(|(id, _)| *id);
+        Either::Right(items.into_iter().map(|(_, item)| item))
+    }
+
+    // reservoir sampling, so only `n` records are held at a time; the sample is returned in id order
+    pub fn sample<R: Rng + ?Sized>(&self, n: usize, rng: &mut R) -> Vec<&'a RecordData<T>> {
+        let mut sample = Vec::with_capacity(n);
+        for (i, record) in self.live().enumerate() {
+            if sample.len() < n {
+                sample.push(record);
+            } else {
+                let j = rng.random_range(0..=i);
+                if j < n {
+                    sample[j] = record;
+                }
+            }
+        }
+        sample.sort_unstable_by_key(|data| data.id);
+        sample
+    }
+
+    pub fn records_include_deleted(&self) -> impl Iterator<Item = &'a RecordData<T>> {
+        let mut items = self
+            .records
+            .iter()
+            .rev()
+            .filter_map(Record::data)
+            .unique_by(|record| record.id)
+            .collect::<Vec<_>>();
+        items.sort_by_key(|data| data.id);
+        items.into_iter()
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.records().count()
+    }
+
+    pub fn get(&self, id: RecordId) -> Option<&'a RecordData<T>> {
+        self.latest(id).and_then(|(_, record)| record.data())
+    }
+
+    pub fn get_deleted(&self, id: RecordId) -> Option<&'a RecordData<T>> {
+        let (index, latest) = self.latest(id)?;
+        if latest.data().is_some() {
+            return None;
+        }
+
+        self.records[..index]
+            .iter()
+            .rev()
+            .filter(|record| record.id() == id)
+            .find_map(Record::data)
+    }
+
+    // the latest entry of a record, along with where it is
+    fn latest(&self, id: RecordId) -> Option<(usize, &'a Record<T>)> {
+        if let Some(latest) = self.latest {
+            return latest.get(&id).map(|&index| (index, &self.records[index]));
+        }
+        self.records
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, record)| !record.is_unresolved_merge())
+            .find(|(_, record)| record.id() == id)
+    }
+
+    pub fn history(&self, id: RecordId) -> impl Iterator<Item = &'a Record<T>> {
+        self.records.iter().filter(move |record| record.id() == id)
+    }
+
+    pub fn meta(&self, id: RecordId) -> Option<&'a RecordMeta> {
+        self.latest(id)
+            .map(|(_, record)| record)
+            .filter(|record| record.data().is_some())
+            .and_then(Record::meta)
+    }
+}
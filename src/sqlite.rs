@@ -0,0 +1,174 @@
+use indexmap::IndexMap;
+use rusqlite::{params_from_iter, types::Value as SqlValue, Connection, Error as SqlError};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::io::{Read, Seek};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordId},
+};
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColumnType {
+    Null,
+    Integer,
+    Real,
+    Text,
+    Json,
+    Mixed,
+}
+
+impl ColumnType {
+    fn of(value: &Value) -> ColumnType {
+        match value {
+            Value::Null => ColumnType::Null,
+            Value::Bool(_) => ColumnType::Integer,
+            Value::Number(n) if n.is_i64() => ColumnType::Integer,
+            Value::Number(_) => ColumnType::Real,
+            Value::String(_) => ColumnType::Text,
+            Value::Array(_) | Value::Object(_) => ColumnType::Json,
+        }
+    }
+
+    fn unify(self, other: ColumnType) -> ColumnType {
+        match (self, other) {
+            (ColumnType::Null, other) | (other, ColumnType::Null) => other,
+            (a, b) if a == b => a,
+            (ColumnType::Integer, ColumnType::Real) | (ColumnType::Real, ColumnType::Integer) => {
+                ColumnType::Real
+            }
+            _ => ColumnType::Mixed,
+        }
+    }
+
+    // columns without a single type are left without one, so values are stored as they are
+    fn declared(self) -> &'static str {
+        match self {
+            ColumnType::Integer => " INTEGER",
+            ColumnType::Real => " REAL",
+            ColumnType::Text | ColumnType::Json => " TEXT",
+            ColumnType::Null | ColumnType::Mixed => "",
+        }
+    }
+}
+
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(n) => SqlValue::Integer(n),
+            None => SqlValue::Real(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        Value::Array(_) | Value::Object(_) => SqlValue::Text(value.to_string()),
+    }
+}
+
+fn conversion_error(err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> SqlError {
+    SqlError::ToSqlConversionFailure(err.into())
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // writes the latest version of every record, deleted ones included, to a new table
+    // `(id, deleted, data)` with the payload as JSON; returns the number of rows written
+    pub fn export_sqlite(&self, conn: &Connection, table: &str) -> rusqlite::Result<usize> {
+        let rows = self.sqlite_rows()?;
+        let columns = [("data".to_string(), ColumnType::Text)];
+        write_table(conn, table, &columns, rows, |data| {
+            vec![SqlValue::Text(data.to_string())]
+        })
+    }
+
+    // like `export_sqlite`, but with a column for each top-level field of the payloads instead,
+    // typed after the values they hold; nested values are stored as JSON
+    pub fn export_sqlite_exploded(
+        &self,
+        conn: &Connection,
+        table: &str,
+    ) -> rusqlite::Result<usize> {
+        let rows = self.sqlite_rows()?;
+
+        let mut columns = IndexMap::new();
+        for (id, _, data) in &rows {
+            let object = data
+                .as_object()
+                .ok_or_else(|| conversion_error(format!("record {id} is not an object")))?;
+            for (key, value) in object {
+                let column = columns.entry(key.clone()).or_insert(ColumnType::Null);
+                *column = column.unify(ColumnType::of(value));
+            }
+        }
+
+        let columns = columns.into_iter().collect::<Vec<_>>();
+        write_table(conn, table, &columns, rows, |data| {
+            columns
+                .iter()
+                .map(|(key, _)| data.get(key).map_or(SqlValue::Null, sql_value))
+                .collect()
+        })
+    }
+
+    fn sqlite_rows(&self) -> rusqlite::Result<Vec<(RecordId, bool, Value)>> {
+        self.records_include_deleted()
+            .map(|record| {
+                let data = serde_json::to_value(&record.data).map_err(conversion_error)?;
+                Ok((record.id, !self.contains(record.id), data))
+            })
+            .collect()
+    }
+}
+
+fn write_table<F>(
+    conn: &Connection,
+    table: &str,
+    columns: &[(String, ColumnType)],
+    rows: Vec<(RecordId, bool, Value)>,
+    mut values: F,
+) -> rusqlite::Result<usize>
+where
+    F: FnMut(&Value) -> Vec<SqlValue>,
+{
+    let definitions = columns
+        .iter()
+        .map(|(name, ty)| format!(", {}{}", quote(name), ty.declared()))
+        .collect::<String>();
+    let placeholders = ", ?".repeat(columns.len());
+
+    // all or nothing, so a failed export doesn't leave a partial table behind
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        &format!(
+            "CREATE TABLE {} (id INTEGER PRIMARY KEY, deleted INTEGER NOT NULL{definitions})",
+            quote(table),
+        ),
+        [],
+    )?;
+    let mut insert = tx.prepare(&format!(
+        "INSERT INTO {} VALUES (?, ?{placeholders})",
+        quote(table),
+    ))?;
+    let count = rows.len();
+    for (id, deleted, data) in rows {
+        let mut row = vec![
+            SqlValue::Integer(id.into()),
+            SqlValue::Integer(deleted as i64),
+        ];
+        row.extend(values(&data));
+        insert.execute(params_from_iter(row))?;
+    }
+    drop(insert);
+    tx.commit()?;
+    Ok(count)
+}
@@ -0,0 +1,185 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{self, Read, Seek, Write};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    record::{Record, RecordData, RecordId},
+};
+
+// changes made through a staging area only show up in its own reads until they're committed, at
+// which point they're written with a single write; `discard` or dropping it discards them, though
+// dropping it with pending changes is logged as a likely mistake
+pub struct Staged<'a, T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    database: &'a mut Database<T, S, C>,
+    records: Vec<Record<T>>,
+    latest: HashMap<RecordId, usize>,
+    staged_ids: BTreeSet<RecordId>,
+    inserted: BTreeSet<RecordId>,
+    // the cache tag the database must still have when committing
+    expected_tag: Option<u64>,
+}
+
+impl<'a, T, S, C> Staged<'a, T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub(crate) fn new(database: &'a mut Database<T, S, C>) -> Staged<'a, T, S, C> {
+        Staged {
+            database,
+            records: Vec::new(),
+            latest: HashMap::new(),
+            staged_ids: BTreeSet::new(),
+            inserted: BTreeSet::new(),
+            expected_tag: None,
+        }
+    }
+
+    pub fn get(&self, id: RecordId) -> Option<&RecordData<T>> {
+        match self.latest.get(&id) {
+            Some(&index) => self.records[index].data(),
+            None => self.database.get(id),
+        }
+    }
+
+    pub fn contains(&self, id: RecordId) -> bool {
+        self.get(id).is_some()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &RecordData<T>> {
+        let mut records = self
+            .database
+            .records()
+            .filter(|record| !self.latest.contains_key(&record.id))
+            .chain(
+                self.latest
+                    .values()
+                    .filter_map(|&index| self.records[index].data()),
+            )
+            .collect::<Vec<_>>();
+        records.sort_unstable_by_key(|record| record.id);
+        records.into_iter()
+    }
+
+    pub fn record_count(&self) -> usize {
+        self.records().count()
+    }
+
+    // the records that will be written on commit, in order
+    pub fn pending(&self) -> &[Record<T>] {
+        &self.records
+    }
+
+    // ids come from the database's allocator, skipping ids already used in this staging area
+    pub fn insert(&mut self, data: T) -> io::Result<RecordId> {
+        let id = self.database.allocate_id(&self.staged_ids)?;
+        self.inserted.insert(id);
+
+        self.stage(Record::upsert(id, data));
+        Ok(id)
+    }
+
+    pub fn upsert<F>(&mut self, id: RecordId, f: F)
+    where
+        F: FnOnce(Option<&T>) -> Option<T>,
+    {
+        let data = self.get(id).map(|record_data| &record_data.data);
+
+        match f(data) {
+            Some(new_data) => self.stage(Record::upsert(id, new_data)),
+            None if data.is_some() => self.stage(Record::delete(id)),
+            None => (),
+        }
+    }
+
+    pub fn delete(&mut self, id: RecordId) {
+        self.stage(Record::delete(id));
+    }
+
+    fn stage(&mut self, record: Record<T>) {
+        self.staged_ids.insert(record.id());
+        self.latest.insert(record.id(), self.records.len());
+        self.records.push(record);
+    }
+
+    pub fn commit(mut self) -> io::Result<()> {
+        let records = std::mem::take(&mut self.records);
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.database
+            .commit_staged(records, &self.inserted, self.expected_tag)
+    }
+
+    pub fn discard(mut self) {
+        self.records.clear();
+    }
+}
+
+impl<'a, T, S, C> Drop for Staged<'a, T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    fn drop(&mut self) {
+        if std::thread::panicking() {
+            return;
+        }
+        #[cfg(feature = "tracing")]
+        if !self.records.is_empty() {
+            tracing::warn!(
+                records = self.records.len(),
+                "staged changes dropped without `commit` or `discard`"
+            );
+        }
+        debug_assert!(
+            self.records.is_empty(),
+            "{} staged changes dropped without `commit` or `discard`",
+            self.records.len()
+        );
+    }
+}
+
+impl<T, S, C> Database<T, S, C>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn staged(&mut self) -> Staged<'_, T, S, C> {
+        Staged::new(self)
+    }
+
+    // makes the changes staged by `f` only if the cache tag is still `expected_tag`, both before
+    // calling `f` and when committing, and fails with `Error::Conflict` otherwise; nothing is written
+    // if `f` fails
+    pub fn write_if_unchanged<F, R>(&mut self, expected_tag: u64, f: F) -> io::Result<R>
+    where
+        F: FnOnce(&mut Staged<'_, T, S, C>) -> io::Result<R>,
+    {
+        self.reload()?;
+        self.check_cache_tag(expected_tag)?;
+
+        let mut staged = self.staged();
+        staged.expected_tag = Some(expected_tag);
+        match f(&mut staged) {
+            Ok(result) => {
+                staged.commit()?;
+                Ok(result)
+            }
+            Err(err) => {
+                staged.discard();
+                Err(err)
+            }
+        }
+    }
+}
@@ -0,0 +1,130 @@
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Seek};
+use std::time::Duration;
+
+use crate::{
+    cache_tag::CacheTag,
+    checksum::encode_record,
+    database::Database,
+    record::{Record, RecordId},
+};
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DatabaseStats {
+    pub live_records: usize,
+    pub deleted_records: usize,
+    pub log_entries: usize,
+    pub log_bytes: u64,
+    pub live_bytes: u64,
+    pub min_id: Option<RecordId>,
+    pub max_id: Option<RecordId>,
+    pub field_counts: BTreeMap<String, usize>,
+    pub timings: Timings,
+}
+
+// time spent by this handle since it was opened; write times include catching up with the log first
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Timings {
+    pub reloads: u64,
+    pub reload_time: Duration,
+    pub writes: u64,
+    pub write_time: Duration,
+    pub compactions: u64,
+    pub compaction_time: Duration,
+}
+
+// what a single reload picked up; merges count as new records
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ReloadSummary {
+    pub new_records: usize,
+    pub new_deletes: usize,
+    pub bytes_read: u64,
+    pub duration: Duration,
+    // entries left out under `InvalidRecordPolicy::Skip` or `Collect`
+    pub skipped: Vec<SkippedEntry>,
+    // under `OpenOptions::tolerate_duplicates`, entries left out for being the same as the
+    // record they would have replaced, and entries loaded under another id than their own
+    pub duplicates: usize,
+    pub remapped: usize,
+    // whether the log had been replaced, by compaction or a restore, and was read from the start
+    pub replaced: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkippedEntry {
+    pub offset: u64,
+    pub message: String,
+    // only kept under `InvalidRecordPolicy::Collect`
+    pub line: Option<String>,
+}
+
+impl ReloadSummary {
+    pub fn has_changes(&self) -> bool {
+        self.new_records > 0 || self.new_deletes > 0 || self.replaced
+    }
+}
+
+impl DatabaseStats {
+    // share of the log taken up by superseded entries, i.e. what compaction would reclaim
+    pub fn dead_bytes_ratio(&self) -> f64 {
+        if self.log_bytes == 0 {
+            return 0.0;
+        }
+        self.log_bytes.saturating_sub(self.live_bytes) as f64 / self.log_bytes as f64
+    }
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    pub fn stats(&self) -> io::Result<DatabaseStats> {
+        let entries = self.entries();
+
+        let mut latest = HashMap::new();
+        for record in entries
+            .iter()
+            .filter(|record| !record.is_unresolved_merge())
+        {
+            latest.insert(record.id(), record);
+        }
+
+        let mut stats = DatabaseStats {
+            log_entries: self.entry_count(),
+            log_bytes: self.log_position().offset,
+            timings: self.timings(),
+            ..DatabaseStats::default()
+        };
+        let mut buffer = Vec::new();
+        for (&id, record) in &latest {
+            let data = match record.data() {
+                Some(data) => data,
+                None => {
+                    stats.deleted_records += 1;
+                    continue;
+                }
+            };
+
+            stats.live_records += 1;
+            buffer.clear();
+            encode_record(record, false, self.format(), &mut buffer)?;
+            stats.live_bytes += buffer.len() as u64;
+            stats.min_id = Some(stats.min_id.map_or(id, |min_id| min_id.min(id)));
+            stats.max_id = Some(stats.max_id.map_or(id, |max_id| max_id.max(id)));
+
+            if let Value::Object(fields) = serde_json::to_value(&data.data)? {
+                for (field, value) in fields {
+                    if !value.is_null() {
+                        *stats.field_counts.entry(field).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+}
@@ -0,0 +1,266 @@
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// where a log is kept; the log is only ever appended to, so this is all a database needs
+pub trait StorageBackend {
+    // reads from `offset` into `buf`, returning how much was read, which is 0 at the end
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()>;
+
+    fn len(&mut self) -> io::Result<u64>;
+
+    fn is_empty(&mut self) -> io::Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    // makes everything appended so far durable
+    fn sync(&self) -> io::Result<()>;
+}
+
+impl StorageBackend for File {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        self.seek(SeekFrom::Start(offset))?;
+        self.read(buf)
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.seek(SeekFrom::End(0))?;
+        self.write_all(bytes)
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        self.sync_all()
+    }
+}
+
+// kept in memory only, so there's nothing to sync
+impl StorageBackend for Vec<u8> {
+    fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+        let start = (offset as usize).min(Vec::len(self));
+        let n = buf.len().min(Vec::len(self) - start);
+        buf[..n].copy_from_slice(&self[start..start + n]);
+        Ok(n)
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.extend_from_slice(bytes);
+        Ok(())
+    }
+
+    fn len(&mut self) -> io::Result<u64> {
+        Ok(Vec::len(self) as u64)
+    }
+
+    fn sync(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// a backend presented as a stream a database can be opened on; every write is appended at the
+// end, wherever the stream was
+#[derive(Debug)]
+pub struct BackendStream<B> {
+    backend: B,
+    position: u64,
+}
+
+impl<B: StorageBackend> BackendStream<B> {
+    pub const fn new(backend: B) -> BackendStream<B> {
+        BackendStream {
+            backend,
+            position: 0,
+        }
+    }
+
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    pub fn into_backend(self) -> B {
+        self.backend
+    }
+
+    pub fn sync_all(&self) -> io::Result<()> {
+        self.backend.sync()
+    }
+}
+
+impl<B: StorageBackend> Read for BackendStream<B> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.backend.read_at(self.position, buf)?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<B: StorageBackend> Write for BackendStream<B> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.backend.append(buf)?;
+        self.position = self.backend.len()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<B: StorageBackend> Seek for BackendStream<B> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(offset) => self.backend.len()?.checked_add_signed(offset),
+            SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(feature = "object_store")]
+mod remote {
+    use object_store::{path::Path, ObjectStore, PutMode, PutOptions, PutPayload};
+    use std::io;
+    use std::sync::Arc;
+    use tokio::runtime::Handle;
+
+    use super::StorageBackend;
+
+    // a log in an object store such as S3 or GCS, which can't append to objects, so each append
+    // is an object of its own under `prefix`, named after the offset it starts at; a second
+    // writer appending at the same offset fails rather than overwriting
+    //
+    // requests are made by blocking on `runtime`, so this must not be used from async code
+    #[derive(Debug)]
+    pub struct ObjectStoreBackend {
+        store: Arc<dyn ObjectStore>,
+        prefix: Path,
+        runtime: Handle,
+        // the start and length of each chunk as of the last listing
+        chunks: Vec<(u64, u64)>,
+    }
+
+    impl ObjectStoreBackend {
+        pub fn new(store: Arc<dyn ObjectStore>, prefix: Path, runtime: Handle) -> Self {
+            ObjectStoreBackend {
+                store,
+                prefix,
+                runtime,
+                chunks: Vec::new(),
+            }
+        }
+
+        fn chunk_path(&self, start: u64) -> Path {
+            self.prefix.child(format!("{start:020}"))
+        }
+
+        fn end(&self) -> u64 {
+            self.chunks.last().map_or(0, |&(start, len)| start + len)
+        }
+
+        // picks up chunks appended since the last listing, including by other writers
+        fn refresh(&mut self) -> io::Result<()> {
+            let listing = self
+                .runtime
+                .block_on(self.store.list_with_delimiter(Some(&self.prefix)))
+                .map_err(io::Error::other)?;
+
+            let mut objects = listing.objects;
+            objects.sort_by(|a, b| a.location.cmp(&b.location));
+            let mut chunks = Vec::with_capacity(objects.len());
+            let mut end = 0;
+            for object in objects {
+                let start = object
+                    .location
+                    .filename()
+                    .and_then(|name| name.parse::<u64>().ok());
+                if start != Some(end) {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("unexpected object {} in log at {end}", object.location),
+                    ));
+                }
+                chunks.push((end, object.size));
+                end += object.size;
+            }
+            self.chunks = chunks;
+            Ok(())
+        }
+    }
+
+    impl StorageBackend for ObjectStoreBackend {
+        fn read_at(&mut self, offset: u64, buf: &mut [u8]) -> io::Result<usize> {
+            if offset >= self.end() {
+                self.refresh()?;
+            }
+            let chunk = self
+                .chunks
+                .iter()
+                .find(|&&(start, len)| start <= offset && offset < start + len);
+            let (start, len) = match chunk {
+                Some(&chunk) => chunk,
+                None => return Ok(0),
+            };
+
+            let from = offset - start;
+            let to = len.min(from + buf.len() as u64);
+            let bytes = self
+                .runtime
+                .block_on(self.store.get_range(&self.chunk_path(start), from..to))
+                .map_err(io::Error::other)?;
+            buf[..bytes.len()].copy_from_slice(&bytes);
+            Ok(bytes.len())
+        }
+
+        fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+            if bytes.is_empty() {
+                return Ok(());
+            }
+
+            self.refresh()?;
+            let start = self.end();
+            let opts = PutOptions {
+                mode: PutMode::Create,
+                ..PutOptions::default()
+            };
+            let path = self.chunk_path(start);
+            let payload = PutPayload::from(bytes.to_vec());
+            let put = self.store.put_opts(&path, payload, opts);
+            match self.runtime.block_on(put) {
+                Ok(_) => (),
+                Err(object_store::Error::AlreadyExists { .. }) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AlreadyExists,
+                        format!("another writer appended to the log at {start}"),
+                    ));
+                }
+                Err(err) => return Err(io::Error::other(err)),
+            }
+            self.chunks.push((start, bytes.len() as u64));
+            Ok(())
+        }
+
+        fn len(&mut self) -> io::Result<u64> {
+            self.refresh()?;
+            Ok(self.end())
+        }
+
+        // objects are durable once they've been written
+        fn sync(&self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(feature = "object_store")]
+pub use self::remote::ObjectStoreBackend;
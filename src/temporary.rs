@@ -0,0 +1,28 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::io::{self, Cursor};
+
+use crate::database::Database;
+
+// a database that only lives in memory, e.g. for tests
+pub type MemoryDatabase<T> = Database<T, Cursor<Vec<u8>>>;
+
+impl<T> Database<T, Cursor<Vec<u8>>>
+where
+    T: Serialize + DeserializeOwned,
+{
+    pub fn in_memory() -> io::Result<MemoryDatabase<T>> {
+        Database::new(Cursor::new(Vec::new()))
+    }
+}
+
+#[cfg(feature = "tempfile")]
+impl<T> Database<T, std::fs::File>
+where
+    T: Serialize + DeserializeOwned,
+{
+    // backed by an unnamed temporary file, which the OS removes once the database is dropped;
+    // without a path, it can't be compacted, backed up by path or reopened
+    pub fn temporary() -> io::Result<Database<T, std::fs::File>> {
+        Database::new(tempfile::tempfile()?)
+    }
+}
@@ -1,15 +1,25 @@
 use serde::{Deserialize, Serialize};
-use std::io::Cursor;
+use std::io::{self, Cursor};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use crate::*;
 
-#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
 struct MyObject {
     a: String,
     b: i32,
     c: Option<i32>,
 }
 
+fn obj(b: i32) -> MyObject {
+    MyObject {
+        a: "foo".into(),
+        b,
+        c: None,
+    }
+}
+
 #[test]
 fn read_test() {
     let database_contents = r#"
@@ -359,3 +369,4235 @@ fn parallel_write_test() {
     })
     .unwrap()
 }
+
+#[test]
+fn as_of_test() {
+    let mut database_contents = Vec::from(
+        br#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+    "# as &[u8],
+    );
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    let before = database.log_position();
+    assert_eq!(before.records, 2);
+
+    database.delete(1).unwrap();
+    database
+        .insert(MyObject {
+            a: "baz".into(),
+            b: 1,
+            c: None,
+        })
+        .unwrap();
+    database.reload().unwrap();
+
+    let after = database.log_position();
+    assert_eq!(after.records, 4);
+    assert!(after > before);
+
+    assert_eq!(
+        database
+            .as_of(before)
+            .unwrap()
+            .records()
+            .map(|record| record.id)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+    assert_eq!(
+        database
+            .as_of(after)
+            .unwrap()
+            .records()
+            .map(|record| record.id)
+            .collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert_eq!(
+        database
+            .as_of(LogPosition::default())
+            .unwrap()
+            .record_count(),
+        0
+    );
+
+    let changes = database.changes_since(before).unwrap();
+    assert_eq!(changes.len(), 2);
+    assert!(changes[0].data().is_none());
+    assert_eq!(changes[1].id(), 3);
+    assert!(database.changes_since(after).unwrap().is_empty());
+    assert_eq!(
+        database
+            .as_of(before)
+            .unwrap()
+            .get(1)
+            .map(|record| record.b),
+        Some(33)
+    );
+}
+
+#[test]
+fn undo_test() {
+    let mut database_contents = Vec::from(
+        br#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+    "# as &[u8],
+    );
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+    let original = database.records().cloned().collect::<Vec<_>>();
+
+    database
+        .insert(MyObject {
+            a: "new".into(),
+            b: 0,
+            c: None,
+        })
+        .unwrap();
+    database
+        .upsert(1, |data| {
+            data.cloned().map(|data| MyObject { b: 34, ..data })
+        })
+        .unwrap();
+    database.delete(2).unwrap();
+
+    assert_eq!(database.undo_last(1).unwrap(), 1);
+    assert_eq!(database.get(2).map(|record| record.b), Some(66));
+    assert_eq!(database.get(1).map(|record| record.b), Some(34));
+
+    assert_eq!(database.undo_last(5).unwrap(), 2);
+    assert_eq!(database.records().cloned().collect::<Vec<_>>(), original);
+    assert_eq!(database.undo_last(1).unwrap(), 0);
+
+    // a record another handle wrote since isn't set back, even before this one reloads
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let mut first = Database::<MyObject, _>::open(&path).unwrap();
+    let mut second = Database::<MyObject, _>::open(&path).unwrap();
+    let id = first.insert(obj(1)).unwrap();
+    first.upsert(id, |_| Some(obj(2))).unwrap();
+    second.reload().unwrap();
+    second.upsert(id, |_| Some(obj(3))).unwrap();
+    assert!(first.undo_last(1).is_err());
+    assert!(first.undo_last(2).is_err());
+    assert_eq!(first.get(id).map(|record| record.b), Some(3));
+    assert_eq!(first.history(id).count(), 3);
+
+    // records nobody else wrote to can still be undone
+    let other = first.insert(obj(4)).unwrap();
+    first.upsert(other, |_| Some(obj(5))).unwrap();
+    assert_eq!(first.undo_last(1).unwrap(), 1);
+    assert_eq!(first.get(other).map(|record| record.b), Some(4));
+    assert_eq!(first.undo_last(1).unwrap(), 1);
+    assert_eq!(first.get(other), None);
+}
+
+#[cfg(feature = "arrow")]
+#[test]
+fn record_batch_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"deleted":true}
+        {"id":3,"a":"baz","b":0}
+    "#;
+
+    let stream = Cursor::new(database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    let batch = database.to_record_batch().unwrap();
+    assert_eq!(batch.num_rows(), 2);
+    assert_eq!(batch.num_columns(), 4);
+    assert!(batch.schema().field_with_name("id").is_ok());
+}
+
+#[cfg(feature = "parquet")]
+#[test]
+fn export_parquet_test() {
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use std::fmt::Write;
+
+    let mut log = String::new();
+    for id in 1..=10000 {
+        writeln!(
+            log,
+            "{{\"id\":{},\"a\":\"record {}\",\"b\":{}}}",
+            id,
+            id,
+            id % 7
+        )
+        .unwrap();
+    }
+    writeln!(log, "{{\"id\":5,\"deleted\":true}}").unwrap();
+    let mut database = Database::<MyObject, _>::new(Cursor::new(log)).unwrap();
+    database.reload().unwrap();
+
+    let batches = database.export_arrow().unwrap();
+    let rows = batches.iter().map(|batch| batch.num_rows());
+    assert_eq!(rows.collect::<Vec<_>>(), vec![8192, 1807]);
+    assert!(batches
+        .iter()
+        .all(|batch| batch.schema() == batches[0].schema()));
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("export.parquet");
+    database.export_parquet(&path).unwrap();
+    let reader = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap())
+        .unwrap()
+        .build()
+        .unwrap();
+    let rows = reader.map(|batch| batch.unwrap().num_rows()).sum::<usize>();
+    assert_eq!(rows, 9999);
+}
+
+#[test]
+fn state_cache_tag_test() {
+    let load = |contents: &str| {
+        let stream = Cursor::new(contents.to_string());
+        let mut database = Database::<MyObject, _>::new(stream)
+            .unwrap()
+            .with_cache_tag(StateCacheTag::new());
+        database.reload().unwrap();
+        database.cache_tag()
+    };
+
+    let a = load(
+        r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"a":"qwe","b":9}
+    "#,
+    );
+    let b = load(
+        r#"
+        {"id":2,"a":"bar","b":66}
+        {"id":3,"a":"gone","b":0}
+        {"id":1,"a":"qwe","b":9}
+        {"id":3,"deleted":true}
+    "#,
+    );
+    let c = load(
+        r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":3,"a":"qwe","b":9}
+    "#,
+    );
+
+    assert_eq!(a, b);
+    assert_ne!(a, c);
+    assert_eq!(load(""), load(r#"{"id":1,"deleted":true}"#));
+}
+
+#[test]
+fn json_array_test() {
+    let array = r#"[
+        {"a":"foo","b":1},
+        {"id":5,"a":"bar","b":2},
+        {"id":2,"a":"baz","b":3},
+        {"id":2,"deleted":true}
+    ]"#;
+
+    let database = Database::<MyObject, _>::from_json_array(array.as_bytes()).unwrap();
+    assert_eq!(
+        database
+            .records()
+            .map(|record| (record.id, record.b))
+            .collect::<Vec<_>>(),
+        vec![(5, 2), (6, 1)]
+    );
+
+    let mut out = Vec::new();
+    database.write_json_array(&mut out).unwrap();
+    let exported: Vec<RecordData<MyObject>> = serde_json::from_slice(&out).unwrap();
+    assert_eq!(exported, database.records().cloned().collect::<Vec<_>>());
+}
+
+#[test]
+fn timestamps_test() {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    #[derive(Default)]
+    struct StepClock(AtomicU64);
+
+    impl Clock for StepClock {
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_millis(self.0.fetch_add(1000, Ordering::SeqCst))
+        }
+    }
+
+    let mut database_contents = Vec::new();
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream)
+        .unwrap()
+        .with_clock(StepClock::default());
+
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+    let id = database.insert(obj.clone()).unwrap();
+    assert_eq!(
+        database.meta(id),
+        Some(&RecordMeta {
+            created_at: Some(0),
+            updated_at: Some(0),
+            ..RecordMeta::default()
+        })
+    );
+
+    database.upsert(id, |_| Some(obj.clone())).unwrap();
+    assert_eq!(
+        database.meta(id),
+        Some(&RecordMeta {
+            created_at: Some(0),
+            updated_at: Some(1000),
+            ..RecordMeta::default()
+        })
+    );
+
+    database.delete(id).unwrap();
+    assert_eq!(database.meta(id), None);
+    database.close().unwrap();
+
+    let records = serde_json::Deserializer::from_slice(&database_contents)
+        .into_iter()
+        .collect::<Result<Vec<Record<MyObject>>, _>>()
+        .unwrap();
+    assert_eq!(
+        records[2].meta().and_then(|meta| meta.updated_at),
+        Some(2000)
+    );
+    assert_eq!(
+        records[1].data(),
+        Some(&RecordData {
+            id,
+            data: obj.clone()
+        })
+    );
+
+    // a payload field of the same name would shadow the metadata, or be read back as it
+    let payload = serde_json::json!({"_meta": {"created_at": 1}, "a": 1});
+    for layout in [Layout::Flat, Layout::Nested] {
+        let mut database = Database::<serde_json::Value, _>::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .with_layout(layout);
+        let mut stamped = Database::<serde_json::Value, _>::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .with_layout(layout)
+            .with_clock(StepClock::default());
+        let mut field_names = Database::<serde_json::Value, _>::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .with_layout(layout)
+            .with_field_names("_id", "_deleted");
+        for result in [
+            database.insert(payload.clone()),
+            stamped.insert(payload.clone()),
+            field_names.insert(payload.clone()),
+        ] {
+            assert_eq!(result.is_ok(), layout == Layout::Nested);
+        }
+        assert_eq!(
+            database.records().count(),
+            (layout == Layout::Nested) as usize
+        );
+    }
+}
+
+#[test]
+fn log_writer_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut writer = LogWriter::<MyObject>::open(&path).unwrap().sync(true);
+    for id in 1..=3 {
+        writer
+            .upsert(
+                id,
+                MyObject {
+                    a: id.to_string(),
+                    b: id as i32,
+                    c: None,
+                },
+            )
+            .unwrap();
+    }
+    writer
+        .append_all(&[Record::delete(2), Record::delete(3)])
+        .unwrap();
+    drop(writer);
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.record_count(), 1);
+    assert_eq!(database.get(1).map(|record| record.a.as_str()), Some("1"));
+}
+
+#[test]
+fn write_context_test() {
+    let context = |actor: &str| {
+        let mut context = serde_json::Map::new();
+        context.insert("actor".into(), actor.into());
+        context
+    };
+
+    let mut database_contents = Vec::new();
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream)
+        .unwrap()
+        .with_write_context(context("service-A"));
+
+    let id = database.insert(obj(1)).unwrap();
+    database.set_write_context(context("service-B"));
+    database.delete(id).unwrap();
+
+    let actors = database
+        .history(id)
+        .map(|record| record.meta().unwrap().context["actor"].clone())
+        .collect::<Vec<_>>();
+    assert_eq!(actors, vec!["service-A", "service-B"]);
+    database.close().unwrap();
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+    assert_eq!(database.history(id).count(), 2);
+    assert_eq!(
+        database.history(id).next().unwrap().data().unwrap().a,
+        "foo"
+    );
+}
+
+#[test]
+fn compact_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    for i in 0..200 {
+        let id = database
+            .insert(MyObject {
+                a: i.to_string(),
+                b: i,
+                c: None,
+            })
+            .unwrap();
+        if i % 3 == 0 {
+            database.delete(id).unwrap();
+        } else if i % 5 == 0 {
+            database
+                .upsert(id - 1, |data| {
+                    data.cloned().map(|data| MyObject { c: Some(i), ..data })
+                })
+                .unwrap();
+        }
+    }
+    let expected = database.records().cloned().collect::<Vec<_>>();
+    database.close().unwrap();
+
+    let opts = CompactOptions::new()
+        .threads(4)
+        .chunk_size(256)
+        .memory_limit(1024);
+    let stats = compact(&path, &opts).unwrap();
+    assert_eq!(stats.live_records, expected.len());
+    assert!(stats.records_read > stats.live_records);
+    assert!(stats.bytes_after < stats.bytes_before);
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.records().cloned().collect::<Vec<_>>(), expected);
+    assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn tombstone_retention_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let now = SystemClock.now_millis();
+    std::fs::write(
+        &path,
+        format!(
+            r#"{{"id":1,"a":"foo","b":1}}
+{{"id":2,"a":"bar","b":2}}
+{{"id":3,"a":"baz","b":3}}
+{{"id":4,"a":"qux","b":4}}
+{{"id":1,"deleted":true,"_meta":{{"updated_at":{}}}}}
+{{"id":2,"deleted":true,"_meta":{{"updated_at":{}}}}}
+{{"id":3,"deleted":true}}
+"#,
+            now - 2 * 3_600_000,
+            now - 60_000
+        ),
+    )
+    .unwrap();
+
+    // the hour-old marker is dropped, while the recent one and the one without a timestamp stay
+    let opts = CompactOptions::new()
+        .tombstone_retention(TombstoneRetention::KeepFor(Duration::from_secs(3600)));
+    let stats = compact(&path, &opts).unwrap();
+    assert_eq!((stats.live_records, stats.tombstones), (1, 2));
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.records_include_deleted().count(), 1);
+    assert_eq!(database.history(2).count(), 1);
+
+    // a marker written after `before` is left alone
+    let before = database.log_position();
+    database.delete(4).unwrap();
+    assert_eq!(database.purge_tombstones(before).unwrap(), 2);
+    assert_eq!(database.history(2).count(), 0);
+    assert_eq!(database.history(4).count(), 2);
+    // purged ids are free to be used again
+    database
+        .insert_with_id(
+            3,
+            MyObject {
+                a: "baz".into(),
+                b: 5,
+                c: None,
+            },
+        )
+        .unwrap();
+    assert_eq!(std::fs::read_dir(tmp_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn tombstone_max_id_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    database.insert(obj(3)).unwrap();
+    database.delete(2).unwrap();
+    database.delete(3).unwrap();
+    database.close().unwrap();
+
+    // only the marker for the highest id survives, so that id isn't used again
+    let stats = compact(&path, &CompactOptions::new()).unwrap();
+    assert_eq!((stats.live_records, stats.tombstones), (1, 1));
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.history(2).count(), 0);
+    assert!(database.insert_with_id(3, obj(4)).is_err());
+    assert_eq!(database.insert(obj(4)).unwrap(), 4);
+
+    database.delete(4).unwrap();
+    let before = database.log_position();
+    assert_eq!(database.purge_tombstones(before).unwrap(), 1);
+    database.close().unwrap();
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.history(3).count(), 0);
+    assert_eq!(database.insert(obj(5)).unwrap(), 5);
+}
+
+#[test]
+fn compact_multiline_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    std::fs::write(
+        &path,
+        r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,
+         "a":"bar","b":66}
+        {"id":1,"deleted":true}
+    "#,
+    )
+    .unwrap();
+
+    let stats = compact(&path, &CompactOptions::new().chunk_size(16)).unwrap();
+    assert_eq!(stats.records_read, 3);
+    assert_eq!(stats.live_records, 1);
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.get(2).map(|record| record.b), Some(66));
+}
+
+#[test]
+fn merge_test() {
+    let add = |existing: Option<&MyObject>, operand: &serde_json::Value| {
+        let mut data = existing.cloned().unwrap_or(MyObject {
+            a: "counter".into(),
+            b: 0,
+            c: None,
+        });
+        data.b += operand.as_i64().unwrap() as i32;
+        data
+    };
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut database = Database::<MyObject, _>::open(&path)
+        .unwrap()
+        .with_merge_operator(add);
+    database.merge(1, 5.into()).unwrap();
+    database.merge(1, 2.into()).unwrap();
+    database.merge(2, 1.into()).unwrap();
+    database.delete(2).unwrap();
+    database.merge(2, 10.into()).unwrap();
+    assert_eq!(database.get(1).map(|record| record.b), Some(7));
+    assert_eq!(database.get(2).map(|record| record.b), Some(10));
+    database.close().unwrap();
+
+    // merges are ignored until an operator is registered
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.record_count(), 0);
+    let database = database.with_merge_operator(add);
+    assert_eq!(database.get(1).map(|record| record.b), Some(7));
+    database.close().unwrap();
+
+    let stats = compact(&path, &CompactOptions::new()).unwrap();
+    assert_eq!(stats.records_read, 5);
+    assert_eq!(stats.live_records, 2);
+
+    let database = Database::<MyObject, _>::open(&path)
+        .unwrap()
+        .with_merge_operator(add);
+    assert_eq!(database.history(1).count(), 2);
+    assert_eq!(database.history(2).count(), 2);
+    assert_eq!(database.get(1).map(|record| record.b), Some(7));
+    assert_eq!(database.get(2).map(|record| record.b), Some(10));
+
+    // a flat payload with a `_merge` field would be read back as a merge record
+    let payload = serde_json::json!({"_merge": 1});
+    let path = tmp_dir.path().join("payloads.json");
+    let mut database = Database::<serde_json::Value, _>::open(&path).unwrap();
+    assert!(database.insert(payload.clone()).is_err());
+    let mut field_names = Database::<serde_json::Value, _>::open(&path)
+        .unwrap()
+        .with_field_names("_id", "_deleted");
+    assert!(field_names.insert(payload.clone()).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+
+    // nested, it round-trips as an upsert
+    let mut database = Database::<serde_json::Value, _>::open(&path)
+        .unwrap()
+        .with_layout(Layout::Nested);
+    let id = database.insert(payload.clone()).unwrap();
+    drop(database);
+    let contents = std::fs::read(&path).unwrap();
+    let mut database = Database::<serde_json::Value, _>::new(Cursor::new(contents))
+        .unwrap()
+        .with_layout(Layout::Nested);
+    database.reload().unwrap();
+    assert_eq!(database.get(id).unwrap().data, payload);
+}
+
+#[test]
+fn lazy_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    for i in 0..10 {
+        database
+            .insert(MyObject {
+                a: i.to_string(),
+                b: i,
+                c: None,
+            })
+            .unwrap();
+    }
+    database.delete(3).unwrap();
+    database.close().unwrap();
+
+    let mut lazy = OpenOptions::new()
+        .cache_capacity(2)
+        .open_lazy::<MyObject, _>(&path)
+        .unwrap();
+    assert_eq!(lazy.record_count(), 9);
+    assert!(!lazy.contains(3));
+    assert_eq!(lazy.get(3).unwrap(), None);
+    assert_eq!(lazy.get(5).unwrap().unwrap().a, "4");
+
+    lazy.upsert(5, |data| {
+        data.cloned().map(|data| MyObject { b: 100, ..data })
+    })
+    .unwrap();
+    let id = lazy
+        .insert(MyObject {
+            a: "new".into(),
+            b: 0,
+            c: None,
+        })
+        .unwrap();
+    assert_eq!(id, 11);
+    for id in lazy.ids().collect::<Vec<_>>() {
+        assert!(lazy.get(id).unwrap().is_some());
+    }
+    lazy.close().unwrap();
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.get(5).map(|record| record.b), Some(100));
+    assert_eq!(database.record_count(), 10);
+
+    // ids are taken after catching up with other writers
+    let mut first = LazyDatabase::<MyObject>::open(&path).unwrap();
+    let mut second = LazyDatabase::<MyObject>::open(&path).unwrap();
+    assert_eq!(first.insert(obj(1)).unwrap(), 12);
+    assert_eq!(second.insert(obj(2)).unwrap(), 13);
+    assert_eq!(first.insert(obj(3)).unwrap(), 14);
+    drop((first, second));
+
+    // merges can't be resolved without reading every entry of the record
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.merge(12, serde_json::json!({ "b": 10 })).unwrap();
+    drop(database);
+    let err = LazyDatabase::<MyObject>::open(&path).err().unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}
+
+#[test]
+fn restore_test() {
+    let mut database_contents = Vec::from(
+        br#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":1,"a":"qwe","b":9}
+        {"id":1,"deleted":true}
+        {"id":2,"a":"bar","b":66}
+    "# as &[u8],
+    );
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    assert_eq!(database.get(1), None);
+    assert_eq!(database.get_deleted(1).map(|record| record.b), Some(9));
+    assert_eq!(database.get_deleted(2), None);
+    assert_eq!(database.get_deleted(3), None);
+
+    assert!(database.restore(1).unwrap());
+    assert_eq!(database.get(1).map(|record| record.b), Some(9));
+    assert_eq!(database.get_deleted(1), None);
+    assert!(!database.restore(2).unwrap());
+}
+
+#[test]
+fn versioned_test() {
+    #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+    struct MyObjectV1 {
+        a: String,
+    }
+
+    #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
+    #[serde(tag = "version")]
+    enum MyObjectVersions {
+        #[serde(rename = "1")]
+        V1(MyObjectV1),
+        #[serde(rename = "2")]
+        V2(MyObject),
+    }
+
+    impl VersionedPayload for MyObjectVersions {
+        type Latest = MyObject;
+
+        const LATEST_VERSION: &'static str = "2";
+
+        fn upgrade(self) -> MyObject {
+            match self {
+                MyObjectVersions::V1(v1) => MyObject {
+                    a: v1.a,
+                    b: 0,
+                    c: None,
+                },
+                MyObjectVersions::V2(v2) => v2,
+            }
+        }
+
+        fn is_latest(&self) -> bool {
+            matches!(self, MyObjectVersions::V2(_))
+        }
+    }
+
+    let mut database_contents = Vec::from(
+        br#"
+        {"id":1,"version":"1","a":"old"}
+        {"id":2,"version":"2","a":"new","b":2,"c":null}
+    "# as &[u8],
+    );
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<Versioned<MyObjectVersions>, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    assert_eq!(database.get(1).map(|record| record.a.as_str()), Some("old"));
+    assert_eq!(database.get(1).map(|record| record.b), Some(0));
+    assert!(database.get(1).unwrap().was_upgraded());
+    assert!(!database.get(2).unwrap().was_upgraded());
+
+    assert_eq!(database.rewrite_upgraded().unwrap(), 1);
+    assert!(!database.get(1).unwrap().was_upgraded());
+    assert_eq!(database.rewrite_upgraded().unwrap(), 0);
+    database.close().unwrap();
+
+    let last_line = std::str::from_utf8(&database_contents)
+        .unwrap()
+        .lines()
+        .last()
+        .unwrap()
+        .to_string();
+    let value: serde_json::Value = serde_json::from_str(&last_line).unwrap();
+    assert_eq!(value["version"], "2");
+    assert_eq!(value["id"], 1);
+}
+
+#[test]
+fn lock_timeout_test() {
+    use std::time::Duration;
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let obj = MyObject {
+        a: "".into(),
+        b: 0,
+        c: None,
+    };
+
+    let mut database = OpenOptions::new()
+        .lock_timeout(Some(Duration::from_millis(50)))
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    let mut unlocked = OpenOptions::new()
+        .lock(false)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+
+    let holder = std::fs::File::open(&path).unwrap();
+    holder.lock().unwrap();
+
+    let err = database.insert(obj.clone()).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::WouldBlock);
+    unlocked.insert(obj.clone()).unwrap();
+
+    holder.unlock().unwrap();
+    database.insert(obj).unwrap();
+    assert_eq!(database.record_count(), 2);
+
+    // the holder has to have locked the same inode on the same device, other than us
+    #[cfg(target_os = "linux")]
+    {
+        use crate::lock::find_lock_holder;
+
+        let locks = "1: FLOCK  ADVISORY  WRITE 1234 08:01:5678 0 EOF\n\
+                     1: -> FLOCK  ADVISORY  WRITE 99 00:2a:5678 0 EOF\n\
+                     2: FLOCK  ADVISORY  WRITE 4321 00:2a:5678 0 EOF\n";
+        assert_eq!(find_lock_holder(locks, 0x801, 5678, 1), Some(1234));
+        assert_eq!(find_lock_holder(locks, 0x2a, 5678, 1), Some(4321));
+        assert_eq!(find_lock_holder(locks, 0x2a, 5678, 4321), None);
+        assert_eq!(find_lock_holder(locks, 0x802, 5678, 1), None);
+        assert_eq!(find_lock_holder(locks, 0x801, 5679, 1), None);
+    }
+}
+
+#[test]
+fn health_check_test() {
+    use std::io::Write;
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert(obj(1)).unwrap();
+
+    let health = database.health_check();
+    assert!(health.is_healthy(), "{:?}", health);
+    assert!(matches!(health.writable, Check::Ok));
+
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    file.write_all(br#"{"id":2,"a":"#).unwrap();
+
+    let health = database.health_check();
+    assert!(!health.is_healthy());
+    assert!(health.tail.is_failed());
+    assert!(matches!(health.reachable, Check::Ok));
+
+    let mut read_only = OpenOptions::new()
+        .read_only(true)
+        .open::<MyObject, _>(tmp_dir.path().join("other.json"));
+    assert!(read_only.is_err());
+
+    std::fs::write(tmp_dir.path().join("other.json"), "").unwrap();
+    read_only = OpenOptions::new()
+        .read_only(true)
+        .open::<MyObject, _>(tmp_dir.path().join("other.json"));
+    let health = read_only.unwrap().health_check();
+    assert!(health.is_healthy());
+    assert!(matches!(health.writable, Check::Ok));
+}
+
+#[test]
+fn stats_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"a":"qwe","b":9}
+        {"id":3,"a":"hello","b":0}
+        {"id":2,"deleted":true}
+    "#;
+
+    let stream = Cursor::new(database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    let stats = database.stats().unwrap();
+    assert_eq!(stats.live_records, 2);
+    assert_eq!(stats.deleted_records, 1);
+    assert_eq!(stats.log_entries, 5);
+    assert_eq!(stats.log_bytes, database_contents.len() as u64);
+    assert_eq!(stats.min_id, Some(1));
+    assert_eq!(stats.max_id, Some(3));
+    assert_eq!(stats.field_counts["a"], 2);
+    assert!(!stats.field_counts.contains_key("c"));
+    assert!(stats.dead_bytes_ratio() > 0.5);
+    assert_eq!(stats.timings.reloads, 1);
+    assert_eq!(stats.timings.writes, 0);
+}
+
+#[test]
+fn replication_test() {
+    let obj = |a: &str, b| MyObject {
+        a: a.into(),
+        b,
+        c: None,
+    };
+
+    let mut leader = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let mut follower = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+
+    leader.insert(obj("foo", 1)).unwrap();
+    leader.insert(obj("bar", 2)).unwrap();
+
+    let mut log = Vec::new();
+    let leader_position = leader
+        .export_since(LogPosition::default(), &mut log)
+        .unwrap();
+    let follower_position = follower
+        .apply_log(&log[..], LogPosition::default())
+        .unwrap();
+    assert_eq!(
+        follower.records().collect::<Vec<_>>(),
+        leader.records().collect::<Vec<_>>()
+    );
+
+    leader.upsert(1, |_| Some(obj("qwe", 3))).unwrap();
+    leader.delete(2).unwrap();
+
+    let mut log = Vec::new();
+    let leader_position = leader.export_since(leader_position, &mut log).unwrap();
+    let follower_position = follower.apply_log(&log[..], follower_position).unwrap();
+    assert_eq!(
+        follower.records().collect::<Vec<_>>(),
+        leader.records().collect::<Vec<_>>()
+    );
+    assert_eq!(follower.insert(obj("baz", 4)).unwrap(), 3);
+
+    // the follower's own change to record 3 conflicts with the leader's
+    leader.insert(obj("hello", 5)).unwrap();
+    leader.upsert(1, |_| Some(obj("asd", 6))).unwrap();
+
+    let mut log = Vec::new();
+    leader.export_since(leader_position, &mut log).unwrap();
+    let err = follower.apply_log(&log[..], follower_position).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::AlreadyExists);
+    assert_eq!(follower.get(1).map(|record| record.b), Some(3));
+    assert_eq!(follower.get(3).map(|record| record.b), Some(4));
+}
+
+#[test]
+fn replication_format_test() {
+    let new = || {
+        Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+            .unwrap()
+            .with_field_names("_id", "_deleted")
+            .with_layout(Layout::Nested)
+    };
+
+    let mut leader = new();
+    let mut follower = new();
+    leader.insert(obj(1)).unwrap();
+    leader.insert(obj(2)).unwrap();
+    leader.delete(1).unwrap();
+
+    let mut log = Vec::new();
+    let leader_position = leader
+        .export_since(LogPosition::default(), &mut log)
+        .unwrap();
+    let follower_position = follower
+        .apply_log(&log[..], LogPosition::default())
+        .unwrap();
+    assert_eq!(
+        follower.records().collect::<Vec<_>>(),
+        leader.records().collect::<Vec<_>>()
+    );
+
+    // applied records go through the validator like any other write
+    follower.set_validator(|data: &MyObject| match data.b {
+        b if b < 0 => Err(ValidationError::new("b must not be negative")),
+        _ => Ok(()),
+    });
+    leader.upsert(2, |_| Some(obj(-1))).unwrap();
+    let mut log = Vec::new();
+    leader.export_since(leader_position, &mut log).unwrap();
+    let err = follower.apply_log(&log[..], follower_position).unwrap_err();
+    assert!(matches!(
+        Error::from_io(&err),
+        Some(Error::InvalidRecord { id: 2, .. })
+    ));
+    assert_eq!(follower.get(2).map(|record| record.b), Some(2));
+}
+
+#[test]
+fn merge_from_test() {
+    let open = |contents: &str| {
+        let mut database =
+            Database::<MyObject, _>::new(Cursor::new(contents.as_bytes().to_vec())).unwrap();
+        database.reload().unwrap();
+        database
+    };
+    let ours = r#"
+        {"id":1,"a":"one","b":1,"_meta":{"updated_at":100}}
+        {"id":2,"a":"two","b":2,"_meta":{"updated_at":300}}
+        {"id":3,"a":"three","b":3}
+    "#;
+    let theirs = open(
+        r#"
+        {"id":1,"a":"uno","b":1,"_meta":{"updated_at":200}}
+        {"id":2,"a":"dos","b":2,"_meta":{"updated_at":200}}
+        {"id":3,"a":"three","b":3}
+        {"id":4,"a":"four","b":4}
+    "#,
+    );
+    let names = |database: &Database<MyObject, _>| {
+        database
+            .records()
+            .map(|record| record.a.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let mut database = open(ours);
+    assert_eq!(
+        database
+            .merge_from(&theirs, ConflictStrategy::PreferSelf)
+            .unwrap(),
+        1
+    );
+    assert_eq!(names(&database), vec!["one", "two", "three", "four"]);
+
+    let mut database = open(ours);
+    assert_eq!(
+        database
+            .merge_from(&theirs, ConflictStrategy::PreferOther)
+            .unwrap(),
+        3
+    );
+    assert_eq!(names(&database), vec!["uno", "dos", "three", "four"]);
+
+    let mut database = open(ours);
+    assert_eq!(
+        database
+            .merge_from(&theirs, ConflictStrategy::PreferNewer)
+            .unwrap(),
+        2
+    );
+    assert_eq!(names(&database), vec!["uno", "two", "three", "four"]);
+
+    let mut database = open(ours);
+    let strategy = ConflictStrategy::resolve(|_, ours: &MyObject, theirs: &MyObject| MyObject {
+        a: format!("{}/{}", ours.a, theirs.a),
+        ..ours.clone()
+    });
+    assert_eq!(database.merge_from(&theirs, strategy).unwrap(), 3);
+    assert_eq!(
+        names(&database),
+        vec!["one/uno", "two/dos", "three", "four"]
+    );
+}
+
+#[test]
+fn merge_from_untimestamped_test() {
+    let open = |contents: &str| {
+        let mut database =
+            Database::<MyObject, _>::new(Cursor::new(contents.as_bytes().to_vec())).unwrap();
+        database.reload().unwrap();
+        database
+    };
+    let ours = open(
+        r#"
+        {"id":1,"a":"one","b":1}
+        {"id":2,"a":"two","b":2,"_meta":{"updated_at":100}}
+        {"id":3,"a":"three","b":3}
+    "#,
+    );
+    let theirs = open(
+        r#"
+        {"id":1,"a":"uno","b":1}
+        {"id":2,"a":"dos","b":2,"_meta":{"updated_at":100}}
+        {"id":3,"a":"tres","b":3,"_meta":{"updated_at":100}}
+    "#,
+    );
+
+    // neither side is newer, so theirs are taken, with or without timestamps
+    let mut database = ours;
+    assert_eq!(
+        database
+            .merge_from(&theirs, ConflictStrategy::PreferNewer)
+            .unwrap(),
+        3
+    );
+    let names = database
+        .records()
+        .map(|record| record.a.clone())
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec!["uno", "dos", "tres"]);
+
+    // and a record without a timestamp still loses to one with
+    let mut theirs = open(r#"{"id":3,"a":"three","b":3}"#);
+    assert_eq!(
+        theirs
+            .merge_from(&database, ConflictStrategy::PreferNewer)
+            .unwrap(),
+        3
+    );
+    assert_eq!(theirs.get(3).map(|record| record.a.as_str()), Some("tres"));
+    assert_eq!(
+        database
+            .merge_from(&theirs, ConflictStrategy::PreferNewer)
+            .unwrap(),
+        0
+    );
+}
+
+#[test]
+fn checksum_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert(obj.clone()).unwrap();
+    database.close().unwrap();
+
+    let mut database = OpenOptions::new()
+        .checksums(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    database.insert(obj.clone()).unwrap();
+    database.close().unwrap();
+
+    // records with and without checksums can be mixed
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents.lines().collect::<Vec<_>>();
+    assert!(!lines[0].contains("_crc"));
+    assert!(lines[1].contains(r#","_crc":"#));
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.get(2).map(|record| &record.data), Some(&obj));
+    database.close().unwrap();
+
+    let lazy = LazyDatabase::<MyObject>::open(&path).unwrap();
+    assert_eq!(lazy.record_count(), 2);
+    lazy.close().unwrap();
+
+    // a value that still parses but no longer matches its checksum is rejected
+    let offset = lines[0].len() as u64 + 1;
+    std::fs::write(
+        &path,
+        contents.replace(
+            r#""a":"foo","b":1,"c":null,"_crc""#,
+            r#""a":"fox","b":1,"c":null,"_crc""#,
+        ),
+    )
+    .unwrap();
+    let err = Database::<MyObject, _>::open(&path).err().unwrap();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert!(matches!(
+        Error::from_io(&err),
+        Some(Error::ChecksumMismatch { offset: o }) if *o == offset
+    ));
+
+    let mut lazy = LazyDatabase::<MyObject>::open(&path).unwrap();
+    let err = lazy.get(2).unwrap_err();
+    assert!(matches!(
+        Error::from_io(&err),
+        Some(Error::ChecksumMismatch { offset: o }) if *o == offset
+    ));
+}
+
+#[test]
+fn checksum_key_test() {
+    let payload = serde_json::json!({ "_crc": 5 });
+
+    // without checksums, the property would be taken for one when reading the record back
+    let mut database = Database::<serde_json::Value, _>::new(Cursor::new(Vec::new())).unwrap();
+    let err = database.insert(payload.clone()).unwrap_err();
+    assert!(
+        matches!(
+            Error::from_io(&err),
+            Some(Error::InvalidRecord { id: 1, .. })
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(database.record_count(), 0);
+    let id = database
+        .insert(serde_json::json!({ "_crc": 5, "a": 1 }))
+        .unwrap();
+    database.reload().unwrap();
+    assert_eq!(
+        database.get(id).map(|record| &record.data),
+        Some(&serde_json::json!({ "_crc": 5, "a": 1 }))
+    );
+
+    // with checksums, only the one appended after it is
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let mut database = OpenOptions::new()
+        .checksums(true)
+        .open::<serde_json::Value, _>(&path)
+        .unwrap();
+    let id = database.insert(payload.clone()).unwrap();
+    database.close().unwrap();
+    let database = Database::<serde_json::Value, _>::open(&path).unwrap();
+    assert_eq!(database.get(id).map(|record| &record.data), Some(&payload));
+}
+#[test]
+fn header_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+
+    let mut database = OpenOptions::new()
+        .header(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    let header = database.metadata().cloned().unwrap();
+    assert_eq!(header.version, FORMAT_VERSION);
+    assert_eq!(header.id_type.as_deref(), Some("u32"));
+    assert!(header.created.is_some());
+    database.insert(obj.clone()).unwrap();
+    database.insert(obj.clone()).unwrap();
+    database.delete(1).unwrap();
+    database.close().unwrap();
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.metadata(), Some(&header));
+    assert_eq!(database.record_count(), 1);
+    database.close().unwrap();
+
+    let lazy = LazyDatabase::<MyObject>::open(&path).unwrap();
+    assert_eq!(lazy.metadata(), Some(&header));
+    assert_eq!(lazy.ids().collect::<Vec<_>>(), vec![2]);
+    lazy.close().unwrap();
+
+    compact(&path, &CompactOptions::new()).unwrap();
+    let database = OpenOptions::new()
+        .header(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    assert_eq!(database.metadata(), Some(&header));
+    assert_eq!(database.record_count(), 1);
+    database.close().unwrap();
+
+    // files without a header are only rejected when one is required
+    let other = tmp_dir.path().join("other.json");
+    std::fs::write(&other, "{\"id\":1,\"a\":\"foo\",\"b\":1}\n").unwrap();
+    let database = Database::<MyObject, _>::open(&other).unwrap();
+    assert_eq!(database.metadata(), None);
+    database.close().unwrap();
+    let err = OpenOptions::new()
+        .header(true)
+        .open::<MyObject, _>(&other)
+        .err()
+        .unwrap();
+    assert!(matches!(Error::from_io(&err), Some(Error::MissingHeader)));
+
+    std::fs::write(&other, "{\"jsondb\":2}\n").unwrap();
+    let err = Database::<MyObject, _>::open(&other).err().unwrap();
+    assert!(matches!(
+        Error::from_io(&err),
+        Some(Error::UnsupportedHeader(_))
+    ));
+}
+
+#[test]
+fn update_where_test() {
+    let mut database_contents = Vec::from(
+        br#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":3,"a":"baz","b":5}
+        {"id":3,"deleted":true}
+    "# as &[u8],
+    );
+
+    let stream = Cursor::new(&mut database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+    let before = database.log_position();
+
+    let count = database
+        .update_where(
+            |data| data.c.is_none(),
+            |data| MyObject {
+                c: Some(0),
+                ..data.clone()
+            },
+        )
+        .unwrap();
+    assert_eq!(count, 1);
+    assert_eq!(database.get(2).and_then(|record| record.c), Some(0));
+    assert_eq!(database.get(1).and_then(|record| record.c), Some(99));
+    assert!(database.get(3).is_none());
+
+    let count = database
+        .update_where(
+            |_| true,
+            |data| MyObject {
+                b: data.b + 1,
+                ..data.clone()
+            },
+        )
+        .unwrap();
+    assert_eq!(count, 2);
+    assert_eq!(database.changes_since(before).unwrap().len(), 3);
+    assert_eq!(database.update_where(|_| false, Clone::clone).unwrap(), 0);
+
+    assert_eq!(database.undo_last(2).unwrap(), 2);
+    assert_eq!(database.get(1).map(|record| record.b), Some(33));
+    assert_eq!(database.get(2).map(|record| record.b), Some(66));
+}
+
+#[test]
+fn sorted_and_mapped_records_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"a":"qwe","b":9}
+        {"id":3,"a":"hello","b":0}
+        {"id":4,"a":"abc","b":12}
+        {"id":4,"deleted":true}
+    "#;
+
+    let stream = Cursor::new(database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    assert_eq!(
+        database
+            .records_sorted_by(|x, y| x.b.cmp(&y.b))
+            .map(|record| record.id)
+            .collect::<Vec<_>>(),
+        vec![3, 1, 2]
+    );
+    assert_eq!(
+        database
+            .records_sorted_by(|x, y| x.a.cmp(&y.a))
+            .map(|record| record.a.as_str())
+            .collect::<Vec<_>>(),
+        vec!["bar", "hello", "qwe"]
+    );
+    assert_eq!(
+        database
+            .records_map(|record| (record.id, record.b))
+            .collect::<Vec<_>>(),
+        vec![(1, 9), (2, 66), (3, 0)]
+    );
+
+    // records are projected as they're taken, in id order, straight from the index
+    let mut projected = 0;
+    let first = database
+        .records_map(|record| {
+            projected += 1;
+            record.id
+        })
+        .next();
+    assert_eq!((first, projected), (Some(1), 1));
+
+    assert_eq!(
+        database
+            .as_of(LogPosition::new(2, 0))
+            .unwrap()
+            .records_map(|record| record.a.clone())
+            .collect::<Vec<_>>(),
+        vec!["foo", "bar"]
+    );
+}
+
+#[test]
+fn sample_test() {
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::collections::HashMap;
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    for b in 0..20 {
+        database.insert(obj(b)).unwrap();
+    }
+    for id in 11..=20 {
+        database.delete(id).unwrap();
+    }
+
+    let mut rng = StdRng::seed_from_u64(42);
+    let mut counts = HashMap::new();
+    for _ in 0..1000 {
+        let sample = database.sample(3, &mut rng);
+        assert_eq!(sample.len(), 3);
+        assert!(sample.windows(2).all(|w| w[0].id < w[1].id));
+        for record in sample {
+            assert!(record.id <= 10);
+            *counts.entry(record.id).or_insert(0) += 1;
+        }
+    }
+    // every live record shows up roughly 300 times
+    assert_eq!(counts.len(), 10);
+    assert!(counts.values().all(|&count| (200..400).contains(&count)));
+
+    assert_eq!(database.sample(50, &mut rng).len(), 10);
+    assert!(database.sample(0, &mut rng).is_empty());
+}
+
+#[test]
+fn count_and_contains_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":2,"deleted":true}
+        {"id":3,"_merge":5}
+        {"id":1,"a":"qwe","b":9}
+        {"id":4,"deleted":true}
+    "#;
+
+    let stream = Cursor::new(database_contents);
+    let mut database = Database::<MyObject, _>::new(stream).unwrap();
+    database.reload().unwrap();
+
+    assert_eq!(database.record_count(), 1);
+    assert!(database.contains(1));
+    assert!(!database.contains(2));
+    assert!(!database.contains(3));
+    assert!(!database.contains(4));
+    assert_eq!(database.get(1).map(|record| record.b), Some(9));
+
+    // resolving merges brings record 3 to life
+    let database = database.with_merge_operator(|existing, operand| MyObject {
+        a: "counter".into(),
+        b: existing.map_or(0, |data| data.b) + operand.as_i64().unwrap() as i32,
+        c: None,
+    });
+    assert_eq!(database.record_count(), 2);
+    assert_eq!(database.get(3).map(|record| record.b), Some(5));
+    assert_eq!(database.record_count(), database.records().count());
+
+    // reads go through the index of latest entries, and agree with scanning the log for them
+    let end = database.log_position();
+    let scanned = database
+        .as_of(LogPosition::new(end.records - 1, 0))
+        .unwrap();
+    assert_eq!(
+        database.records().collect::<Vec<_>>(),
+        scanned.records().collect::<Vec<_>>()
+    );
+    assert_eq!(database.as_of(end).unwrap().get(3), scanned.get(3));
+    assert_eq!(database.get_deleted(2).map(|record| record.b), Some(66));
+    assert_eq!(database.get_deleted(2), scanned.get_deleted(2));
+    assert_eq!(database.get_deleted(1), None);
+    assert_eq!(database.get_deleted(4), None);
+}
+
+#[test]
+fn keyed_database_test() {
+    let database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let mut keyed = KeyedDatabase::new(database, |data: &MyObject| data.a.clone());
+
+    let foo = keyed.upsert_by_key(obj(1)).unwrap();
+    let bar = keyed
+        .upsert_by_key(MyObject {
+            a: "bar".into(),
+            b: 2,
+            c: None,
+        })
+        .unwrap();
+    assert_ne!(foo, bar);
+
+    let id = keyed.upsert_by_key(obj(3)).unwrap();
+    assert_eq!(id, foo);
+    assert_eq!(keyed.record_count(), 2);
+    assert_eq!(
+        keyed.get_by_key(&"foo".into()).map(|record| record.b),
+        Some(3)
+    );
+
+    assert!(keyed.delete_by_key(&"bar".into()).unwrap());
+    assert!(!keyed.delete_by_key(&"bar".into()).unwrap());
+    assert!(keyed.get_by_key(&"bar".into()).is_none());
+
+    // the index is rebuilt from existing contents
+    let keyed = KeyedDatabase::new(keyed.into_inner(), |data: &MyObject| data.b);
+    assert_eq!(keyed.id_for_key(&3), Some(foo));
+    assert_eq!(keyed.id_for_key(&2), None);
+}
+
+#[test]
+fn auto_compact_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsondb");
+
+    let policy = CompactionPolicy::new()
+        .max_dead_ratio(Some(0.5))
+        .min_file_size(0);
+    let mut database = OpenOptions::new()
+        .auto_compact(Some(policy))
+        .open::<MyObject, _>(&path)
+        .unwrap();
+
+    let id = database.insert(obj(0)).unwrap();
+    for b in 1..100 {
+        database.upsert(id, |_| Some(obj(b))).unwrap();
+    }
+
+    // the log never grows beyond a couple of entries
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert!(contents.lines().count() <= 2, "{}", contents);
+    assert_eq!(database.get(id).map(|record| record.b), Some(99));
+
+    let next = database
+        .insert(MyObject {
+            a: "bar".into(),
+            b: 0,
+            c: None,
+        })
+        .unwrap();
+    assert_ne!(next, id);
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.get(id).map(|record| record.b), Some(99));
+    assert_eq!(database.record_count(), 2);
+}
+
+#[test]
+fn segmented_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let opts = OpenOptions::new().max_segment_size(100);
+
+    let mut database = opts.clone().open_segmented::<MyObject, _>(&path).unwrap();
+    let mut reader = opts
+        .clone()
+        .read_only(true)
+        .open_segmented::<MyObject, _>(&path)
+        .unwrap();
+
+    for b in 0..10 {
+        database.insert(obj(b)).unwrap();
+    }
+    database.delete(1).unwrap();
+
+    assert!(SegmentedFile::segment_path(&path, 1).exists());
+    assert!(SegmentedFile::segment_path(&path, 2).exists());
+    for number in 1.. {
+        let size = match std::fs::metadata(SegmentedFile::segment_path(&path, number)) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => break,
+        };
+        assert!(size <= 100, "{}", size);
+    }
+
+    // readers pick up segments created after they were opened
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 9);
+
+    let database = opts.open_segmented::<MyObject, _>(&path).unwrap();
+    assert_eq!(database.record_count(), 9);
+    assert_eq!(database.get(10).map(|record| record.b), Some(9));
+    assert!(database.get(1).is_none());
+}
+
+#[test]
+fn backup_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let backup_path = tmp_dir.path().join("backup.json");
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    let position = database.backup_to(&backup_path).unwrap();
+    assert_eq!(position, database.log_position());
+
+    database.insert(obj(3)).unwrap();
+    database.delete(1).unwrap();
+    drop(database);
+
+    let database =
+        Database::<MyObject, _>::restore_from(&backup_path, &path, OpenOptions::new()).unwrap();
+    assert_eq!(database.log_position(), position);
+    assert_eq!(
+        database
+            .records()
+            .map(|record| record.b)
+            .collect::<Vec<_>>(),
+        vec![1, 2]
+    );
+
+    // a corrupt backup is rejected and leaves the database untouched
+    std::fs::write(&backup_path, "{\"id\":1,").unwrap();
+    assert!(
+        Database::<MyObject, _>::restore_from(&backup_path, &path, OpenOptions::new()).is_err()
+    );
+    assert_eq!(
+        Database::<MyObject, _>::open(&path).unwrap().record_count(),
+        2
+    );
+
+    // writes held back by the flush policy go into the backup as well
+    let mut database = OpenOptions::new()
+        .flush_policy(FlushPolicy::EveryN(10))
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    database.insert(obj(3)).unwrap();
+    let position = database.backup_to(&backup_path).unwrap();
+    assert_eq!(position, database.log_position());
+    drop(database);
+    let backup = Database::<MyObject, _>::open_read_only(&backup_path).unwrap();
+    assert_eq!(backup.log_position(), position);
+    assert_eq!(backup.record_count(), 3);
+}
+
+#[test]
+fn filter_test() {
+    let value = serde_json::json!({"id": 1, "a": "foo", "b": 7, "c": null, "d": {"e": [1, 2]}});
+    let matches = |filter: &str| Filter::parse(filter).unwrap().matches(&value);
+
+    assert!(matches(r#"b > 5 && a == "foo""#));
+    assert!(!matches(r#"b > 5 && a != "foo""#));
+    assert!(matches("b >= 7.0 && b <= 7"));
+    assert!(matches("!(b < 5) || missing"));
+    assert!(matches("d.e.1 == 2 && d.e.5 == null"));
+    assert!(matches("c == null && !c && !missing"));
+    assert!(!matches(r#"b == "7""#));
+    assert!(matches(r#"a == "foo""#));
+
+    let err = Filter::parse("b > && a").unwrap_err();
+    assert!(
+        matches!(err, Error::InvalidFilter { position: 4, .. }),
+        "{:?}",
+        err
+    );
+    assert!(Filter::parse("(b > 1").is_err());
+    assert!(Filter::parse("b > 1 a").is_err());
+}
+
+#[test]
+fn append_only_test() {
+    let mut database = Database::<MyObject, _>::append_only(Vec::new()).unwrap();
+    let id = database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    database.upsert(id, |_| Some(obj(3))).unwrap();
+    database.delete(2).unwrap();
+    assert_eq!(database.get(id).map(|record| record.b), Some(3));
+    assert_eq!(database.record_count(), 1);
+
+    let written = database.into_writer();
+    let mut database = Database::<MyObject, _>::new(Cursor::new(written)).unwrap();
+    database.reload().unwrap();
+    assert_eq!(database.get(id).map(|record| record.b), Some(3));
+    assert!(database.get(2).is_none());
+}
+
+#[test]
+fn from_reader_test() {
+    let database_contents = r#"
+        {"jsondb":1}
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"a":"qwe","b":9}
+        {"id":2,"deleted":true}
+    "#;
+
+    // a plain `Read`, with no way to seek
+    let reader = io::Read::chain(database_contents.as_bytes(), &b""[..]);
+    let mut database = Database::<MyObject, _>::from_reader(reader).unwrap();
+    assert!(database.metadata().is_some());
+    assert_eq!(database.record_count(), 1);
+    assert_eq!(database.get(1).map(|record| record.b), Some(9));
+    assert!(database.get(2).is_none());
+
+    database.reload().unwrap();
+    assert_eq!(database.record_count(), 1);
+    assert_eq!(database.log_position().records, 4);
+}
+
+#[test]
+fn etag_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_cache_tag(StateCacheTag::new());
+    database.insert(obj(1)).unwrap();
+
+    let etag = database.etag();
+    assert!(etag.starts_with('"') && etag.ends_with('"'), "{}", etag);
+    assert_eq!(database.check_precondition(&etag), Ok(()));
+    assert_eq!(database.check_precondition("*"), Ok(()));
+    assert_eq!(
+        database.check_precondition(&format!("\"other\", {}", etag)),
+        Ok(())
+    );
+    assert!(database.check_precondition(&format!("W/{}", etag)).is_err());
+
+    database.upsert(1, |_| Some(obj(2))).unwrap();
+    assert_eq!(
+        database.check_precondition(&etag),
+        Err(PreconditionFailed {
+            etag: database.etag()
+        })
+    );
+
+    // writing back the same content restores the tag
+    database.upsert(1, |_| Some(obj(1))).unwrap();
+    assert_eq!(database.etag(), etag);
+
+    // without per-record tags, records are tagged like `ContentCacheTag` does, which is stable
+    let mut untagged = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let mut content = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_cache_tag(ContentCacheTag::new());
+    untagged.insert(obj(1)).unwrap();
+    content.insert(obj(1)).unwrap();
+    assert!(content.record_tag(1).is_some());
+    assert_eq!(untagged.record_tag(1), content.record_tag(1));
+}
+
+#[test]
+fn shared_database_test() {
+    let database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let shared = database.into_shared();
+
+    // the handle itself is shared between threads, without any external locking
+    crossbeam::scope(|scope| {
+        for b in 0..4 {
+            let shared = &shared;
+            scope.spawn(move |_| {
+                let id = shared.insert(obj(b)).unwrap();
+                assert_eq!(shared.get(id).unwrap().map(|record| record.data.b), Some(b));
+            });
+        }
+    })
+    .unwrap();
+
+    assert_eq!(shared.record_count().unwrap(), 4);
+    shared.delete(1).unwrap();
+    assert!(!shared.contains(1).unwrap());
+    assert_eq!(shared.records().unwrap().len(), 3);
+
+    // readers don't hold each other up, even across threads
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+    assert_send_sync(&shared);
+    let read = shared.read();
+    crossbeam::scope(|scope| {
+        scope.spawn(|_| assert_eq!(shared.record_count().unwrap(), 3));
+    })
+    .unwrap();
+    drop(read);
+
+    // a handle taken before a write keeps the record as it was; which thread got id 2 is a race
+    let record = shared.get(2).unwrap().unwrap();
+    let b = record.data.b;
+    shared
+        .upsert(2, |data| {
+            data.map(|data| MyObject {
+                b: 10,
+                ..data.clone()
+            })
+        })
+        .unwrap();
+    assert_eq!(record.data.b, b);
+    assert_eq!(shared.get(2).unwrap().map(|record| record.data.b), Some(10));
+}
+
+#[test]
+fn shared_database_concurrency_test() {
+    let database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let shared = database.into_shared();
+    let id = shared
+        .insert(MyObject {
+            a: "counter".into(),
+            b: 0,
+            c: None,
+        })
+        .unwrap();
+
+    // writes are serialized, so no increment is lost, while readers only ever see whole writes
+    crossbeam::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|_| {
+                for _ in 0..50 {
+                    shared
+                        .upsert(id, |data| {
+                            data.map(|data| MyObject {
+                                b: data.b + 1,
+                                ..data.clone()
+                            })
+                        })
+                        .unwrap();
+                }
+            });
+        }
+        for _ in 0..2 {
+            scope.spawn(|_| {
+                let mut last = 0;
+                while last < 200 {
+                    let b = shared.get(id).unwrap().unwrap().data.b;
+                    assert!(b >= last);
+                    last = b;
+                }
+            });
+        }
+    })
+    .unwrap();
+
+    assert_eq!(
+        shared.get(id).unwrap().map(|record| record.data.b),
+        Some(200)
+    );
+    assert_eq!(shared.read().history(id).count(), 201);
+}
+
+#[test]
+fn auto_reload_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let on_read = OpenOptions::new()
+        .auto_reload(AutoReload::OnRead)
+        .open::<MyObject, _>(&path)
+        .unwrap()
+        .into_shared();
+    let manual = Database::<MyObject, _>::open(&path).unwrap().into_shared();
+    let mut lazy = OpenOptions::new()
+        .auto_reload(AutoReload::OnRead)
+        .open_lazy::<MyObject, _>(&path)
+        .unwrap();
+    let mut interval = OpenOptions::new()
+        .auto_reload(AutoReload::OnInterval(Duration::from_secs(3600)))
+        .open::<MyObject, _>(&path)
+        .unwrap();
+
+    let id = writer.insert(obj(1)).unwrap();
+    assert_eq!(
+        on_read.get(id).unwrap().map(|record| record.data.b),
+        Some(1)
+    );
+    assert_eq!(lazy.get(id).unwrap().map(|record| record.data.b), Some(1));
+    assert_eq!(manual.get(id).unwrap(), None);
+
+    // the interval hasn't passed since the database was opened
+    assert!(!interval.reload_due());
+    interval.reload_if_due().unwrap();
+    assert_eq!(interval.record_count(), 0);
+}
+
+#[test]
+fn is_stale_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open(&path).unwrap();
+    assert!(!reader.is_stale().unwrap());
+
+    writer.insert(obj(1)).unwrap();
+    assert!(reader.is_stale().unwrap());
+    reader.reload().unwrap();
+    assert!(!reader.is_stale().unwrap());
+
+    // replacing the file, as compaction does, also counts, until the reader moves on to it
+    compact(&path, &CompactOptions::new()).unwrap();
+    assert!(reader.is_stale().unwrap());
+    reader.reload().unwrap();
+    assert!(!reader.is_stale().unwrap());
+
+    // the writer moves on as well when it next writes
+    writer
+        .insert(MyObject {
+            a: "bar".into(),
+            b: 2,
+            c: None,
+        })
+        .unwrap();
+    assert!(!writer.is_stale().unwrap());
+    assert!(reader.is_stale().unwrap());
+    reader.reload().unwrap();
+    assert!(!reader.is_stale().unwrap());
+    assert_eq!(reader.record_count(), 2);
+}
+
+#[cfg(feature = "notify")]
+#[test]
+fn watch_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open(&path).unwrap();
+    reader.watch().unwrap();
+    reader.reload().unwrap();
+    assert!(!reader.is_stale().unwrap());
+
+    writer.insert(obj(1)).unwrap();
+
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while !reader.is_stale().unwrap() {
+        assert!(std::time::Instant::now() < deadline, "no change noticed");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 1);
+}
+
+#[test]
+fn validator_test() {
+    let non_negative = |data: &MyObject| {
+        if data.b < 0 {
+            return Err(ValidationError::new("b must not be negative"));
+        }
+        Ok(())
+    };
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj(-1)).unwrap();
+    database.set_validator(non_negative);
+
+    let id = database.insert(obj(1)).unwrap();
+    let err = database.upsert(id, |_| Some(obj(-2))).unwrap_err();
+    assert!(
+        matches!(
+            Error::from_io(&err),
+            Some(Error::InvalidRecord { id: 2, .. })
+        ),
+        "{:?}",
+        err
+    );
+    assert_eq!(database.get(id).map(|record| record.b), Some(1));
+
+    // the record written before the validator was set is only rejected on request
+    assert!(database
+        .set_reload_validation(ReloadValidation::Strict)
+        .is_err());
+    database
+        .set_reload_validation(ReloadValidation::Lenient)
+        .unwrap();
+    assert!(database.get(1).is_none());
+    assert_eq!(database.record_count(), 1);
+}
+
+#[test]
+fn staged_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+
+    let mut staged = database.staged();
+    let id = staged.insert(obj(3)).unwrap();
+    staged.upsert(1, |data| {
+        data.map(|data| MyObject {
+            b: 10,
+            ..data.clone()
+        })
+    });
+    staged.delete(2);
+    assert_eq!(id, 3);
+    assert_eq!(staged.get(1).map(|record| record.b), Some(10));
+    assert!(!staged.contains(2));
+    assert_eq!(
+        staged.records().map(|record| record.id).collect::<Vec<_>>(),
+        vec![1, 3]
+    );
+    staged.discard();
+    assert_eq!(database.get(1).map(|record| record.b), Some(1));
+    assert_eq!(database.record_count(), 2);
+
+    let mut staged = database.staged();
+    staged.insert(obj(3)).unwrap();
+    staged.delete(2);
+    assert_eq!(staged.pending().len(), 2);
+    staged.commit().unwrap();
+    assert_eq!(database.get(3).map(|record| record.b), Some(3));
+    assert!(!database.contains(2));
+    assert_eq!(database.undo_last(2).unwrap(), 2);
+    assert_eq!(database.record_count(), 2);
+}
+
+#[test]
+fn id_allocator_test() {
+    struct FixedClock(SystemTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> SystemTime {
+            self.0
+        }
+    }
+
+    // node 3 of up to 16 gets ids 0x3000_0000 to 0x3fff_ffff
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_id_allocator(ShardedIds::new(3, 4));
+    database.upsert(0x4000_0000, |_| Some(obj(1))).unwrap();
+    assert_eq!(database.insert(obj(1)).unwrap(), 0x3000_0000);
+    assert_eq!(database.insert(obj(1)).unwrap(), 0x3000_0001);
+    assert_eq!(ShardedIds::new(3, 4).node_of(0x3000_0001), 3);
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_id_allocator(RandomIds::new(10..=12));
+    let mut ids = (0..3)
+        .map(|_| database.insert(obj(1)).unwrap())
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    assert_eq!(ids, vec![10, 11, 12]);
+    let err = database.insert(obj(1)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::StorageFull);
+
+    // two minutes after the epoch, with two random bits
+    let epoch = SystemTime::UNIX_EPOCH;
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_id_allocator(
+            TimeOrderedIds::new()
+                .epoch(epoch)
+                .random_bits(2)
+                .clock(FixedClock(epoch + Duration::from_secs(150))),
+        );
+    let ids = (0..5)
+        .map(|_| database.insert(obj(1)).unwrap())
+        .collect::<Vec<_>>();
+    assert!(ids[..4].iter().all(|id| (8..12).contains(id)), "{:?}", ids);
+    assert_eq!(ids[4], 12);
+
+    // staged inserts skip each other's ids
+    let mut staged = database.staged();
+    let first = staged.insert(obj(1)).unwrap();
+    let second = staged.insert(obj(1)).unwrap();
+    assert_ne!(first, second);
+    staged.discard();
+}
+
+#[test]
+fn insert_with_id_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert_with_id(10, obj(1)).unwrap();
+    database.insert_with_id(5, obj(2)).unwrap();
+    database.delete(5).unwrap();
+
+    for id in [10, 5] {
+        let err = database.insert_with_id(id, obj(3)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(
+            matches!(Error::from_io(&err), Some(Error::IdInUse { id: found }) if *found == id),
+            "{:?}",
+            err
+        );
+    }
+    assert_eq!(database.get(10).map(|record| record.b), Some(1));
+    assert_eq!(database.insert(obj(4)).unwrap(), 11);
+}
+
+#[test]
+fn get_or_insert_with_test() {
+    let obj = |a: &str, b| MyObject {
+        a: a.into(),
+        b,
+        c: None,
+    };
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    let mut other = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert(obj("foo", 1)).unwrap();
+
+    let (id, record) = database
+        .get_or_insert_with(|data| data.a == "foo", || obj("foo", 2))
+        .unwrap();
+    assert_eq!((id, record.b), (1, 1));
+
+    // the other handle sees the record inserted by the first one, rather than adding its own
+    let (id, record) = database
+        .get_or_insert_with(|data| data.a == "bar", || obj("bar", 3))
+        .unwrap();
+    assert_eq!((id, record.b), (2, 3));
+    let (id, record) = other
+        .get_or_insert_with(|data| data.a == "bar", || obj("bar", 4))
+        .unwrap();
+    assert_eq!((id, record.b), (2, 3));
+    assert_eq!(other.record_count(), 2);
+}
+
+#[test]
+fn project_test() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Name {
+        a: String,
+    }
+
+    let obj = |a: &str, b| MyObject {
+        a: a.into(),
+        b,
+        c: None,
+    };
+
+    let rename = |existing: Option<&MyObject>, operand: &serde_json::Value| {
+        let mut data = existing.cloned().unwrap();
+        data.a = operand.as_str().unwrap().to_string();
+        data
+    };
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_checksums(true)
+        .with_merge_operator(rename);
+    database.insert(obj("foo", 1)).unwrap();
+    database.insert(obj("bar", 2)).unwrap();
+    database.insert(obj("baz", 3)).unwrap();
+    database.upsert(1, |_| Some(obj("qux", 4))).unwrap();
+    database.delete(2).unwrap();
+    database.merge(3, serde_json::json!("quux")).unwrap();
+
+    let names = database.project::<Name>().unwrap();
+    assert_eq!(
+        names,
+        vec![
+            RecordData {
+                id: 1,
+                data: Name { a: "qux".into() }
+            },
+            RecordData {
+                id: 3,
+                data: Name { a: "quux".into() }
+            },
+        ]
+    );
+    assert_eq!(database.insert(obj("foo", 5)).unwrap(), 4);
+
+    // loaded from the log, the entries themselves are read, checksums and all
+    let mut log = database.into_inner();
+    log.set_position(0);
+    let mut database = Database::<MyObject, _>::new(log)
+        .unwrap()
+        .with_checksums(true)
+        .with_merge_operator(rename);
+    database.reload().unwrap();
+    let names = database.project::<Name>().unwrap();
+    assert_eq!(
+        names.into_iter().map(|r| r.data.a).collect::<Vec<_>>(),
+        vec!["qux", "quux", "foo"]
+    );
+}
+
+#[test]
+fn entry_offset_test() {
+    let log = "{\"id\":1,\"a\":\"one\",\"b\":1}\n\n  {\"id\":2,\"a\":\"two\",\"b\":2}\n{\"id\":1,\"a\":\"uno\",\"b\":1}\n";
+    let mut database = Database::<MyObject, _>::new(Cursor::new(log.as_bytes().to_vec())).unwrap();
+    database.reload().unwrap();
+
+    // offsets point at the latest entry itself, past any whitespace before it
+    let offset = |entry: &str| log.find(entry).map(|offset| offset as u64);
+    assert_eq!(database.entry_offset(1), offset("{\"id\":1,\"a\":\"uno\""));
+    assert_eq!(database.entry_offset(2), offset("{\"id\":2"));
+    assert_eq!(database.entry_offset(3), None);
+
+    // records written through the handle weren't read from the log
+    database
+        .upsert(2, |data| {
+            data.map(|data| MyObject {
+                b: 20,
+                ..data.clone()
+            })
+        })
+        .unwrap();
+    assert_eq!(database.entry_offset(2), None);
+    database.delete(1).unwrap();
+    assert_eq!(database.entry_offset(1), None);
+}
+
+#[test]
+fn project_concatenated_test() {
+    #[derive(Deserialize)]
+    struct Name {
+        a: String,
+    }
+
+    let dir = tempfile::tempdir().unwrap();
+    let object = |a: &str| MyObject {
+        a: a.to_string(),
+        b: 0,
+        c: None,
+    };
+    let mut logs = Vec::new();
+    for names in [["foo", "bar"], ["foo", "baz"]] {
+        let path = dir.path().join(format!("{}.json", names[1]));
+        let mut database = OpenOptions::new()
+            .header(true)
+            .open::<MyObject, _>(&path)
+            .unwrap();
+        for name in names {
+            database.insert(object(name)).unwrap();
+        }
+        drop(database);
+        logs.push(std::fs::read(&path).unwrap());
+    }
+
+    // the second log's record 2 is loaded as record 3, so the first log's record 2 is the one kept
+    let path = dir.path().join("merged.json");
+    std::fs::write(&path, logs.concat()).unwrap();
+    let mut database = OpenOptions::new()
+        .header(true)
+        .tolerate_duplicates(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    let names = database
+        .project::<Name>()
+        .unwrap()
+        .into_iter()
+        .map(|record| (record.id, record.data.a))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        names,
+        vec![(1, "foo".into()), (2, "bar".into()), (3, "baz".into())]
+    );
+}
+
+#[test]
+fn project_skipped_test() {
+    #[derive(Debug, PartialEq, Deserialize)]
+    struct Name {
+        a: String,
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    std::fs::write(
+        &path,
+        concat!(
+            "{\"id\":1,\"a\":\"foo\",\"b\":1}\n",
+            "{\"id\":1,\"a\":\"bar\",\"b\":-1}\n",
+            "not json\n",
+            "{\"id\":2,\"a\":\"baz\",\"b\":2}\n",
+        ),
+    )
+    .unwrap();
+
+    // entries left out when loading, unreadable or invalid, are left out of the projection too
+    let mut database = OpenOptions::new()
+        .on_invalid_record(InvalidRecordPolicy::Skip)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    database.set_validator(|data: &MyObject| {
+        if data.b < 0 {
+            return Err(ValidationError::new("b must not be negative"));
+        }
+        Ok(())
+    });
+    database
+        .set_reload_validation(ReloadValidation::Lenient)
+        .unwrap();
+    let names = database
+        .project::<Name>()
+        .unwrap()
+        .into_iter()
+        .map(|record| (record.id, record.data.a))
+        .collect::<Vec<_>>();
+    assert_eq!(names, vec![(1, "foo".into()), (2, "baz".into())]);
+}
+
+#[test]
+fn for_each_raw_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_checksums(true);
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    database.delete(1).unwrap();
+
+    let mut entries = Vec::new();
+    database
+        .for_each_raw(|id, raw| entries.push((id, raw.get().to_string())))
+        .unwrap();
+    assert_eq!(
+        entries,
+        vec![
+            (1, r#"{"id":1,"a":"foo","b":1,"c":null}"#.to_string()),
+            (2, r#"{"id":2,"a":"foo","b":2,"c":null}"#.to_string()),
+            (1, r#"{"id":1,"deleted":true}"#.to_string()),
+        ]
+    );
+
+    let log =
+        "{\"jsondb\":1}\n{\"id\":1,\"a\":\"foo\",\"b\":1}\n{\"id\":1,\"a\":\"foo\",\"b\":2}\n";
+    let mut sum = 0;
+    for_each_raw(log.as_bytes(), |_, raw| {
+        sum += serde_json::from_str::<MyObject>(raw.get()).unwrap().b
+    })
+    .unwrap();
+    assert_eq!(sum, 3);
+}
+
+#[test]
+fn keep_history_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let opts = OpenOptions::new().keep_history(false);
+
+    let mut database = opts.clone().open::<MyObject, _>(&path).unwrap();
+    let id = database.insert(obj(0)).unwrap();
+    database.insert(obj(0)).unwrap();
+    for b in 1..=100 {
+        database.upsert(id, |_| Some(obj(b))).unwrap();
+    }
+    database.delete(2).unwrap();
+    assert_eq!(database.undo_last(1).unwrap(), 0);
+
+    for database in [database, opts.open(&path).unwrap()] {
+        assert_eq!(database.get(id).map(|record| record.b), Some(100));
+        assert_eq!(database.record_count(), 1);
+        assert_eq!(database.stats().unwrap().log_entries, 103);
+        assert_eq!(database.history(id).count(), 1);
+    }
+}
+
+#[test]
+fn keep_history_positions_test() {
+    let is_history_disabled =
+        |err: io::Error| matches!(Error::from_io(&err), Some(Error::HistoryDisabled));
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let opts = OpenOptions::new().keep_history(false);
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let id = database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    let position = database.log_position();
+
+    // the entry of `id` is overwritten where it is, so nothing after `position` would show it
+    database.upsert(id, |_| Some(obj(3))).unwrap();
+    assert!(is_history_disabled(
+        database.changes_since(position).unwrap_err()
+    ));
+    assert!(is_history_disabled(database.as_of(position).err().unwrap()));
+    assert!(is_history_disabled(
+        database.export_since(position, Vec::new()).unwrap_err()
+    ));
+    assert!(is_history_disabled(
+        database.apply_log(&b""[..], position).unwrap_err()
+    ));
+    assert_eq!(database.stats().unwrap().log_entries, 3);
+
+    // the key index can't follow the changes either, so it's built anew instead
+    let mut keyed = KeyedDatabase::new(database, |data: &MyObject| data.b);
+    assert_eq!(keyed.id_for_key(&3), Some(id));
+    assert!(keyed.delete_by_key(&3).unwrap());
+    assert_eq!(keyed.id_for_key(&3), None);
+    assert_eq!(keyed.id_for_key(&2), Some(2));
+}
+
+#[test]
+fn record_tag_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_cache_tag(StateCacheTag::new());
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+
+    let tag = database.record_tag(1).unwrap();
+    let etag = database.record_etag(1).unwrap();
+    assert_eq!(database.check_record_precondition(1, &etag), Ok(()));
+
+    // other records don't affect the tag, but changing the record does
+    database.upsert(2, |_| Some(obj(3))).unwrap();
+    assert_eq!(database.record_tag(1), Some(tag));
+    database.upsert(1, |_| Some(obj(4))).unwrap();
+    assert_ne!(database.record_tag(1), Some(tag));
+    assert!(database.check_record_precondition(1, &etag).is_err());
+
+    database.upsert(1, |_| Some(obj(1))).unwrap();
+    assert_eq!(database.record_tag(1), Some(tag));
+
+    database.delete(1).unwrap();
+    assert_eq!(database.record_tag(1), None);
+    assert!(database.check_record_precondition(1, "*").is_err());
+
+    // without per-record tags in the cache tag, the tag is a hash of the record
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(1)).unwrap();
+    let tag = database.record_tag(1).unwrap();
+    assert_ne!(database.record_tag(2), Some(tag));
+    database.upsert(1, |_| Some(obj(2))).unwrap();
+    database.upsert(1, |_| Some(obj(1))).unwrap();
+    assert_eq!(database.record_tag(1), Some(tag));
+}
+
+#[test]
+fn cache_tag_reset_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":1}
+        {"id":2,"a":"bar","b":-1}
+    "#;
+    let valid = r#"{"id":1,"a":"foo","b":1}"#;
+
+    let state_tag = |contents: &'static str| {
+        let mut database = Database::<MyObject, _>::new(Cursor::new(contents))
+            .unwrap()
+            .with_cache_tag(StateCacheTag::new());
+        database.reload().unwrap();
+        database
+    };
+
+    // dropping invalid records recomputes the tag from what's left
+    let mut database = state_tag(database_contents);
+    database.set_validator(|data| match data.b {
+        b if b < 0 => Err(ValidationError::new("negative")),
+        _ => Ok(()),
+    });
+    database
+        .set_reload_validation(ReloadValidation::Lenient)
+        .unwrap();
+    assert_eq!(database.cache_tag(), state_tag(valid).cache_tag());
+
+    let snapshot = database.cache_tag_snapshot();
+    database.restore_cache_tag(StateCacheTag::new());
+    assert_eq!(database.cache_tag(), 0);
+    database.restore_cache_tag(snapshot);
+    assert_eq!(database.cache_tag(), state_tag(valid).cache_tag());
+
+    // counter tags never go back to a tag they've already handed out
+    let mut database = Database::<MyObject, _>::new(Cursor::new(database_contents)).unwrap();
+    database.reload().unwrap();
+    let tag = database.cache_tag();
+    database.recompute_cache_tag();
+    assert_ne!(database.cache_tag(), tag);
+
+    // as do tags that don't say how to start over
+    struct Counting(u64);
+    impl<T> CacheTag<T> for Counting {
+        fn process_value(&mut self, _value: &T) {
+            self.0 += 1;
+        }
+
+        fn tag(&self) -> u64 {
+            self.0
+        }
+    }
+    let mut database = Database::<MyObject, _>::new(Cursor::new(database_contents))
+        .unwrap()
+        .with_cache_tag(Counting(0));
+    database.reload().unwrap();
+    database.recompute_cache_tag();
+    assert_eq!(database.cache_tag(), 4);
+}
+
+#[test]
+fn observer_test() {
+    use std::collections::{BTreeSet, HashMap};
+    use std::sync::{Arc, Mutex};
+
+    // ids of the records with each value of `a`
+    #[derive(Default)]
+    struct Index(HashMap<String, BTreeSet<RecordId>>);
+
+    impl RecordObserver<MyObject> for Index {
+        fn observe(&mut self, record: &Record<MyObject>, previous: Option<&RecordData<MyObject>>) {
+            if let Some(previous) = previous {
+                self.0
+                    .entry(previous.data.a.clone())
+                    .or_default()
+                    .remove(&previous.id);
+            }
+            if let Some(data) = record.data() {
+                self.0
+                    .entry(data.data.a.clone())
+                    .or_default()
+                    .insert(data.id);
+            }
+        }
+
+        fn reset(&mut self) {
+            self.0.clear();
+        }
+    }
+
+    let obj = |a: &str| MyObject {
+        a: a.into(),
+        b: 0,
+        c: None,
+    };
+    let ids = |index: &Arc<Mutex<Index>>, a: &str| {
+        let index = index.lock().unwrap();
+        index
+            .0
+            .get(a)
+            .into_iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>()
+    };
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_cache_tag(StateCacheTag::new());
+    database.insert(obj("foo")).unwrap();
+
+    // records that were already loaded are observed when it's added
+    let index = Arc::new(Mutex::new(Index::default()));
+    database.add_observer(index.clone());
+    assert_eq!(ids(&index, "foo"), vec![1]);
+
+    database.insert(obj("bar")).unwrap();
+    database.insert(obj("foo")).unwrap();
+    database.upsert(1, |_| Some(obj("bar"))).unwrap();
+    assert_eq!(ids(&index, "foo"), vec![3]);
+    assert_eq!(ids(&index, "bar"), vec![1, 2]);
+
+    database.delete(2).unwrap();
+    assert_eq!(ids(&index, "bar"), vec![1]);
+    assert_ne!(database.cache_tag(), 0);
+}
+
+#[test]
+fn corrupt_entry_test() {
+    let database_contents = "{\"id\":1,\"a\":\"foo\",\"b\":1}\n{\"id\":2,\"a\":\n";
+    let err = Database::<MyObject, _>::new(Cursor::new(database_contents))
+        .unwrap()
+        .reload()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+    let database_contents = "{\"id\":1,\"a\":\"foo\",\"b\":1}\n{\"id\":2,\"a\":true}\n";
+    let err = Database::<MyObject, _>::new(Cursor::new(database_contents))
+        .unwrap()
+        .reload()
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert!(
+        matches!(
+            Error::from_io(&err),
+            Some(Error::Corrupt { offset: 25, .. })
+        ),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn reserve_id_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj(1)).unwrap();
+
+    assert_eq!(database.peek_next_id(), Some(2));
+    let id = database.reserve_id().unwrap();
+    assert_eq!(id, 2);
+    assert!(!database.contains(id));
+
+    // reserved ids are skipped by other inserts
+    assert_eq!(database.peek_next_id(), Some(3));
+    assert_eq!(database.insert(obj(3)).unwrap(), 3);
+
+    database.insert_reserved(id, obj(id as i32)).unwrap();
+    assert_eq!(database.get(id).map(|record| record.data.b), Some(2));
+    assert_eq!(
+        database.insert_reserved(id, obj(0)).unwrap_err().kind(),
+        io::ErrorKind::InvalidInput
+    );
+
+    let id = database.reserve_id().unwrap();
+    assert!(database.release_id(id));
+    assert!(!database.release_id(id));
+    assert_eq!(database.insert(obj(4)).unwrap(), id);
+}
+
+#[test]
+fn reference_test() {
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Order {
+        customer: Ref<MyObject>,
+    }
+
+    impl References<MyObject> for Order {
+        fn refs(&self) -> Vec<Ref<MyObject>> {
+            vec![self.customer]
+        }
+    }
+
+    let mut customers = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    let mut orders = Database::<Order, _>::new(Cursor::new(Vec::new())).unwrap();
+
+    let customer = customers.insert(obj(1)).unwrap();
+    let order = orders
+        .insert(Order {
+            customer: Ref::new(customer),
+        })
+        .unwrap();
+    orders
+        .insert(Order {
+            customer: Ref::new(9),
+        })
+        .unwrap();
+
+    // serialized as the bare id
+    assert_eq!(
+        serde_json::to_value(orders.get(order).unwrap()).unwrap(),
+        serde_json::json!({ "id": 1, "customer": 1 })
+    );
+    let customer_ref = orders.get(order).unwrap().data.customer;
+    assert_eq!(customers.resolve(&customer_ref).unwrap().data.b, 1);
+    assert_eq!(orders.dangling_refs(&customers), vec![(2, Ref::new(9))]);
+
+    let err = customers
+        .delete_unreferenced(customer, &[&orders])
+        .unwrap_err();
+    assert!(
+        matches!(
+            Error::from_io(&err),
+            Some(Error::StillReferenced { id: 1, referenced_by }) if referenced_by == &[order]
+        ),
+        "{:?}",
+        err
+    );
+    assert!(customers.contains(customer));
+
+    orders.delete(order).unwrap();
+    customers.delete_unreferenced(customer, &[&orders]).unwrap();
+    assert!(!customers.contains(customer));
+}
+
+#[test]
+fn view_test() {
+    use std::collections::BTreeMap;
+
+    let obj = |a: &str, b| MyObject {
+        a: a.into(),
+        b,
+        c: None,
+    };
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj("foo", 1)).unwrap();
+    database.insert(obj("bar", 2)).unwrap();
+
+    database.create_view("total", 0, |total, change| match change {
+        ViewChange::Added(data) => *total += data.b,
+        ViewChange::Removed(data) => *total -= data.b,
+    });
+    database.create_view(
+        "by_a",
+        BTreeMap::<String, i32>::new(),
+        |counts, change| match change {
+            ViewChange::Added(data) => *counts.entry(data.a.clone()).or_default() += 1,
+            ViewChange::Removed(data) => *counts.entry(data.a.clone()).or_default() -= 1,
+        },
+    );
+    assert_eq!(database.view::<i32>("total"), Some(&3));
+
+    database.insert(obj("foo", 10)).unwrap();
+    database.upsert(2, |_| Some(obj("foo", 20))).unwrap();
+    database.delete(1).unwrap();
+    assert_eq!(database.view::<i32>("total"), Some(&30));
+    assert_eq!(
+        database.view::<BTreeMap<String, i32>>("by_a"),
+        Some(&BTreeMap::from([
+            ("bar".to_string(), 0),
+            ("foo".to_string(), 2)
+        ]))
+    );
+
+    assert_eq!(database.view::<u64>("total"), None);
+    assert!(database.drop_view("total"));
+    assert_eq!(database.view::<i32>("total"), None);
+}
+
+#[test]
+fn aggregate_test() {
+    use std::collections::HashMap;
+
+    let obj = |a: &str, b, c| MyObject { a: a.into(), b, c };
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj("foo", 1, None)).unwrap();
+    database.insert(obj("bar", 2, Some(1))).unwrap();
+    database.insert(obj("foo", 3, Some(2))).unwrap();
+    database.insert(obj("foo", 5, None)).unwrap();
+    database.delete(4).unwrap();
+
+    assert_eq!(database.aggregate().count(), HashMap::from([((), 3)]));
+
+    let by_a = || database.aggregate().group_by(|data| data.a.clone());
+    assert_eq!(
+        by_a().count(),
+        HashMap::from([("foo".to_string(), 2), ("bar".to_string(), 1)])
+    );
+    assert_eq!(
+        by_a().sum(|data| data.b),
+        HashMap::from([("foo".to_string(), 4), ("bar".to_string(), 2)])
+    );
+    assert_eq!(
+        by_a().min(|data| data.b),
+        HashMap::from([("foo".to_string(), 1), ("bar".to_string(), 2)])
+    );
+    assert_eq!(
+        by_a().max(|data| data.c),
+        HashMap::from([("foo".to_string(), Some(2)), ("bar".to_string(), Some(1))])
+    );
+    assert_eq!(
+        by_a().filter(|data| data.c.is_some()).count(),
+        HashMap::from([("foo".to_string(), 1), ("bar".to_string(), 1)])
+    );
+}
+
+#[test]
+fn text_index_test() {
+    let obj = |a: &str| MyObject {
+        a: a.into(),
+        b: 0,
+        c: None,
+    };
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj("The quick brown fox")).unwrap();
+    database.insert(obj("A quick-witted reply")).unwrap();
+    assert_eq!(database.search("quick"), Vec::<RecordId>::new());
+
+    database.create_text_index(|data| vec![&data.a]);
+    database.insert(obj("Brown bears")).unwrap();
+    assert_eq!(database.search("QUICK"), vec![1, 2]);
+    assert_eq!(database.search("bro"), vec![1, 3]);
+    assert_eq!(database.search("quick bro"), vec![1]);
+    assert_eq!(database.search("wit"), vec![2]);
+    assert_eq!(database.search("slow"), Vec::<RecordId>::new());
+
+    // old versions and deleted records don't match
+    database.upsert(1, |_| Some(obj("The slow fox"))).unwrap();
+    database.delete(3).unwrap();
+    assert_eq!(database.search("brown"), Vec::<RecordId>::new());
+    assert_eq!(database.search("slow fox"), vec![1]);
+
+    assert!(database.drop_text_index());
+    assert_eq!(database.search("fox"), Vec::<RecordId>::new());
+}
+
+#[test]
+fn reload_summary_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open(&path).unwrap();
+    assert!(!reader.reload().unwrap().has_changes());
+
+    writer.insert(obj(1)).unwrap();
+    writer.insert(obj(2)).unwrap();
+    writer.delete(1).unwrap();
+
+    let summary = reader.reload().unwrap();
+    assert_eq!(summary.new_records, 2);
+    assert_eq!(summary.new_deletes, 1);
+    assert_eq!(summary.bytes_read, std::fs::metadata(&path).unwrap().len());
+    assert!(summary.has_changes());
+
+    let summary = reader.reload().unwrap();
+    assert_eq!(summary.bytes_read, 0);
+    assert!(!summary.has_changes());
+}
+
+#[test]
+fn field_names_test() {
+    use serde_json::{Map, Value};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct Item {
+        id: String,
+        deleted: bool,
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let opts = OpenOptions::new().field_names("_id", "_deleted");
+
+    let item = |id: &str| Item {
+        id: id.into(),
+        deleted: false,
+    };
+    let mut database = opts.clone().open::<Item, _>(&path).unwrap();
+    let first = database.insert(item("abc")).unwrap();
+    let second = database.insert(item("def")).unwrap();
+    database.delete(first).unwrap();
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    let lines = contents.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], r#"{"_id":1,"deleted":false,"id":"abc"}"#);
+    assert_eq!(lines[2], r#"{"_deleted":true,"_id":1}"#);
+
+    let database = opts.clone().open::<Item, _>(&path).unwrap();
+    assert_eq!(database.get(first), None);
+    assert_eq!(database.get(second).unwrap().data, item("def"));
+    drop(database);
+
+    compact(&path, &CompactOptions::new().field_names("_id", "_deleted")).unwrap();
+    let database = opts.clone().open::<Item, _>(&path).unwrap();
+    assert_eq!(database.records().count(), 1);
+
+    // the default names can't be used with a payload that has an `id` field
+    assert!(Database::<Item, _>::open(&path).is_err());
+
+    // a first record that looks like a header is only one if it has no id under the names in use
+    let path = tmp_dir.path().join("versioned.json");
+    let payload = serde_json::json!({"jsondb": 1})
+        .as_object()
+        .unwrap()
+        .clone();
+    let mut database = opts.clone().open::<Map<String, Value>, _>(&path).unwrap();
+    let id = database.insert(payload.clone()).unwrap();
+    drop(database);
+    let database = opts.clone().open::<Map<String, Value>, _>(&path).unwrap();
+    assert_eq!(database.metadata(), None);
+    assert_eq!(database.get(id).unwrap().data, payload);
+    let mut lazy = LazyDatabase::<Map<String, Value>>::open_with_opts(&path, opts).unwrap();
+    assert_eq!(lazy.get(id).unwrap().unwrap().data, payload);
+}
+
+#[test]
+fn nested_layout_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33}
+        {"id":2,"data":{"a":"bar","b":66}}
+    "#;
+    let mut database = Database::<MyObject, _>::new(Cursor::new(database_contents))
+        .unwrap()
+        .with_layout(Layout::Nested);
+    database.reload().unwrap();
+    assert_eq!(database.get(1).unwrap().a, "foo");
+    assert_eq!(database.get(2).unwrap().a, "bar");
+
+    // payloads no longer have to be objects
+    let mut database = Database::<Vec<i32>, _>::new(Cursor::new(Vec::new()))
+        .unwrap()
+        .with_layout(Layout::Nested);
+    let id = database.insert(vec![1, 2, 3]).unwrap();
+    database.delete(id).unwrap();
+    let id = database.insert(vec![4]).unwrap();
+
+    let contents = database.stream_mut().get_ref().get_ref().clone();
+    assert_eq!(
+        String::from_utf8(contents.clone()).unwrap(),
+        "{\"data\":[1,2,3],\"id\":1}\n{\"deleted\":true,\"id\":1}\n{\"data\":[4],\"id\":2}\n"
+    );
+
+    let mut database = Database::<Vec<i32>, _>::new(Cursor::new(contents))
+        .unwrap()
+        .with_layout(Layout::Nested);
+    database.reload().unwrap();
+    assert_eq!(database.get(id).unwrap().data, vec![4]);
+    assert_eq!(database.records().count(), 1);
+}
+
+#[test]
+fn non_object_payload_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut database = Database::<Vec<f64>, _>::open(&path).unwrap();
+    let id = database.insert(vec![1.0, 2.5]).unwrap();
+    assert_eq!(database.stats().unwrap().live_records, 1);
+    assert!(database.record_tag(id).is_some());
+
+    let contents = std::fs::read_to_string(&path).unwrap();
+    assert_eq!(contents, "{\"data\":[1.0,2.5],\"id\":1}\n");
+
+    let database = Database::<Vec<f64>, _>::open(&path).unwrap();
+    assert_eq!(database.get(id).unwrap().data, vec![1.0, 2.5]);
+}
+
+#[test]
+fn content_cache_tag_test() {
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    let tag = |contents: &'static str| {
+        let mut database = Database::<Point, _>::new(Cursor::new(contents))
+            .unwrap()
+            .with_cache_tag(ContentCacheTag::new());
+        database.reload().unwrap();
+        (database.cache_tag(), database.record_tag(1))
+    };
+
+    let (tag1, record_tag) = tag(r#"{"id":1,"x":1.5,"y":2.0}"#);
+    let (tag2, _) = tag(r#"
+        {"id":1,"x":0.0,"y":0.0}
+        {"id":2,"x":3.0,"y":4.0}
+        {"id":1,"x":1.5,"y":2.0}
+        {"id":2,"deleted":true}
+    "#);
+    assert_eq!(tag1, tag2);
+    assert_eq!(record_tag, Some(tag1));
+    assert_ne!(tag(r#"{"id":1,"x":1.5,"y":2.5}"#).0, tag1);
+
+    // xxh3 doesn't depend on the process, so tags can be compared across restarts
+    assert_eq!(tag1, 6453893543296244724);
+}
+
+#[test]
+fn read_only_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let id = writer.insert(obj(1)).unwrap();
+
+    // only reading methods exist on a `ReadOnly` database, so it can't call `insert`
+    let mut reader: Database<MyObject, std::fs::File, DefaultCacheTag, ReadOnly> =
+        Database::open_read_only(&path).unwrap();
+    assert_eq!(reader.get(id).unwrap().b, 1);
+    writer.upsert(id, |_| Some(obj(2))).unwrap();
+    reader.reload().unwrap();
+    assert_eq!(reader.get(id).unwrap().b, 2);
+
+    let reader = writer.into_read_only().with_cache_tag(StateCacheTag::new());
+    assert_eq!(reader.get(id).unwrap().b, 2);
+}
+
+#[test]
+fn open_options_create_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+
+    let err = OpenOptions::new()
+        .create(false)
+        .open::<MyObject, _>(&path)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+    let mut database = OpenOptions::new()
+        .create_new(true)
+        .mode(0o600)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    database.insert(obj.clone()).unwrap();
+    drop(database);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    let err = OpenOptions::new()
+        .create_new(true)
+        .open::<MyObject, _>(&path)
+        .err()
+        .unwrap();
+    assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+
+    let database = OpenOptions::new().open::<MyObject, _>(&path).unwrap();
+    assert_eq!(database.record_count(), 1);
+    drop(database);
+    let database = OpenOptions::new()
+        .truncate(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    assert_eq!(database.record_count(), 0);
+
+    let other_path = tmp_dir.path().join("other.json");
+    let database = OpenOptions::new()
+        .read_only(true)
+        .create(true)
+        .open::<MyObject, _>(&other_path)
+        .unwrap();
+    assert_eq!(database.record_count(), 0);
+}
+
+#[test]
+fn in_memory_test() {
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+
+    let mut database: MemoryDatabase<MyObject> = Database::in_memory().unwrap();
+    let id = database.insert(obj.clone()).unwrap();
+    assert_eq!(database.get(id).unwrap().data, obj);
+
+    // e.g. a log a user uploaded, which is only ever in memory
+    let mut database = Database::<MyObject, _>::new(Cursor::new(
+        "{\"jsondb\":1}\n{\"id\":4,\"a\":\"foo\",\"b\":1}\n"
+            .as_bytes()
+            .to_vec(),
+    ))
+    .unwrap()
+    .with_clock(SystemClock);
+    database.reload().unwrap();
+    assert_eq!(database.get(4).unwrap().data, obj);
+    let before = SystemClock.now_millis();
+    let id = database.insert(obj.clone()).unwrap();
+    let updated_at = database.meta(id).and_then(|meta| meta.updated_at).unwrap();
+    assert!(updated_at >= before && updated_at <= SystemClock.now_millis());
+    let log = String::from_utf8(database.into_inner().into_inner()).unwrap();
+    assert_eq!(log.lines().count(), 3);
+
+    #[cfg(feature = "tempfile")]
+    {
+        let mut database = Database::<MyObject, _>::temporary().unwrap();
+        let id = database.insert(obj.clone()).unwrap();
+        database.reload().unwrap();
+        assert_eq!(database.get(id).unwrap().data, obj);
+    }
+}
+
+#[test]
+fn custom_backend_test() {
+    use std::cell::RefCell;
+    use std::io::{Read, Seek, SeekFrom, Write};
+    use std::rc::Rc;
+
+    // a log kept somewhere other than a file, such as a blob in the browser's IndexedDB, which
+    // every handle reads and writes through its own position
+    #[derive(Clone, Default)]
+    struct Blob {
+        bytes: Rc<RefCell<Vec<u8>>>,
+        position: u64,
+    }
+
+    impl Read for Blob {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let bytes = self.bytes.borrow();
+            let mut rest = bytes.get(self.position as usize..).unwrap_or_default();
+            let n = rest.read(buf)?;
+            self.position += n as u64;
+            Ok(n)
+        }
+    }
+
+    impl Write for Blob {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut bytes = self.bytes.borrow_mut();
+            let mut cursor = Cursor::new(&mut *bytes);
+            cursor.set_position(self.position);
+            let n = cursor.write(buf)?;
+            self.position += n as u64;
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Seek for Blob {
+        fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+            let len = self.bytes.borrow().len() as i64;
+            self.position = match pos {
+                SeekFrom::Start(offset) => offset as i64,
+                SeekFrom::End(offset) => len + offset,
+                SeekFrom::Current(offset) => self.position as i64 + offset,
+            } as u64;
+            Ok(self.position)
+        }
+    }
+
+    let blob = Blob::default();
+    let mut first = Database::<MyObject, _>::new(blob.clone()).unwrap();
+    let mut second = Database::<MyObject, _>::new(blob.clone()).unwrap();
+
+    let id = first.insert(obj(1)).unwrap();
+    second.reload().unwrap();
+    assert_eq!(second.get(id).unwrap().data, obj(1));
+    second.upsert(id, |_| Some(obj(2))).unwrap();
+    let other = second.insert(obj(3)).unwrap();
+    assert_ne!(other, id);
+
+    first.reload().unwrap();
+    let b = first.records().map(|record| record.b).collect::<Vec<_>>();
+    assert_eq!(b, vec![2, 3]);
+    assert_eq!(blob.bytes.borrow().split(|&b| b == b'\n').count(), 4);
+}
+
+#[test]
+fn verify_test() {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    let log = concat!(
+        "{\"jsondb\":1}\n",
+        "{\"id\":1,\"a\":\"foo\",\"b\":1}\n",
+        "\n",
+        "{\"id\":2,\"a\":\"bar\"\n",
+        "[1,2]\n",
+        "{\"a\":\"baz\"}\n",
+        "{\"id\":\"3\"}\n",
+        "{\"id\":1,\"deleted\":true}\n",
+        "{\"id\":4,\"a\":",
+    );
+    let report = verify(log.as_bytes()).unwrap();
+    assert!(report.header);
+    assert_eq!(report.lines, 9);
+    assert_eq!(report.entries, 2);
+    assert_eq!(report.bytes, log.len() as u64);
+    let issues = report
+        .issues
+        .iter()
+        .map(|issue| (issue.line, issue.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        issues,
+        vec![
+            (4, "EOF while parsing an object"),
+            (5, "entry is not an object"),
+            (6, "record is missing an id"),
+            (7, "invalid type: string \"3\", expected u32"),
+            (9, "entry is cut off at the end of the log"),
+        ]
+    );
+
+    // arbitrary damage to a log is reported rather than panicking, and never breaks loading either
+    let mut rng = StdRng::seed_from_u64(7);
+    for _ in 0..500 {
+        let mut bytes = log.as_bytes().to_vec();
+        for _ in 0..rng.random_range(1..8) {
+            let i = rng.random_range(0..bytes.len());
+            match rng.random_range(0..3) {
+                0 => bytes[i] = rng.random(),
+                1 => drop(bytes.remove(i)),
+                _ => bytes.insert(i, b"{}[]\":,\n\\"[rng.random_range(0..9)]),
+            }
+        }
+        let report = verify(&bytes[..]).unwrap();
+        assert_eq!(report.bytes, bytes.len() as u64);
+        let _ = Database::<serde_json::Value, _>::new(Cursor::new(bytes))
+            .and_then(|mut database| database.reload());
+    }
+}
+
+#[test]
+fn verify_repair_test() {
+    let log = concat!(
+        "{\"id\":2,\"a\":\"foo\",\"b\":1}\n",
+        "{\"id\":1,\"a\":\"bar\",\"b\":2}\n",
+        "{\"id\":2,\"deleted\":true}\n",
+        "{\"id\":2,\"deleted\":true}\n",
+        "{\"id\":3,\"a\":\n",
+        "{\"id\":5,\"deleted\":true}\n",
+        "{\"id\":4,\"a\":\"baz\"",
+    );
+    let opts = VerifyOptions::new().require_checksums(true);
+    let report = verify_with_opts(log.as_bytes(), &opts).unwrap();
+    assert!(!report.is_ok());
+    let issues = report
+        .issues
+        .iter()
+        .filter(|issue| issue.message != "entry has no checksum")
+        .map(|issue| (issue.line, issue.severity, issue.message.as_str()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        issues,
+        vec![
+            (
+                2,
+                Severity::Warning,
+                "record 1 first appears after record 2"
+            ),
+            (4, Severity::Warning, "record 2 is deleted again"),
+            (5, Severity::Error, "EOF while parsing a value"),
+            (
+                6,
+                Severity::Warning,
+                "record 5 is deleted before it was written"
+            ),
+            (7, Severity::Error, "entry is cut off at the end of the log"),
+        ]
+    );
+    assert_eq!(report.warnings().count(), 3 + report.entries);
+
+    // repairing drops the unreadable lines, but keeps the ones that only have warnings
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsonl");
+    std::fs::write(&path, log).unwrap();
+    let repaired = repair(&path, &opts).unwrap();
+    assert_eq!(repaired, report);
+    let report = verify(std::fs::File::open(&path).unwrap()).unwrap();
+    assert!(report.is_ok());
+    assert_eq!((report.lines, report.entries), (5, 5));
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(
+        database.records().map(|r| r.id).collect::<Vec<_>>(),
+        vec![1]
+    );
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn reload_parallel_test() {
+    use std::fmt::Write;
+
+    let mut log = String::from("{\"jsondb\":1}\n");
+    for id in 1..=40000 {
+        writeln!(
+            log,
+            "{{\"id\":{},\"a\":\"record {}\",\"b\":{}}}",
+            id,
+            id,
+            id % 7
+        )
+        .unwrap();
+        if id % 3 == 0 {
+            writeln!(log, "{{\"id\":{},\"deleted\":true}}", id - 1).unwrap();
+        }
+    }
+
+    let load = |parallel: bool| {
+        let mut database = Database::<MyObject, _>::new(Cursor::new(log.clone())).unwrap();
+        let summary = match parallel {
+            true => database.reload_parallel().unwrap(),
+            false => database.reload().unwrap(),
+        };
+        (database, summary)
+    };
+    let (mut sequential, sequential_summary) = load(false);
+    let (mut parallel, parallel_summary) = load(true);
+    assert_eq!(parallel.log_position(), sequential.log_position());
+    assert_eq!(parallel_summary.new_records, sequential_summary.new_records);
+    assert_eq!(parallel_summary.new_deletes, 13333);
+    assert_eq!(parallel_summary.bytes_read, sequential_summary.bytes_read);
+    assert!(parallel.records().eq(sequential.records()));
+    for id in [1, 20000, 40000] {
+        assert_eq!(parallel.entry_offset(id), sequential.entry_offset(id));
+    }
+    let last = log.rfind("{\"id\":40000,").unwrap() as u64;
+    assert_eq!(parallel.entry_offset(40000), Some(last));
+    assert_eq!(
+        parallel.project::<MyObject>().unwrap(),
+        sequential.project::<MyObject>().unwrap()
+    );
+
+    // a broken entry fails at the same offset, with everything before it loaded
+    let broken = log.replacen("{\"id\":30000,", "{\"id\":30000", 1);
+    let mut sequential = Database::<MyObject, _>::new(Cursor::new(broken.clone())).unwrap();
+    let mut parallel = Database::<MyObject, _>::new(Cursor::new(broken)).unwrap();
+    let err = sequential.reload().unwrap_err().to_string();
+    assert_eq!(parallel.reload_parallel().unwrap_err().to_string(), err);
+    assert_eq!(parallel.log_position(), sequential.log_position());
+    assert_eq!(parallel.record_count(), sequential.record_count());
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsonl");
+    std::fs::write(&path, &log).unwrap();
+    let database = OpenOptions::new()
+        .open_parallel::<MyObject, _>(&path)
+        .unwrap();
+    assert_eq!(database.record_count(), load(false).0.record_count());
+}
+
+#[test]
+fn index_file_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let index_path = tmp_dir.path().join("database.json.idx");
+
+    let mut database = OpenOptions::new()
+        .header(true)
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    for i in 0..200 {
+        database
+            .insert(MyObject {
+                a: i.to_string(),
+                b: i,
+                c: None,
+            })
+            .unwrap();
+    }
+    database.delete(3).unwrap();
+    database.close().unwrap();
+
+    let opts = OpenOptions::new().index_file(true);
+    let mut lazy = opts.clone().open_lazy::<MyObject, _>(&path).unwrap();
+    assert!(index_path.exists());
+    lazy.insert(MyObject {
+        a: "new".into(),
+        b: 0,
+        c: None,
+    })
+    .unwrap();
+    lazy.close().unwrap();
+
+    // entries covered by the index aren't read again, so damage to them goes unnoticed
+    let mut log = std::fs::read_to_string(&path).unwrap();
+    let start = log.find("{\"id\":1,").unwrap();
+    log.replace_range(start..start + 8, "garbage!");
+    std::fs::write(&path, &log).unwrap();
+    assert!(Database::<MyObject, _>::open(&path).is_err());
+
+    let mut lazy = opts.clone().open_lazy::<MyObject, _>(&path).unwrap();
+    assert!(lazy.metadata().is_some());
+    assert_eq!(lazy.record_count(), 200);
+    assert!(!lazy.contains(3));
+    assert_eq!(lazy.get(201).unwrap().unwrap().a, "new");
+    assert_eq!(lazy.get(150).unwrap().unwrap().b, 149);
+    lazy.close().unwrap();
+
+    // a log that was rewritten since doesn't match the index anymore
+    std::fs::write(&path, "{\"id\":1,\"a\":\"other\",\"b\":1}\n").unwrap();
+    let mut lazy = opts.open_lazy::<MyObject, _>(&path).unwrap();
+    assert_eq!(lazy.ids().collect::<Vec<_>>(), vec![1]);
+    assert_eq!(lazy.get(1).unwrap().unwrap().a, "other");
+}
+
+#[test]
+fn owned_records_test() {
+    let mut database = Database::<MyObject, _>::in_memory().unwrap();
+    let id = database.insert(obj(1)).unwrap();
+
+    // neither borrows the database, so both outlive the writes below, and both share the record
+    // with the database instead of copying it
+    let record = database.get_owned(id).unwrap();
+    let records = database.records_owned();
+    assert!(Arc::ptr_eq(&record, &records[0]));
+    assert_eq!(Arc::strong_count(&record), 3);
+    database.upsert(id, |_| None).unwrap();
+    database
+        .insert(MyObject {
+            a: "bar".into(),
+            b: 2,
+            c: None,
+        })
+        .unwrap();
+
+    assert_eq!(record.a, "foo");
+    assert_eq!(records, vec![record]);
+    assert_eq!(database.get_owned(id), None);
+    assert_eq!(database.records_owned().len(), 1);
+}
+
+#[test]
+fn close_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let obj = MyObject {
+        a: "foo".into(),
+        b: 1,
+        c: None,
+    };
+
+    let opts = OpenOptions::new().close_marker(true);
+    let mut database = opts.clone().open::<MyObject, _>(&path).unwrap();
+    assert_eq!(database.closed_cleanly(), Some(true));
+    database.insert(obj.clone()).unwrap();
+    database.close().unwrap();
+    assert!(tmp_dir.path().join("database.json.closed").exists());
+
+    let mut database = opts.clone().open::<MyObject, _>(&path).unwrap();
+    assert_eq!(database.closed_cleanly(), Some(true));
+    database.insert(obj.clone()).unwrap();
+    drop(database);
+
+    // dropping skips the marker, so the next session can tell
+    let database = opts.clone().open::<MyObject, _>(&path).unwrap();
+    assert_eq!(database.closed_cleanly(), Some(false));
+    assert_eq!(database.record_count(), 2);
+    database.close().unwrap();
+
+    let database = Database::<MyObject, _>::open_read_only(&path).unwrap();
+    assert_eq!(database.closed_cleanly(), None);
+    database.close().unwrap();
+    assert_eq!(
+        opts.open::<MyObject, _>(&path).unwrap().closed_cleanly(),
+        Some(true)
+    );
+
+    let mut database = Database::<MyObject, _>::in_memory().unwrap();
+    database.insert(obj).unwrap();
+    let log = database.into_inner().into_inner();
+    assert_eq!(log, b"{\"id\":1,\"a\":\"foo\",\"b\":1,\"c\":null}\n");
+}
+
+#[test]
+fn drop_guard_test() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("database.json");
+    let opts = OpenOptions::new().flush_policy(FlushPolicy::EveryN(10));
+
+    // writes that were held back and staged changes that weren't committed are lost on drop,
+    // which debug builds don't let pass
+    let mut database = opts.clone().open::<MyObject, _>(&path).unwrap();
+    database.insert(obj(1)).unwrap();
+    let dropped = catch_unwind(AssertUnwindSafe(|| drop(database)));
+    assert_eq!(dropped.is_err(), cfg!(debug_assertions));
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(database.record_count(), 0);
+
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    let mut staged = database.staged();
+    staged.insert(obj(2)).unwrap();
+    let dropped = catch_unwind(AssertUnwindSafe(|| drop(staged)));
+    assert_eq!(dropped.is_err(), cfg!(debug_assertions));
+    assert_eq!(database.record_count(), 0);
+
+    // nothing is lost once they're written, committed or discarded, even without syncing
+    let mut staged = database.staged();
+    staged.insert(obj(3)).unwrap();
+    staged.discard();
+    let mut staged = database.staged();
+    staged.insert(obj(4)).unwrap();
+    staged.commit().unwrap();
+    drop(database);
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    database.insert(obj(5)).unwrap();
+    database.flush().unwrap();
+    drop(database);
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    let b = database
+        .records()
+        .map(|record| record.b)
+        .collect::<Vec<_>>();
+    assert_eq!(b, vec![4, 5]);
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn export_sqlite_test() {
+    let database_contents = r#"
+        {"id":1,"a":"foo","b":33,"c":99}
+        {"id":2,"a":"bar","b":66}
+        {"id":1,"a":"qwe","b":9}
+        {"id":2,"deleted":true}
+    "#;
+    let mut database = Database::<MyObject, _>::new(Cursor::new(database_contents)).unwrap();
+    database.reload().unwrap();
+
+    let conn = rusqlite::Connection::open_in_memory().unwrap();
+    assert_eq!(database.export_sqlite(&conn, "records").unwrap(), 2);
+    let rows = conn
+        .prepare("SELECT id, deleted, data ->> 'a' FROM records ORDER BY id")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<(u32, bool, String)>>>()
+        .unwrap();
+    assert_eq!(
+        rows,
+        vec![(1, false, "qwe".to_string()), (2, true, "bar".to_string())]
+    );
+
+    assert_eq!(
+        database.export_sqlite_exploded(&conn, "exploded").unwrap(),
+        2
+    );
+    let types = conn
+        .prepare("SELECT name, type FROM pragma_table_info('exploded')")
+        .unwrap()
+        .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<(String, String)>>>()
+        .unwrap();
+    let types = types.iter().map(|(n, t)| (n.as_str(), t.as_str()));
+    assert!(types.eq(vec![
+        ("id", "INTEGER"),
+        ("deleted", "INTEGER"),
+        ("a", "TEXT"),
+        ("b", "INTEGER"),
+        ("c", ""),
+    ]));
+    let row: (String, i32, Option<i32>) = conn
+        .query_row("SELECT a, b, c FROM exploded WHERE id = 1", [], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .unwrap();
+    assert_eq!(row, ("qwe".to_string(), 9, None));
+
+    // the table is new, so exporting twice fails rather than mixing in old rows
+    assert!(database.export_sqlite(&conn, "records").is_err());
+}
+
+#[test]
+fn storage_backend_test() {
+    let object = MyObject {
+        a: "foo".to_string(),
+        b: 1,
+        c: None,
+    };
+    let mut database = Database::<MyObject, _>::from_backend(Vec::new()).unwrap();
+    let id = database.insert(object.clone()).unwrap();
+    let deleted = database.insert(object.clone()).unwrap();
+    database.delete(deleted).unwrap();
+    let backend = database.into_inner().into_backend();
+    assert_eq!(backend.iter().filter(|&&b| b == b'\n').count(), 3);
+    let mut reopened = Database::<MyObject, _>::from_backend(backend).unwrap();
+    reopened.reload().unwrap();
+    assert_eq!(reopened.get(id), Some(&RecordData { id, data: object }));
+    assert_eq!(reopened.record_count(), 1);
+}
+
+#[cfg(feature = "object_store")]
+#[test]
+fn object_store_backend_test() {
+    use object_store::{memory::InMemory, path::Path};
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let store = Arc::new(InMemory::new());
+    let backend = || {
+        let prefix = Path::from("logs/db");
+        ObjectStoreBackend::new(store.clone(), prefix, runtime.handle().clone())
+    };
+
+    let mut writer = Database::<MyObject, _>::from_backend(backend()).unwrap();
+    let first = writer.insert(obj(1)).unwrap();
+    let second = writer.insert(obj(2)).unwrap();
+    writer.delete(first).unwrap();
+
+    let mut reader = Database::<MyObject, _>::from_backend(backend()).unwrap();
+    reader.reload().unwrap();
+    assert_eq!(reader.get(first), None);
+    assert_eq!(reader.get(second).map(|record| record.b), Some(2));
+
+    // appends by other writers are picked up on reload
+    writer.insert(obj(3)).unwrap();
+    assert_eq!(reader.reload().unwrap().new_records, 1);
+    assert_eq!(reader.record_count(), 2);
+}
+
+#[test]
+fn write_if_unchanged_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let mut ours = Database::<MyObject, _>::open(&path).unwrap();
+    let mut theirs = Database::<MyObject, _>::open(&path).unwrap();
+    let id = ours.insert(obj(1)).unwrap();
+
+    let tag = ours.cache_tag();
+    let written = ours
+        .write_if_unchanged(tag, |staged| {
+            let b = staged.get(id).unwrap().b;
+            staged.upsert(id, |_| Some(obj(b + 1)));
+            Ok(b + 1)
+        })
+        .unwrap();
+    assert_eq!(written, 2);
+    assert_eq!(ours.get(id).unwrap().b, 2);
+
+    // a write by someone else in between is a conflict, and nothing is written
+    let tag = ours.cache_tag();
+    theirs.reload().unwrap();
+    theirs.insert(obj(10)).unwrap();
+    let err = ours
+        .write_if_unchanged(tag, |staged| staged.insert(obj(3)))
+        .unwrap_err();
+    assert!(
+        matches!(Error::from_io(&err), Some(Error::Conflict { expected, .. }) if *expected == tag)
+    );
+    assert_eq!(ours.record_count(), 2);
+
+    // with an up-to-date tag it goes through
+    let id = ours
+        .write_if_unchanged(ours.cache_tag(), |staged| staged.insert(obj(3)))
+        .unwrap();
+    theirs.reload().unwrap();
+    assert_eq!(theirs.get(id).unwrap().b, 3);
+}
+
+#[test]
+fn exclusive_lock_file_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let lock_path = dir.path().join("db.json.lock");
+    let opts = || OpenOptions::new().exclusive(true);
+
+    let database = opts().open::<MyObject, _>(&path).unwrap();
+    assert!(lock_path.exists());
+    let err = opts().open::<MyObject, _>(&path).err().unwrap();
+    match Error::from_io(&err) {
+        Some(Error::Locked { pid, since }) => {
+            assert_eq!(*pid, Some(std::process::id()));
+            assert!(since.is_some());
+        }
+        _ => panic!("unexpected error: {}", err),
+    }
+    // readers aren't kept out
+    let reader = opts().read_only(true).open::<MyObject, _>(&path).unwrap();
+    drop(reader);
+    assert!(lock_path.exists());
+
+    database.close().unwrap();
+    assert!(!lock_path.exists());
+    drop(opts().open::<MyObject, _>(&path).unwrap());
+    assert!(!lock_path.exists());
+
+    // a lock left behind by a process that is gone is taken over
+    #[cfg(target_os = "linux")]
+    {
+        std::fs::write(&lock_path, r#"{"pid":4294967295,"since":0}"#).unwrap();
+        let database = opts().open::<MyObject, _>(&path).unwrap();
+        let holder = std::fs::read_to_string(&lock_path).unwrap();
+        assert!(holder.contains(&format!("\"pid\":{}", std::process::id())));
+        drop(database);
+    }
+}
+
+#[test]
+fn flush_policy_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let opts = OpenOptions::new().flush_policy(FlushPolicy::EveryN(3));
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open_read_only(&path).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+    assert_eq!(database.record_count(), 2);
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 0);
+
+    database.insert(obj(3)).unwrap();
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 3);
+
+    database.insert(obj(4)).unwrap();
+    database.flush().unwrap();
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 4);
+
+    database.insert(obj(5)).unwrap();
+    database.close().unwrap();
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 5);
+
+    // records written by others in the meantime come first in the log, and in memory after a flush
+    let opts = OpenOptions::new().flush_policy(FlushPolicy::Interval(Duration::from_secs(3600)));
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let mut other = Database::<MyObject, _>::open(&path).unwrap();
+    database.insert_with_id(10, obj(10)).unwrap();
+    other.insert_with_id(11, obj(11)).unwrap();
+    database.reload().unwrap();
+    assert_eq!(database.record_count(), 7);
+    database.flush().unwrap();
+    let ids = database
+        .changes_since(LogPosition::new(5, 0))
+        .unwrap()
+        .iter()
+        .map(Record::id)
+        .collect::<Vec<_>>();
+    assert_eq!(ids, [11, 10]);
+    reader.reload().unwrap();
+    assert_eq!(reader.record_count(), 7);
+}
+
+#[test]
+fn wait_for_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let opts = OpenOptions::new().flush_policy(FlushPolicy::EveryN(2));
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open_read_only(&path).unwrap();
+    assert_eq!(database.last_write_position(), None);
+
+    // held back writes have no position yet
+    let id = database.insert(obj(1)).unwrap();
+    assert_eq!(database.last_write_position(), None);
+    database.insert(obj(2)).unwrap();
+    let position = database.last_write_position().unwrap();
+    assert_eq!(position, database.log_position());
+
+    assert!(reader.reload_until(position).unwrap());
+    assert!(reader.contains(id));
+
+    let writer = std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(20));
+        database.insert(obj(3)).unwrap();
+        database.flush().unwrap();
+        database.last_write_position().unwrap()
+    });
+    let later = LogPosition::new(3, position.offset + 1);
+    reader.wait_for(later, Duration::from_secs(10)).unwrap();
+    assert_eq!(reader.record_count(), 3);
+    assert!(reader.log_position() >= writer.join().unwrap());
+
+    let beyond = LogPosition::new(4, reader.log_position().offset + 1);
+    assert!(!reader.reload_until(beyond).unwrap());
+    let err = reader
+        .wait_for(beyond, Duration::from_millis(10))
+        .unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn on_invalid_record_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("db.json");
+    let database_contents = concat!(
+        "{\"id\":1,\"a\":\"foo\",\"b\":1}\n",
+        "{\"id\":2,\"a\":true}\n",
+        "  not json\n",
+        "{\"id\":3,\"a\":\"bar\",\"b\":3}\n",
+    );
+    std::fs::write(&path, database_contents).unwrap();
+
+    let err = OpenOptions::new()
+        .open::<MyObject, _>(&path)
+        .map(|_| ())
+        .unwrap_err();
+    assert!(matches!(
+        Error::from_io(&err),
+        Some(Error::Corrupt { offset: 25, .. })
+    ));
+
+    let opts = OpenOptions::new().on_invalid_record(InvalidRecordPolicy::Skip);
+    let database = opts.open::<MyObject, _>(&path).unwrap();
+    assert_eq!(
+        database.records().map(|r| r.id).collect::<Vec<_>>(),
+        vec![1, 3]
+    );
+    let skipped = database.skipped_entries();
+    assert_eq!(
+        skipped.iter().map(|entry| entry.offset).collect::<Vec<_>>(),
+        vec![25, 45]
+    );
+    assert!(skipped.iter().all(|entry| entry.line.is_none()));
+    drop(database);
+
+    let opts = OpenOptions::new().on_invalid_record(InvalidRecordPolicy::Collect);
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let lines = database
+        .skipped_entries()
+        .iter()
+        .map(|entry| entry.line.as_deref().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines, vec!["{\"id\":2,\"a\":true}", "not json"]);
+
+    // entries appended later are reported by the reload that skips them
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    io::Write::write_all(&mut file, b"{\"id\":}\n{\"id\":4,\"a\":\"baz\",\"b\":4}\n").unwrap();
+    let summary = database.reload().unwrap();
+    assert_eq!(summary.skipped.len(), 1);
+    assert_eq!(summary.skipped[0].line.as_deref(), Some("{\"id\":}"));
+    assert!(database.contains(4));
+    assert_eq!(database.skipped_entries().len(), 3);
+}
+
+#[test]
+fn tolerate_duplicates_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let object = |a: &str, b| MyObject {
+        a: a.to_string(),
+        b,
+        c: None,
+    };
+    let opts = || OpenOptions::new().header(true);
+
+    let mut a = opts()
+        .open::<MyObject, _>(dir.path().join("a.json"))
+        .unwrap();
+    a.insert(object("foo", 1)).unwrap();
+    a.insert(object("bar", 2)).unwrap();
+    let mut b = opts()
+        .open::<MyObject, _>(dir.path().join("b.json"))
+        .unwrap();
+    b.insert(object("foo", 1)).unwrap();
+    b.insert(object("baz", 2)).unwrap();
+    b.insert(object("qux", 3)).unwrap();
+    b.delete(2).unwrap();
+    drop((a, b));
+
+    let path = dir.path().join("merged.json");
+    let first = std::fs::read(dir.path().join("a.json")).unwrap();
+    let second = std::fs::read(dir.path().join("b.json")).unwrap();
+    std::fs::write(&path, [&first[..], &second[..]].concat()).unwrap();
+    let err = opts().open::<MyObject, _>(&path).map(|_| ()).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+    // the second log's record 1 is the same as the first's, while its other ids are taken
+    std::fs::write(&path, &first).unwrap();
+    let opts = opts().tolerate_duplicates(true);
+    let mut database = opts.open::<MyObject, _>(&path).unwrap();
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .unwrap();
+    io::Write::write_all(&mut file, &second).unwrap();
+    let summary = database.reload().unwrap();
+    assert_eq!((summary.duplicates, summary.remapped), (1, 3));
+    let records = |database: &Database<MyObject, std::fs::File>| {
+        database
+            .records()
+            .map(|record| (record.id, record.a.clone()))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        records(&database),
+        vec![(1, "foo".into()), (2, "bar".into()), (4, "qux".into())]
+    );
+    assert!(database.get_deleted(3).is_some());
+    let err = database.insert(object("new", 4)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+
+    let ids = database.renumber().unwrap();
+    assert_eq!(
+        ids.into_iter().collect::<Vec<_>>(),
+        vec![(1, 1), (2, 2), (4, 3)]
+    );
+    database.insert(object("new", 4)).unwrap();
+    drop(database);
+
+    let database = OpenOptions::new().open::<MyObject, _>(&path).unwrap();
+    assert_eq!(
+        records(&database),
+        vec![
+            (1, "foo".into()),
+            (2, "bar".into()),
+            (3, "qux".into()),
+            (4, "new".into())
+        ]
+    );
+}
+
+#[test]
+fn write_after_compaction_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsondb");
+    let mut first = Database::<MyObject, _>::open(&path).unwrap();
+    let mut second = Database::<MyObject, _>::open(&path).unwrap();
+    first.insert(obj(1)).unwrap();
+    first.upsert(1, |_| Some(obj(2))).unwrap();
+    second.reload().unwrap();
+
+    // both handles still have the file that compaction renamed over
+    compact(&path, &CompactOptions::new()).unwrap();
+    let id = second.insert(obj(3)).unwrap();
+    first.insert(obj(4)).unwrap();
+    assert!(first.contains(id));
+    drop((first, second));
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(
+        database.records().map(|r| r.b).collect::<Vec<_>>(),
+        vec![2, 3, 4]
+    );
+}
+
+#[test]
+fn reload_after_compaction_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsondb");
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let mut reader = Database::<MyObject, _>::open(&path).unwrap();
+    let mut read_only = Database::<MyObject, _>::open_read_only(&path).unwrap();
+    let mut lazy = LazyDatabase::<MyObject>::open(&path).unwrap();
+    let id = writer.insert(obj(1)).unwrap();
+    writer.upsert(id, |_| Some(obj(2))).unwrap();
+    reader.reload().unwrap();
+
+    // the readers have to move on to the new file to see anything written after compaction
+    compact(&path, &CompactOptions::new()).unwrap();
+    let inserted = writer.insert(obj(3)).unwrap();
+    assert!(reader.reload().unwrap().replaced);
+    read_only.reload().unwrap();
+    lazy.reload().unwrap();
+    assert_eq!(
+        reader.records().map(|r| r.b).collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert_eq!(
+        read_only.records().map(|r| r.b).collect::<Vec<_>>(),
+        vec![2, 3]
+    );
+    assert_eq!(lazy.get(inserted).unwrap().map(|r| r.data.b), Some(3));
+    assert_eq!(lazy.record_count(), 2);
+
+    // and they keep following it afterwards
+    writer.upsert(id, |_| Some(obj(4))).unwrap();
+    assert!(!reader.reload().unwrap().replaced);
+    lazy.reload().unwrap();
+    assert_eq!(reader.get(id).map(|r| r.b), Some(4));
+    assert_eq!(lazy.get(id).unwrap().map(|r| r.data.b), Some(4));
+}
+
+#[test]
+fn auto_compact_two_handles_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.jsondb");
+    let policy = CompactionPolicy::new()
+        .max_dead_ratio(Some(0.5))
+        .min_file_size(0);
+    let mut compacting = OpenOptions::new()
+        .auto_compact(Some(policy))
+        .open::<MyObject, _>(&path)
+        .unwrap();
+    let mut other = Database::<MyObject, _>::open(&path).unwrap();
+
+    let id = compacting.insert(obj(0)).unwrap();
+    other.reload().unwrap();
+    for b in 1..10 {
+        compacting.upsert(id, |_| Some(obj(b))).unwrap();
+    }
+    assert!(std::fs::read_to_string(&path).unwrap().lines().count() <= 2);
+
+    let inserted = other.insert(obj(100)).unwrap();
+    assert_eq!(other.get(id).map(|record| record.b), Some(9));
+    compacting.reload().unwrap();
+    assert_eq!(compacting.get(inserted).map(|record| record.b), Some(100));
+    drop((compacting, other));
+
+    let database = Database::<MyObject, _>::open(&path).unwrap();
+    assert_eq!(
+        database.records().map(|r| r.b).collect::<Vec<_>>(),
+        vec![9, 100]
+    );
+}
+
+#[cfg(any(feature = "axum", feature = "actix"))]
+#[test]
+fn handlers_test() {
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+
+    let mut writer = Database::<MyObject, _>::open(&path).unwrap();
+    let mut database = Database::<MyObject, _>::open(&path).unwrap();
+    database.set_validator(|data: &MyObject| {
+        if data.b < 0 {
+            return Err(ValidationError::new("b must not be negative"));
+        }
+        Ok(())
+    });
+    let shared = database.into_shared();
+
+    let body = |b: i32| format!(r#"{{"a":"foo","b":{}}}"#, b).into_bytes();
+    assert_eq!(shared.handle_insert(&body(1)).status, 201);
+    assert_eq!(shared.handle_insert(&body(-1)).status, 422);
+    assert_eq!(shared.handle_get(2).status, 404);
+    assert_eq!(shared.handle_delete(2, None).status, 404);
+
+    // reads pick up what another handle wrote
+    assert!(!shared.read().is_stale().unwrap());
+    writer.reload().unwrap();
+    writer
+        .insert(MyObject {
+            a: "bar".into(),
+            b: 2,
+            c: None,
+        })
+        .unwrap();
+    assert!(shared.read().is_stale().unwrap());
+    assert_eq!(shared.handle_get(2).status, 200);
+    assert!(!shared.read().is_stale().unwrap());
+    assert_eq!(shared.handle_list().status, 200);
+
+    // as well as the log another handle compacted, and what it wrote after that
+    compact(&path, &CompactOptions::new()).unwrap();
+    assert!(shared.read().is_stale().unwrap());
+    assert_eq!(shared.handle_list().status, 200);
+    assert!(!shared.read().is_stale().unwrap());
+    writer.delete(1).unwrap();
+    assert_eq!(shared.handle_get(1).status, 404);
+    assert_eq!(shared.handle_get(2).status, 200);
+    assert!(!shared.read().is_stale().unwrap());
+}
+
+#[test]
+fn shared_payload_test() {
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.insert(obj(1)).unwrap();
+    database.insert(obj(2)).unwrap();
+
+    // handles share the payload the database holds, however many are handed out
+    let first = database.get_owned(1).unwrap();
+    let again = database.get_owned(1).unwrap();
+    assert!(Arc::ptr_eq(&first, &again));
+    assert!(database
+        .records_owned()
+        .iter()
+        .any(|record| Arc::ptr_eq(record, &first)));
+
+    // a handle keeps the version it was taken for
+    database.upsert(1, |_| Some(obj(3))).unwrap();
+    assert_eq!(first.data, obj(1));
+    assert!(!Arc::ptr_eq(&database.get_owned(1).unwrap(), &first));
+    database.delete(2).unwrap();
+    assert!(database.get_owned(2).is_none());
+
+    // reloading leaves the payloads already loaded where they are
+    let latest = database.get_owned(1).unwrap();
+    database.reload().unwrap();
+    assert!(Arc::ptr_eq(&database.get_owned(1).unwrap(), &latest));
+
+    // snapshots and resolved merges hand out the same payloads as well
+    let snapshot = database.as_of(database.log_position()).unwrap();
+    assert!(std::ptr::eq(snapshot.get(1).unwrap(), &*latest));
+    let mut database = database.with_merge_operator(move |existing, operand| MyObject {
+        b: existing.map_or(0, |data| data.b) + operand.as_i64().unwrap() as i32,
+        ..obj(0)
+    });
+    database.merge(1, 1.into()).unwrap();
+    let merged = database.get_owned(1).unwrap();
+    assert_eq!(merged.data, obj(4));
+    assert!(std::ptr::eq(database.get(1).unwrap(), &*merged));
+}
+
+#[cfg(feature = "metrics")]
+#[test]
+fn metrics_test() {
+    use ::metrics::{
+        Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+        Recorder, SharedString, Unit,
+    };
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    // counters and gauges keep their value, histograms count their samples
+    #[derive(Default)]
+    struct Value(Mutex<f64>);
+
+    impl CounterFn for Value {
+        fn increment(&self, value: u64) {
+            *self.0.lock().unwrap() += value as f64;
+        }
+
+        fn absolute(&self, value: u64) {
+            *self.0.lock().unwrap() = value as f64;
+        }
+    }
+
+    impl GaugeFn for Value {
+        fn increment(&self, value: f64) {
+            *self.0.lock().unwrap() += value;
+        }
+
+        fn decrement(&self, value: f64) {
+            *self.0.lock().unwrap() -= value;
+        }
+
+        fn set(&self, value: f64) {
+            *self.0.lock().unwrap() = value;
+        }
+    }
+
+    impl HistogramFn for Value {
+        fn record(&self, _value: f64) {
+            *self.0.lock().unwrap() += 1.0;
+        }
+    }
+
+    #[derive(Default)]
+    struct TestRecorder(Mutex<HashMap<String, Arc<Value>>>);
+
+    impl TestRecorder {
+        fn value(&self, key: &Key) -> Arc<Value> {
+            let mut values = self.0.lock().unwrap();
+            values.entry(key.name().to_string()).or_default().clone()
+        }
+
+        fn get(&self, name: &str) -> f64 {
+            *self.0.lock().unwrap()[name].0.lock().unwrap()
+        }
+    }
+
+    impl Recorder for TestRecorder {
+        fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+        fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+        fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+            Counter::from_arc(self.value(key))
+        }
+
+        fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+            Gauge::from_arc(self.value(key))
+        }
+
+        fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+            Histogram::from_arc(self.value(key))
+        }
+    }
+
+    let recorder = TestRecorder::default();
+    let metrics = ::metrics::with_local_recorder(&recorder, || Metrics::new("test"));
+    let mut database = Database::<MyObject, _>::new(Cursor::new(
+        "{\"id\":1,\"a\":\"foo\",\"b\":1}\n".as_bytes().to_vec(),
+    ))
+    .unwrap();
+    database.reload().unwrap();
+
+    // records loaded before the metrics were attached count too
+    let mut database = database.with_metrics(metrics);
+    assert_eq!(recorder.get("jsondb_records_loaded_total"), 1.0);
+    assert_eq!(recorder.get("jsondb_live_records"), 1.0);
+
+    database.insert(obj(2)).unwrap();
+    database.insert(obj(3)).unwrap();
+    database.delete(1).unwrap();
+    database.reload().unwrap();
+    assert_eq!(recorder.get("jsondb_writes_total"), 3.0);
+    assert_eq!(recorder.get("jsondb_write_duration_seconds"), 3.0);
+    assert!(recorder.get("jsondb_reload_duration_seconds") >= 1.0);
+    assert_eq!(recorder.get("jsondb_live_records"), 2.0);
+    assert_eq!(
+        recorder.get("jsondb_file_size_bytes"),
+        database.log_position().offset as f64
+    );
+
+    database.update_metrics().unwrap();
+    assert!(recorder.get("jsondb_dead_bytes_ratio") > 0.0);
+}
+
+#[cfg(feature = "tracing")]
+#[test]
+fn tracing_test() {
+    use std::fmt;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Mutex;
+    use tracing::{
+        field::{Field, Visit},
+        span, Event, Subscriber,
+    };
+
+    // the names of the spans that were opened, and the messages of the events
+    #[derive(Default)]
+    struct Captured {
+        spans: Mutex<Vec<String>>,
+        messages: Mutex<Vec<String>>,
+    }
+
+    #[derive(Default)]
+    struct TestSubscriber {
+        next_id: AtomicU64,
+        captured: Arc<Captured>,
+    }
+
+    struct Message<'a>(&'a mut String);
+
+    impl Visit for Message<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                *self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl Subscriber for TestSubscriber {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &span::Attributes<'_>) -> span::Id {
+            let name = span.metadata().name().to_string();
+            self.captured.spans.lock().unwrap().push(name);
+            span::Id::from_u64(self.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+        }
+
+        fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+        fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut message = String::new();
+            event.record(&mut Message(&mut message));
+            self.captured.messages.lock().unwrap().push(message);
+        }
+
+        fn enter(&self, _: &span::Id) {}
+        fn exit(&self, _: &span::Id) {}
+    }
+
+    let tmp_dir = tempfile::tempdir().unwrap();
+    let path = tmp_dir.path().join("db.json");
+    let subscriber = TestSubscriber::default();
+    let captured = subscriber.captured.clone();
+    tracing::subscriber::with_default(subscriber, || {
+        let mut database = Database::<MyObject, _>::open(&path).unwrap();
+        database.insert(obj(1)).unwrap();
+
+        // dropping what's left unfinished is worth a warning
+        let mut staged = database.staged();
+        staged.insert(obj(2)).unwrap();
+        let dropped = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(staged)));
+        assert_eq!(dropped.is_err(), cfg!(debug_assertions));
+        drop(database);
+
+        compact(&path, &CompactOptions::new()).unwrap();
+    });
+
+    let spans = captured.spans.lock().unwrap();
+    for name in ["reload", "write", "compact"] {
+        assert!(spans.iter().any(|span| span == name), "{:?}", spans);
+    }
+    let messages = captured.messages.lock().unwrap();
+    for message in [
+        "staged changes dropped without `commit` or `discard`",
+        "database dropped with unsynced writes; use `close` to sync them",
+    ] {
+        assert!(messages.iter().any(|m| m == message), "{:?}", messages);
+    }
+}
+
+#[cfg(feature = "jsonschema")]
+#[test]
+fn schema_test() {
+    let schema = Schema::new(&serde_json::json!({
+        "type": "object",
+        "properties": { "b": { "type": "integer", "minimum": 0 } },
+        "required": ["a", "b"],
+    }))
+    .unwrap();
+    assert!(Schema::new(&serde_json::json!({ "type": 5 })).is_err());
+
+    let mut database = Database::<MyObject, _>::new(Cursor::new(Vec::new())).unwrap();
+    database.set_schema(schema);
+
+    let id = database.insert(obj(1)).unwrap();
+    let err = database
+        .upsert(id, |data| {
+            data.map(|data| MyObject {
+                b: -1,
+                ..data.clone()
+            })
+        })
+        .unwrap_err();
+    match Error::from_io(&err) {
+        Some(Error::InvalidRecord { id: 1, error }) => {
+            assert!(error.message().starts_with("/b: "), "{:?}", error)
+        }
+        other => panic!("{:?}", other),
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{de::DeserializeOwned, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{Read, Seek};
+
+use crate::{
+    cache_tag::CacheTag,
+    database::Database,
+    observer::{replay, RecordObserver},
+    record::{Record, RecordData, RecordId},
+};
+
+type Fields<T> = dyn Fn(&T) -> Vec<String> + Send + Sync;
+
+// an inverted index from the words in some string fields to the live records containing them
+pub(crate) struct TextIndex<T> {
+    fields: Box<Fields<T>>,
+    words: BTreeMap<String, BTreeSet<RecordId>>,
+    // the words indexed for each record, so they can be removed again
+    records: HashMap<RecordId, BTreeSet<String>>,
+}
+
+impl<T> TextIndex<T> {
+    pub fn new(fields: Box<Fields<T>>) -> TextIndex<T> {
+        TextIndex {
+            fields,
+            words: BTreeMap::new(),
+            records: HashMap::new(),
+        }
+    }
+
+    // ids of the records that contain every word of `query`, where words only have to start with
+    // the query's words
+    pub fn search(&self, query: &str) -> Vec<RecordId> {
+        let mut matches: Option<BTreeSet<RecordId>> = None;
+        for term in tokenize(query) {
+            let ids = self
+                .words
+                .range(term.clone()..)
+                .take_while(|(word, _)| word.starts_with(&term))
+                .flat_map(|(_, ids)| ids.iter().copied())
+                .collect::<BTreeSet<_>>();
+            matches = Some(match matches {
+                Some(matches) => &matches & &ids,
+                None => ids,
+            });
+        }
+        matches.unwrap_or_default().into_iter().collect()
+    }
+
+    fn remove(&mut self, id: RecordId) {
+        for word in self.records.remove(&id).unwrap_or_default() {
+            if let Some(ids) = self.words.get_mut(&word) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.words.remove(&word);
+                }
+            }
+        }
+    }
+}
+
+impl<T> RecordObserver<T> for TextIndex<T> {
+    fn observe(&mut self, record: &Record<T>, _previous: Option<&RecordData<T>>) {
+        if record.is_unresolved_merge() {
+            return;
+        }
+
+        self.remove(record.id());
+        if let Some(data) = record.data() {
+            let words = (self.fields)(&data.data)
+                .iter()
+                .flat_map(|field| tokenize(field))
+                .collect::<BTreeSet<_>>();
+            for word in &words {
+                self.words.entry(word.clone()).or_default().insert(data.id);
+            }
+            self.records.insert(data.id, words);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.words.clear();
+        self.records.clear();
+    }
+}
+
+// lowercased runs of letters and digits
+fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+impl<T, S, C, M> Database<T, S, C, M>
+where
+    T: Serialize + DeserializeOwned,
+    S: Read + Seek,
+    C: CacheTag<Record<T>>,
+{
+    // replaces any existing text index; `fields` picks the strings to index from each record
+    pub fn create_text_index<F, R>(&mut self, fields: F)
+    where
+        F: Fn(&T) -> Vec<&R> + Send + Sync + 'static,
+        R: AsRef<str> + ?Sized,
+    {
+        let fields = move |data: &T| {
+            let fields = fields(data).into_iter();
+            fields.map(|field| field.as_ref().to_string()).collect()
+        };
+        let mut text_index = TextIndex::new(Box::new(fields));
+        replay(&mut text_index, self.loaded_records());
+        self.observers_mut().text_index = Some(text_index);
+    }
+
+    pub fn drop_text_index(&mut self) -> bool {
+        self.observers_mut().text_index.take().is_some()
+    }
+
+    // ids of the live records with a word starting with each word of `query`, in order; nothing
+    // matches without a text index
+    pub fn search(&self, query: &str) -> Vec<RecordId> {
+        match &self.observers().text_index {
+            Some(text_index) => text_index.search(query),
+            None => Vec::new(),
+        }
+    }
+}
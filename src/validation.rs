@@ -0,0 +1,51 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationError {
+    message: String,
+}
+
+impl ValidationError {
+    pub fn new(message: impl Into<String>) -> ValidationError {
+        ValidationError {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl Error for ValidationError {}
+
+// how records that are already in the log are validated when loading them
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum ReloadValidation {
+    #[default]
+    Skip,
+    // fail the reload on the first invalid record
+    Strict,
+    // leave invalid records out, as if they had never been written
+    Lenient,
+}
+
+// what loading does with log entries that can't be read, such as a line that isn't JSON or has
+// the wrong checksum; they're reported in `ReloadSummary::skipped` and
+// `Database::skipped_entries` unless loading fails on them
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum InvalidRecordPolicy {
+    #[default]
+    Fail,
+    // moves on to the next line, reporting where the bad one was
+    Skip,
+    // like `Skip`, but keeps the bad line as well
+    Collect,
+}
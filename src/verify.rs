@@ -0,0 +1,246 @@
+use serde_json::Value;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::{
+    checksum::verify_checksum,
+    compact::sibling_path,
+    error::Error,
+    header::parse_header,
+    record::{Envelope, FieldNames, Format, Record, RecordId},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Severity {
+    // the entry can't be read, so loading the log fails at this point
+    Error,
+    // the entry can be read, but is unusual enough to point at a bug or a botched edit
+    Warning,
+}
+
+// a problem with a single line of a log; lines are counted from 1
+#[derive(Clone, Debug, PartialEq)]
+pub struct VerifyIssue {
+    pub line: usize,
+    pub offset: u64,
+    pub severity: Severity,
+    pub message: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VerifyReport {
+    pub lines: usize,
+    // entries that could be read, not counting the header
+    pub entries: usize,
+    pub header: bool,
+    pub bytes: u64,
+    pub issues: Vec<VerifyIssue>,
+}
+
+impl VerifyReport {
+    // warnings don't count, since the log can still be loaded
+    pub fn is_ok(&self) -> bool {
+        self.errors().next().is_none()
+    }
+
+    pub fn errors(&self) -> impl Iterator<Item = &VerifyIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Error)
+    }
+
+    pub fn warnings(&self) -> impl Iterator<Item = &VerifyIssue> {
+        self.issues
+            .iter()
+            .filter(|issue| issue.severity == Severity::Warning)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct VerifyOptions {
+    pub require_checksums: bool,
+}
+
+impl VerifyOptions {
+    pub const fn new() -> VerifyOptions {
+        VerifyOptions {
+            require_checksums: false,
+        }
+    }
+
+    // warns about entries written without a checksum
+    pub const fn require_checksums(mut self, require_checksums: bool) -> Self {
+        self.require_checksums = require_checksums;
+        self
+    }
+}
+
+// checks a log line by line, carrying on past broken entries, so that malformed input only ever
+// ends up in the report; this only fails if reading does
+pub fn verify(reader: impl Read) -> io::Result<VerifyReport> {
+    verify_with_opts(reader, &VerifyOptions::new())
+}
+
+pub fn verify_with_opts(reader: impl Read, opts: &VerifyOptions) -> io::Result<VerifyReport> {
+    let mut verifier = Verifier {
+        opts,
+        report: VerifyReport::default(),
+        live: HashMap::new(),
+        max_id: 0,
+    };
+
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let offset = verifier.report.bytes;
+        let len = reader.read_until(b'\n', &mut line)?;
+        if len == 0 {
+            break;
+        }
+        verifier.report.bytes += len as u64;
+        verifier.report.lines += 1;
+
+        if let Err(message) = verifier.verify_line(&line, offset) {
+            verifier.issue(offset, Severity::Error, message);
+        }
+    }
+    Ok(verifier.report)
+}
+
+// drops the lines of the log at `path` that can't be read, including an entry cut off at the end,
+// and returns the report of what was found; readable entries are kept as they are, even if they
+// have warnings
+pub fn repair(path: impl AsRef<Path>, opts: &VerifyOptions) -> io::Result<VerifyReport> {
+    let path = path.as_ref();
+    let tmp_path = sibling_path(path, "repair");
+
+    // hold a lock on the source, so cooperating writers can't append while we rewrite it
+    let source = File::open(path)?;
+    source.lock()?;
+
+    let result = verify_with_opts(&source, opts).and_then(|report| {
+        if report.is_ok() {
+            return Ok(report);
+        }
+
+        let broken = report.errors().map(|issue| issue.line).collect();
+        repair_file(path, &tmp_path, &broken)?;
+        fs::rename(&tmp_path, path)?;
+        Ok(report)
+    });
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+
+    source.unlock()?;
+    result
+}
+
+fn repair_file(src: &Path, dst: &Path, broken: &BTreeSet<usize>) -> io::Result<()> {
+    let mut reader = BufReader::new(File::open(src)?);
+    let mut out = BufWriter::new(File::create(dst)?);
+    let mut line = Vec::new();
+    for number in 1.. {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            break;
+        }
+        if !broken.contains(&number) {
+            out.write_all(&line)?;
+        }
+    }
+    let out = out.into_inner().map_err(io::IntoInnerError::into_error)?;
+    out.sync_all()
+}
+
+struct Verifier<'a> {
+    opts: &'a VerifyOptions,
+    report: VerifyReport,
+    // whether each record seen so far is live
+    live: HashMap<RecordId, bool>,
+    max_id: RecordId,
+}
+
+impl Verifier<'_> {
+    fn issue(&mut self, offset: u64, severity: Severity, message: String) {
+        self.report.issues.push(VerifyIssue {
+            line: self.report.lines,
+            offset,
+            severity,
+            message,
+        });
+    }
+
+    fn verify_line(&mut self, line: &[u8], offset: u64) -> Result<(), String> {
+        let complete = line.ends_with(b"\n");
+        let line = std::str::from_utf8(line).map_err(|err| format!("invalid UTF-8: {err}"))?;
+        let raw = line.trim();
+        if raw.is_empty() {
+            return Ok(());
+        }
+
+        let offset = offset + (line.len() - line.trim_start().len()) as u64;
+        let checked = verify_checksum(raw, offset).map_err(|err| err.to_string())?;
+        let has_checksum = checked.len() != raw.len();
+        let raw = checked;
+        let parse_error = |err: serde_json::Error| match err.is_eof() {
+            true if !complete => "entry is cut off at the end of the log".to_string(),
+            _ => Error::parse_message(&err),
+        };
+
+        // only the very first entry of the log may be a header
+        if self.report.entries == 0 && !self.report.header {
+            if let Some(header) = parse_header(&raw, FieldNames::DEFAULT) {
+                header.validate().map_err(|err| err.to_string())?;
+                self.report.header = true;
+                return Ok(());
+            }
+        }
+
+        match serde_json::from_str::<Value>(&raw).map_err(parse_error)? {
+            Value::Object(_) => (),
+            _ => return Err("entry is not an object".to_string()),
+        }
+        let envelope = Envelope::parse(&raw, FieldNames::DEFAULT).map_err(parse_error)?;
+        envelope.validate().map_err(|err| err.to_string())?;
+        let record = Format::default()
+            .decode::<Value>(&raw)
+            .map_err(|_| "entry is not an upsert, delete or merge".to_string())?;
+        self.report.entries += 1;
+
+        if self.opts.require_checksums && !has_checksum {
+            let message = "entry has no checksum".to_string();
+            self.issue(offset, Severity::Warning, message);
+        }
+        self.check_record(&record, offset);
+        Ok(())
+    }
+
+    // ids are handed out in increasing order, and only live records are deleted, unless the log
+    // was written by hand or merged from elsewhere
+    fn check_record(&mut self, record: &Record<Value>, offset: u64) {
+        let id = record.id();
+        let live = self.live.get(&id).copied();
+        if live.is_none() && id < self.max_id {
+            let message = format!("record {id} first appears after record {}", self.max_id);
+            self.issue(offset, Severity::Warning, message);
+        }
+        self.max_id = self.max_id.max(id);
+
+        match record {
+            Record::Delete(_) if live == Some(false) => {
+                let message = format!("record {id} is deleted again");
+                self.issue(offset, Severity::Warning, message);
+            }
+            Record::Delete(_) if live.is_none() => {
+                let message = format!("record {id} is deleted before it was written");
+                self.issue(offset, Severity::Warning, message);
+            }
+            _ => (),
+        }
+        self.live.insert(id, !matches!(record, Record::Delete(_)));
+    }
+}
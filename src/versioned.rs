@@ -0,0 +1,115 @@
+use serde::{
+    de::{DeserializeOwned, Deserializer},
+    ser::Serializer,
+    Deserialize, Serialize,
+};
+use std::io::{self, Read, Seek, Write};
+use std::ops::{Deref, DerefMut};
+
+use crate::{cache_tag::CacheTag, database::Database, record::Record};
+
+pub trait VersionedPayload: DeserializeOwned {
+    type Latest: Serialize;
+
+    const LATEST_VERSION: &'static str;
+
+    fn upgrade(self) -> Self::Latest;
+
+    fn is_latest(&self) -> bool;
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct Versioned<V: VersionedPayload> {
+    data: V::Latest,
+    upgraded: bool,
+}
+
+impl<V: VersionedPayload> Versioned<V> {
+    pub fn new(data: V::Latest) -> Versioned<V> {
+        Versioned {
+            data,
+            upgraded: false,
+        }
+    }
+
+    pub fn was_upgraded(&self) -> bool {
+        self.upgraded
+    }
+
+    pub fn into_inner(self) -> V::Latest {
+        self.data
+    }
+}
+
+impl<V: VersionedPayload> Deref for Versioned<V> {
+    type Target = V::Latest;
+
+    fn deref(&self) -> &V::Latest {
+        &self.data
+    }
+}
+
+impl<V: VersionedPayload> DerefMut for Versioned<V> {
+    fn deref_mut(&mut self) -> &mut V::Latest {
+        &mut self.data
+    }
+}
+
+impl<'de, V: VersionedPayload> Deserialize<'de> for Versioned<V> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let payload = V::deserialize(deserializer)?;
+        let upgraded = !payload.is_latest();
+        Ok(Versioned {
+            data: payload.upgrade(),
+            upgraded,
+        })
+    }
+}
+
+impl<V: VersionedPayload> Serialize for Versioned<V> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        // same layout as an internally tagged enum, without needing an owned variant
+        #[derive(Serialize)]
+        struct Tagged<'a, L> {
+            version: &'static str,
+            #[serde(flatten)]
+            data: &'a L,
+        }
+
+        Tagged {
+            version: V::LATEST_VERSION,
+            data: &self.data,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<V, S, C> Database<Versioned<V>, S, C>
+where
+    V: VersionedPayload,
+    V::Latest: Clone,
+    S: Read + Write + Seek,
+    C: CacheTag<Record<Versioned<V>>>,
+{
+    pub fn rewrite_upgraded(&mut self) -> io::Result<usize> {
+        self.reload()?;
+
+        let upgraded = self
+            .records()
+            .filter(|record| record.was_upgraded())
+            .map(|record| (record.id, record.data.data.clone()))
+            .collect::<Vec<_>>();
+
+        for (id, data) in &upgraded {
+            self.upsert(*id, |_| Some(Versioned::new(data.clone())))?;
+        }
+
+        Ok(upgraded.len())
+    }
+}
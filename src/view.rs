@@ -0,0 +1,103 @@
+use std::any::Any;
+use std::collections::HashMap;
+
+use crate::{
+    observer::RecordObserver,
+    record::{Record, RecordData},
+};
+
+// how the live records changed; updating a record removes the old version and adds the new one
+#[derive(Clone, Copy, Debug)]
+pub enum ViewChange<'a, T> {
+    Added(&'a T),
+    Removed(&'a T),
+}
+
+type State = Box<dyn Any + Send + Sync>;
+
+type Update<T> = dyn Fn(&mut State, ViewChange<'_, T>) + Send + Sync;
+
+// a value folded over the live records, kept up to date as records are applied
+pub(crate) struct View<T> {
+    state: State,
+    initial: Box<dyn Fn() -> State + Send + Sync>,
+    update: Box<Update<T>>,
+}
+
+impl<T> View<T> {
+    pub fn new<V, F>(initial: V, update: F) -> View<T>
+    where
+        V: Clone + Send + Sync + 'static,
+        F: Fn(&mut V, ViewChange<'_, T>) + Send + Sync + 'static,
+    {
+        View {
+            state: Box::new(initial.clone()),
+            initial: Box::new(move || Box::new(initial.clone())),
+            update: Box::new(move |state, change| {
+                if let Some(state) = state.downcast_mut() {
+                    update(state, change);
+                }
+            }),
+        }
+    }
+
+    pub fn get<V: 'static>(&self) -> Option<&V> {
+        self.state.downcast_ref()
+    }
+}
+
+impl<T> RecordObserver<T> for View<T> {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>) {
+        if record.is_unresolved_merge() {
+            return;
+        }
+        if let Some(previous) = previous {
+            (self.update)(&mut self.state, ViewChange::Removed(&previous.data));
+        }
+        if let Some(data) = record.data() {
+            (self.update)(&mut self.state, ViewChange::Added(&data.data));
+        }
+    }
+
+    fn reset(&mut self) {
+        self.state = (self.initial)();
+    }
+}
+
+pub(crate) struct Views<T> {
+    views: HashMap<String, View<T>>,
+}
+
+impl<T> Views<T> {
+    pub fn new() -> Views<T> {
+        Views {
+            views: HashMap::new(),
+        }
+    }
+
+    pub fn insert(&mut self, name: String, view: View<T>) {
+        self.views.insert(name, view);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&View<T>> {
+        self.views.get(name)
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        self.views.remove(name).is_some()
+    }
+}
+
+impl<T> RecordObserver<T> for Views<T> {
+    fn observe(&mut self, record: &Record<T>, previous: Option<&RecordData<T>>) {
+        for view in self.views.values_mut() {
+            view.observe(record, previous);
+        }
+    }
+
+    fn reset(&mut self) {
+        for view in self.views.values_mut() {
+            view.reset();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::io;
+use std::path::{self, Path};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// flags the database as dirty whenever its file may have changed since it was last loaded
+pub(crate) struct ChangeWatcher {
+    dirty: Arc<AtomicBool>,
+    _watcher: RecommendedWatcher,
+}
+
+impl ChangeWatcher {
+    pub fn new(path: &Path) -> io::Result<ChangeWatcher> {
+        let path = path::absolute(path)?;
+        let dirty = Arc::new(AtomicBool::new(true));
+
+        let flag = dirty.clone();
+        let file_path = path.clone();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let changed = match event {
+                    Ok(event) => event.paths.contains(&file_path),
+                    Err(_) => true,
+                };
+                if changed {
+                    flag.store(true, Ordering::SeqCst);
+                }
+            })
+            .map_err(io::Error::other)?;
+
+        // watch the directory rather than the file, so replacing the file (e.g. by compaction) is
+        // noticed as well
+        let dir = path.parent().unwrap_or(&path);
+        watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(io::Error::other)?;
+
+        Ok(ChangeWatcher {
+            dirty,
+            _watcher: watcher,
+        })
+    }
+
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::SeqCst)
+    }
+
+    // called before reading, so changes made during the reload aren't lost
+    pub fn clear(&self) {
+        self.dirty.store(false, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,1083 @@
+use serde_json::Value;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+// runs the command line tool in `dir`, away from any config file of the user's
+fn jsondb(dir: &Path, args: &[&str]) -> Output {
+    jsondb_with_input(dir, args, "")
+}
+
+fn jsondb_with_input(dir: &Path, args: &[&str], input: &str) -> Output {
+    run(&mut command(dir, args), input)
+}
+
+fn command(dir: &Path, args: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_jsondb"));
+    command
+        .args(args)
+        .current_dir(dir)
+        .env("JSONDB_CONFIG", dir.join("config.toml"));
+    command
+}
+
+fn run(command: &mut Command, input: &str) -> Output {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+// the records as `list` prints them
+fn records(dir: &Path, file: &str) -> Vec<Value> {
+    stdout(&jsondb(dir, &["--output", "jsonl", "list", file]))
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+fn ids(dir: &Path, file: &str) -> Vec<u64> {
+    records(dir, file)
+        .iter()
+        .map(|record| record["id"].as_u64().unwrap())
+        .collect()
+}
+
+fn add(dir: &Path, file: &str, records: &[&str]) {
+    let mut args = vec!["add", file];
+    args.extend(records);
+    stdout(&jsondb(dir, &args));
+}
+
+// starts `watch`, handing back what it prints line by line; it's killed when the guard is dropped
+fn watch(dir: &Path, args: &[&str]) -> (KillOnDrop, Receiver<Value>) {
+    let mut child = command(dir, args)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .unwrap();
+    let stdout = BufReader::new(child.stdout.take().unwrap());
+    let (send, receive) = mpsc::channel();
+    thread::spawn(move || {
+        for line in stdout.lines() {
+            let value = serde_json::from_str(&line.unwrap()).unwrap();
+            if send.send(value).is_err() {
+                break;
+            }
+        }
+    });
+    // there's nothing to tell when it has loaded the log, so it's given a moment
+    thread::sleep(Duration::from_millis(500));
+    (KillOnDrop(child), receive)
+}
+
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn next(changes: &Receiver<Value>) -> Value {
+    changes.recv_timeout(Duration::from_secs(5)).unwrap()
+}
+
+#[test]
+fn remove_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#, r#"{"a":4}"#],
+    );
+
+    // nothing is picked, so nothing may be listed or removed
+    let output = jsondb(dir, &["rm", "db.json", "--dry-run"]);
+    assert!(!output.status.success());
+    let output = jsondb(dir, &["rm", "db.json"]);
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1, 2, 3, 4]);
+
+    // a dry run lists what a real run removes, and removes nothing
+    let dry_run = stdout(&jsondb(
+        dir,
+        &["--output", "jsonl", "rm", "db.json", "--dry-run", "2", "3"],
+    ));
+    assert_eq!(dry_run.lines().count(), 2);
+    assert_eq!(ids(dir, "db.json"), vec![1, 2, 3, 4]);
+    stdout(&jsondb(dir, &["rm", "db.json", "2", "3"]));
+    assert_eq!(ids(dir, "db.json"), vec![1, 4]);
+
+    let output = jsondb(dir, &["rm", "db.json", "2"]);
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1, 4]);
+
+    stdout(&jsondb(dir, &["rm", "db.json", "--filter", "a == 4"]));
+    assert_eq!(ids(dir, "db.json"), vec![1]);
+}
+
+#[test]
+#[cfg(any(feature = "jq", feature = "jaq"))]
+fn remove_jq_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#, r#"{"a":4}"#],
+    );
+
+    // records `select` leaves out are kept, just like ones the expression is false for
+    let dry_run = stdout(&jsondb(
+        dir,
+        &[
+            "--output",
+            "jsonl",
+            "rm",
+            "db.json",
+            "--dry-run",
+            "--jq",
+            "select(.a == 3)",
+        ],
+    ));
+    assert_eq!(dry_run.lines().count(), 1);
+    stdout(&jsondb(dir, &["rm", "db.json", "--jq", "select(.a == 3)"]));
+    assert_eq!(ids(dir, "db.json"), vec![1, 2, 4]);
+    stdout(&jsondb(dir, &["rm", "db.json", "--jq", ".a >= 2"]));
+    assert_eq!(ids(dir, "db.json"), vec![1]);
+}
+
+#[test]
+fn add_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::write(dir.join("more.json"), "{\"a\":3}\n{\"a\":4}\n").unwrap();
+
+    let printed = stdout(&jsondb(
+        dir,
+        &[
+            "add",
+            "db.json",
+            r#"{"a":1}"#,
+            r#"{"a":2}"#,
+            "--from",
+            "more.json",
+            "--print-ids",
+        ],
+    ));
+    assert_eq!(printed, "1\n2\n3\n4\n");
+    let a = records(dir, "db.json")
+        .iter()
+        .map(|record| record["a"].as_u64().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(a, vec![1, 2, 3, 4]);
+
+    // stdin is only read when nothing else is given, and ids are only printed when asked for
+    let printed = stdout(&jsondb_with_input(dir, &["add", "db.json"], r#"{"a":5}"#));
+    assert_eq!(printed, "");
+    assert_eq!(ids(dir, "db.json"), vec![1, 2, 3, 4, 5]);
+
+    // nothing is added if any record doesn't parse
+    let output = jsondb(dir, &["add", "db.json", r#"{"a":6}"#, "{"]);
+    assert!(!output.status.success());
+    std::fs::write(dir.join("broken.json"), "{\"a\":6}\n[1]\n").unwrap();
+    let output = jsondb(dir, &["add", "db.json", "--from", "broken.json"]);
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1, 2, 3, 4, 5]);
+}
+
+#[test]
+fn update_set_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"a":1,"b":"x"}"#, r#"{"a":2,"b":"y"}"#, r#"{"a":3}"#],
+    );
+
+    // nothing is picked, so nothing may be changed
+    let output = jsondb(dir, &["update", "db.json", "--set", "c=1"]);
+    assert!(!output.status.success());
+    let output = jsondb(dir, &["update", "db.json", "--unset", "b"]);
+    assert!(!output.status.success());
+
+    // a dry run prints what a real run writes, and writes nothing
+    let dry_run = stdout(&jsondb(
+        dir,
+        &[
+            "--output",
+            "jsonl",
+            "update",
+            "db.json",
+            "--dry-run",
+            "--set",
+            "c=[1]",
+            "--unset",
+            "b",
+            "1",
+        ],
+    ));
+    assert_eq!(dry_run.lines().count(), 1);
+    let before = records(dir, "db.json");
+    assert_eq!(before[0]["b"], "x");
+    assert!(before[0].get("c").is_none());
+
+    // values are JSON if they parse and strings otherwise
+    stdout(&jsondb(
+        dir,
+        &[
+            "update", "db.json", "--set", "c=[1]", "--set", "d=hello", "--unset", "b", "1", "2",
+        ],
+    ));
+    stdout(&jsondb(
+        dir,
+        &["update", "db.json", "--filter", "a == 3", "--unset", "a"],
+    ));
+    let after = records(dir, "db.json");
+    assert_eq!(
+        after[0],
+        serde_json::json!({"id":1,"a":1,"c":[1],"d":"hello"})
+    );
+    assert_eq!(
+        after[1],
+        serde_json::json!({"id":2,"a":2,"c":[1],"d":"hello"})
+    );
+    assert_eq!(after[2], serde_json::json!({"id":3}));
+
+    // every id has to exist, or nothing is changed
+    let output = jsondb(dir, &["update", "db.json", "--set", "e=1", "1", "9"]);
+    assert!(!output.status.success());
+    assert!(records(dir, "db.json")[0].get("e").is_none());
+
+    // `--set` and `--unset` can't be mixed with `--jq`
+    let output = jsondb(
+        dir,
+        &["update", "db.json", "--jq", ".", "--set", "e=1", "1"],
+    );
+    assert!(!output.status.success());
+}
+
+#[test]
+fn edit_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#]);
+    let tmp = dir.join("tmp");
+    std::fs::create_dir(&tmp).unwrap();
+
+    // the editor is a script, which is handed the file to edit
+    let edit = |script: &str, input: &str| {
+        std::fs::write(dir.join("editor.sh"), script).unwrap();
+        let output = run(
+            command(dir, &["edit", "db.json", "1"])
+                .env_remove("VISUAL")
+                .env("EDITOR", format!("sh {}", dir.join("editor.sh").display()))
+                .env("TMPDIR", &tmp),
+            input,
+        );
+        assert_eq!(std::fs::read_dir(&tmp).unwrap().count(), 0);
+        output
+    };
+
+    stdout(&edit(r#"sed -i 's/"a": 1/"a": 2/' "$1""#, ""));
+    assert_eq!(records(dir, "db.json")[0]["a"], 2);
+
+    // invalid JSON can be given up on, leaving the record as it was
+    let output = edit(r#"echo '{' > "$1""#, "n\n");
+    assert!(!output.status.success());
+    assert_eq!(records(dir, "db.json")[0]["a"], 2);
+
+    // as can the editor failing
+    let output = edit("exit 1", "");
+    assert!(!output.status.success());
+    assert_eq!(records(dir, "db.json")[0]["a"], 2);
+}
+
+#[cfg(feature = "jsonschema")]
+#[test]
+fn validate_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::write(
+        dir.join("schema.json"),
+        r#"{"properties":{"a":{"type":"integer"}}}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        dir.join("db.json"),
+        r#"{"id":1,"a":1}
+{"id":2,"a":"two"}
+{"id":2,"a":2}
+{"id":3,"a":"three"}
+{"id":4,"a":"four"}
+{"id":4,"deleted":true}
+{"id":5,"a":5}
+{"id":5,"_merge":"five"}
+"#,
+    )
+    .unwrap();
+
+    // only the records as they are now count, and merges that can't be applied aren't guessed at
+    let output = jsondb(dir, &["validate", "db.json", "--schema", "schema.json"]);
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].starts_with("record 3 (offset 49): "), "{}", stdout);
+    assert!(lines[1].starts_with("record 5: skipped"), "{}", stdout);
+
+    // the global options are honored, and checksums are checked as the log is read
+    let add = ["--checksums", "--no-lock", "add", "crc.json", r#"{"a":1}"#];
+    assert!(jsondb(dir, &add).status.success());
+    let validate = [
+        "--checksums",
+        "--no-lock",
+        "validate",
+        "crc.json",
+        "--schema",
+        "schema.json",
+    ];
+    assert!(jsondb(dir, &validate).status.success());
+    let log = std::fs::read_to_string(dir.join("crc.json")).unwrap();
+    std::fs::write(dir.join("crc.json"), log.replace("\"a\":1", "\"a\":2")).unwrap();
+    let output = jsondb(dir, &validate);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("checksum mismatch"), "{}", stderr);
+}
+
+#[cfg(feature = "jsonschema")]
+#[test]
+fn validate_locked_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::write(dir.join("schema.json"), r#"{"type":"object"}"#).unwrap();
+    stdout(&jsondb(
+        dir,
+        &["init", "db.json", "--schema", "schema.json"],
+    ));
+    add(dir, "db.json", &[r#"{"a":1}"#]);
+
+    // the schema is read from the header under the same options as the records
+    let log = std::fs::File::open(dir.join("db.json")).unwrap();
+    log.lock().unwrap();
+    let output = jsondb(dir, &["--lock-timeout", "100ms", "validate", "db.json"]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("locked"), "{}", stderr);
+    stdout(&jsondb(dir, &["--no-lock", "validate", "db.json"]));
+    log.unlock().unwrap();
+    stdout(&jsondb(dir, &["validate", "db.json"]));
+}
+
+#[test]
+fn init_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::create_dir(dir.join("schemas")).unwrap();
+    std::fs::write(dir.join("schemas/schema.json"), r#"{"type":"object"}"#).unwrap();
+
+    stdout(&jsondb(
+        dir,
+        &["init", "db.json", "--schema", "schemas/schema.json"],
+    ));
+    let contents = std::fs::read_to_string(dir.join("db.json")).unwrap();
+    let header: Value = serde_json::from_str(contents.trim_end()).unwrap();
+    assert_eq!(header["id_type"], "u32");
+    assert_eq!(header["schema"], "schemas/schema.json");
+    assert!(ids(dir, "db.json").is_empty());
+
+    // an existing database is left alone
+    add(dir, "db.json", &[r#"{"a":1}"#]);
+    let output = jsondb(dir, &["init", "db.json"]);
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1]);
+
+    // ids are always u32, so no database is created for anything else
+    let output = jsondb(dir, &["init", "uuid.json", "--id-type", "uuid"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("uuid"));
+    assert!(!dir.join("uuid.json").exists());
+
+    let output = jsondb(dir, &["init", "missing.json", "--schema", "missing.json"]);
+    assert!(!output.status.success());
+    assert!(!dir.join("missing.json").exists());
+}
+
+#[test]
+fn import_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#]);
+
+    // documents with an id field only replace the record with that id when asked to
+    let input = "{\"key\":1,\"a\":2}\n{\"key\":\"5\",\"a\":5}\n";
+    let output = jsondb_with_input(dir, &["import", "db.json", "--id-field", "key"], input);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("record id 1 is already in use"),
+        "{}",
+        stderr
+    );
+    assert_eq!(ids(dir, "db.json"), vec![1]);
+    let args = ["import", "db.json", "--id-field", "key", "--replace"];
+    stdout(&jsondb_with_input(dir, &args, input));
+    assert_eq!(ids(dir, "db.json"), vec![1, 5]);
+    assert_eq!(records(dir, "db.json")[0]["a"], 2);
+
+    // the same goes for csv rows
+    std::fs::write(dir.join("keyed.csv"), "key,a\n5,6\n").unwrap();
+    let args = [
+        "import",
+        "db.json",
+        "--format",
+        "csv",
+        "--from",
+        "keyed.csv",
+        "--id-field",
+        "key",
+    ];
+    assert!(!jsondb(dir, &args).status.success());
+    assert_eq!(records(dir, "db.json")[1]["a"], 5);
+    stdout(&jsondb(dir, &[&args[..], &["--replace"]].concat()));
+    assert_eq!(records(dir, "db.json")[1]["a"], 6);
+
+    // and for an id that appears twice in what is imported
+    let input = "{\"key\":3,\"a\":3}\n{\"key\":3,\"a\":4}\n";
+    let output = jsondb_with_input(dir, &["import", "db.json", "--id-field", "key"], input);
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1, 5]);
+
+    std::fs::write(dir.join("rows.csv"), "name,a\nx,1\ny,2\n").unwrap();
+    let output = stdout(&jsondb(
+        dir,
+        &[
+            "import",
+            "db.json",
+            "--format",
+            "csv",
+            "--from",
+            "rows.csv",
+            "--id-column",
+            "name",
+        ],
+    ));
+    assert_eq!(output, "x\t6\ny\t7\n");
+    assert_eq!(ids(dir, "db.json"), vec![1, 5, 6, 7]);
+
+    // a bad row is caught before anything is imported
+    std::fs::write(dir.join("rows.csv"), "name,a\nz,1\n,2\n").unwrap();
+    let output = jsondb(
+        dir,
+        &[
+            "import",
+            "db.json",
+            "--format",
+            "csv",
+            "--from",
+            "rows.csv",
+            "--id-column",
+            "name",
+        ],
+    );
+    assert!(!output.status.success());
+    let output = jsondb_with_input(
+        dir,
+        &["import", "db.json", "--id-field", "key"],
+        "{\"key\":8,\"a\":8}\n{\"a\":9}\n",
+    );
+    assert!(!output.status.success());
+    assert_eq!(ids(dir, "db.json"), vec![1, 5, 6, 7]);
+}
+
+#[test]
+fn compact_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#, r#"{"a":4}"#],
+    );
+    stdout(&jsondb(dir, &["update", "db.json", "--set", "a=10", "1"]));
+    stdout(&jsondb(dir, &["rm", "db.json", "2", "4"]));
+    let before = records(dir, "db.json");
+    let lines = |dir: &Path| {
+        std::fs::read_to_string(dir.join("db.json"))
+            .unwrap()
+            .lines()
+            .count()
+    };
+    assert_eq!(lines(dir), 7);
+
+    stdout(&jsondb(dir, &["compact", "db.json"]));
+    assert_eq!(records(dir, "db.json"), before);
+    // the marker for the highest id stays, so it isn't handed out again
+    assert_eq!(lines(dir), 3);
+    add(dir, "db.json", &[r#"{"a":5}"#]);
+    assert_eq!(ids(dir, "db.json"), vec![1, 3, 5]);
+
+    // nothing is created for a database that isn't there
+    let output = jsondb(dir, &["compact", "missing.json"]);
+    assert!(!output.status.success());
+    assert!(!dir.join("missing.json").exists());
+}
+
+#[test]
+#[cfg(any(feature = "jq", feature = "jaq"))]
+fn watch_jq_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":0}"#]);
+    let args = |jq| ["watch", "db.json", "--interval", "20ms", "--jq", jq];
+    let (_selecting, selected) = watch(dir, &args("select(.a == 3)"));
+    let (_splitting, split) = watch(dir, &args(".id, .a"));
+
+    // changes the filter has no output for are left out, rather than failing
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":3}"#]);
+    assert_eq!(next(&selected), serde_json::json!({"id": 3, "a": 3}));
+    let outputs = (0..4).map(|_| next(&split)).collect::<Vec<_>>();
+    assert_eq!(outputs, [2, 1, 3, 3]);
+    add(dir, "db.json", &[r#"{"a":3}"#]);
+    assert_eq!(next(&selected)["id"], 4);
+}
+
+#[test]
+fn watch_compact_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#]);
+    let (_watching, changes) = watch(dir, &["watch", "db.json", "--interval", "20ms"]);
+
+    stdout(&jsondb(dir, &["update", "db.json", "--set", "a=10", "1"]));
+    assert_eq!(next(&changes), serde_json::json!({"id": 1, "a": 10}));
+
+    // it keeps going on the new file after compaction, printing only what changed
+    stdout(&jsondb(dir, &["compact", "db.json"]));
+    add(dir, "db.json", &[r#"{"a":3}"#]);
+    assert_eq!(next(&changes), serde_json::json!({"id": 3, "a": 3}));
+    stdout(&jsondb(dir, &["rm", "db.json", "2"]));
+    assert_eq!(next(&changes)["id"], 2);
+    assert!(changes.recv_timeout(Duration::from_millis(200)).is_err());
+}
+
+#[test]
+fn health_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#]);
+    let before = std::fs::read(dir.join("db.json")).unwrap();
+
+    let output = stdout(&jsondb(dir, &["--checksums", "health", "db.json"]));
+    assert_eq!(
+        output,
+        "reachable: ok\nlockable: ok\ntail: ok\nwritable: ok\n"
+    );
+    assert_eq!(std::fs::read(dir.join("db.json")).unwrap(), before);
+
+    // a partly written entry at the end fails the probe, without anything being written
+    let mut torn = before.clone();
+    torn.extend_from_slice(br#"{"id":2,"a":"#);
+    std::fs::write(dir.join("db.json"), &torn).unwrap();
+    let output = jsondb(dir, &["health", "db.json"]);
+    assert!(!output.status.success());
+    assert_eq!(std::fs::read(dir.join("db.json")).unwrap(), torn);
+
+    let output = jsondb(dir, &["health", "missing.json"]);
+    assert!(!output.status.success());
+    assert!(!dir.join("missing.json").exists());
+}
+
+#[test]
+fn merge_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#]);
+    add(
+        dir,
+        "other.json",
+        &[r#"{"a":10}"#, r#"{"a":2}"#, r#"{"a":30}"#],
+    );
+
+    // only what's different is written, and nothing is said about it unless asked for
+    let output = jsondb(dir, &["merge", "db.json", "other.json", "-s", "theirs"]);
+    assert_eq!(stdout(&output), "");
+    assert!(output.stderr.is_empty());
+    let a = |dir| {
+        records(dir, "db.json")
+            .iter()
+            .map(|record| record["a"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(a(dir), vec![10, 2, 30]);
+
+    let args = [
+        "--verbose",
+        "--log-format",
+        "json",
+        "merge",
+        "db.json",
+        "other.json",
+    ];
+    let output = jsondb(dir, &args);
+    stdout(&output);
+    let merged = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .find(|event| event["event"] == "merged")
+        .unwrap();
+    assert_eq!(merged["other"], "other.json");
+    assert_eq!(merged["records"], 0);
+}
+
+#[test]
+fn profile_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    std::fs::create_dir(dir.join("data")).unwrap();
+    std::fs::write(
+        dir.join("config.toml"),
+        "[databases.people]\npath = \"data/people.json\"\noutput = \"jsonl\"\nfilter = \"a > 1\"\n",
+    )
+    .unwrap();
+
+    // paths are relative to the config file, and the profile's defaults apply to `list`
+    add(dir, "@people", &[r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#]);
+    assert!(dir.join("data/people.json").exists());
+    let listed = stdout(&jsondb(dir, &["list", "@people"]));
+    assert_eq!(listed, "{\"id\":2,\"a\":2}\n{\"id\":3,\"a\":3}\n");
+    let listed = stdout(&jsondb(dir, &["list", "@people", "--filter", "a == 1"]));
+    assert_eq!(listed, "{\"id\":1,\"a\":1}\n");
+    let listed = stdout(&jsondb(dir, &["--output", "json", "list", "@people"]));
+    assert_eq!(listed, "[{\"id\":2,\"a\":2},{\"id\":3,\"a\":3}]\n");
+
+    // another config file can be given, and unknown names are an error
+    std::fs::write(
+        dir.join("other.toml"),
+        "[databases.others]\npath = \"data/people.json\"\n",
+    )
+    .unwrap();
+    let args = [
+        "--config",
+        "other.toml",
+        "--output",
+        "jsonl",
+        "list",
+        "@others",
+    ];
+    assert_eq!(stdout(&jsondb(dir, &args)).lines().count(), 3);
+    let output = jsondb(dir, &["list", "@nobody"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("no database named `nobody`"));
+}
+
+#[test]
+fn completions_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#]);
+
+    // the script calls back into this same binary to complete
+    let script = stdout(&jsondb(dir, &["completions", "bash"]));
+    assert!(script.contains("COMPLETE=\"bash\""));
+    assert!(script.contains(env!("CARGO_BIN_EXE_jsondb")));
+
+    // ids are offered with a preview of the record, for databases named by profile as well
+    std::fs::write(
+        dir.join("config.toml"),
+        "[databases.db]\npath = \"db.json\"\n",
+    )
+    .unwrap();
+    let complete = |file: &str| {
+        let mut command = command(dir, &["--", "jsondb", "rm", file, ""]);
+        stdout(&run(command.env("COMPLETE", "fish"), ""))
+            .lines()
+            .filter(|line| !line.starts_with('-'))
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(complete("db.json"), ["1\t{\"a\":1}", "2\t{\"a\":2}"]);
+    assert_eq!(complete("@db"), complete("db.json"));
+
+    // and completing never creates a database
+    assert!(complete("missing.json").is_empty());
+    assert!(!dir.join("missing.json").exists());
+}
+
+#[test]
+fn output_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#]);
+
+    let pretty =
+        "[\n  {\n    \"id\": 1,\n    \"a\": 1\n  },\n  {\n    \"id\": 2,\n    \"a\": 2\n  }\n]\n";
+    let listed = stdout(&jsondb(
+        dir,
+        &["--output", "json", "--pretty", "list", "db.json"],
+    ));
+    assert_eq!(listed, pretty);
+    let listed = stdout(&jsondb(
+        dir,
+        &["--output", "jsonl", "--pretty", "list", "db.json"],
+    ));
+    assert_eq!(
+        listed,
+        "{\n  \"id\": 1,\n  \"a\": 1\n}\n{\n  \"id\": 2,\n  \"a\": 2\n}\n"
+    );
+
+    // exporting a json array honors --pretty the same way
+    let exported = stdout(&jsondb(
+        dir,
+        &["--pretty", "export", "db.json", "-f", "json"],
+    ));
+    assert_eq!(exported, pretty);
+    let exported = stdout(&jsondb(dir, &["export", "db.json", "-f", "json"]));
+    let exported: Value = serde_json::from_str(&exported).unwrap();
+    assert_eq!(exported, serde_json::from_str::<Value>(pretty).unwrap());
+
+    let output = jsondb(dir, &["--output", "yaml", "list", "db.json"]);
+    if cfg!(feature = "yaml") {
+        assert_eq!(stdout(&output), "- id: 1\n  a: 1\n- id: 2\n  a: 2\n");
+    } else {
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("`yaml` feature"));
+    }
+}
+
+#[test]
+fn table_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":10}"#, r#"{"a":"x y","b":[1]}"#]);
+
+    // columns are as wide as their widest cell, and missing values are left blank
+    let table = stdout(&jsondb(dir, &["--output", "table", "list", "db.json"]));
+    assert_eq!(table, "id  a    b\n--  ---  ---\n1   10\n2   x y  [1]\n");
+
+    // the id always comes first, whichever columns are picked
+    let args = ["--output", "table", "list", "db.json", "--columns", "b"];
+    let table = stdout(&jsondb(dir, &args));
+    assert_eq!(table, "id  b\n--  ---\n1\n2   [1]\n");
+}
+
+#[test]
+fn export_csv_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"a":10}"#, r#"{"a":"q,\"r\"","b":[1]}"#],
+    );
+
+    // nested values are written as json, and cells are quoted where csv needs them to be
+    let csv = stdout(&jsondb(dir, &["export", "db.json", "-f", "csv"]));
+    assert_eq!(csv, "id,a,b\n1,10,\n2,\"q,\"\"r\"\"\",[1]\n");
+
+    // picked columns come in the order they're given, and can go to a file instead
+    let args = ["export", "db.json", "-f", "csv", "--columns", "b,a"];
+    let csv = stdout(&jsondb(dir, &args));
+    assert_eq!(csv, "b,a\n,10\n[1],\"q,\"\"r\"\"\"\n");
+    let args = ["export", "db.json", "-f", "csv", "--to", "out.csv"];
+    assert_eq!(stdout(&jsondb(dir, &args)), "");
+    let written = std::fs::read_to_string(dir.join("out.csv")).unwrap();
+    assert_eq!(written, "id,a,b\n1,10,\n2,\"q,\"\"r\"\"\",[1]\n");
+
+    // strings that would be read back as something else are written as json strings, so that
+    // importing the export gives the same records again
+    let values = [
+        r#"{"a":"123","b":123}"#,
+        r#"{"a":"true","b":true}"#,
+        r#"{"a":"","b":null}"#,
+        r#"{"a":"null","b":"\"quoted\""}"#,
+        r#"{"a":"[1]","b":{"c":[1]}}"#,
+    ];
+    add(dir, "values.json", &values);
+    let csv = stdout(&jsondb(dir, &["export", "values.json", "-f", "csv"]));
+    assert_eq!(
+        csv.lines().skip(1).collect::<Vec<_>>(),
+        vec![
+            r#"1,"""123""",123"#,
+            r#"2,"""true""",true"#,
+            r#"3,"""""",null"#,
+            r#"4,"""null""","""\""quoted\""""""#,
+            r#"5,"""[1]""","{""c"":[1]}""#,
+        ]
+    );
+    std::fs::write(dir.join("values.csv"), csv).unwrap();
+    let args = ["import", "copy.json", "-f", "csv", "--from", "values.csv"];
+    stdout(&jsondb(dir, &args));
+    assert_eq!(records(dir, "copy.json"), records(dir, "values.json"));
+}
+
+#[test]
+fn verbose_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#]);
+
+    // nothing is logged unless asked for, and the log never goes to stdout
+    let output = jsondb(dir, &["rm", "db.json", "1"]);
+    assert_eq!(stdout(&output), "");
+    assert!(output.stderr.is_empty());
+
+    let output = jsondb(dir, &["--verbose", "rm", "db.json", "2"]);
+    assert_eq!(stdout(&output), "");
+    let log = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = log.lines().collect();
+    assert_eq!(lines.len(), 3, "{log}");
+    assert!(lines[0].starts_with("jsondb: opened file=\"db.json\" records=1 entries=3 "));
+    assert!(lines[1].starts_with("jsondb: wrote records=1 "));
+    assert!(lines[2].starts_with("jsondb: finished command=\"remove\" elapsed="));
+
+    add(dir, "db.json", &[r#"{"a":3}"#]);
+    let args = ["--verbose", "--log-format", "json", "rm", "db.json", "3"];
+    let output = jsondb(dir, &args);
+    assert_eq!(stdout(&output), "");
+    let events: Vec<Value> = String::from_utf8(output.stderr)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let names: Vec<_> = events.iter().map(|event| &event["event"]).collect();
+    assert_eq!(names, ["opened", "wrote", "finished"]);
+    assert_eq!(events[0]["file"], "db.json");
+    assert_eq!(events[0]["records"], 1);
+    assert_eq!(events[1]["records"], 1);
+    assert_eq!(events[2]["command"], "remove");
+    assert!(events[2]["elapsed_ms"].is_f64());
+}
+
+#[test]
+fn search_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[
+            r#"{"name":"Alice","note":"likes Bob"}"#,
+            r#"{"name":"Bob","tags":["x",{"deep":"ALICE"}]}"#,
+            r#"{"name":"Carol","age":1}"#,
+        ],
+    );
+
+    // strings are matched without regard to case, however deep they're nested
+    let found = |args: &[&str]| {
+        let mut all = vec!["--output", "jsonl", "search", "db.json"];
+        all.extend(args);
+        stdout(&jsondb(dir, &all))
+            .lines()
+            .map(|line| {
+                serde_json::from_str::<Value>(line).unwrap()["id"]
+                    .as_u64()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(found(&["alice"]), [1, 2]);
+    assert_eq!(found(&["bob"]), [1, 2]);
+    assert_eq!(found(&["bob", "--fields", "name"]), [2]);
+    assert_eq!(found(&["alice", "--fields", "note,name"]), [1]);
+
+    // only strings are searched
+    assert!(found(&["1"]).is_empty());
+}
+
+#[test]
+fn jaq_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(dir, "db.json", &[r#"{"a":1}"#, r#"{"a":2}"#, r#"{"a":3}"#]);
+
+    let jaq = |args: &[&str]| {
+        let mut all = vec!["--jq-engine", "jaq"];
+        all.extend(args);
+        jsondb(dir, &all)
+    };
+    let output = jaq(&["update", "db.json", "1", "2", "--jq", ".a += 10"]);
+    if !cfg!(feature = "jaq") {
+        assert!(!output.status.success());
+        assert!(String::from_utf8_lossy(&output.stderr).contains("`jaq` feature"));
+        return;
+    }
+    stdout(&output);
+    let a = |dir| {
+        records(dir, "db.json")
+            .iter()
+            .map(|record| record["a"].as_u64().unwrap())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(a(dir), vec![11, 12, 3]);
+
+    // `select` works the same as with libjq, and so do errors in the expression
+    stdout(&jaq(&["rm", "db.json", "--jq", "select(.a > 11)"]));
+    assert_eq!(a(dir), vec![11, 3]);
+    let output = jaq(&["update", "db.json", "1", "--jq", "., ."]);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("expected exactly one output per input"),
+        "{}",
+        stderr
+    );
+    let output = jaq(&["update", "db.json", "1", "--jq", "nope"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("jq error: undefined nope"));
+    assert_eq!(a(dir), vec![11, 3]);
+}
+
+#[test]
+fn stats_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    stdout(&jsondb(dir, &["init", "db.json"]));
+    let stats = stdout(&jsondb(dir, &["stats", "db.json"]));
+    let lines: Vec<_> = stats.lines().collect();
+    assert_eq!(lines[0], "live records:    0");
+    assert_eq!(lines[5], "min id:          -");
+    assert_eq!(lines[6], "max id:          -");
+    assert_eq!(lines.len(), 7);
+
+    add(
+        dir,
+        "db.json",
+        &[
+            r#"{"a":1}"#,
+            r#"{"a":2,"bb":1}"#,
+            r#"{"a":3}"#,
+            r#"{"a":4}"#,
+        ],
+    );
+    stdout(&jsondb(dir, &["rm", "db.json", "1", "4"]));
+    let stats = stdout(&jsondb(dir, &["stats", "db.json"]));
+    let size = std::fs::metadata(dir.join("db.json")).unwrap().len();
+    let lines: Vec<_> = stats.lines().collect();
+    assert_eq!(
+        lines[..4],
+        [
+            "live records:    2",
+            "deleted records: 2",
+            "log entries:     6",
+            &format!("file size:       {size} bytes"),
+        ]
+    );
+    assert!(lines[4].starts_with("dead bytes:      "));
+    assert_eq!(lines[5..7], ["min id:          2", "max id:          3"]);
+
+    // then how many of the live records have each field, lined up by the longest name
+    assert_eq!(lines[7..], ["", "a   2 (100.0%)", "bb  1 (50.0%)"]);
+}
+
+#[test]
+fn bench_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+
+    // a row per operation, then the counters of the handle, and the scratch file is gone after
+    let args = ["bench", "scratch.json", "--records", "20", "--ops", "10"];
+    let output = stdout(&jsondb(dir, &args));
+    let operations = output
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .map(|line| {
+            line.split_whitespace()
+                .take(2)
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(
+        operations,
+        [
+            "insert 20",
+            "reload 20",
+            "get 10",
+            "update 10",
+            "compact 30"
+        ]
+    );
+    assert!(output.contains("\nreloads: "));
+    assert!(output.contains("\nwrites: "));
+    assert_eq!(std::fs::read_dir(dir).unwrap().count(), 0);
+
+    // an existing file is never touched
+    std::fs::write(dir.join("db.json"), "").unwrap();
+    let output = jsondb(dir, &["bench", "db.json", "--records", "5"]);
+    assert!(!output.status.success());
+    assert!(dir.join("db.json").exists());
+}
+
+#[cfg(feature = "sqlite")]
+#[test]
+fn import_sqlite_test() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    add(
+        dir,
+        "db.json",
+        &[r#"{"name":"x","a":1}"#, r#"{"name":"y","b":[1]}"#],
+    );
+    let args = [
+        "export",
+        "db.json",
+        "-f",
+        "sqlite",
+        "--to",
+        "db.sqlite",
+        "--explode",
+    ];
+    stdout(&jsondb(dir, &args));
+    add(dir, "copy.json", &[r#"{"a":0}"#]);
+
+    // rows become records, and the source keys are printed along with the ids they were given
+    let import = |table| {
+        let args = ["import", "copy.json", "-f", "sqlite", "--from", "db.sqlite"];
+        let mut args = args.to_vec();
+        args.extend(["--table", table, "--id-column", "name"]);
+        jsondb(dir, &args)
+    };
+    assert_eq!(stdout(&import("records")), "x\t2\ny\t3\n");
+    assert_eq!(
+        records(dir, "copy.json")[1..],
+        [
+            serde_json::json!({"id": 2, "name": "x", "a": 1}),
+            serde_json::json!({"id": 3, "name": "y", "b": [1]}),
+        ]
+    );
+
+    // nothing is imported from a table that isn't there, or without one
+    assert!(!import("missing").status.success());
+    let args = ["import", "copy.json", "-f", "sqlite", "--from", "db.sqlite"];
+    assert!(!jsondb(dir, &args).status.success());
+    assert_eq!(ids(dir, "copy.json"), vec![1, 2, 3]);
+}